@@ -0,0 +1,71 @@
+//! Chat backends that stand in for the Anthropic and OpenAI clients
+
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use super::ChatMessage;
+
+/// The reply a `ChatBackend` is working on
+pub type BackendFuture<'a> = Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>;
+
+/// Answers chat requests in place of the built-in providers
+///
+/// A backend set with `AiEngine::with_backend` receives every request,
+/// with the model the engine would have used and the system prompt after
+/// the project context has been added to it. JSON mode and streaming are
+/// plain requests to it.
+pub trait ChatBackend: Send + Sync {
+    fn complete<'a>(&'a self, model: &'a str, system_prompt: &'a str, messages: &'a [ChatMessage]) -> BackendFuture<'a>;
+}
+
+/// A request received by a `ScriptedBackend`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptedRequest {
+    pub system_prompt: String,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl ScriptedRequest {
+    /// Text of the last message, which is the user's
+    pub fn user_message(&self) -> &str {
+        self.messages.last().map(|message| message.content.as_str()).unwrap_or_default()
+    }
+}
+
+/// A backend that replies with canned responses in order, for tests
+///
+/// Requests are recorded so tests can check what would have been sent.
+/// Running out of responses is an error.
+#[derive(Debug, Default)]
+pub struct ScriptedBackend {
+    responses: Mutex<VecDeque<String>>,
+    requests: Mutex<Vec<ScriptedRequest>>,
+}
+
+impl ScriptedBackend {
+    pub fn new<S: Into<String>>(responses: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            responses: Mutex::new(responses.into_iter().map(Into::into).collect()),
+            requests: Mutex::default(),
+        }
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<ScriptedRequest> {
+        self.requests.lock().expect("requests lock poisoned").clone()
+    }
+}
+
+impl ChatBackend for ScriptedBackend {
+    fn complete<'a>(&'a self, _model: &'a str, system_prompt: &'a str, messages: &'a [ChatMessage]) -> BackendFuture<'a> {
+        self.requests.lock().expect("requests lock poisoned").push(ScriptedRequest {
+            system_prompt: system_prompt.to_string(),
+            messages: messages.to_vec(),
+        });
+        let response = self.responses.lock().expect("responses lock poisoned").pop_front();
+        Box::pin(async move { response.ok_or_else(|| anyhow!("The scripted backend has no responses left")) })
+    }
+}
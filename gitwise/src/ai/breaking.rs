@@ -0,0 +1,117 @@
+use anyhow::Result;
+use git2::{Diff, DiffLineType};
+use regex::Regex;
+use std::fmt;
+
+/// A public Rust item that a diff removes or whose signature it changes
+#[derive(Debug, Clone, PartialEq)]
+pub enum BreakingChange {
+    Removed { path: String, signature: String },
+    Changed { path: String, old: String, new: String },
+}
+
+impl fmt::Display for BreakingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakingChange::Removed { path, signature } => write!(f, "removed `{}` ({})", signature, path),
+            BreakingChange::Changed { path, old, new } => write!(f, "changed `{}` to `{}` ({})", old, new, path),
+        }
+    }
+}
+
+/// A public item declared on a changed line
+struct Item {
+    path: String,
+    kind: String,
+    name: String,
+    signature: String,
+}
+
+/// Find public Rust items that a diff removes or changes the signature of
+///
+/// This is a heuristic over the first line of each declaration. Items are
+/// matched by kind and name across the whole diff, so moving an item between
+/// files is not reported. Methods without `pub` count when the hunk header
+/// names a `pub trait`.
+pub fn detect_breaking_changes(diff: &Diff<'_>) -> Result<Vec<BreakingChange>> {
+    let declaration = Regex::new(
+        r"^\s*(pub\s+)?(?:(?:async|const|unsafe|extern)\s+)*(fn|struct|enum|trait|type|const|static|mod)\s+([A-Za-z_][A-Za-z0-9_]*)",
+    ).expect("declaration pattern is valid");
+
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |delta, hunk, line| {
+        let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+            return true;
+        };
+        if path.extension().is_none_or(|extension| extension != "rs") {
+            return true;
+        }
+        let side = match line.origin_value() {
+            DiffLineType::Deletion => &mut removed,
+            DiffLineType::Addition => &mut added,
+            _ => return true,
+        };
+        let content = String::from_utf8_lossy(line.content());
+        let Some(captures) = declaration.captures(&content) else {
+            return true;
+        };
+        let in_pub_trait = hunk.is_some_and(|hunk| String::from_utf8_lossy(hunk.header()).contains("pub trait "));
+        if captures.get(1).is_some() || (&captures[2] == "fn" && in_pub_trait) {
+            side.push(Item {
+                path: path.display().to_string(),
+                kind: captures[2].to_string(),
+                name: captures[3].to_string(),
+                signature: signature(&content),
+            });
+        }
+        true
+    })?;
+
+    Ok(removed.into_iter()
+        .filter_map(|old| {
+            match added.iter().find(|new| new.kind == old.kind && new.name == old.name) {
+                None => Some(BreakingChange::Removed { path: old.path, signature: old.signature }),
+                Some(new) if new.signature != old.signature => Some(BreakingChange::Changed {
+                    path: new.path.clone(),
+                    old: old.signature,
+                    new: new.signature.clone(),
+                }),
+                Some(_) => None,
+            }
+        })
+        .collect())
+}
+
+/// A declaration line with whitespace collapsed and any trailing `{` or `;`
+fn signature(line: &str) -> String {
+    let collapsed = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.trim_end_matches(['{', ';']).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_breaking_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = |content: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("lib.rs", repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let old = tree("pub fn parse(input: &str) -> u32 {\n    0\n}\n\npub struct Config;\n\nfn helper() {}\n\npub fn keep() {}\n");
+        let new = tree("pub fn parse(input: &[u8]) -> u32 {\n    0\n}\n\nfn helper(x: u32) {}\n\npub fn keep() {}\n");
+
+        let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), None).unwrap();
+        let changes: Vec<String> = detect_breaking_changes(&diff).unwrap().iter().map(|c| c.to_string()).collect();
+        assert_eq!(changes, vec![
+            "changed `pub fn parse(input: &str) -> u32` to `pub fn parse(input: &[u8]) -> u32` (lib.rs)",
+            "removed `pub struct Config` (lib.rs)",
+        ]);
+    }
+}
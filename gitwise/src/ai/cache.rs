@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// Default number of days a cached summary stays valid
+pub const DEFAULT_CACHE_TTL_DAYS: u64 = 7;
+
+/// On-disk cache of AI summaries keyed by diff content, model and prompt
+pub struct SummaryCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl SummaryCache {
+    /// Open the cache in its default location, evicting stale entries
+    pub fn open(ttl_days: u64) -> Result<Self> {
+        let cache = Self {
+            dir: default_cache_dir()?,
+            ttl: Duration::from_secs(ttl_days * 24 * 60 * 60),
+        };
+        cache.evict_stale()?;
+        Ok(cache)
+    }
+
+    /// Build the cache key for a request
+    pub fn key(model: &str, system_prompt: &str, diff_text: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [model, system_prompt, diff_text] {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Look up a cached summary
+    pub fn get(&self, key: &str) -> Option<String> {
+        let path = self.entry_path(key);
+        if self.is_stale(&path) {
+            return None;
+        }
+        let summary = fs::read_to_string(&path).ok()?;
+        debug!("Summary cache hit for {}", key);
+        Some(summary)
+    }
+
+    /// Store a summary
+    pub fn put(&self, key: &str, summary: &str) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory {}", self.dir.display()))?;
+        fs::write(self.entry_path(key), summary)?;
+        Ok(())
+    }
+
+    /// Remove entries older than the TTL
+    fn evict_stale(&self) -> Result<()> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(());
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if self.is_stale(&path) {
+                debug!("Evicting stale cache entry {}", path.display());
+                fs::remove_file(&path).ok();
+            }
+        }
+        Ok(())
+    }
+
+    fn is_stale(&self, path: &Path) -> bool {
+        let age = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        !matches!(age, Some(age) if age <= self.ttl)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.txt", key))
+    }
+}
+
+/// Remove every cached summary
+pub fn clear() -> Result<()> {
+    let dir = default_cache_dir()?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to remove cache directory {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// `$XDG_CACHE_HOME/gitwise`, falling back to `~/.cache/gitwise`
+fn default_cache_dir() -> Result<PathBuf> {
+    if let Ok(dir) = env::var("XDG_CACHE_HOME") {
+        if !dir.is_empty() {
+            return Ok(PathBuf::from(dir).join("gitwise"));
+        }
+    }
+    let home = env::var("HOME").context("Neither XDG_CACHE_HOME nor HOME is set")?;
+    Ok(PathBuf::from(home).join(".cache").join("gitwise"))
+}
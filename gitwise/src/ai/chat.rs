@@ -0,0 +1,105 @@
+//! Follow-up questions about a diff
+
+use anyhow::Result;
+use git2::Diff;
+
+use super::{patch, tokens, AiEngine, ChatMessage, ChatRole};
+
+/// A running conversation about one diff
+///
+/// The diff lives in the system prompt so it is never trimmed; when the
+/// history outgrows the input token budget the oldest exchanges are dropped.
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    system_prompt: String,
+    messages: Vec<ChatMessage>,
+}
+
+impl Conversation {
+    /// The messages exchanged so far, oldest first
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// Drop the oldest exchanges until the system prompt and messages fit in
+    /// `max_tokens`, always keeping the latest message
+    fn trim_to(&mut self, max_tokens: usize, model: &str) {
+        let count = |text: &str| tokens::count_tokens(text, model);
+        let mut total = count(&self.system_prompt) + self.messages.iter().map(|message| count(&message.content)).sum::<usize>();
+        while total > max_tokens && self.messages.len() > 1 {
+            total -= count(&self.messages.remove(0).content);
+            // A conversation has to start with the user's turn
+            while self.messages.len() > 1 && self.messages[0].role == ChatRole::Assistant {
+                total -= count(&self.messages.remove(0).content);
+            }
+        }
+    }
+}
+
+impl AiEngine {
+    /// Start a conversation about a diff, picking up from its summary
+    ///
+    /// The diff is trimmed to half the input token budget, leaving the other
+    /// half for the questions and answers.
+    pub fn start_conversation(&self, diff: &Diff<'_>, summary: &str) -> Result<Conversation> {
+        let (files, _) = self.render(diff)?;
+        let diff_text = patch::trim_to_budget(&patch::join_files_with_headers(&files), self.max_input_tokens / 2, self.model_name());
+        let system_prompt = format!(
+            "You are a helpful assistant answering a developer's questions about the git diff below. \
+            Answer from the diff, quote the relevant code where it helps, and say so when the diff alone \
+            does not settle a question.\n\n```diff\n{}\n```",
+            diff_text
+        );
+        Ok(Conversation {
+            system_prompt,
+            messages: vec![
+                ChatMessage::user("Summarize these changes."),
+                ChatMessage::assistant(summary),
+            ],
+        })
+    }
+
+    /// Ask a follow-up question, adding it and the answer to the conversation
+    ///
+    /// A question that fails is taken back out, so it can be asked again.
+    pub async fn ask(&self, conversation: &mut Conversation, question: &str) -> Result<String> {
+        conversation.messages.push(ChatMessage::user(question));
+        conversation.trim_to(self.max_input_tokens, self.model_name());
+        match self.generate_reply(&conversation.system_prompt, &conversation.messages).await {
+            Ok(answer) => {
+                conversation.messages.push(ChatMessage::assistant(answer.clone()));
+                Ok(answer)
+            }
+            Err(e) => {
+                conversation.messages.pop();
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_drops_oldest_exchanges() {
+        let mut conversation = Conversation {
+            system_prompt: "x".repeat(40),
+            messages: vec![
+                ChatMessage::user("a".repeat(40)),
+                ChatMessage::assistant("b".repeat(40)),
+                ChatMessage::user("c".repeat(40)),
+                ChatMessage::assistant("d".repeat(40)),
+                ChatMessage::user("e".repeat(40)),
+            ],
+        };
+        conversation.trim_to(40, "test-model");
+        assert_eq!(conversation.messages().len(), 3);
+        assert_eq!(conversation.messages()[0], ChatMessage::user("c".repeat(40)));
+
+        // The latest question is kept even when it alone is over budget
+        conversation.trim_to(1, "test-model");
+        assert_eq!(conversation.messages(), &[ChatMessage::user("e".repeat(40))]);
+    }
+}
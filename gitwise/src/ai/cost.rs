@@ -0,0 +1,278 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long to wait for another run to finish updating the ledger
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Age after which a lock file is assumed left behind by a crashed run
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+
+/// USD per million prompt and completion tokens, matched by model name prefix
+///
+/// More specific prefixes come first.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 5.00, 15.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-sonnet", 3.00, 15.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-opus", 15.00, 75.00),
+];
+
+/// Tokens consumed by one or more requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Estimated cost in USD, if the model's price is known
+    pub fn cost(&self, model: &str) -> Option<f64> {
+        let (_, prompt_price, completion_price) = PRICES.iter().find(|(prefix, _, _)| model.starts_with(prefix))?;
+        Some((self.prompt_tokens as f64 * prompt_price + self.completion_tokens as f64 * completion_price) / 1_000_000.0)
+    }
+}
+
+/// Token usage of the requests made while measuring, and the model that
+/// answered the last of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Measured {
+    pub usage: TokenUsage,
+    pub model: Option<String>,
+}
+
+tokio::task_local! {
+    /// Usage recorded inside `UsageMeter::measure`
+    static MEASURED: RefCell<Measured>;
+}
+
+/// Running token totals per model, shared by every clone
+#[derive(Debug, Clone, Default)]
+pub struct UsageMeter {
+    totals: Arc<Mutex<BTreeMap<String, TokenUsage>>>,
+    /// Ledger every request is also added to
+    ledger: Option<UsageLedger>,
+}
+
+impl UsageMeter {
+    /// Also add every request to a ledger kept across runs
+    pub fn with_ledger(mut self, ledger: UsageLedger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Add the usage reported for one request
+    pub async fn record(&self, model: &str, prompt_tokens: u64, completion_tokens: u64) {
+        tracing::debug!("Token usage ({}): {} prompt + {} completion", model, prompt_tokens, completion_tokens);
+        {
+            let mut totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+            let usage = totals.entry(model.to_string()).or_default();
+            usage.prompt_tokens += prompt_tokens;
+            usage.completion_tokens += completion_tokens;
+        }
+        let _ = MEASURED.try_with(|measured| {
+            let mut measured = measured.borrow_mut();
+            measured.usage.prompt_tokens += prompt_tokens;
+            measured.usage.completion_tokens += completion_tokens;
+            measured.model = Some(model.to_string());
+        });
+
+        if let Some(ledger) = &self.ledger {
+            let day = chrono::Local::now().format("%Y-%m-%d").to_string();
+            if let Err(e) = ledger.add(&day, model, prompt_tokens, completion_tokens).await {
+                tracing::debug!("Failed to update usage ledger: {}", e);
+            }
+        }
+    }
+
+    /// Totals so far, by model
+    pub fn totals(&self) -> BTreeMap<String, TokenUsage> {
+        self.totals.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Run a future, returning its output with the usage of the requests
+    /// it made
+    ///
+    /// Only requests awaited within the future itself count, so futures
+    /// measured at the same time do not see each other's usage.
+    pub async fn measure<F: Future>(future: F) -> (F::Output, Measured) {
+        MEASURED.scope(RefCell::default(), async move {
+            let output = future.await;
+            (output, MEASURED.with(|measured| measured.borrow().clone()))
+        }).await
+    }
+}
+
+/// Token totals by day (`YYYY-MM-DD`) and model
+pub type LedgerEntries = BTreeMap<String, BTreeMap<String, TokenUsage>>;
+
+/// Token totals kept in a JSON file across runs
+///
+/// Updates take a lock file and replace the ledger with a rename, so
+/// concurrent runs neither lose each other's usage nor leave a half-written
+/// file behind.
+#[derive(Debug, Clone)]
+pub struct UsageLedger {
+    path: PathBuf,
+}
+
+impl UsageLedger {
+    /// The ledger in its default location
+    pub fn open() -> Result<Self> {
+        Ok(Self { path: default_ledger_path()? })
+    }
+
+    /// Everything recorded so far; empty if nothing has been
+    pub fn entries(&self) -> Result<LedgerEntries> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid usage ledger {}", self.path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(LedgerEntries::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+
+    /// Add one request's usage to a day's totals for a model
+    pub async fn add(&self, day: &str, model: &str, prompt_tokens: u64, completion_tokens: u64) -> Result<()> {
+        let _lock = self.lock().await?;
+        let mut entries = self.entries()?;
+        let usage = entries.entry(day.to_string()).or_default().entry(model.to_string()).or_default();
+        usage.prompt_tokens += prompt_tokens;
+        usage.completion_tokens += completion_tokens;
+
+        let temp = self.path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp, serde_json::to_string_pretty(&entries)?)
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Forget everything recorded
+    pub async fn reset(&self) -> Result<()> {
+        let _lock = self.lock().await?;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for exclusive access to the ledger
+    async fn lock(&self) -> Result<LedgerLock> {
+        let dir = self.path.parent().unwrap_or(&self.path);
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = self.path.with_extension("json.lock");
+        let started = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(LedgerLock(path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+                    if age.is_some_and(|age| age > STALE_LOCK_AGE) {
+                        fs::remove_file(&path).ok();
+                    } else if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(anyhow!("Timed out waiting for {}", path.display()));
+                    } else {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path.display())),
+            }
+        }
+    }
+}
+
+/// A held ledger lock, released when dropped
+struct LedgerLock(PathBuf);
+
+impl Drop for LedgerLock {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// `$XDG_DATA_HOME/gitwise/usage.json`, falling back to
+/// `~/.local/share/gitwise/usage.json`
+fn default_ledger_path() -> Result<PathBuf> {
+    if let Ok(dir) = env::var("XDG_DATA_HOME") {
+        if !dir.is_empty() {
+            return Ok(PathBuf::from(dir).join("gitwise").join("usage.json"));
+        }
+    }
+    let home = env::var("HOME").context("Neither XDG_DATA_HOME nor HOME is set")?;
+    Ok(PathBuf::from(home).join(".local").join("share").join("gitwise").join("usage.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_measure_counts_only_its_own_requests() {
+        let meter = UsageMeter::default();
+        let ((), first) = UsageMeter::measure(async {
+            meter.record("gpt-4o", 100, 10).await;
+            meter.record("gpt-4o-mini", 50, 5).await;
+        }).await;
+        let ((), second) = UsageMeter::measure(meter.record("gpt-4o", 7, 3)).await;
+        meter.record("gpt-4o", 1, 1).await;
+
+        assert_eq!(first, Measured {
+            usage: TokenUsage { prompt_tokens: 150, completion_tokens: 15 },
+            model: Some("gpt-4o-mini".to_string()),
+        });
+        assert_eq!(second.usage.total(), 10);
+        assert_eq!(meter.totals()["gpt-4o"], TokenUsage { prompt_tokens: 108, completion_tokens: 14 });
+    }
+
+    #[test]
+    fn test_cost_uses_most_specific_price() {
+        let usage = TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 1_000_000 };
+        assert_eq!(usage.cost("gpt-4o-2024-05-13"), Some(20.0));
+        assert_eq!(usage.cost("gpt-4-0613"), Some(90.0));
+        assert_eq!(usage.cost("my-local-model"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ledger_survives_concurrent_updates() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let ledger = UsageLedger { path: temp_dir.path().join("gitwise").join("usage.json") };
+        let tasks: Vec<_> = (0..8).map(|_| {
+            let ledger = ledger.clone();
+            tokio::spawn(async move {
+                for _ in 0..10 {
+                    ledger.add("2024-05-01", "gpt-4o", 100, 10).await.unwrap();
+                }
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let entries = ledger.entries().unwrap();
+        assert_eq!(entries["2024-05-01"]["gpt-4o"], TokenUsage { prompt_tokens: 8000, completion_tokens: 800 });
+        ledger.reset().await.unwrap();
+        assert!(ledger.entries().unwrap().is_empty());
+    }
+}
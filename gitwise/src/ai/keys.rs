@@ -0,0 +1,78 @@
+//! Where the OpenAI API key is read from
+
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Environment variable naming a file that holds the OpenAI API key
+const KEY_FILE_VAR: &str = "OPENAI_API_KEY_FILE";
+/// Keychain item holding the OpenAI API key, as service and account
+#[cfg(all(feature = "keychain", target_os = "macos"))]
+const KEYCHAIN_ITEM: (&str, &str) = ("gitwise", "openai");
+
+/// The OpenAI API key from the first of OPENAI_API_KEY, the file named by
+/// OPENAI_API_KEY_FILE and, with the `keychain` feature on macOS, the login
+/// keychain that has one
+///
+/// A key file that cannot be read is warned about and skipped, so a broken
+/// OpenAI setup does not stop other providers from being used.
+pub fn openai_api_key_from_env() -> Option<String> {
+    if let Ok(key) = env::var("OPENAI_API_KEY") {
+        debug!("Found OpenAI API key");
+        return Some(key);
+    }
+    if let Ok(path) = env::var(KEY_FILE_VAR) {
+        debug!("Reading OpenAI API key from {}", path);
+        match read_key_file(Path::new(&path)) {
+            Ok(key) => return Some(key),
+            Err(e) => warn!("Ignoring {}: {:#}", KEY_FILE_VAR, e),
+        }
+    }
+    #[cfg(all(feature = "keychain", target_os = "macos"))]
+    if let Some(key) = keychain_password(KEYCHAIN_ITEM.0, KEYCHAIN_ITEM.1) {
+        debug!("Found OpenAI API key in the keychain");
+        return Some(key);
+    }
+    None
+}
+
+/// Read an API key from a file, ignoring surrounding whitespace
+pub fn read_key_file(path: &Path) -> Result<String> {
+    let key = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read API key file {}", path.display()))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(anyhow!("API key file {} is empty", path.display()));
+    }
+    Ok(key.to_string())
+}
+
+/// A generic password from the login keychain, if there is one
+#[cfg(all(feature = "keychain", target_os = "macos"))]
+fn keychain_password(service: &str, account: &str) -> Option<String> {
+    let output = std::process::Command::new("security")
+        .args(["find-generic-password", "-s", service, "-a", account, "-w"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let password = String::from_utf8(output.stdout).ok()?;
+    Some(password.trim().to_string()).filter(|password| !password.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_key_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("openai.key");
+        std::fs::write(&path, "  sk-test\n").unwrap();
+        assert_eq!(read_key_file(&path).unwrap(), "sk-test");
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(read_key_file(&path).unwrap_err().to_string().contains("is empty"));
+        assert!(read_key_file(&temp_dir.path().join("missing.key")).is_err());
+    }
+}
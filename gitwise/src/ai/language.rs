@@ -0,0 +1,94 @@
+use git2::Diff;
+
+/// Share of recognized files one language needs to count as dominant
+const DOMINANT_SHARE: f64 = 0.7;
+
+/// Programming language of the files in a diff
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Python,
+    TypeScript,
+    Go,
+    Java,
+    C,
+    Sql,
+    Shell,
+}
+
+impl Language {
+    /// Recognize a language from a file extension
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "rs" => Some(Language::Rust),
+            "py" | "pyi" => Some(Language::Python),
+            "ts" | "tsx" | "js" | "jsx" | "mjs" | "cjs" => Some(Language::TypeScript),
+            "go" => Some(Language::Go),
+            "java" | "kt" => Some(Language::Java),
+            "c" | "h" | "cc" | "cpp" | "hpp" => Some(Language::C),
+            "sql" => Some(Language::Sql),
+            "sh" | "bash" | "zsh" => Some(Language::Shell),
+            _ => None,
+        }
+    }
+
+    /// Prompt instructions pointing the model at what matters in this language
+    pub fn prompt_hint(self) -> &'static str {
+        match self {
+            Language::Rust => "These are Rust changes; mention trait/impl/lifetime impacts, public API changes, unsafe code and error handling.",
+            Language::Python => "These are Python changes; mention changed function signatures, type hints, raised exceptions and imports.",
+            Language::TypeScript => "These are JavaScript/TypeScript changes; mention changes to exported APIs, types and interfaces, async behavior and components.",
+            Language::Go => "These are Go changes; mention changes to exported identifiers, interfaces, error handling and goroutines or channels.",
+            Language::Java => "These are JVM changes; mention changes to class hierarchies, public methods, annotations and exceptions.",
+            Language::C => "These are C/C++ changes; mention memory management, header and ABI changes, and undefined behavior risks.",
+            Language::Sql => "These are SQL changes; mention schema changes, whether migrations are reversible, indexes and any risk of data loss.",
+            Language::Shell => "These are shell script changes; mention portability, quoting and error handling.",
+        }
+    }
+}
+
+/// Detect the dominant language of the files changed in a diff
+///
+/// Returns `None` when no language accounts for most of the recognized
+/// files, so mixed diffs keep the generic prompt.
+pub fn detect_language(diff: &Diff<'_>) -> Option<Language> {
+    let paths: Vec<String> = diff.deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.display().to_string())
+        .collect();
+    dominant_language(&paths)
+}
+
+fn dominant_language(paths: &[String]) -> Option<Language> {
+    let languages: Vec<Language> = paths.iter()
+        .filter_map(|path| path.rsplit_once('.'))
+        .filter_map(|(_, extension)| Language::from_extension(&extension.to_lowercase()))
+        .collect();
+
+    let mut counts: Vec<(Language, usize)> = Vec::new();
+    for language in &languages {
+        match counts.iter_mut().find(|(l, _)| l == language) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((*language, 1)),
+        }
+    }
+    let (language, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    (count as f64 / languages.len() as f64 >= DOMINANT_SHARE).then_some(language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_dominant_language() {
+        assert_eq!(dominant_language(&paths(&["src/lib.rs", "src/main.rs", "README.md"])), Some(Language::Rust));
+        assert_eq!(dominant_language(&paths(&["db/001_init.sql"])), Some(Language::Sql));
+        assert_eq!(dominant_language(&paths(&["api.py", "web/app.ts"])), None);
+        assert_eq!(dominant_language(&paths(&["README.md", "Makefile"])), None);
+    }
+}
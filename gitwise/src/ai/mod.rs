@@ -1,43 +1,702 @@
 use anyhow::{Result, Context};
 use async_openai::{
     types::{
+        ChatCompletionRequestAssistantMessage,
+        ChatCompletionRequestMessage,
         ChatCompletionRequestSystemMessage,
         ChatCompletionRequestUserMessage,
         ChatCompletionRequestUserMessageContent,
+        ChatCompletionResponseFormat,
+        ChatCompletionResponseFormatType,
+        ChatCompletionResponseStream,
         CreateChatCompletionRequest,
+        CreateChatCompletionResponse,
         Role,
+        Stop,
     },
-    Client, config::OpenAIConfig,
+    config::{AzureConfig, OpenAIConfig},
+    error::OpenAIError,
+    Client,
 };
 use anthropic::{
     client::{Client as AnthropicClient, ClientBuilder},
     types::{MessagesRequest, Role as AnthropicRole, Message, ContentBlock},
 };
+use crate::config::Config;
+use crate::git::conflicts::Conflict;
+use crate::lint;
+use crate::version::VersionBump;
+use futures::stream::{self, Stream, StreamExt};
 use git2::Diff;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::env;
-use tracing::{debug, info};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, trace, warn};
+
+pub mod backend;
+mod breaking;
+pub mod cache;
+mod chat;
+pub mod cost;
+pub mod keys;
+mod language;
+mod patch;
+pub mod rate;
+mod retry;
+pub mod tokens;
+
+use backend::ChatBackend;
+use cache::SummaryCache;
+pub use chat::Conversation;
+use cost::{TokenUsage, UsageLedger, UsageMeter};
+use patch::{FilePatch, RenderOptions};
+pub use patch::{is_binary_content, unified_patch};
+use rate::RateLimiter;
+
+const ANTHROPIC_MODEL: &str = "claude-3-sonnet-20240229";
+const OPENAI_MODEL: &str = "gpt-3.5-turbo";
+// Cheaper and stronger models for budget-aware selection
+const ANTHROPIC_SMALL_MODEL: &str = "claude-3-haiku-20240307";
+const OPENAI_LARGE_MODEL: &str = "gpt-4o";
+/// Default number of changed lines up to which a diff counts as small
+pub const DEFAULT_SMALL_DIFF_LINES: usize = 50;
 
 // Constants for token limits
-const ANTHROPIC_MAX_TOKENS: usize = 4096;
-const OPENAI_MAX_TOKENS: u16 = 4096;
+/// Default cap on tokens generated per response
+pub const DEFAULT_MAX_TOKENS: u16 = 2048;
+/// Default cap for short outputs such as commit messages and branch names
+pub const TERSE_MAX_TOKENS: u16 = 1024;
+/// Default cap for long-form outputs such as changelogs, PR descriptions and
+/// explanations
+pub const LONG_FORM_MAX_TOKENS: u16 = 4096;
+/// Default sampling temperature
+pub const DEFAULT_TEMPERATURE: f32 = 0.7;
+/// Seed sent with every OpenAI request in deterministic mode
+pub const DETERMINISTIC_SEED: i64 = 42;
+/// Range of sampling temperatures accepted by OpenAI
+pub const TEMPERATURE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+/// Default estimated token budget for diff text in a single request
+pub const DEFAULT_MAX_INPUT_TOKENS: usize = 12_000;
+/// Default size above which a rendered diff is never sent in one request
+pub const DEFAULT_MAX_DIFF_BYTES: usize = 1_000_000;
+pub use patch::{DEFAULT_GENERATED_PATTERNS, DEFAULT_TEST_PATTERNS};
+/// Placeholder in a custom prompt that is replaced by the diff text
+pub const DIFF_PLACEHOLDER: &str = "{diff}";
+/// Estimated token budget for the global context prefix
+const CONTEXT_PREFIX_MAX_TOKENS: usize = 1_000;
+/// Added to summary prompts when hunk headers are kept in the diff
+const LINE_REFERENCES_PROMPT: &str = "The `@@ -a,b +c,d @@` hunk headers give line numbers: when you mention a specific change, \
+    cite where it is as `path:line` in the new file, for example `src/auth.rs:42`.";
+/// Added to summary prompts when only the end state of a range matters
+const NET_EFFECT_PROMPT: &str = "The diff compares the two ends of a range of commits. Describe only its net effect: \
+    what is different at the end compared with the start. Do not speculate about intermediate commits, \
+    work that was added and later removed, or the order the changes were made in.";
+/// Added to the system prompt when summarizing a trivial diff
+const TRIVIAL_SUMMARY_PROMPT: &str = "This is a very small change: describe it in one short sentence, with no headings or lists.";
+/// Added to the system prompt when writing a commit message for a trivial diff
+const TRIVIAL_COMMIT_PROMPT: &str = "This is a very small change: reply with the subject line only, with no body.";
+/// Start of the system prompt for reviews, followed by the response format
+const REVIEW_PROMPT: &str = "You are a meticulous senior engineer reviewing a change. \
+    Look for potential bugs, security concerns and style issues in the diff. \
+    Use the `@@ -a,b +c,d @@` hunk headers to give approximate line numbers in the new file. \
+    Do not comment on code that did not change.";
+/// System prompt for choosing the best of several commit messages
+const PICK_COMMIT_PROMPT: &str = "You are given a set of changes followed by candidate commit messages for them, \
+    each under a `=== Candidate N ===` heading. Pick the one that describes the changes most accurately and concisely. \
+    Respond with a JSON object only, no other text, of the form {\"best\": <candidate number>, \"reason\": \"<one sentence>\"}.";
+/// Most untracked files listed alongside a commit message request
+const MAX_UNTRACKED_FILES_LISTED: usize = 30;
+/// Most chunk requests spent on one summary before the diff is trimmed instead
+const MAX_SUMMARY_CHUNKS: usize = 8;
+/// Follow-up sent with a response that could not be parsed as JSON
+const JSON_RETRY_MESSAGE: &str = "Your previous output was not valid JSON. Return ONLY the JSON object in the requested format, with no explanations or code fences.";
+/// Azure OpenAI REST API version used unless AZURE_OPENAI_API_VERSION is set
+const AZURE_OPENAI_API_VERSION: &str = "2024-02-01";
+
+/// A stream of text chunks as they arrive from the model
+pub type TextStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// Style of commit message to generate
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CommitStyle {
+    /// Summary line plus wrapped description
+    #[default]
+    Freeform,
+    /// Conventional Commits (`type(scope): subject`)
+    Conventional,
+    /// Freeform with a leading gitmoji (`✨ Add endpoint`)
+    Gitmoji,
+}
+
+/// The standard gitmoji the model may choose from: emoji, shortcode and meaning
+const GITMOJI: &[(&str, &str, &str)] = &[
+    ("✨", ":sparkles:", "introduce new features"),
+    ("🐛", ":bug:", "fix a bug"),
+    ("🚑️", ":ambulance:", "critical hotfix"),
+    ("🩹", ":adhesive_bandage:", "simple fix for a non-critical issue"),
+    ("♻️", ":recycle:", "refactor code"),
+    ("🎨", ":art:", "improve structure or format of the code"),
+    ("⚡️", ":zap:", "improve performance"),
+    ("🔥", ":fire:", "remove code or files"),
+    ("📝", ":memo:", "add or update documentation"),
+    ("✅", ":white_check_mark:", "add, update or pass tests"),
+    ("🔒️", ":lock:", "fix security or privacy issues"),
+    ("💥", ":boom:", "introduce breaking changes"),
+    ("🏷️", ":label:", "add or update types"),
+    ("🚨", ":rotating_light:", "fix compiler or linter warnings"),
+    ("🔧", ":wrench:", "add or update configuration files"),
+    ("👷", ":construction_worker:", "add or update the CI build system"),
+    ("💄", ":lipstick:", "add or update the UI and style files"),
+    ("🚚", ":truck:", "move or rename resources"),
+    ("➕", ":heavy_plus_sign:", "add a dependency"),
+    ("➖", ":heavy_minus_sign:", "remove a dependency"),
+    ("⬆️", ":arrow_up:", "upgrade dependencies"),
+    ("⬇️", ":arrow_down:", "downgrade dependencies"),
+    ("🔖", ":bookmark:", "release or version tags"),
+    ("🚀", ":rocket:", "deploy stuff"),
+];
+
+/// Seconds to wait for a model response by default
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Files listed in the body of an offline commit message before eliding
+const OFFLINE_MAX_LISTED_FILES: usize = 20;
+
+/// The AI provider is not configured or could not be reached
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ProviderUnavailable(String);
+
+/// A request got no response within the configured timeout
+#[derive(Debug, thiserror::Error)]
+#[error("request timed out after {0}s")]
+pub struct RequestTimedOut(u64);
+
+/// The Anthropic API rejected a request or could not be reached; the
+/// client only reports errors as text, kept here
+#[derive(Debug, thiserror::Error)]
+#[error("Anthropic API error: {0}")]
+pub struct AnthropicError(pub String);
+
+/// A diff is over the byte limit and chunking is off
+#[derive(Debug, thiserror::Error)]
+#[error("the diff is {bytes} bytes, over the limit of {limit}; leave files out with --exclude, check what changed with --stat, or raise --max-diff-bytes")]
+pub struct DiffTooLarge {
+    pub bytes: usize,
+    pub limit: usize,
+}
+
+/// Who wrote a message in a conversation with the model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
+/// A message in a conversation with the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self { role: ChatRole::User, content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self { role: ChatRole::Assistant, content: content.into() }
+    }
+}
+
+/// Changed files and line counts of a diff
+struct DiffStat {
+    /// Single-letter status and path of each file, as in `--name-status`
+    files: Vec<(char, String)>,
+    insertions: usize,
+    deletions: usize,
+}
+
+impl DiffStat {
+    fn new(diff: &Diff<'_>) -> Result<Self> {
+        let stats = diff.stats()?;
+        Ok(Self {
+            files: crate::git::get_name_status(diff),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        })
+    }
+
+    fn changed_lines(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
 
+/// A generated pull request title and Markdown description
 #[derive(Debug, Clone, PartialEq)]
+pub struct PrDescription {
+    pub title: String,
+    pub body: String,
+}
+
+impl PrDescription {
+    /// Split a Markdown document into a title (its first line, with any
+    /// heading marker removed) and the remaining body
+    fn from_markdown(markdown: &str) -> Self {
+        let markdown = markdown.trim();
+        let (first, rest) = markdown.split_once('\n').unwrap_or((markdown, ""));
+        Self {
+            title: first.trim_start_matches('#').trim().to_string(),
+            body: rest.trim().to_string(),
+        }
+    }
+
+    /// Render as a single Markdown document
+    pub fn to_markdown(&self) -> String {
+        format!("# {}\n\n{}", self.title, self.body)
+    }
+}
+
+/// Reader a summary is written for
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Audience {
+    /// Technical detail for engineers
+    Engineer,
+    /// Scope, impact and risk without jargon
+    Manager,
+    /// Threat surface and risky changes
+    Security,
+    /// Behavior changes and what to test
+    Qa,
+    /// User-facing impact only
+    User,
+}
+
+impl Audience {
+    /// Prompt instructions tuning vocabulary and focus for this audience
+    fn prompt_fragment(self) -> &'static str {
+        match self {
+            Audience::Engineer => "Write for a software engineer: use precise technical terms, name the functions, types and modules involved, and explain implementation choices.",
+            Audience::Manager => "Write for an engineering manager: avoid jargon and code identifiers, focus on what the change delivers, its scope, risk and any follow-up work.",
+            Audience::Security => "Write for a security reviewer: focus on changes to the attack surface such as input handling, authentication, authorization, cryptography, secrets, unsafe code and dependencies, and flag anything risky.",
+            Audience::Qa => "Write for a QA engineer: focus on behavior that changed, what needs to be tested, edge cases and likely regressions.",
+            Audience::User => "Write for an end user of the software: describe only user-visible impact such as new features, changed behavior and fixed bugs, in plain language without implementation details.",
+        }
+    }
+}
+
+/// How much detail a summary goes into
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Detail {
+    /// A single-sentence TL;DR
+    Brief,
+    /// A concise summary
+    #[default]
+    Normal,
+    /// A breakdown by area
+    Detailed,
+}
+
+impl Detail {
+    /// Prompt instructions for this level of detail, if it differs from the
+    /// default
+    fn prompt_fragment(self) -> Option<&'static str> {
+        match self {
+            Detail::Brief => Some("Reply with a single-sentence TL;DR of the change only, without headings or lists."),
+            Detail::Normal => None,
+            Detail::Detailed => Some("Give a detailed breakdown: group the changes by area (such as module, component or concern) under a heading each, and for each area explain what changed and why it matters."),
+        }
+    }
+}
+
+/// Response length cap for brief summaries and trivial diffs
+const BRIEF_MAX_TOKENS: u16 = 128;
+/// Default number of changed lines below which a single-file diff is trivial
+pub const DEFAULT_TRIVIAL_DIFF_LINES: usize = 5;
+
+/// How confident the model is in a summary
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+/// How serious a review finding is, from least to most
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Style and minor suggestions
+    Info,
+    /// Likely problems worth fixing
+    Warning,
+    /// Bugs and security problems that should block merging
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A review comment about one place in a diff
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReviewFinding {
+    pub file: String,
+    /// Approximate line in the new file, when the comment is about one
+    #[serde(default)]
+    pub line: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Review findings, as returned by the model
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ReviewFindings {
+    findings: Vec<ReviewFinding>,
+}
+
+/// A diff summary together with the model's own assessment of its limits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SelfCheckedSummary {
+    pub summary: String,
+    pub confidence: Confidence,
+    /// Things that cannot be determined from the diff alone
+    #[serde(default)]
+    pub unclear: Vec<String>,
+}
+
+/// A diff summary with what it took to produce it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SummaryResult {
+    pub text: String,
+    /// Model that wrote the summary, or the configured one if it came from
+    /// the cache
+    pub model: String,
+    /// Tokens used by every request behind the summary; zero when cached
+    pub usage: TokenUsage,
+    /// Whether the diff, or the partial summaries of a chunked one, was
+    /// trimmed to fit the input limits, leaving out part of the changes
+    pub truncated: bool,
+}
+
+/// Summary of the changes to a single file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileSummary {
+    /// The file's path, or `old -> new` for a rename
+    pub path: String,
+    pub summary: String,
+}
+
+/// The kind of change made to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeCategory {
+    Feature,
+    Fix,
+    Refactor,
+    Test,
+    Docs,
+    Chore,
+    /// Anything that does not fit one of the others
+    #[serde(other)]
+    Other,
+}
+
+impl std::fmt::Display for ChangeCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChangeCategory::Feature => "feature",
+            ChangeCategory::Fix => "fix",
+            ChangeCategory::Refactor => "refactor",
+            ChangeCategory::Test => "test",
+            ChangeCategory::Docs => "docs",
+            ChangeCategory::Chore => "chore",
+            ChangeCategory::Other => "other",
+        })
+    }
+}
+
+/// Summary of the changes to a single file, tagged with their kind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CategorizedFile {
+    /// The file's path, or `old -> new` for a rename
+    pub path: String,
+    pub category: ChangeCategory,
+    pub summary: String,
+}
+
+/// Categorized files, as returned by the model
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CategorizedFiles {
+    files: Vec<CategorizedFile>,
+}
+
+/// A suggested release version bump and the reason for it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BumpSuggestion {
+    pub bump: VersionBump,
+    /// One-line justification
+    pub rationale: String,
+}
+
+/// Commits that share a theme, such as "Authentication work"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitTheme {
+    pub title: String,
+    /// Hashes of the commits in the theme, as they were passed in
+    pub commits: Vec<String>,
+}
+
+/// Commit messages for a batch of commits, as returned by the model
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CommitMessages {
+    messages: Vec<String>,
+}
+
+/// The model's choice among candidate commit messages
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CommitPick {
+    /// 1-based number of the chosen candidate
+    best: usize,
+    #[serde(default)]
+    reason: String,
+}
+
+/// Commit themes, as returned by the model
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CommitThemes {
+    themes: Vec<CommitTheme>,
+}
+
+/// Files grouped into separate commits, as returned by the model
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ChangeGroups {
+    groups: Vec<Group>,
+}
+
+/// Files that belong in one commit
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Group {
+    files: Vec<String>,
+}
+
+/// Extra sampling parameters passed through to OpenAI requests
+///
+/// Parsed from JSON such as `{"top_p": 0.9, "stop": ["\n\n"]}`. Keys other
+/// than these are rejected; the temperature, seed and response length have
+/// dedicated settings instead.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelParams {
+    pub top_p: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub stop: Option<Stop>,
+    pub user: Option<String>,
+}
+
+impl ModelParams {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Invalid model parameters")
+    }
+
+    /// Set the parameters given on a request, leaving the others alone
+    fn apply(&self, request: &mut CreateChatCompletionRequest) {
+        let params = self.clone();
+        request.top_p = params.top_p.or(request.top_p);
+        request.frequency_penalty = params.frequency_penalty.or(request.frequency_penalty);
+        request.presence_penalty = params.presence_penalty.or(request.presence_penalty);
+        request.stop = params.stop.or(request.stop.take());
+        request.user = params.user.or(request.user.take());
+    }
+}
+
+/// Thresholds and candidate models for picking a model by diff size
+#[derive(Debug, Clone)]
+pub struct ModelBudget {
+    /// Diffs with at most this many added and removed lines count as small
+    pub small_diff_lines: usize,
+    /// Model for small diffs, defaulting to the provider's cheaper model
+    pub small_model: Option<String>,
+    /// Model for larger diffs, defaulting to the provider's stronger model
+    pub large_model: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ModelProvider {
     Anthropic,
     OpenAI,
 }
 
+/// A chat completions client for OpenAI itself or an Azure OpenAI deployment
+#[derive(Clone)]
+enum OpenAiClient {
+    OpenAI(Client<OpenAIConfig>),
+    Azure(Client<AzureConfig>),
+}
+
+impl OpenAiClient {
+    /// Configure a client from the environment
+    ///
+    /// Azure is used when AZURE_OPENAI_ENDPOINT, AZURE_OPENAI_API_KEY and
+    /// AZURE_OPENAI_DEPLOYMENT are all set, taking precedence over an OpenAI
+    /// key from the environment but not over `api_key`. Otherwise the key is
+    /// `api_key` or else found by `keys::openai_api_key_from_env`. OpenAI
+    /// requests go to `base_url`, or OPENAI_BASE_URL if unset, for
+    /// OpenAI-compatible gateways.
+    fn from_env(base_url: Option<&str>, api_key: Option<&str>) -> Result<Option<Self>> {
+        let azure = (
+            env::var("AZURE_OPENAI_ENDPOINT"),
+            env::var("AZURE_OPENAI_API_KEY"),
+            env::var("AZURE_OPENAI_DEPLOYMENT"),
+        );
+        if let (None, (Ok(endpoint), Ok(api_key), Ok(deployment))) = (api_key, azure) {
+            debug!("Found Azure OpenAI deployment {}", deployment);
+            let api_version = env::var("AZURE_OPENAI_API_VERSION").unwrap_or_else(|_| AZURE_OPENAI_API_VERSION.to_string());
+            let config = AzureConfig::new()
+                .with_api_base(endpoint)
+                .with_api_key(api_key)
+                .with_deployment_id(deployment)
+                .with_api_version(api_version);
+            if base_url.is_some() {
+                debug!("Ignoring the OpenAI base URL in favor of the Azure endpoint");
+            }
+            return Ok(Some(OpenAiClient::Azure(Client::with_config(config))));
+        }
+
+        let base_url = match (base_url, env::var("OPENAI_BASE_URL")) {
+            (Some(url), _) => Some(parse_base_url(url)?),
+            (None, Ok(url)) => Some(parse_base_url(&url).context("Invalid OPENAI_BASE_URL")?),
+            (None, Err(_)) => None,
+        };
+        let api_key = match api_key {
+            Some(key) => Some(key.to_string()),
+            None => keys::openai_api_key_from_env(),
+        };
+        match api_key {
+            Some(api_key) => {
+                let mut config = OpenAIConfig::new().with_api_key(api_key);
+                if let Some(url) = base_url {
+                    debug!("Using OpenAI base URL {}", url);
+                    config = config.with_api_base(url);
+                }
+                Ok(Some(OpenAiClient::OpenAI(Client::with_config(config))))
+            },
+            None => {
+                debug!("No OpenAI API key found");
+                Ok(None)
+            }
+        }
+    }
+
+    async fn create(&self, request: CreateChatCompletionRequest) -> Result<CreateChatCompletionResponse, OpenAIError> {
+        match self {
+            OpenAiClient::OpenAI(client) => client.chat().create(request).await,
+            OpenAiClient::Azure(client) => client.chat().create(request).await,
+        }
+    }
+
+    async fn create_stream(&self, request: CreateChatCompletionRequest) -> Result<ChatCompletionResponseStream, OpenAIError> {
+        match self {
+            OpenAiClient::OpenAI(client) => client.chat().create_stream(request).await,
+            OpenAiClient::Azure(client) => client.chat().create_stream(request).await,
+        }
+    }
+}
+
 pub struct AiEngine {
-    openai_client: Option<Client<OpenAIConfig>>,
+    openai_client: Option<OpenAiClient>,
+    /// Base URL and API key the OpenAI client was built with, if not the
+    /// ones from the environment
+    openai_base_url: Option<String>,
+    openai_api_key: Option<String>,
     anthropic_client: Option<AnthropicClient>,
+    /// Backend that receives every request instead of the clients
+    backend: Option<Arc<dyn ChatBackend>>,
     enforced_provider: Option<ModelProvider>,
+    render_options: RenderOptions,
+    max_input_tokens: usize,
+    /// Largest rendered diff sent in one request
+    max_diff_bytes: usize,
+    /// Summarize oversized diffs in chunks rather than in one request
+    chunking: bool,
+    context_prefix: Option<String>,
+    /// Untracked files mentioned when generating commit messages
+    untracked_files: Vec<String>,
+    /// Existing commit messages whose style new ones should match
+    commit_examples: Vec<String>,
+    /// Monorepo subproject the diffs are limited to
+    scope: Option<String>,
+    audience: Option<Audience>,
+    detail: Detail,
+    /// Language code summaries and commit messages are written in, if not English
+    response_language: Option<String>,
+    /// Ask for a section on removed or changed public items
+    flag_breaking: bool,
+    /// Summarize only the end-state difference of a range
+    net_effect: bool,
+    cache: Option<SummaryCache>,
+    test_patterns: Option<Vec<String>>,
+    max_retries: u32,
+    /// Requests-per-minute limit shared by concurrent requests
+    rate_limiter: Option<RateLimiter>,
+    /// Times a response that is not valid JSON is asked for again
+    json_retries: u32,
+    /// Randomize the delay between retries
+    retry_jitter: bool,
+    /// How long to wait for each response
+    timeout: Duration,
+    /// Model to use instead of the provider's default
+    model: Option<String>,
+    /// Models to try in turn when a request to the model fails
+    fallback_models: Vec<String>,
+    temperature: f32,
+    /// Sampling seed for OpenAI requests
+    seed: Option<i64>,
+    /// Extra parameters for OpenAI requests
+    params: ModelParams,
+    max_tokens: u16,
+    usage: UsageMeter,
+    /// Settings from config files
+    config: Config,
 }
 
 impl AiEngine {
     /// Create a new AI engine, preferring Claude if available
+    ///
+    /// Prompts and the model can be overridden in config files; see
+    /// `config::config_paths` for where they are read from.
     pub fn new() -> Result<Self> {
         dotenv::dotenv().ok();
-        
+        let repo = git2::Repository::open_from_env().ok();
+        let workdir = repo.as_ref().and_then(|repo| repo.workdir());
+        Self::for_profile(env::var("GITWISE_PROFILE").ok().filter(|name| !name.is_empty()).as_deref(), workdir)
+    }
+
+    /// Create an engine with the settings of a config profile, such as
+    /// `work` for `[profile.work]`, over the top-level ones, reading the
+    /// repo-local config file from `workdir`
+    pub fn for_profile(profile: Option<&str>, workdir: Option<&std::path::Path>) -> Result<Self> {
+        dotenv::dotenv().ok();
+        let mut config = Config::load(workdir)?;
+        if let Some(name) = profile {
+            debug!("Using config profile {}", name);
+            config = config.with_profile(name)?;
+        }
+
         // Try to create Anthropic client first
         let anthropic_client = match env::var("ANTHROPIC_API_KEY") {
             Ok(api_key) => {
@@ -54,22 +713,69 @@ impl AiEngine {
         };
 
         // Try to create OpenAI client as fallback
-        let openai_client = match env::var("OPENAI_API_KEY") {
-            Ok(api_key) => {
-                debug!("Found OpenAI API key");
-                Some(Client::with_config(OpenAIConfig::new().with_api_key(api_key)))
-            },
-            Err(_) => {
-                debug!("No OpenAI API key found");
-                None
-            }
-        };
+        let openai_client = OpenAiClient::from_env(None, None)?;
 
-        Ok(Self {
+        Ok(Self::with_clients(config, openai_client, anthropic_client))
+    }
+
+    /// Create an engine that sends every request to `backend`
+    ///
+    /// No config files or API keys are read, so this is what tests use.
+    pub fn from_backend(backend: Arc<dyn ChatBackend>) -> Self {
+        Self::with_clients(Config::default(), None, None).with_backend(backend)
+    }
+
+    fn with_clients(config: Config, openai_client: Option<OpenAiClient>, anthropic_client: Option<AnthropicClient>) -> Self {
+        let generated = config.generated_patterns.clone()
+            .unwrap_or_else(|| DEFAULT_GENERATED_PATTERNS.iter().map(|p| p.to_string()).collect());
+        let enforced_provider = config.provider.clone();
+        Self {
             openai_client,
+            openai_base_url: None,
+            openai_api_key: None,
             anthropic_client,
-            enforced_provider: None,
-        })
+            backend: None,
+            enforced_provider,
+            render_options: RenderOptions { hunk_headers: true, generated: Some(generated), ..Default::default() },
+            max_input_tokens: DEFAULT_MAX_INPUT_TOKENS,
+            max_diff_bytes: DEFAULT_MAX_DIFF_BYTES,
+            chunking: true,
+            context_prefix: None,
+            untracked_files: Vec::new(),
+            commit_examples: Vec::new(),
+            scope: None,
+            audience: None,
+            detail: Detail::default(),
+            response_language: None,
+            flag_breaking: false,
+            net_effect: false,
+            cache: None,
+            test_patterns: None,
+            max_retries: retry::max_retries_from_env(),
+            rate_limiter: None,
+            json_retries: retry::json_retries_from_env(),
+            retry_jitter: true,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            model: config.model.clone(),
+            fallback_models: Vec::new(),
+            temperature: DEFAULT_TEMPERATURE,
+            seed: None,
+            params: ModelParams::default(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            usage: UsageMeter::default(),
+            config,
+        }
+    }
+
+    /// Send requests to `backend` instead of Anthropic or OpenAI
+    pub fn with_backend(mut self, backend: Arc<dyn ChatBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Settings loaded from config files
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
     /// Set the enforced model provider
@@ -78,224 +784,1779 @@ impl AiEngine {
         self
     }
 
-    /// Helper to generate text using available AI provider
-    pub async fn generate_text(&self, system_prompt: &str, user_message: &str) -> Result<String> {
-        debug!("Generating text with system prompt: {}", system_prompt);
-        debug!("User message: {}", user_message);
+    /// Replace whitespace-only hunks with a note when rendering diffs
+    pub fn with_whitespace_hunks_collapsed(mut self, collapse: bool) -> Self {
+        self.render_options.collapse_whitespace = collapse;
+        self
+    }
 
-        match (self.enforced_provider.as_ref(), &self.anthropic_client, &self.openai_client) {
-            // Enforced Anthropic
-            (Some(ModelProvider::Anthropic), Some(client), _) => {
-                info!("Using Anthropic's Claude model");
-                let request = MessagesRequest {
-                    model: "claude-3-sonnet-20240229".to_string(),
-                    system: system_prompt.to_string(),
-                    messages: vec![
-                        Message {
-                            role: AnthropicRole::User,
-                            content: vec![ContentBlock::Text { text: user_message.to_string() }],
-                        }
-                    ],
-                    max_tokens: ANTHROPIC_MAX_TOKENS,
-                    ..Default::default()
-                };
+    /// Only describe hunks overlapping these lines of the new file
+    pub fn with_line_range(mut self, lines: std::ops::RangeInclusive<u32>) -> Self {
+        self.render_options.lines = Some(lines);
+        self
+    }
+
+    /// Keep file paths and `@@` hunk headers in diffs and ask summaries to
+    /// cite `file:line` locations, which is the default
+    pub fn with_line_references(mut self, line_references: bool) -> Self {
+        self.render_options.hunk_headers = line_references;
+        self
+    }
+
+    /// Show changes to prose files such as Markdown as word diffs
+    pub fn with_word_diff(mut self, word_diff: bool) -> Self {
+        self.render_options.word_diff = word_diff;
+        self
+    }
+
+    /// Send lockfiles and other generated files in full instead of as a
+    /// one-line note
+    pub fn with_generated_files(mut self, include: bool) -> Self {
+        if include {
+            self.render_options.generated = None;
+        }
+        self
+    }
+
+    /// Read `.gitattributes` and checked-out submodules of the repository at
+    /// `git_dir` when describing diffs taken from it
+    pub fn with_repository(mut self, git_dir: &std::path::Path) -> Self {
+        self.render_options.repo_dir = Some(git_dir.to_path_buf());
+        self
+    }
+
+    /// Ask the model to pay special attention to changed lines matching any
+    /// of these patterns
+    pub fn with_focus_patterns(mut self, patterns: Vec<Regex>) -> Self {
+        self.render_options.focus = patterns;
+        self
+    }
+
+    /// Tailor summaries to a particular audience
+    pub fn with_audience(mut self, audience: Audience) -> Self {
+        self.audience = Some(audience);
+        self
+    }
+
+    /// Set how much detail summaries go into
+    ///
+    /// Brief summaries also cap the response length, since a single sentence
+    /// needs few tokens.
+    pub fn with_detail(mut self, detail: Detail) -> Self {
+        if detail == Detail::Brief {
+            self.max_tokens = self.max_tokens.min(BRIEF_MAX_TOKENS);
+        }
+        self.detail = detail;
+        self
+    }
+
+    /// Have summaries and commit messages written in another natural
+    /// language, given as a code such as `es` or `ja`
+    pub fn with_response_language(mut self, code: &str) -> Self {
+        let code = code.trim();
+        self.response_language = (!code.is_empty() && !code.eq_ignore_ascii_case("en")).then(|| code.to_string());
+        self
+    }
+
+    /// Have summaries and reviews end with a breaking changes section,
+    /// listing the public Rust items the diff removes or changes
+    pub fn with_breaking_changes_flagged(mut self, flag: bool) -> Self {
+        self.flag_breaking = flag;
+        self
+    }
+
+    /// Have summaries describe only the net effect of a range of commits,
+    /// not how it came about
+    pub fn with_net_effect(mut self, net_effect: bool) -> Self {
+        self.net_effect = net_effect;
+        self
+    }
+
+    /// Set the estimated token budget for diff text sent in one request
+    pub fn with_max_input_tokens(mut self, max_input_tokens: usize) -> Self {
+        self.max_input_tokens = max_input_tokens;
+        self
+    }
+
+    /// Cap the size of a rendered diff
+    ///
+    /// With chunking, larger diffs are always summarized in chunks. Without
+    /// it, they fail with `DiffTooLarge` before any request is made.
+    pub fn with_max_diff_bytes(mut self, max_diff_bytes: usize) -> Self {
+        self.max_diff_bytes = max_diff_bytes;
+        self
+    }
+
+    /// Send diffs over the token budget in one request instead of
+    /// summarizing them in chunks
+    pub fn with_chunking(mut self, chunking: bool) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Tell the model that diffs only cover one subproject of a monorepo,
+    /// named after the last component of `dir`
+    pub fn with_scope(mut self, dir: &str) -> Self {
+        self.scope = Some(dir.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Set context prepended to the system prompt of every request
+    ///
+    /// The context is truncated to a fixed token budget so it cannot crowd
+    /// out the diff itself.
+    pub fn with_context_prefix(mut self, context: String) -> Self {
+        let context = context.trim();
+        self.context_prefix = if context.is_empty() {
+            None
+        } else {
+            Some(patch::truncate_to_tokens(context, CONTEXT_PREFIX_MAX_TOKENS))
+        };
+        self
+    }
+
+    /// Tell the model which untracked files exist when it writes commit
+    /// messages, so staged changes that refer to new files make sense
+    pub fn with_untracked_files(mut self, paths: Vec<String>) -> Self {
+        self.untracked_files = paths;
+        self
+    }
+
+    /// Have commit messages match the tone, tense and structure of these
+    /// existing ones
+    pub fn with_commit_examples(mut self, messages: Vec<String>) -> Self {
+        self.commit_examples = messages;
+        self
+    }
+
+    /// Cache diff summaries on disk
+    pub fn with_cache(mut self, cache: SummaryCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Leave files matching these patterns (test files) out of summaries
+    pub fn with_tests_excluded(mut self, patterns: Vec<String>) -> Self {
+        self.test_patterns = Some(patterns);
+        self
+    }
+
+    /// Send OpenAI requests to an OpenAI-compatible API instead, such as a
+    /// proxy or a gateway like OpenRouter
+    pub fn with_openai_base_url(mut self, base_url: &str) -> Result<Self> {
+        self.openai_base_url = Some(base_url.to_string());
+        self.openai_client = OpenAiClient::from_env(self.openai_base_url.as_deref(), self.openai_api_key.as_deref())?;
+        Ok(self)
+    }
+
+    /// Use this OpenAI API key instead of one from the environment, a key
+    /// file or the keychain
+    pub fn with_openai_api_key(mut self, api_key: &str) -> Result<Self> {
+        self.openai_api_key = Some(api_key.to_string());
+        self.openai_client = OpenAiClient::from_env(self.openai_base_url.as_deref(), self.openai_api_key.as_deref())?;
+        Ok(self)
+    }
+
+    /// Pass extra sampling parameters to OpenAI
+    ///
+    /// Anthropic requests do not take them, so they are ignored there with
+    /// a warning.
+    pub fn with_params(mut self, params: ModelParams) -> Self {
+        if params != ModelParams::default() && self.active_provider() == Some(ModelProvider::Anthropic) {
+            warn!("Model parameters only apply to OpenAI requests and are ignored for Anthropic");
+        }
+        self.params = params;
+        self
+    }
+
+    /// Send at most `rpm` requests per minute, however many run concurrently
+    pub fn with_rate_limit(mut self, rpm: u32) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(rpm));
+        self
+    }
+
+    /// Set how many times a response that cannot be parsed as JSON is sent
+    /// back to the model for a corrected one
+    pub fn with_json_retries(mut self, retries: u32) -> Self {
+        self.json_retries = retries;
+        self
+    }
+
+    /// Set how long to wait for a response before failing with
+    /// `RequestTimedOut`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the sampling temperature for OpenAI requests
+    pub fn with_temperature(mut self, temperature: f32) -> Result<Self> {
+        if !TEMPERATURE_RANGE.contains(&temperature) {
+            return Err(anyhow::anyhow!("Temperature must be between 0.0 and 2.0, got {}", temperature));
+        }
+        self.temperature = temperature;
+        Ok(self)
+    }
+
+    /// Make output as reproducible as the provider allows
+    ///
+    /// OpenAI requests are sent with temperature 0 and `DETERMINISTIC_SEED`,
+    /// and retries wait a fixed time. Models are not fully deterministic even
+    /// so, and Anthropic requests have no seed.
+    pub fn with_deterministic_sampling(mut self) -> Self {
+        self.temperature = 0.0;
+        self.seed = Some(DETERMINISTIC_SEED);
+        self.retry_jitter = false;
+        self
+    }
+
+    /// Cap the number of tokens generated per response
+    pub fn with_max_tokens(mut self, max_tokens: u16) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Pick the model by diff size: a cheaper one for small diffs and a
+    /// stronger one for everything else
+    pub fn with_budget_aware_model(mut self, diff: &Diff<'_>, budget: &ModelBudget) -> Result<Self> {
+        let changed_lines = DiffStat::new(diff)?.changed_lines();
+        let (small, large) = match self.active_provider() {
+            Some(ModelProvider::OpenAI) => (OPENAI_MODEL, OPENAI_LARGE_MODEL),
+            _ => (ANTHROPIC_SMALL_MODEL, ANTHROPIC_MODEL),
+        };
+        let model = if changed_lines <= budget.small_diff_lines {
+            budget.small_model.as_deref().unwrap_or(small)
+        } else {
+            budget.large_model.as_deref().unwrap_or(large)
+        };
+        info!("{} changed lines, using model {}", changed_lines, model);
+        self.model = Some(model.to_string());
+        Ok(self)
+    }
+
+    /// Retry failed requests with each of these models in turn
+    ///
+    /// A model is only abandoned for errors another model might not hit:
+    /// rate limits and server errors that outlast the retries, exhausted
+    /// quota, an unknown model or a timeout.
+    pub fn with_model_fallback(mut self, models: Vec<String>) -> Self {
+        self.fallback_models = models;
+        self
+    }
+
+    /// Add the token usage of every request to a ledger kept across runs
+    pub fn with_usage_ledger(mut self, ledger: UsageLedger) -> Self {
+        self.usage = self.usage.with_ledger(ledger);
+        self
+    }
+
+    /// Handle on the token usage of every request this engine makes
+    ///
+    /// Streamed OpenAI responses do not report usage and are not counted.
+    pub fn usage_meter(&self) -> UsageMeter {
+        self.usage.clone()
+    }
+
+    /// Render a diff for the model, applying the configured filters
+    ///
+    /// Returns the rendered files and notes describing anything left out.
+    fn render(&self, diff: &Diff<'_>) -> Result<(Vec<FilePatch>, Vec<String>)> {
+        let mut files = patch::render_files(diff, &self.render_options)?;
+        let mut notes = Vec::new();
+        let text = patch::join_files(&files);
+        debug!("Rendered diff: {} files, ~{} tokens", files.len(), self.count_tokens(&text));
+        self.check_diff_size(&text)?;
+
+        if let Some(patterns) = &self.test_patterns {
+            let omitted = patch::remove_matching(&mut files, patterns);
+            if omitted > 0 {
+                notes.push(format!("({} test file{} omitted)", omitted, if omitted == 1 { "" } else { "s" }));
+            }
+        }
+
+        Ok((files, notes))
+    }
+
+    /// Join rendered files into the diff text of a summary request, naming
+    /// each file when line references are on so they can be cited
+    fn summary_text(&self, files: &[FilePatch]) -> String {
+        if self.render_options.hunk_headers {
+            patch::join_files_with_headers(files)
+        } else {
+            patch::join_files(files)
+        }
+    }
+
+    /// Fail with `DiffTooLarge` if chunking is off and the diff text is over
+    /// the byte limit
+    fn check_diff_size(&self, text: &str) -> Result<()> {
+        if !self.chunking && text.len() > self.max_diff_bytes {
+            return Err(DiffTooLarge { bytes: text.len(), limit: self.max_diff_bytes }.into());
+        }
+        Ok(())
+    }
+
+    /// Number of tokens `text` takes up for the configured model
+    fn count_tokens(&self, text: &str) -> usize {
+        tokens::count_tokens(text, self.model_name())
+    }
+
+    /// Whether diff text is too large for one request and should be
+    /// summarized in chunks
+    fn needs_chunking(&self, text: &str) -> bool {
+        self.chunking && (self.count_tokens(text) > self.max_input_tokens || text.len() > self.max_diff_bytes)
+    }
+
+    /// Whether a diff is small enough for a terse prompt: one file with
+    /// fewer changed lines than the configured `trivial_diff_lines`
+    fn is_trivial(&self, diff: &Diff<'_>) -> Result<bool> {
+        let threshold = self.config.trivial_diff_lines.unwrap_or(DEFAULT_TRIVIAL_DIFF_LINES);
+        let stat = DiffStat::new(diff)?;
+        Ok(stat.files.len() == 1 && stat.changed_lines() < threshold)
+    }
+
+    /// Split a custom prompt into the system prompt and an optional user
+    /// message template
+    ///
+    /// A custom prompt containing the `{diff}` placeholder is used as the
+    /// whole user message template. Any other custom prompt is added to the
+    /// system prompt as an extra instruction.
+    fn summary_request<'p>(&self, custom_prompt: Option<&'p str>) -> (String, Option<&'p str>) {
+        match custom_prompt {
+            Some(template) if template.contains(DIFF_PLACEHOLDER) => (self.summary_prompt(None), Some(template)),
+            _ => (self.summary_prompt(custom_prompt), None),
+        }
+    }
+
+    /// Build the system prompt for diff summaries, including any focus
+    /// instructions
+    fn summary_prompt(&self, custom_prompt: Option<&str>) -> String {
+        let mut prompt = summary_prompt(self.config.summarize_prompt.as_deref(), custom_prompt);
+        if let Some(audience) = self.audience {
+            prompt = format!("{}\n\n{}", prompt, audience.prompt_fragment());
+        }
+        if let Some(detail) = self.detail.prompt_fragment() {
+            prompt = format!("{}\n\n{}", prompt, detail);
+        }
+        if self.net_effect {
+            prompt = format!("{}\n\n{}", prompt, NET_EFFECT_PROMPT);
+        }
+        if let Some(language) = self.response_language_line(false) {
+            prompt = format!("{}\n\n{}", prompt, language);
+        }
+        if self.render_options.hunk_headers {
+            prompt = format!("{}\n\n{}", prompt, LINE_REFERENCES_PROMPT);
+        }
+        if self.render_options.focus.is_empty() {
+            return prompt;
+        }
+        let patterns = self.render_options.focus.iter()
+            .map(|pattern| format!("`{}`", pattern.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{}\n\nChanged lines prefixed with `{}` match patterns the reader is specifically concerned about ({}). \
+             Pay special attention to them and call out each one explicitly in a dedicated section, \
+             while still summarizing the rest of the diff.",
+            prompt,
+            patch::FOCUS_MARKER.trim(),
+            patterns
+        )
+    }
+
+    /// Prompt line asking for a response in the configured language, keeping
+    /// code and, for Conventional Commits, the type keywords in English
+    fn response_language_line(&self, conventional: bool) -> Option<String> {
+        let code = self.response_language.as_ref()?;
+        let mut line = format!(
+            "Write your response in the natural language with code `{}`. \
+             Keep code identifiers, file paths, commands and quoted code untranslated.",
+            code
+        );
+        if conventional {
+            line.push_str(" Keep the Conventional Commits type (e.g. `feat`, `fix`), the scope and \
+                `BREAKING CHANGE` in English; translate only the subject and body text.");
+        }
+        Some(line)
+    }
+
+    /// Ask for a `Breaking changes` section when flagged, listing the public
+    /// items a scan of the diff found removed or changed
+    fn with_breaking_changes(&self, prompt: String, diff: &Diff<'_>) -> Result<String> {
+        if !self.flag_breaking {
+            return Ok(prompt);
+        }
+        let changes = breaking::detect_breaking_changes(diff)?;
+        debug!("Detected {} possible breaking changes", changes.len());
+        let section = if changes.is_empty() {
+            "Also end with a `## Breaking changes` section. A scan of the diff found no removed or changed \
+             public items, so unless the diff clearly breaks compatibility in another way, the section should \
+             say exactly \"No breaking changes detected\".".to_string()
+        } else {
+            let list: Vec<String> = changes.iter().map(|change| format!("- {}", change)).collect();
+            format!(
+                "Also end with a `## Breaking changes` section. A scan of the diff found these public items \
+                 removed or changed; explain each one and what callers must change:\n{}",
+                list.join("\n")
+            )
+        };
+        Ok(format!("{}\n\n{}", prompt, section))
+    }
+
+    /// The provider that requests will be sent to, if any is available
+    fn active_provider(&self) -> Option<ModelProvider> {
+        match (self.enforced_provider.as_ref(), &self.anthropic_client, &self.openai_client) {
+            (Some(ModelProvider::Anthropic), Some(_), _) | (None, Some(_), _) => Some(ModelProvider::Anthropic),
+            (Some(ModelProvider::OpenAI), _, Some(_)) | (None, None, Some(_)) => Some(ModelProvider::OpenAI),
+            _ => None,
+        }
+    }
+
+    /// Name of the model that requests will be sent to
+    fn model_name(&self) -> &str {
+        if let Some(model) = &self.model {
+            return model;
+        }
+        match self.active_provider() {
+            Some(ModelProvider::OpenAI) => OPENAI_MODEL,
+            _ => ANTHROPIC_MODEL,
+        }
+    }
+
+    /// Key identifying a summary request in the cache
+    fn cache_key(&self, system_prompt: &str, diff_text: &str) -> String {
+        SummaryCache::key(self.model_name(), &self.with_context(system_prompt), diff_text)
+    }
+
+    /// Prepend the configured context prefix and scope to a system prompt
+    fn with_context(&self, system_prompt: &str) -> String {
+        let system_prompt = match &self.scope {
+            Some(dir) => {
+                let name = dir.rsplit('/').next().unwrap_or(dir);
+                format!(
+                    "The changes are limited to the {} subproject ({}/) of a monorepo; describe them in terms of that subproject.\n\n{}",
+                    name, dir, system_prompt
+                )
+            }
+            None => system_prompt.to_string(),
+        };
+        match &self.context_prefix {
+            Some(context) => format!("Project context:\n{}\n\n{}", context, system_prompt),
+            None => system_prompt,
+        }
+    }
+
+    /// Helper to generate text using available AI provider
+    ///
+    /// Falls back to the next model in the `with_model_fallback` chain when
+    /// a model fails.
+    pub async fn generate_text(&self, system_prompt: &str, user_message: &str) -> Result<String> {
+        self.generate_reply(system_prompt, &[ChatMessage::user(user_message)]).await
+    }
+
+    /// Generate the model's next message in a conversation, which must end
+    /// with a user message
+    pub async fn generate_reply(&self, system_prompt: &str, messages: &[ChatMessage]) -> Result<String> {
+        self.generate_reply_with_max_tokens(system_prompt, messages, self.max_tokens).await
+    }
+
+    /// Generate text with a tighter cap on the response length than the
+    /// configured one
+    async fn generate_short_text(&self, system_prompt: &str, user_message: &str, max_tokens: u16) -> Result<String> {
+        self.generate_reply_with_max_tokens(system_prompt, &[ChatMessage::user(user_message)], self.max_tokens.min(max_tokens)).await
+    }
+
+    /// Generate `count` independent responses to the same request
+    ///
+    /// OpenAI produces them all from one request through its `n` parameter;
+    /// other providers are sent one request per response at the same time.
+    async fn generate_text_choices(&self, system_prompt: &str, user_message: &str, count: u8) -> Result<Vec<String>> {
+        if count <= 1 {
+            return Ok(vec![self.generate_text(system_prompt, user_message).await?]);
+        }
+        if let (None, Some(ModelProvider::OpenAI), Some(client)) = (&self.backend, self.active_provider(), &self.openai_client) {
+            let mut request = self.openai_request(self.model_name(), &self.with_context(system_prompt), user_message);
+            request.n = Some(count);
+            return self.generate_openai_choices(client, request).await;
+        }
+        futures::future::try_join_all((0..count).map(|_| self.generate_text(system_prompt, user_message))).await
+    }
+
+    async fn generate_reply_with_max_tokens(&self, system_prompt: &str, messages: &[ChatMessage], max_tokens: u16) -> Result<String> {
+        self.try_each_model(|model| async move {
+            self.generate_text_with_model(&model, system_prompt, messages, max_tokens, false).await
+        }).await
+    }
+
+    /// Send `request` with the configured model, then with each fallback
+    /// model in turn while the failures are ones another model may not have
+    async fn try_each_model<T, F, Fut>(&self, mut request: F) -> Result<T>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut models = vec![self.model_name()];
+        models.extend(self.fallback_models.iter().map(String::as_str).filter(|model| *model != self.model_name()));
+        let mut remaining = models.iter().peekable();
+        while let Some(model) = remaining.next() {
+            match request(model.to_string()).await {
+                Err(e) if should_fall_back(&e) && remaining.peek().is_some() => {
+                    warn!("Model {} failed: {}. Falling back to {}", model, e, remaining.peek().expect("checked above"));
+                }
+                result => return result,
+            }
+        }
+        unreachable!("the model chain always has a first model")
+    }
+
+    /// Generate text with a particular model of the available provider,
+    /// asking OpenAI for a JSON object if `json` is set
+    async fn generate_text_with_model(&self, model: &str, system_prompt: &str, messages: &[ChatMessage], max_tokens: u16, json: bool) -> Result<String> {
+        let system_prompt = &self.with_context(system_prompt);
+        debug!(
+            "Requesting {} (~{} prompt tokens) with system prompt: {}",
+            model,
+            tokens::count_tokens(system_prompt, model) + messages.iter().map(|message| tokens::count_tokens(&message.content, model)).sum::<usize>(),
+            system_prompt
+        );
+        if let Some(message) = messages.last() {
+            trace!("User message: {}", message.content);
+        }
+
+        if let Some(backend) = &self.backend {
+            return self.generate_backend(backend.as_ref(), model, system_prompt, messages).await;
+        }
+        match (self.active_provider(), &self.anthropic_client, &self.openai_client) {
+            (Some(ModelProvider::Anthropic), Some(client), _) => {
+                if self.enforced_provider.is_some() {
+                    info!("Using Anthropic's Claude model");
+                } else {
+                    info!("Using default provider: Anthropic's Claude model");
+                }
+                self.generate_anthropic(client, model, system_prompt, messages, max_tokens).await
+            },
+            (Some(ModelProvider::OpenAI), _, Some(client)) => {
+                if self.enforced_provider.is_some() {
+                    info!("Using OpenAI's GPT model");
+                } else {
+                    info!("Using fallback provider: OpenAI's GPT model");
+                }
+                let mut request = self.openai_conversation_request(model, system_prompt, messages, max_tokens);
+                if json {
+                    request.response_format = Some(ChatCompletionResponseFormat { r#type: ChatCompletionResponseFormatType::JsonObject });
+                }
+                self.generate_openai(client, request).await
+            },
+            // No available clients
+            _ => {
+                info!("No AI provider available");
+                Err(ProviderUnavailable("No AI provider available. Please set ANTHROPIC_API_KEY, OPENAI_API_KEY, OPENAI_API_KEY_FILE or the AZURE_OPENAI_* environment variables.".to_string()).into())
+            },
+        }
+    }
+
+    /// Send a single request to the backend set with `with_backend`
+    ///
+    /// Backends do not report usage, so it is counted from the text sent
+    /// and received.
+    async fn generate_backend(&self, backend: &dyn ChatBackend, model: &str, system_prompt: &str, messages: &[ChatMessage]) -> Result<String> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+        let text = within_timeout(self.timeout, backend.complete(model, system_prompt, messages)).await??;
+        let prompt_tokens = tokens::count_tokens(system_prompt, model)
+            + messages.iter().map(|message| tokens::count_tokens(&message.content, model)).sum::<usize>();
+        self.usage.record(model, prompt_tokens as u64, tokens::count_tokens(&text, model) as u64).await;
+        Ok(text)
+    }
+
+    /// Send a single request to Anthropic
+    async fn generate_anthropic(&self, client: &AnthropicClient, model: &str, system_prompt: &str, messages: &[ChatMessage], max_tokens: u16) -> Result<String> {
+        let request = MessagesRequest {
+            model: model.to_string(),
+            system: system_prompt.to_string(),
+            messages: messages.iter()
+                .map(|message| Message {
+                    role: match message.role {
+                        ChatRole::User => AnthropicRole::User,
+                        ChatRole::Assistant => AnthropicRole::Assistant,
+                    },
+                    content: vec![ContentBlock::Text { text: message.content.clone() }],
+                })
+                .collect(),
+            max_tokens: usize::from(max_tokens),
+            ..Default::default()
+        };
+
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+        debug!("Sending request to Anthropic API");
+        let response = within_timeout(self.timeout, client.messages(request)).await?
+            .map_err(|e| {
+                let message = e.to_string();
+                // The client only exposes errors as text, in which reqwest
+                // reports transport failures this way
+                if message.contains("error sending request") {
+                    ProviderUnavailable(format!("Anthropic API error: {}", message)).into()
+                } else {
+                    anyhow::Error::from(AnthropicError(message))
+                }
+            })?;
+        
+        debug!("Received response from Anthropic API");
+        self.usage.record(model, response.usage.input_tokens as u64, response.usage.output_tokens as u64).await;
+        let text = response.content.into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(text)
+    }
+
+    /// Helper to generate a JSON object using available AI provider
+    ///
+    /// OpenAI is asked for JSON output, which guarantees a parsable object.
+    /// Other providers get a plain request, so callers should still parse
+    /// the response leniently. Fallback models are tried like for any
+    /// other request.
+    pub async fn generate_json(&self, system_prompt: &str, user_message: &str) -> Result<String> {
+        self.generate_json_reply(system_prompt, &[ChatMessage::user(user_message)]).await
+    }
+
+    /// Generate the model's next message in a conversation as JSON
+    async fn generate_json_reply(&self, system_prompt: &str, messages: &[ChatMessage]) -> Result<String> {
+        self.try_each_model(|model| async move {
+            self.generate_text_with_model(&model, system_prompt, messages, self.max_tokens, true).await
+        }).await
+    }
+
+    /// Generate JSON and parse it with `parse`
+    ///
+    /// A response that does not parse is sent back with a request for only
+    /// the JSON, up to `json_retries` times. Each response that failed is
+    /// logged at debug level. Responses that parse are cached like
+    /// summaries when a cache is configured.
+    async fn generate_parsed_json<T>(&self, system_prompt: &str, user_message: &str, parse: impl Fn(&str) -> Result<T>) -> Result<T> {
+        let cache_key = self.cache_key(system_prompt, user_message);
+        if let Some(parsed) = self.cache.as_ref().and_then(|cache| cache.get(&cache_key)).and_then(|cached| parse(&cached).ok()) {
+            return Ok(parsed);
+        }
+        let mut response = self.generate_json(system_prompt, user_message).await?;
+        let mut messages = vec![ChatMessage::user(user_message)];
+        let mut attempt = 0;
+        let parsed = loop {
+            let error = match parse(&response) {
+                Ok(parsed) => break parsed,
+                Err(e) if attempt < self.json_retries => e,
+                Err(e) => return Err(e),
+            };
+            attempt += 1;
+            debug!("Response was not valid JSON ({}), asking again (attempt {}): {}", error.root_cause(), attempt, response);
+            messages.push(ChatMessage::assistant(response));
+            messages.push(ChatMessage::user(JSON_RETRY_MESSAGE));
+            response = self.generate_json_reply(system_prompt, &messages).await?;
+            debug!("Retried response: {}", response);
+        };
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.put(&cache_key, &response) {
+                debug!("Failed to cache response: {}", e);
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Send a single request to OpenAI, retrying transient failures
+    async fn generate_openai(&self, client: &OpenAiClient, request: CreateChatCompletionRequest) -> Result<String> {
+        Ok(self.generate_openai_choices(client, request).await?
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "No response available.".to_string()))
+    }
+
+    /// Send a single request to OpenAI, returning every choice it produced
+    async fn generate_openai_choices(&self, client: &OpenAiClient, request: CreateChatCompletionRequest) -> Result<Vec<String>> {
+        debug!("Sending request to OpenAI API");
+        let response = retry::with_retry(self.max_retries, self.retry_jitter, self.rate_limiter.as_ref(), self.timeout, || {
+            let request = request.clone();
+            async move { client.create(request).await }
+        }).await.map_err(|e| match e.downcast_ref::<OpenAIError>() {
+            Some(OpenAIError::Reqwest(inner)) if inner.is_connect() || inner.is_timeout() => {
+                ProviderUnavailable(format!("Could not reach OpenAI: {}", e)).into()
+            }
+            _ => e,
+        })?;
+        debug!("Received response from OpenAI API");
+        if let Some(usage) = &response.usage {
+            self.usage.record(&request.model, usage.prompt_tokens as u64, usage.completion_tokens as u64).await;
+        }
+        Ok(response.choices.into_iter()
+            .map(|choice| choice.message.content.unwrap_or_else(|| "No response available.".to_string()))
+            .collect())
+    }
+
+    /// Build an OpenAI chat request from a system prompt and user message
+    fn openai_request(&self, model: &str, system_prompt: &str, user_message: &str) -> CreateChatCompletionRequest {
+        self.openai_conversation_request(model, system_prompt, &[ChatMessage::user(user_message)], self.max_tokens)
+    }
+
+    /// Build an OpenAI chat request from a system prompt and conversation
+    fn openai_conversation_request(&self, model: &str, system_prompt: &str, conversation: &[ChatMessage], max_tokens: u16) -> CreateChatCompletionRequest {
+        let mut messages: Vec<ChatCompletionRequestMessage> = vec![
+            ChatCompletionRequestSystemMessage {
+                content: Some(system_prompt.to_string()),
+                name: None,
+                role: Role::System,
+            }.into(),
+        ];
+        messages.extend(conversation.iter().map(|message| -> ChatCompletionRequestMessage {
+            match message.role {
+                ChatRole::User => ChatCompletionRequestUserMessage {
+                    content: Some(ChatCompletionRequestUserMessageContent::Text(message.content.clone())),
+                    name: None,
+                    role: Role::User,
+                }.into(),
+                ChatRole::Assistant => ChatCompletionRequestAssistantMessage {
+                    content: Some(message.content.clone()),
+                    name: None,
+                    role: Role::Assistant,
+                    ..Default::default()
+                }.into(),
+            }
+        }));
+
+        let mut request = CreateChatCompletionRequest {
+            model: model.into(),
+            messages,
+            temperature: Some(self.temperature),
+            seed: self.seed,
+            max_tokens: Some(max_tokens),
+            ..Default::default()
+        };
+        self.params.apply(&mut request);
+        request
+    }
+
+    /// Summarize a git diff using AI
+    ///
+    /// Diffs larger than the input token budget are summarized in chunks and
+    /// the partial summaries merged into one. Diffs that would take more than
+    /// `MAX_SUMMARY_CHUNKS` chunks are trimmed to the budget instead, keeping
+    /// every file's path and the start of its changes. Trivial diffs without
+    /// a custom prompt get a one-sentence summary. Results are cached by
+    /// diff content, model and prompt when a cache is configured.
+    pub async fn summarize_diff(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<String> {
+        Ok(self.summarize_diff_result(diff, custom_prompt).await?.text)
+    }
+
+    /// Summarize a git diff like `summarize_diff`, also reporting the model,
+    /// the token usage and whether the diff had to be trimmed
+    pub async fn summarize_diff_result(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<SummaryResult> {
+        let (files, notes) = self.render(diff)?;
+        let diff_text = self.summary_text(&files);
+        let (prompt, template) = self.summary_request(custom_prompt);
+        let mut prompt = self.with_breaking_changes(with_language_hint(prompt, diff), diff)?;
+        let trivial = custom_prompt.is_none() && self.is_trivial(diff)?;
+        if trivial {
+            prompt = format!("{}\n\n{}", prompt, TRIVIAL_SUMMARY_PROMPT);
+        }
+
+        let cache_key = self.cache_key(&format!("{}{}", prompt, template.unwrap_or_default()), &diff_text);
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&cache_key));
+        let chunks = || patch::chunk_files(&files, self.max_input_tokens, self.model_name()).len();
+        let trimmed = cached.is_none() && !trivial && self.needs_chunking(&diff_text) && chunks() > MAX_SUMMARY_CHUNKS;
+        let (summary, measured) = UsageMeter::measure(async {
+            if let Some(summary) = cached {
+                return Ok((summary, false));
+            }
+            let (summary, truncated) = if trivial {
+                (self.generate_short_text(&prompt, &summary_user_message(template, &diff_text), BRIEF_MAX_TOKENS).await?, false)
+            } else if !self.needs_chunking(&diff_text) {
+                (self.generate_text(&prompt, &summary_user_message(template, &diff_text)).await?, false)
+            } else if !trimmed {
+                self.summarize_chunked(&files, &prompt).await?
+            } else {
+                info!("Diff exceeds {} chunks, trimming it to {} tokens", MAX_SUMMARY_CHUNKS, self.max_input_tokens);
+                let trimmed = patch::trim_to_budget(&patch::join_files_with_headers(&files), self.max_input_tokens, self.model_name());
+                (self.generate_text(&prompt, &summary_user_message(template, &trimmed)).await?, true)
+            };
+
+            if let Some(cache) = &self.cache {
+                if let Err(e) = cache.put(&cache_key, &summary) {
+                    debug!("Failed to cache summary: {}", e);
+                }
+            }
+            Ok::<_, anyhow::Error>((summary, truncated))
+        }).await;
+        let (summary, truncated) = summary?;
+
+        Ok(SummaryResult {
+            text: append_notes(summary, &notes),
+            model: measured.model.unwrap_or_else(|| self.model_name().to_string()),
+            usage: measured.usage,
+            truncated,
+        })
+    }
+
+    /// Summarize raw patch text, such as a `.patch` file, without a repository
+    ///
+    /// The text is split per file so oversized patches are summarized in
+    /// chunks like any other diff. Results are not cached.
+    pub async fn summarize_patch_text(&self, patch_text: &str, custom_prompt: Option<&str>) -> Result<String> {
+        if patch_text.trim().is_empty() {
+            return Err(anyhow::anyhow!("The patch is empty"));
+        }
+        self.check_diff_size(patch_text)?;
+        let files = patch::split_patch_text(patch_text);
+        let (prompt, template) = self.summary_request(custom_prompt);
+        if self.needs_chunking(patch_text) {
+            Ok(self.summarize_chunked(&files, &prompt).await?.0)
+        } else {
+            self.generate_text(&prompt, &summary_user_message(template, patch_text)).await
+        }
+    }
+
+    /// Summarize staged and unstaged changes together, saying which is which
+    ///
+    /// `staged` should run from HEAD to the index and `unstaged` from the
+    /// index to the working directory, so a line edited again after staging
+    /// shows up once per edit rather than twice for the same one. Results
+    /// are not cached.
+    pub async fn summarize_combined(&self, staged: &Diff<'_>, unstaged: &Diff<'_>, custom_prompt: Option<&str>) -> Result<String> {
+        let (staged_files, staged_notes) = self.render(staged)?;
+        let (unstaged_files, unstaged_notes) = self.render(unstaged)?;
+        if staged_files.is_empty() && unstaged_files.is_empty() {
+            return Ok("No changes.".to_string());
+        }
+
+        let (prompt, template) = self.summary_request(custom_prompt);
+        let prompt = format!(
+            "{}\n\nThe diff has a staged section (HEAD to index) and an unstaged section (index to working directory). \
+            Unstaged changes apply on top of staged ones, so a file in both sections was edited again after staging: \
+            describe each change once. Split the summary into a 'Staged' part and a 'Not staged' part, omitting an empty one.",
+            with_language_hint(prompt, staged),
+        );
+
+        // Give each section half the budget when both do not fit
+        let section = |files: &[FilePatch]| {
+            let text = patch::join_files_with_headers(files);
+            if self.count_tokens(&text) * 2 > self.max_input_tokens {
+                patch::trim_to_budget(&text, self.max_input_tokens / 2, self.model_name())
+            } else {
+                text
+            }
+        };
+        let diff_text = format!(
+            "=== Staged changes ===\n{}\n=== Unstaged changes ===\n{}",
+            if staged_files.is_empty() { "(none)\n".to_string() } else { section(&staged_files) },
+            if unstaged_files.is_empty() { "(none)\n".to_string() } else { section(&unstaged_files) },
+        );
+
+        let summary = self.generate_text(&prompt, &summary_user_message(template, &diff_text)).await?;
+        let notes: Vec<String> = staged_notes.iter().map(|note| format!("Staged: {}", note))
+            .chain(unstaged_notes.iter().map(|note| format!("Unstaged: {}", note)))
+            .collect();
+        Ok(append_notes(summary, &notes))
+    }
+
+    /// Summarize a git diff using AI, yielding the summary as it is generated
+    pub async fn summarize_diff_stream(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<TextStream> {
+        let (files, notes) = self.render(diff)?;
+        let diff_text = self.summary_text(&files);
+        let (prompt, template) = self.summary_request(custom_prompt);
+        let prompt = self.with_breaking_changes(with_language_hint(prompt, diff), diff)?;
+        let notes = append_notes(String::new(), &notes);
+
+        let cache_key = self.cache_key(&format!("{}{}", prompt, template.unwrap_or_default()), &diff_text);
+        if let Some(summary) = self.cache.as_ref().and_then(|cache| cache.get(&cache_key)) {
+            return Ok(Box::pin(stream::once(async move { Ok(summary + &notes) })));
+        }
+
+        let summary = if self.needs_chunking(&diff_text) {
+            let (merge_request, _) = self.summarize_chunks(&files, &prompt).await?;
+            self.generate_text_stream(&prompt, &merge_request).await?
+        } else {
+            self.generate_text_stream(&prompt, &summary_user_message(template, &diff_text)).await?
+        };
+
+        if notes.is_empty() {
+            return Ok(summary);
+        }
+        Ok(Box::pin(summary.chain(stream::once(async move { Ok(notes) }))))
+    }
+
+    /// Summarize a git diff and have the model flag its own uncertainty
+    ///
+    /// Alongside the summary the model reports a confidence level and lists
+    /// anything it could not determine from the diff alone, such as runtime
+    /// behavior, so guesses are not presented as facts.
+    pub async fn summarize_diff_with_self_check(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<SelfCheckedSummary> {
+        let (files, notes) = self.render(diff)?;
+        let diff_text = patch::truncate_to_tokens(&self.summary_text(&files), self.max_input_tokens);
+        let prompt = format!(
+            "{}\n\nAfter summarizing, assess the limits of your analysis. \
+             Respond with a JSON object only, no other text, of the form:\n\
+             {{\"summary\": \"<the summary>\", \"confidence\": \"high\" | \"medium\" | \"low\", \
+             \"unclear\": [\"<anything that needs human review because it cannot be determined from the diff alone>\"]}}\n\
+             Lower the confidence when the diff is truncated, lacks context, or its effect depends on code not shown.",
+            self.summary_prompt(custom_prompt)
+        );
+
+        let response = self.generate_text(&prompt, &format!("Please summarize this git diff:\n```\n{}\n```", diff_text)).await?;
+        let mut checked: SelfCheckedSummary = serde_json::from_str(extract_json(&response))
+            .with_context(|| format!("Failed to parse self-checked summary. Response was: {}", response))?;
+        checked.summary = append_notes(checked.summary, &notes);
+        Ok(checked)
+    }
+
+    /// Review a diff for bugs, style issues and security concerns
+    ///
+    /// The review separates issues that must be fixed from nits, each
+    /// grouped by file with line hints taken from the hunk headers.
+    pub async fn review_diff(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<String> {
+        let Some(user_message) = self.review_request(diff)? else {
+            return Ok("No changes to review.".to_string());
+        };
+        let mut prompt = format!(
+            "{}\nRespond in Markdown with exactly two sections:\n\
+            ## Must fix\n\
+            Bugs, security problems and anything else that should block merging.\n\
+            ## Nits\n\
+            Style, naming, readability and minor suggestions.\n\
+            Within each section group comments under a `### <file path>` heading, one bullet per comment, \
+            starting with a line hint such as `L42:`. Write `None.` for an empty section.",
+            REVIEW_PROMPT
+        );
+        if let Some(custom) = custom_prompt {
+            prompt = format!("{}\n\nAdditional instructions: {}", prompt, custom);
+        }
+        let prompt = self.with_breaking_changes(prompt, diff)?;
+        self.generate_text(&prompt, &user_message).await
+    }
+
+    /// Review a diff like `review_diff`, returning each comment as a
+    /// finding with a file, line and severity instead of Markdown
+    ///
+    /// Breaking changes found with `with_breaking_changes_flagged` are
+    /// reported as warnings.
+    pub async fn review_findings(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<Vec<ReviewFinding>> {
+        let Some(user_message) = self.review_request(diff)? else {
+            return Ok(Vec::new());
+        };
+        let mut prompt = format!(
+            "{}\nRespond with a JSON object only, no other text, of the form \
+            {{\"findings\": [{{\"file\": \"<path>\", \"line\": <line number or null>, \"severity\": \"error\" | \"warning\" | \"info\", \"message\": \"<comment>\"}}]}}. \
+            Use `error` for bugs, security problems and anything else that should block merging, \
+            `warning` for likely problems worth fixing, and `info` for style and minor suggestions. \
+            Return an empty list if there is nothing to say.",
+            REVIEW_PROMPT
+        );
+        if let Some(custom) = custom_prompt {
+            prompt = format!("{}\n\nAdditional instructions: {}", prompt, custom);
+        }
+        if self.flag_breaking {
+            let changes = breaking::detect_breaking_changes(diff)?;
+            if !changes.is_empty() {
+                let list: Vec<String> = changes.iter().map(|change| format!("- {}", change)).collect();
+                prompt = format!(
+                    "{}\n\nA scan of the diff found these public items removed or changed; report each one as a \
+                    `warning` finding saying what callers must change:\n{}",
+                    prompt, list.join("\n")
+                );
+            }
+        }
+        self.generate_parsed_json(&prompt, &user_message, parse_review_findings).await
+    }
+
+    /// The user message of a review request, or `None` if there is nothing
+    /// to review
+    fn review_request(&self, diff: &Diff<'_>) -> Result<Option<String>> {
+        let options = RenderOptions { hunk_headers: true, ..self.render_options.clone() };
+        let mut files = patch::render_files(diff, &options)?;
+        if let Some(patterns) = &self.test_patterns {
+            patch::remove_matching(&mut files, patterns);
+        }
+        let diff_text: String = files.iter()
+            .map(|file| format!("File: {}\n{}", file.path, file.text()))
+            .collect();
+        if diff_text.is_empty() {
+            return Ok(None);
+        }
+        let diff_text = patch::truncate_to_tokens(&diff_text, self.max_input_tokens);
+        Ok(Some(format!("Please review this git diff:\n```\n{}\n```", diff_text)))
+    }
+
+    /// Explain a commit in depth for someone new to the code
+    ///
+    /// The commit message is given to the model as the author's stated
+    /// intent, so the explanation of the problem, approach and trade-offs is
+    /// grounded in it rather than guessed from the diff alone.
+    pub async fn explain_commit(&self, diff: &Diff<'_>, message: &str) -> Result<String> {
+        let (files, notes) = self.render(diff)?;
+        let diff_text: String = files.iter()
+            .map(|file| format!("File: {}\n{}", file.path, file.text()))
+            .collect();
+        let diff_text = patch::truncate_to_tokens(&diff_text, self.max_input_tokens);
+
+        let prompt = "You are a senior engineer explaining a commit to a new team member during onboarding. \
+            Use the commit message as the author's stated intent and the diff as the ground truth; \
+            say so when they disagree or when something cannot be determined from them. \
+            Respond in Markdown with these sections:\n\
+            ## Problem\nWhat was wrong or missing before this commit, and why it mattered.\n\
+            ## Approach\nHow the change solves it, walking through the key parts of the diff in a sensible order \
+            and naming the functions, types and files involved.\n\
+            ## Trade-offs\nAlternatives the approach rules out, its costs and limitations, and risks to watch for.\n\
+            ## Concepts\nBackground a newcomer needs to follow the change, such as patterns, APIs or domain terms.";
+        let prompt = with_language_hint(prompt.to_string(), diff);
+        let explanation = self.generate_text(
+            &prompt,
+            &format!("Commit message:\n{}\n\nDiff:\n```\n{}\n```", message.trim(), diff_text),
+        ).await?;
+        Ok(append_notes(explanation, &notes))
+    }
+
+    /// Suggest test cases for the functions a diff adds or changes
+    ///
+    /// Suggestions are advisory: happy-path, edge-case and error-handling
+    /// cases per function, plus `#[test]` signatures for Rust changes.
+    pub async fn suggest_tests(&self, diff: &Diff<'_>) -> Result<String> {
+        let (files, _) = self.render(diff)?;
+        let diff_text: String = files.iter()
+            .map(|file| format!("File: {}\n{}", file.path, file.text()))
+            .collect();
+        if diff_text.is_empty() {
+            return Ok("No changes to suggest tests for.".to_string());
+        }
+
+        let mut prompt = "You are an experienced engineer helping a reviewer decide which tests a change needs. \
+            Identify the functions and behavior the diff adds or changes, and for each propose concrete test cases \
+            covering the happy path, edge cases and error handling. Skip code that was only moved or reformatted, \
+            and note cases already covered by tests in the diff. Respond in Markdown with a `### <function or area>` \
+            heading per item and one bullet per test case giving its input and expected outcome.".to_string();
+        if language::detect_language(diff) == Some(language::Language::Rust) {
+            prompt.push_str("\n\nThis is Rust code: under each heading also propose `#[test]` function signatures \
+                such as `#[test] fn parse_rejects_empty_input()`, without writing the test bodies.");
+        }
+
+        let diff_text = patch::truncate_to_tokens(&diff_text, self.max_input_tokens);
+        self.generate_text(&prompt, &format!("Please suggest tests for this git diff:\n```\n{}\n```", diff_text)).await
+    }
+
+    /// Summarize each file in a diff separately
+    ///
+    /// When the whole diff fits in the input token budget all files are
+    /// summarized in one request returning a JSON map of path to summary;
+    /// otherwise each file gets its own request. Renamed files are reported
+    /// as `old -> new`.
+    pub async fn summarize_files(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<Vec<FileSummary>> {
+        let (files, _) = self.render(diff)?;
+        let renames = rename_labels(diff);
+        let label = |path: &str| renames.get(path).cloned().unwrap_or_else(|| path.to_string());
+        let prompt = self.summary_prompt(custom_prompt);
+
+        let (changed, unchanged): (Vec<&FilePatch>, Vec<&FilePatch>) = files.iter()
+            .partition(|file| !file.hunks.is_empty());
+        let mut summaries: std::collections::HashMap<String, String> = unchanged.iter()
+            .map(|file| (file.path.clone(), "No content changes.".to_string()))
+            .collect();
+
+        let batch: String = changed.iter()
+            .map(|file| format!("File: {}\n{}", file.path, file.text()))
+            .collect();
+        if changed.len() > 1 && self.count_tokens(&batch) <= self.max_input_tokens {
+            let prompt = format!(
+                "{}\n\nSummarize each file separately in one or two sentences. Respond with a JSON object only, \
+                 no other text, mapping each file path exactly as given after `File:` to its summary.",
+                prompt
+            );
+            let response = self.generate_text(&prompt, &format!("Please summarize these changed files:\n```\n{}\n```", batch)).await?;
+            let parsed: std::collections::HashMap<String, String> = serde_json::from_str(extract_json(&response))
+                .with_context(|| format!("Failed to parse per-file summaries. Response was: {}", response))?;
+            summaries.extend(parsed);
+        } else {
+            for file in &changed {
+                let text = patch::truncate_to_tokens(&file.text(), self.max_input_tokens);
+                let summary = self.generate_text(&prompt, &format!("Please summarize the changes to {}:\n```\n{}\n```", file.path, text)).await?;
+                summaries.insert(file.path.clone(), summary.trim().to_string());
+            }
+        }
+
+        Ok(files.iter()
+            .map(|file| FileSummary {
+                path: label(&file.path),
+                summary: summaries.remove(&file.path).unwrap_or_else(|| "(no summary returned)".to_string()),
+            })
+            .collect())
+    }
+
+    /// Summarize each file in a diff in a sentence, tagged as a feature,
+    /// fix, refactor, test, docs or chore change, or `other`
+    ///
+    /// All files go to the model in one JSON request, trimmed to the input
+    /// token budget. Files the model leaves out are tagged `other`.
+    pub async fn categorize_files(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<Vec<CategorizedFile>> {
+        let (files, _) = self.render(diff)?;
+        let renames = rename_labels(diff);
+        let label = |path: &str| renames.get(path).cloned().unwrap_or_else(|| path.to_string());
+
+        let mut categorized: std::collections::HashMap<String, CategorizedFile> = std::collections::HashMap::new();
+        let changed: String = files.iter()
+            .filter(|file| !file.hunks.is_empty())
+            .map(|file| format!("File: {}\n{}", file.path, file.text()))
+            .collect();
+        if !changed.is_empty() {
+            let prompt = format!(
+                "{}\n\nSummarize each file separately in one sentence and tag it with the kind of change: \
+                 `feature`, `fix`, `refactor`, `test`, `docs` or `chore`, or `other` if it does not fit one cleanly. \
+                 Respond with a JSON object only, no other text, of the form \
+                 {{\"files\": [{{\"path\": \"<path exactly as given after `File:`>\", \"category\": \"<kind>\", \"summary\": \"<sentence>\"}}]}}.",
+                self.summary_prompt(custom_prompt)
+            );
+            let changed = patch::truncate_to_tokens(&changed, self.max_input_tokens);
+            let request = format!("Please categorize these changed files:\n```\n{}\n```", changed);
+            for file in self.generate_parsed_json(&prompt, &request, parse_categorized_files).await? {
+                categorized.insert(file.path.clone(), file);
+            }
+        }
+
+        Ok(files.iter()
+            .map(|file| {
+                let (category, summary) = match categorized.remove(&file.path) {
+                    Some(found) => (found.category, found.summary),
+                    None if file.hunks.is_empty() => (ChangeCategory::Other, "No content changes.".to_string()),
+                    None => (ChangeCategory::Other, "(no summary returned)".to_string()),
+                };
+                CategorizedFile { path: label(&file.path), category, summary }
+            })
+            .collect())
+    }
+
+    /// Summarize an oversized diff chunk by chunk, then merge the results,
+    /// also reporting whether the partial summaries had to be cut
+    async fn summarize_chunked(&self, files: &[FilePatch], prompt: &str) -> Result<(String, bool)> {
+        let (merge_request, truncated) = self.summarize_chunks(files, prompt).await?;
+        Ok((self.generate_text(prompt, &merge_request).await?, truncated))
+    }
+
+    /// Summarize each chunk of an oversized diff and build the request that
+    /// merges the partial summaries into one
+    ///
+    /// Partial summaries too long to send together are cut to the input
+    /// token and byte limits, which the returned flag reports.
+    async fn summarize_chunks(&self, files: &[FilePatch], prompt: &str) -> Result<(String, bool)> {
+        let chunks = patch::chunk_files(files, self.max_input_tokens, self.model_name());
+        info!("Diff exceeds {} tokens, summarizing in {} chunks", self.max_input_tokens, chunks.len());
+
+        let mut partials = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            debug!("Summarizing chunk {}/{}", i + 1, chunks.len());
+            let partial = self.generate_text(
+                prompt,
+                &format!("Please summarize part {} of {} of a larger git diff:\n```\n{}\n```", i + 1, chunks.len(), chunk),
+            ).await?;
+            partials.push(partial);
+        }
+
+        let mut partials = partials.join("\n\n---\n\n");
+        let truncated = self.needs_chunking(&partials);
+        if truncated {
+            info!("Partial summaries exceed the input limits, cutting them");
+            partials = patch::truncate_to_tokens(&partials, self.max_input_tokens.min(self.max_diff_bytes / patch::CHARS_PER_TOKEN));
+        }
+        Ok((format!(
+            "The following are summaries of consecutive parts of a single git diff. \
+             Combine them into one coherent summary of the whole change. \
+             Do not mention the parts or list them separately:\n\n{}",
+            partials
+        ), truncated))
+    }
+
+    /// Helper to stream text using available AI provider.
+    ///
+    /// OpenAI responses are streamed delta by delta. Anthropic and backend
+    /// responses are requested in one go and yielded as a single chunk.
+    pub async fn generate_text_stream(&self, system_prompt: &str, user_message: &str) -> Result<TextStream> {
+        if self.backend.is_some() || self.active_provider() != Some(ModelProvider::OpenAI) {
+            let text = self.generate_text(system_prompt, user_message).await?;
+            return Ok(Box::pin(stream::once(async move { Ok(text) })));
+        }
+
+        let client = self.openai_client.as_ref().expect("OpenAI client checked above");
+        let system_prompt = &self.with_context(system_prompt);
+        info!("Streaming from OpenAI's GPT model");
+        let response = self.try_each_model(|model| async move {
+            debug!("Opening stream to OpenAI API with {}", model);
+            let request = CreateChatCompletionRequest {
+                stream: Some(true),
+                ..self.openai_request(&model, system_prompt, user_message)
+            };
+            retry::with_retry(self.max_retries, self.retry_jitter, self.rate_limiter.as_ref(), self.timeout, || {
+                let request = request.clone();
+                async move { client.create_stream(request).await }
+            }).await
+        }).await?;
+
+        // Each event carries only the newly generated delta, so chunks can be
+        // printed as-is without tracking what has already been shown.
+        let chunks = response.filter_map(|event| async move {
+            match event {
+                Ok(event) => {
+                    let text: String = event.choices
+                        .into_iter()
+                        .filter_map(|choice| choice.delta.content)
+                        .collect();
+                    if text.is_empty() { None } else { Some(Ok(text)) }
+                }
+                Err(e) => Some(Err(anyhow::anyhow!("OpenAI stream error: {}", e))),
+            }
+        });
+
+        Ok(Box::pin(chunks))
+    }
+
+    /// Generate a commit message for the given diff
+    ///
+    /// Trivial diffs, such as a typo fix, get a subject line only.
+    pub async fn generate_commit_message(&self, diff: &Diff<'_>, style: CommitStyle) -> Result<String> {
+        match self.commit_message_request(diff, style)? {
+            Some((prompt, user_message)) => {
+                let message = if self.is_trivial(diff)? {
+                    debug!("Diff is trivial, asking for a subject line only");
+                    self.generate_short_text(&format!("{}\n\n{}", prompt, TRIVIAL_COMMIT_PROMPT), &user_message, BRIEF_MAX_TOKENS).await?
+                } else {
+                    self.generate_text(&prompt, &user_message).await?
+                };
+                Ok(if style == CommitStyle::Gitmoji { expand_gitmoji_shortcode(&message) } else { message })
+            }
+            None => Ok("No changes detected.".to_string()),
+        }
+    }
+
+    /// Write up to `count` alternative commit messages for a diff
+    ///
+    /// Duplicates are dropped, so fewer than `count` may come back.
+    pub async fn generate_commit_message_candidates(&self, diff: &Diff<'_>, style: CommitStyle, count: u8) -> Result<Vec<String>> {
+        let Some((mut prompt, user_message)) = self.commit_message_request(diff, style)? else {
+            return Ok(vec!["No changes detected.".to_string()]);
+        };
+        if self.is_trivial(diff)? {
+            prompt = format!("{}\n\n{}", prompt, TRIVIAL_COMMIT_PROMPT);
+        }
+        let mut candidates: Vec<String> = Vec::new();
+        for message in self.generate_text_choices(&prompt, &user_message, count).await? {
+            let message = if style == CommitStyle::Gitmoji { expand_gitmoji_shortcode(&message) } else { message };
+            let message = message.trim().to_string();
+            if !candidates.contains(&message) {
+                candidates.push(message);
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Ask which of several commit messages best describes a diff
+    ///
+    /// Returns the index of the chosen message and the model's reason.
+    pub async fn pick_commit_message(&self, diff: &Diff<'_>, candidates: &[String]) -> Result<(usize, String)> {
+        if candidates.len() < 2 {
+            return Ok((0, String::new()));
+        }
+        let Some((_, changes)) = self.commit_message_request(diff, CommitStyle::Freeform)? else {
+            return Ok((0, String::new()));
+        };
+        let listed: Vec<String> = candidates.iter()
+            .enumerate()
+            .map(|(i, message)| format!("=== Candidate {} ===\n{}", i + 1, message))
+            .collect();
+        let response = self.generate_json(PICK_COMMIT_PROMPT, &format!("{}\n\n{}", changes, listed.join("\n\n"))).await?;
+        parse_commit_pick(&response, candidates.len())
+    }
+
+    /// Write freeform commit messages for several diffs in one request
+    ///
+    /// Returns one message per diff, in the same order. Each diff gets an
+    /// equal share of the input token budget.
+    pub async fn generate_commit_messages(&self, diffs: &[&Diff<'_>]) -> Result<Vec<String>> {
+        if diffs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let budget = self.max_input_tokens / diffs.len();
+        let mut prompt = None;
+        let mut sections = Vec::new();
+        for (i, diff) in diffs.iter().enumerate() {
+            let changes = match self.commit_message_request(diff, CommitStyle::Freeform)? {
+                Some((system, user_message)) => {
+                    prompt.get_or_insert(system);
+                    patch::truncate_to_tokens(&user_message, budget)
+                }
+                None => "(no changes)".to_string(),
+            };
+            sections.push(format!("=== Commit {} ===\n{}", i + 1, changes));
+        }
+        let Some(prompt) = prompt else {
+            return Err(anyhow::anyhow!("None of the commits have changes to describe"));
+        };
+
+        let prompt = format!(
+            "{}\n\nYou are given the changes of {n} commits, each under a `=== Commit N ===` heading. \
+            Write one commit message per commit following the rules above. \
+            Respond with a JSON object only, no other text, of the form \
+            {{\"messages\": [\"<message for commit 1>\", ...]}} with exactly {n} messages in the order given.",
+            prompt,
+            n = diffs.len(),
+        );
+        let response = self.generate_json(&prompt, &sections.join("\n\n")).await?;
+        parse_commit_messages(&response, diffs.len())
+    }
+
+    /// Ask for a corrected commit message after `message` broke the rules
+    /// described in `problems`
+    pub async fn revise_commit_message(&self, diff: &Diff<'_>, style: CommitStyle, message: &str, problems: &[String]) -> Result<String> {
+        let Some((prompt, user_message)) = self.commit_message_request(diff, style)? else {
+            return Ok(message.to_string());
+        };
+        let prompt = format!(
+            "{}\n\nYour previous message for these changes broke these rules:\n{}\n\
+             Rewrite it so it follows every rule strictly. Output only the commit message.",
+            prompt,
+            problems.iter().map(|p| format!("- {}", p)).collect::<Vec<_>>().join("\n")
+        );
+        let user_message = format!("{}\n\nPrevious message:\n```\n{}\n```", user_message, message);
+        let revised = self.generate_text(&prompt, &user_message).await?;
+        Ok(if style == CommitStyle::Gitmoji { expand_gitmoji_shortcode(&revised) } else { revised })
+    }
+
+    /// Write a commit message from the diff stats alone, without a model
+    ///
+    /// Used with `--offline` and when no provider can be reached, e.g.
+    /// "Update 3 files in src/auth" followed by the changed files.
+    pub fn generate_offline_commit_message(&self, diff: &Diff<'_>, style: CommitStyle) -> Result<String> {
+        let stat = DiffStat::new(diff)?;
+        if stat.files.is_empty() {
+            return Ok("No changes detected.".to_string());
+        }
+
+        let statuses: Vec<char> = stat.files.iter().map(|(status, _)| *status).collect();
+        let (verb, emoji) = if statuses.iter().all(|s| *s == 'A') {
+            ("Add", "✨")
+        } else if statuses.iter().all(|s| *s == 'D') {
+            ("Remove", "🔥")
+        } else if statuses.iter().all(|s| *s == 'R') {
+            ("Rename", "🚚")
+        } else {
+            ("Update", "🎨")
+        };
+
+        let paths: Vec<String> = stat.files.iter().map(|(_, path)| path.clone()).collect();
+        let object = match &paths[..] {
+            [path] => path.clone(),
+            _ => match common_directory(&paths) {
+                Some(dir) => format!("{} files in {}", paths.len(), dir),
+                None => format!("{} files", paths.len()),
+            },
+        };
+        let mut subject = match style {
+            CommitStyle::Freeform => format!("{} {}", verb, object),
+            CommitStyle::Gitmoji => format!("{} {} {}", emoji, verb, object),
+            CommitStyle::Conventional => match infer_scope(&paths) {
+                Some(scope) => format!("chore({}): {} {}", scope, verb.to_lowercase(), object),
+                None => format!("chore: {} {}", verb.to_lowercase(), object),
+            },
+        };
+        // A long single path is shortened to its file name
+        if subject.chars().count() > lint::FREEFORM_SUBJECT_LENGTH && paths.len() == 1 {
+            let name = paths[0].rsplit('/').next().unwrap_or(&paths[0]);
+            subject = subject.replace(&paths[0], name);
+        }
+
+        let plural = |count: usize, word: &str| format!("{} {}{}", count, word, if count == 1 { "" } else { "s" });
+        let mut body = vec![format!(
+            "{} changed, {}(+), {}(-)\n",
+            plural(paths.len(), "file"),
+            plural(stat.insertions, "insertion"),
+            plural(stat.deletions, "deletion"),
+        )];
+        body.extend(stat.files.iter().take(OFFLINE_MAX_LISTED_FILES).map(|(status, path)| format!("{} {}", status, path)));
+        if stat.files.len() > OFFLINE_MAX_LISTED_FILES {
+            body.push(format!("... and {} more", stat.files.len() - OFFLINE_MAX_LISTED_FILES));
+        }
+
+        Ok(format!("{}\n\n{}", subject, body.join("\n")))
+    }
+
+    /// Estimate the prompt tokens `generate_commit_message` would send
+    pub fn estimate_commit_message_tokens(&self, diff: &Diff<'_>, style: CommitStyle) -> Result<usize> {
+        Ok(match self.commit_message_request(diff, style)? {
+            Some((prompt, user_message)) => self.count_tokens(&self.with_context(&prompt)) + self.count_tokens(&user_message),
+            None => 0,
+        })
+    }
+
+    /// Build the system prompt and user message for a commit message, or
+    /// `None` if the diff has no changes
+    fn commit_message_request(&self, diff: &Diff<'_>, style: CommitStyle) -> Result<Option<(String, String)>> {
+        let mut changes = String::new();
+        let mut paths: Vec<String> = Vec::new();
+        let generated = patch::generated_paths(diff, &self.render_options);
+        let repo = self.render_options.repository();
+        diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
+            if let Some(path) = delta.new_file().path() {
+                let path_str = path.to_string_lossy();
+                let is_generated = generated.contains(&*path_str);
+                if paths.last().map(String::as_str) != Some(&*path_str) {
+                    paths.push(path_str.into_owned());
+                }
+                match line.origin_value() {
+                    git2::DiffLineType::FileHeader if patch::is_submodule(&delta) => changes.push_str(&patch::submodule_note(&delta, repo.as_ref())),
+                    _ if patch::is_submodule(&delta) => (),
+                    git2::DiffLineType::FileHeader if is_generated => changes.push_str(&patch::generated_note(&delta)),
+                    _ if is_generated => (),
+                    git2::DiffLineType::FileHeader if delta.flags().is_binary() => changes.push_str(&patch::binary_note(&delta)),
+                    _ if delta.flags().is_binary() => (),
+                    git2::DiffLineType::FileHeader => {
+                        changes.push_str(&patch::rename_note(&delta).unwrap_or_default());
+                        changes.push_str(&patch::mode_note(&delta).unwrap_or_default());
+                    }
+                    git2::DiffLineType::Addition => changes.push_str(&format!("+ {} ({})\n", patch::decode_line(line.content()).0, path.display())),
+                    git2::DiffLineType::Deletion => changes.push_str(&format!("- {} ({})\n", patch::decode_line(line.content()).0, path.display())),
+                    _ => (),
+                }
+            }
+            true
+        })?;
+
+        if changes.is_empty() {
+            return Ok(None);
+        }
+
+        let default_freeform_prompt = "You are a helpful AI that generates git commit messages. Follow these rules strictly:\n\
+                     1. Format must be:\n\
+                        - First line: Short summary in imperative mood, max 50 chars\n\
+                        - Blank line\n\
+                        - Detailed description wrapped at 72 chars\n\
+                     2. First line must:\n\
+                        - Use imperative mood ('Add' not 'Added')\n\
+                        - Not end with a period\n\
+                        - Be max 50 characters\n\
+                        - Accurately describe the main change in the diff\n\
+                     3. Description must:\n\
+                        - Start with a blank line after the summary\n\
+                        - Explain WHY the changes in the diff were made\n\
+                        - Wrap text at 72 characters\n\
+                        - Use proper punctuation\n\
+                        - Be specific to the actual changes shown\n\
+                        - Include affected files or components";
+
+        let freeform_prompt = self.config.commit_prompt.as_deref().unwrap_or(default_freeform_prompt);
+        let prompt = match style {
+            CommitStyle::Freeform => freeform_prompt.to_string(),
+            CommitStyle::Gitmoji => format!("{}\n{}", freeform_prompt, gitmoji_prompt()),
+            CommitStyle::Conventional => conventional_commit_prompt(infer_scope(&paths).as_deref()),
+        };
+        let mut prompt = with_language_hint(prompt, diff);
+        if diff.deltas().any(|delta| delta.status() == git2::Delta::Renamed) {
+            prompt.push_str("\n\nFiles listed as `Renamed <old> to <new>` were moved, not deleted and recreated; \
+                describe them as renames, e.g. 'Rename a.rs to b.rs'.");
+        }
+        if let Some(language) = self.response_language_line(style == CommitStyle::Conventional) {
+            prompt = format!("{}\n\n{}", prompt, language);
+        }
+        if !self.commit_examples.is_empty() {
+            prompt.push_str("\n\nMatch the tone, tense and structure of these commit messages from the same repository, \
+                but describe only the changes given to you:");
+            for example in &self.commit_examples {
+                prompt.push_str(&format!("\n```\n{}\n```", example.trim()));
+            }
+        }
+
+        let mut user_message = format!("Analyze these changes and create a commit summary:\n```\n{}\n```", changes);
+        if !self.untracked_files.is_empty() {
+            user_message.push_str("\n\nThese untracked files also exist in the working directory. They are not part of this commit, \
+                but the changes may refer to them:\n");
+            for path in self.untracked_files.iter().take(MAX_UNTRACKED_FILES_LISTED) {
+                user_message.push_str(&format!("- {}\n", path));
+            }
+            if self.untracked_files.len() > MAX_UNTRACKED_FILES_LISTED {
+                user_message.push_str(&format!("({} more)\n", self.untracked_files.len() - MAX_UNTRACKED_FILES_LISTED));
+            }
+        }
+
+        Ok(Some((prompt, user_message)))
+    }
+
+    /// Draft a pull request title and Markdown description for the given diff
+    ///
+    /// `commit_subjects` are the subjects of the commits on the branch and help
+    /// the model describe the intent behind the changes.
+    pub async fn generate_pr_description(&self, diff: &Diff<'_>, commit_subjects: &[String]) -> Result<PrDescription> {
+        let (files, _) = self.render(diff)?;
+        let mut diff_text = patch::join_files(&files);
+        if self.needs_chunking(&diff_text) {
+            diff_text = self.summarize_chunked(&files, &self.summary_prompt(None)).await?.0;
+        }
+
+        let prompt = "You are a helpful AI that writes GitHub pull request descriptions. \
+            Respond with Markdown only, in exactly this structure:\n\
+            # <concise PR title in imperative mood, max 72 characters>\n\
+            \n\
+            ## Summary\n\
+            <one or two paragraphs explaining what the PR does and why>\n\
+            \n\
+            ## Changes\n\
+            - <one bullet per notable change>\n\
+            \n\
+            ## Testing\n\
+            <how the changes were or should be tested>\n\
+            \n\
+            Base the description on the actual changes shown and use the commit subjects to understand intent.";
+
+        let subjects = if commit_subjects.is_empty() {
+            "(none)".to_string()
+        } else {
+            commit_subjects.iter().map(|s| format!("- {}", s)).collect::<Vec<_>>().join("\n")
+        };
+
+        let response = self.generate_text(
+            prompt,
+            &format!("Commit subjects:\n{}\n\nChanges:\n```\n{}\n```", subjects, diff_text),
+        ).await?;
+
+        Ok(PrDescription::from_markdown(&response))
+    }
+
+    /// Generate a Keep a Changelog section from a list of commit messages
+    ///
+    /// Near-identical messages are collapsed before they are sent to the model.
+    pub async fn generate_changelog(&self, messages: &[String]) -> Result<String> {
+        let messages = dedupe_messages(messages);
+        if messages.is_empty() {
+            return Ok("No changes.".to_string());
+        }
+
+        let prompt = "You are a helpful AI that writes release changelogs in the Keep a Changelog format. \
+            Group the changes under these Markdown headings, in this order, omitting empty ones:\n\
+            ### Added\n### Changed\n### Fixed\n### Removed\n\
+            Write one concise bullet per user-facing change, merging commits that describe the same change. \
+            Leave out purely internal changes such as formatting or CI tweaks unless they affect users. \
+            Only output the Markdown sections, no other text.";
+
+        let commits = messages.iter()
+            .map(|m| format!("- {}", m.trim().replace('\n', "\n  ")))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.generate_text(prompt, &format!("Write a changelog for these commits:\n{}", commits)).await
+    }
+
+    /// Write release notes from a list of commit messages: a few highlights
+    /// followed by the full list of changes
+    ///
+    /// Near-identical messages are collapsed before they are sent to the model.
+    pub async fn generate_release_notes(&self, messages: &[String]) -> Result<String> {
+        let messages = dedupe_messages(messages);
+        if messages.is_empty() {
+            return Ok("No changes.".to_string());
+        }
+
+        let prompt = "You are a helpful AI that writes polished release notes for software releases. \
+            Start with a `## Highlights` section: a short paragraph or up to five bullets on the changes users will care about most. \
+            Follow it with a `## All changes` section listing every change as one concise bullet, \
+            merging commits that describe the same change and leaving out purely internal ones such as formatting or CI tweaks. \
+            Write for users of the software rather than its developers. \
+            Only output the Markdown sections, no other text.";
+
+        let commits = messages.iter()
+            .map(|m| format!("- {}", m.trim().replace('\n', "\n  ")))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.generate_text(prompt, &format!("Write release notes for these commits:\n{}", commits)).await
+    }
+
+    /// Group commits by theme from their summaries
+    ///
+    /// `commits` pairs each commit's hash with its summary. Every commit ends
+    /// up in exactly one theme, in the order given; commits the model leaves
+    /// out are collected under "Other changes".
+    pub async fn cluster_commits(&self, commits: &[(String, String)]) -> Result<Vec<CommitTheme>> {
+        if commits.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let prompt = "You are a helpful AI that organizes a project's history into themes. \
+            Group the commits below by the work they contribute to, such as \"Authentication work\" or \"CI fixes\", \
+            giving each theme a short title. Prefer a handful of meaningful themes over one theme per commit, \
+            and put every commit in exactly one theme. \
+            Respond with a JSON object only, no other text, of the form:\n\
+            {\"themes\": [{\"title\": \"<theme>\", \"commits\": [\"<hash>\", ...]}]}";
+
+        let listing = commits.iter()
+            .map(|(hash, summary)| format!("Commit {}:\n{}", hash, summary.trim()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let listing = patch::truncate_to_tokens(&listing, self.max_input_tokens);
+
+        let response = self.generate_json(prompt, &format!("Group these commits by theme:\n\n{}", listing)).await?;
+        let hashes: Vec<&str> = commits.iter().map(|(hash, _)| hash.as_str()).collect();
+        parse_commit_themes(&response, &hashes)
+    }
+
+    /// Suggest a semantic version bump for a release
+    ///
+    /// Breaking changes to the public API, such as removed or renamed
+    /// public items, call for a major bump, new features for a minor bump
+    /// and anything else for a patch bump.
+    pub async fn suggest_version_bump(&self, diff: &Diff<'_>, messages: &[String]) -> Result<BumpSuggestion> {
+        let (files, _) = self.render(diff)?;
+        let diff_text = patch::truncate_to_tokens(&patch::join_files(&files), self.max_input_tokens);
 
-                debug!("Sending request to Anthropic API");
-                let response = client.messages(request).await
-                    .map_err(|e| anyhow::anyhow!("Anthropic API error: {}", e))?;
-                
-                debug!("Received response from Anthropic API");
-                let text = response.content.into_iter()
-                    .filter_map(|block| match block {
-                        ContentBlock::Text { text } => Some(text),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                Ok(text)
-            },
-            // Enforced OpenAI
-            (Some(ModelProvider::OpenAI), _, Some(client)) => {
-                info!("Using OpenAI's GPT model");
-                let messages = vec![
-                    ChatCompletionRequestSystemMessage {
-                        content: Some(system_prompt.to_string()),
-                        name: None,
-                        role: Role::System,
-                    }.into(),
-                    ChatCompletionRequestUserMessage {
-                        content: Some(ChatCompletionRequestUserMessageContent::Text(
-                            user_message.to_string()
-                        )),
-                        name: None,
-                        role: Role::User,
-                    }.into(),
-                ];
-
-                let request = CreateChatCompletionRequest {
-                    model: "gpt-3.5-turbo".into(),
-                    messages,
-                    temperature: Some(0.7),
-                    max_tokens: Some(OPENAI_MAX_TOKENS),
-                    ..Default::default()
-                };
+        let prompt = "You are a release manager applying semantic versioning. \
+            Decide which version component a release containing these changes must increment:\n\
+            - major: a breaking change to the public API, such as a removed or renamed public function, type, \
+            field, flag or endpoint, a changed signature, or behavior that existing users must adapt to\n\
+            - minor: new backwards-compatible functionality\n\
+            - patch: only backwards-compatible bug fixes, documentation or internal changes\n\
+            Pick the highest level any single change requires. \
+            Respond with a JSON object only, no other text, of the form:\n\
+            {\"bump\": \"major\" | \"minor\" | \"patch\", \"rationale\": \"<one sentence naming the change that decides the bump>\"}";
 
-                debug!("Sending request to OpenAI API");
-                let response = client.chat().create(request).await?;
-                debug!("Received response from OpenAI API");
-                Ok(response.choices[0]
-                    .message
-                    .content
-                    .clone()
-                    .unwrap_or_else(|| "No response available.".to_string()))
-            },
-            // Default behavior: prefer Anthropic if available
-            (None, Some(client), _) => {
-                info!("Using default provider: Anthropic's Claude model");
-                let request = MessagesRequest {
-                    model: "claude-3-sonnet-20240229".to_string(),
-                    system: system_prompt.to_string(),
-                    messages: vec![
-                        Message {
-                            role: AnthropicRole::User,
-                            content: vec![ContentBlock::Text { text: user_message.to_string() }],
-                        }
-                    ],
-                    max_tokens: ANTHROPIC_MAX_TOKENS,
-                    ..Default::default()
-                };
+        let commits = dedupe_messages(messages).iter()
+            .map(|m| format!("- {}", m.trim().replace('\n', "\n  ")))
+            .collect::<Vec<_>>()
+            .join("\n");
 
-                debug!("Sending request to Anthropic API");
-                let response = client.messages(request).await
-                    .map_err(|e| anyhow::anyhow!("Anthropic API error: {}", e))?;
-                
-                debug!("Received response from Anthropic API");
-                let text = response.content.into_iter()
-                    .filter_map(|block| match block {
-                        ContentBlock::Text { text } => Some(text),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                Ok(text)
-            },
-            // Fallback to OpenAI
-            (None, None, Some(client)) => {
-                info!("Using fallback provider: OpenAI's GPT model");
-                let messages = vec![
-                    ChatCompletionRequestSystemMessage {
-                        content: Some(system_prompt.to_string()),
-                        name: None,
-                        role: Role::System,
-                    }.into(),
-                    ChatCompletionRequestUserMessage {
-                        content: Some(ChatCompletionRequestUserMessageContent::Text(
-                            user_message.to_string()
-                        )),
-                        name: None,
-                        role: Role::User,
-                    }.into(),
-                ];
-
-                let request = CreateChatCompletionRequest {
-                    model: "gpt-3.5-turbo".into(),
-                    messages,
-                    temperature: Some(0.7),
-                    max_tokens: Some(OPENAI_MAX_TOKENS),
-                    ..Default::default()
-                };
+        let response = self.generate_text(
+            prompt,
+            &format!("Commit messages:\n{}\n\nChanges:\n```\n{}\n```", commits, diff_text),
+        ).await?;
+        let mut suggestion: BumpSuggestion = serde_json::from_str(extract_json(&response))
+            .with_context(|| format!("Failed to parse version bump. Response was: {}", response))?;
+        suggestion.rationale = suggestion.rationale.lines().next().unwrap_or_default().trim().to_string();
+        Ok(suggestion)
+    }
 
-                debug!("Sending request to OpenAI API");
-                let response = client.chat().create(request).await?;
-                debug!("Received response from OpenAI API");
-                Ok(response.choices[0]
-                    .message
-                    .content
-                    .clone()
-                    .unwrap_or_else(|| "No response available.".to_string()))
-            },
-            // No available clients
-            _ => {
-                info!("No AI provider available");
-                Err(anyhow::anyhow!("No AI provider available. Please set ANTHROPIC_API_KEY or OPENAI_API_KEY environment variable."))
-            },
-        }
+    /// Suggest a short, descriptive branch name for work in progress
+    ///
+    /// The suggestion is the model's raw answer; run it through
+    /// `git::sanitize_branch_name` before using it as a ref.
+    pub async fn suggest_branch_name(&self, diff: &Diff<'_>) -> Result<String> {
+        let (files, _) = self.render(diff)?;
+        let diff_text = patch::truncate_to_tokens(&patch::join_files(&files), self.max_input_tokens);
+
+        let prompt = "You are a helpful AI that names git branches. \
+            Given the changes being worked on, reply with a single kebab-case branch name of two to five words \
+            that describes the work, such as fix-auth-token-refresh or add-csv-export. \
+            Use only lowercase letters, digits and hyphens, with no prefix, quotes or other text.";
+
+        let response = self.generate_text(prompt, &format!("Suggest a branch name for these changes:\n```\n{}\n```", diff_text)).await?;
+        Ok(response.trim().lines().next().unwrap_or_default().trim_matches(['`', '"', '\'']).to_string())
     }
 
-    /// Summarize a git diff using AI
-    pub async fn summarize_diff(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<String> {
-        let mut diff_text = String::new();
-        diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
-            use git2::DiffLineType::*;
-            match line.origin_value() {
-                Addition => diff_text.push_str(&format!("+{}", String::from_utf8_lossy(line.content()))),
-                Deletion => diff_text.push_str(&format!("-{}", String::from_utf8_lossy(line.content()))),
-                Context => diff_text.push_str(&format!(" {}", String::from_utf8_lossy(line.content()))),
-                _ => (),
-            }
-            true
-        })?;
+    /// Propose a merged version of a conflicted file
+    ///
+    /// Returns the full proposed file content. The result is an unverified
+    /// suggestion and must be reviewed before use.
+    pub async fn propose_resolution(&self, conflict: &Conflict) -> Result<String> {
+        let prompt = "You are an expert at resolving git merge conflicts. \
+            Given the common ancestor and both sides of a conflicted file, produce the merged file \
+            that preserves the intent of both sides. \
+            Output only the complete merged file content, with no conflict markers, explanations or code fences.";
 
-        let base_prompt = "You are a helpful AI that summarizes git diffs. Focus on the key changes and their implications. Be concise but informative.";
-        let prompt = if let Some(custom) = custom_prompt {
-            format!("{}. Additional instruction: {}", base_prompt, custom)
-        } else {
-            base_prompt.to_string()
-        };
+        let side = |content: &Option<String>| content.clone().unwrap_or_else(|| "(file does not exist)".to_string());
+        let response = self.generate_text(
+            prompt,
+            &format!(
+                "File: {}\n\nCommon ancestor:\n```\n{}\n```\n\nOurs (current branch):\n```\n{}\n```\n\nTheirs (incoming):\n```\n{}\n```",
+                conflict.path,
+                side(&conflict.ancestor),
+                side(&conflict.ours),
+                side(&conflict.theirs),
+            ),
+        ).await?;
 
-        self.generate_text(&prompt, &format!("Please summarize this git diff:\n```\n{}\n```", diff_text)).await
+        Ok(strip_code_fence(&response))
     }
 
-    /// Generate a commit message for the given diff
-    pub async fn generate_commit_message(&self, diff: &Diff<'_>) -> Result<String> {
-        let mut changes = String::new();
-        diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
-            if let Some(path) = delta.new_file().path() {
-                match line.origin_value() {
-                    git2::DiffLineType::Addition => changes.push_str(&format!("+ {} ({})\n", String::from_utf8_lossy(line.content()), path.display())),
-                    git2::DiffLineType::Deletion => changes.push_str(&format!("- {} ({})\n", String::from_utf8_lossy(line.content()), path.display())),
-                    _ => (),
-                }
-            }
-            true
-        })?;
+    /// Narrate how a piece of code evolved and why, from the commits that
+    /// last touched it
+    ///
+    /// `commits` pairs each commit message with that commit's diff of the
+    /// file, oldest first.
+    pub async fn summarize_blame(&self, path: &str, commits: &[(String, Diff<'_>)]) -> Result<String> {
+        let prompt = "You are an expert software archaeologist. Given the commits that shaped a piece of code, \
+            oldest first, explain how the code evolved and why: what each change did, the reasons given or implied, \
+            and how the current shape of the code came about. Write a concise narrative, not a list of commits.";
 
-        if changes.is_empty() {
-            return Ok("No changes detected.".to_string());
+        // Share the input budget between the commits
+        let budget = (self.max_input_tokens / commits.len().max(1)).max(1);
+        let mut history = Vec::new();
+        for (message, diff) in commits {
+            let (files, _) = self.render(diff)?;
+            let diff_text = patch::truncate_to_tokens(&patch::join_files(&files), budget);
+            history.push(format!("Commit message:\n{}\n\nDiff:\n```\n{}\n```", message.trim(), diff_text));
         }
 
-        let prompt = "You are a helpful AI that generates git commit messages. Follow these rules strictly:\n\
-                     1. Format must be:\n\
-                        - First line: Short summary in imperative mood, max 50 chars\n\
-                        - Blank line\n\
-                        - Detailed description wrapped at 72 chars\n\
-                     2. First line must:\n\
-                        - Use imperative mood ('Add' not 'Added')\n\
-                        - Not end with a period\n\
-                        - Be max 50 characters\n\
-                        - Accurately describe the main change in the diff\n\
-                     3. Description must:\n\
-                        - Start with a blank line after the summary\n\
-                        - Explain WHY the changes in the diff were made\n\
-                        - Wrap text at 72 characters\n\
-                        - Use proper punctuation\n\
-                        - Be specific to the actual changes shown\n\
-                        - Include affected files or components";
+        self.generate_text(
+            prompt,
+            &format!("History of {}:\n\n{}", path, history.join("\n\n---\n\n")),
+        ).await
+    }
 
-        self.generate_text(prompt, &format!("Analyze these changes and create a commit summary:\n```\n{}\n```", changes)).await
+    /// Explain what each side of a set of conflicted files changed and
+    /// suggest how to resolve each one
+    ///
+    /// Binary conflicts should be filtered out beforehand.
+    pub async fn summarize_conflicts(&self, conflicts: &[Conflict]) -> Result<String> {
+        let prompt = "You are an expert at resolving git merge conflicts. \
+            For each conflicted file, given the common ancestor and both sides: \
+            1. Explain briefly what our side (the current branch) changed relative to the ancestor \
+            2. Explain briefly what their side (the incoming branch) changed \
+            3. Suggest how to resolve the conflict, naming which changes to keep or how to combine them \
+            Use the file path as a heading for each file.";
+
+        // Share the input budget between every side of every file
+        let side_budget = (self.max_input_tokens / (3 * conflicts.len().max(1))).max(1);
+        let side = |content: &Option<String>| match content {
+            Some(content) => patch::truncate_to_tokens(content, side_budget),
+            None => "(file does not exist)".to_string(),
+        };
+        let files: Vec<String> = conflicts.iter()
+            .map(|conflict| format!(
+                "File: {}\n\nCommon ancestor:\n```\n{}\n```\n\nOurs (current branch):\n```\n{}\n```\n\nTheirs (incoming):\n```\n{}\n```",
+                conflict.path,
+                side(&conflict.ancestor),
+                side(&conflict.ours),
+                side(&conflict.theirs),
+            ))
+            .collect();
+
+        self.generate_text(prompt, &files.join("\n\n---\n\n")).await
     }
 
     /// Analyze changes and group them by feature
     pub async fn analyze_changes(&self, staged_diff: &Diff<'_>, unstaged_diff: &Diff<'_>, prompt: Option<&str>) -> Result<Vec<Vec<String>>> {
         let mut all_changes = String::new();
-        
+        let repo = self.render_options.repository();
+
         // Helper function to format diff
         let mut format_diff = |diff: &Diff<'_>, prefix: &str| -> Result<()> {
             diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
                 if let Some(path) = delta.new_file().path() {
                     match line.origin_value() {
-                        git2::DiffLineType::Addition => all_changes.push_str(&format!("{} +{} ({})\n", prefix, String::from_utf8_lossy(line.content()), path.display())),
-                        git2::DiffLineType::Deletion => all_changes.push_str(&format!("{} -{} ({})\n", prefix, String::from_utf8_lossy(line.content()), path.display())),
+                        git2::DiffLineType::FileHeader if patch::is_submodule(&delta) => {
+                            all_changes.push_str(&format!("{} {}", prefix, patch::submodule_note(&delta, repo.as_ref())));
+                        }
+                        _ if patch::is_submodule(&delta) => (),
+                        git2::DiffLineType::FileHeader if delta.flags().is_binary() => {
+                            all_changes.push_str(&format!("{} {}", prefix, patch::binary_note(&delta)));
+                        }
+                        _ if delta.flags().is_binary() => (),
+                        git2::DiffLineType::FileHeader => if let Some(note) = patch::rename_note(&delta) {
+                            all_changes.push_str(&format!("{} {}", prefix, note));
+                        },
+                        git2::DiffLineType::Addition => all_changes.push_str(&format!("{} +{} ({})\n", prefix, patch::decode_line(line.content()).0, path.display())),
+                        git2::DiffLineType::Deletion => all_changes.push_str(&format!("{} -{} ({})\n", prefix, patch::decode_line(line.content()).0, path.display())),
                         _ => (),
                     }
                 }
@@ -341,41 +2602,746 @@ impl AiEngine {
             - It's better to group too much than too little \
             - Only split if it would be IMPOSSIBLE to describe the changes together \
             \
-            IMPORTANT: Your response must be a valid JSON array where each element is an array of file paths. \
-            Example response format: [[\"file1.rs\", \"file2.rs\", \"test1.rs\", \"mod.rs\", \"config.toml\", \"docs.md\"]] \
+            IMPORTANT: Your response must be a valid JSON object with a \"groups\" array, each group listing its file paths under \"files\". \
+            Example response format: {\"groups\": [{\"files\": [\"file1.rs\", \"file2.rs\", \"test1.rs\", \"mod.rs\", \"config.toml\", \"docs.md\"]}]} \
             Note how the example shows everything in ONE group - this is what we usually want! \
-            Only output the JSON array, no other text or explanations.";
+            Only output the JSON object, no other text or explanations.";
 
-        let response = self.generate_text(
+        self.generate_parsed_json(
             default_prompt,
             &format!("Group these changes by feature (custom focus: {}):\n```\n{}\n```",
                 prompt.unwrap_or("none"),
-                all_changes)
-        ).await?;
+                all_changes),
+            parse_change_groups,
+        ).await
+    }
+}
+
+/// Build the system prompt for Conventional Commits messages
+fn conventional_commit_prompt(scope: Option<&str>) -> String {
+    let scope_rule = match scope {
+        Some(scope) => format!("Use `{}` as the scope, e.g. `feat({}): ...`.", scope, scope),
+        None => "Changes span the repository root, so omit the scope, e.g. `feat: ...`.".to_string(),
+    };
+
+    format!(
+        "You are a helpful AI that generates git commit messages following the Conventional Commits specification. \
+         Follow these rules strictly:\n\
+         1. First line must be `<type>(<scope>): <subject>` where:\n\
+            - type is one of {}\n\
+            - {}\n\
+            - subject uses imperative mood, is lowercase, has no trailing period\n\
+            - the whole line is max {} characters\n\
+         2. Then a blank line and a body wrapped at 72 chars explaining WHY the change was made\n\
+         3. If the change is breaking (e.g. public items removed or renamed, signatures changed, \
+            behavior changed incompatibly):\n\
+            - Add `!` before the colon, e.g. `feat(api)!: ...`\n\
+            - End the message with a footer `BREAKING CHANGE: <description of what breaks>`\n\
+         4. Output only the commit message, no other text",
+        lint::CONVENTIONAL_TYPES.join(", "),
+        scope_rule,
+        lint::CONVENTIONAL_SUBJECT_LENGTH
+    )
+}
+
+/// Instructions for starting the subject with a gitmoji from the standard set
+fn gitmoji_prompt() -> String {
+    let table = GITMOJI.iter()
+        .map(|(emoji, code, meaning)| format!("{} {} {}", emoji, code, meaning))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "4. Start the first line with exactly one gitmoji followed by a space, chosen from this table \
+         for the nature of the change (emoji, code, meaning):\n{}\n\
+         Use only emoji from the table. An emoji is as wide as two characters, so the first line \
+         including the emoji and space must still be at most 50 characters: keep the text after it to 47.",
+        table
+    )
+}
+
+/// Replace a leading gitmoji shortcode such as `:bug:` with its emoji
+fn expand_gitmoji_shortcode(message: &str) -> String {
+    let trimmed = message.trim_start();
+    for (emoji, code, _) in GITMOJI {
+        if let Some(rest) = trimmed.strip_prefix(code) {
+            return format!("{}{}", emoji, rest);
+        }
+    }
+    message.to_string()
+}
+
+/// Infer a Conventional Commits scope from the most common top-level
+/// directory among the changed paths
+fn infer_scope(paths: &[String]) -> Option<String> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for path in paths {
+        let Some((top, _)) = path.split_once('/') else { continue };
+        match counts.iter_mut().find(|(dir, _)| *dir == top) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((top, 1)),
+        }
+    }
+    // Earlier directories win ties so the result is stable
+    counts.iter()
+        .fold(None, |best: Option<(&str, usize)>, &(dir, count)| match best {
+            Some((_, best_count)) if best_count >= count => best,
+            _ => Some((dir, count)),
+        })
+        .map(|(dir, _)| dir.to_string())
+}
+
+/// The deepest directory containing every path, if any
+fn common_directory(paths: &[String]) -> Option<String> {
+    let mut common: Vec<&str> = paths.first()?.split('/').collect();
+    common.pop();
+    for path in &paths[1..] {
+        let dirs: Vec<&str> = path.split('/').collect();
+        let shared = common.iter()
+            .zip(&dirs[..dirs.len() - 1])
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(shared);
+    }
+    (!common.is_empty()).then(|| common.join("/"))
+}
+
+/// Drop commit messages that are near-identical to an earlier one
+///
+/// Messages are compared by their subject line, ignoring case, punctuation
+/// and whitespace.
+fn dedupe_messages(messages: &[String]) -> Vec<String> {
+    let normalize = |message: &str| -> String {
+        message.lines().next().unwrap_or("")
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    messages.iter()
+        .filter(|message| seen.insert(normalize(message)))
+        .cloned()
+        .collect()
+}
+
+
+/// Append a hint for the diff's dominant language, if it has one
+fn with_language_hint(prompt: String, diff: &Diff<'_>) -> String {
+    match language::detect_language(diff) {
+        Some(language) => format!("{}\n\n{}", prompt, language.prompt_hint()),
+        None => prompt,
+    }
+}
+
+/// Whether a failed request might succeed with a different model
+fn should_fall_back(error: &anyhow::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<OpenAIError>() {
+        return retry::calls_for_other_model(error);
+    }
+    if error.downcast_ref::<RequestTimedOut>().is_some() {
+        return true;
+    }
+    match error.downcast_ref::<AnthropicError>() {
+        Some(AnthropicError(message)) => ["overloaded_error", "rate_limit_error", "not_found_error"].iter().any(|kind| message.contains(kind)),
+        None => false,
+    }
+}
+
+/// Whether an error came from the AI provider: no provider configured, a
+/// request that failed or timed out, or an error response such as a
+/// rejected API key
+pub fn is_provider_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<ProviderUnavailable>()
+            || cause.is::<RequestTimedOut>()
+            || cause.is::<OpenAIError>()
+            || cause.is::<AnthropicError>()
+    })
+}
+
+/// Await an API call, giving up with `RequestTimedOut` after `limit`
+async fn within_timeout<T>(limit: Duration, call: impl Future<Output = T>) -> Result<T> {
+    tokio::time::timeout(limit, call).await.map_err(|_| RequestTimedOut(limit.as_secs()).into())
+}
+
+/// Check an OpenAI-compatible API base URL, e.g. `https://openrouter.ai/api/v1`
+fn parse_base_url(url: &str) -> Result<String> {
+    let url = url.trim().trim_end_matches('/');
+    if url.is_empty() {
+        return Err(anyhow::anyhow!("The OpenAI base URL is empty"));
+    }
+    let host = url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .and_then(|rest| rest.split('/').next())
+        .filter(|host| !host.is_empty() && !host.contains(char::is_whitespace));
+    match host {
+        Some(_) if !url.contains(char::is_whitespace) => Ok(url.to_string()),
+        _ => Err(anyhow::anyhow!("'{}' is not a valid base URL; expected e.g. https://host/v1", url)),
+    }
+}
+
+/// Build the user message asking for a summary of diff text
+fn summary_user_message(template: Option<&str>, diff_text: &str) -> String {
+    match template {
+        Some(template) => template.replace(DIFF_PLACEHOLDER, diff_text),
+        None => format!("Please summarize this git diff:\n```\n{}\n```", diff_text),
+    }
+}
+
+/// Append notes about omitted content to the end of a summary
+fn append_notes(mut summary: String, notes: &[String]) -> String {
+    for note in notes {
+        summary.push_str("\n\n");
+        summary.push_str(note);
+    }
+    summary
+}
+
+/// Remove a Markdown code fence wrapped around a whole response
+fn strip_code_fence(response: &str) -> String {
+    let trimmed = response.trim();
+    if let Some(rest) = trimmed.strip_prefix("```") {
+        if let Some(body) = rest.strip_suffix("```") {
+            // Drop the language tag on the opening fence line
+            let body = body.split_once('\n').map_or("", |(_, body)| body);
+            return body.to_string();
+        }
+    }
+    let mut text = response.to_string();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+/// Parse the files returned by `categorize_files`
+fn parse_categorized_files(response: &str) -> Result<Vec<CategorizedFile>> {
+    let parsed: CategorizedFiles = serde_json::from_str(extract_json(response))
+        .with_context(|| format!("Failed to parse AI response as JSON file categories. Response was: {}", response))?;
+    Ok(parsed.files)
+}
+
+/// `old -> new` labels for renamed files, keyed by the new path
+fn rename_labels(diff: &Diff<'_>) -> std::collections::HashMap<String, String> {
+    diff.deltas()
+        .filter(|delta| delta.status() == git2::Delta::Renamed)
+        .filter_map(|delta| {
+            let old = delta.old_file().path()?.display().to_string();
+            let new = delta.new_file().path()?.display().to_string();
+            Some((new.clone(), format!("{} -> {}", old, new)))
+        })
+        .collect()
+}
+
+/// Parse the findings returned by `review_findings`
+fn parse_review_findings(response: &str) -> Result<Vec<ReviewFinding>> {
+    let parsed: ReviewFindings = serde_json::from_str(extract_json(response))
+        .with_context(|| format!("Failed to parse AI response as JSON review findings. Response was: {}", response))?;
+    Ok(parsed.findings)
+}
+
+/// Parse the file groups returned by `analyze_changes`
+fn parse_change_groups(response: &str) -> Result<Vec<Vec<String>>> {
+    let parsed: ChangeGroups = serde_json::from_str(extract_json(response))
+        .with_context(|| format!("Failed to parse AI response as JSON file groups. Response was: {}", response))?;
+    Ok(parsed.groups.into_iter().map(|group| group.files).collect())
+}
 
-        // Try to parse the response
-        let groups: Vec<Vec<String>> = serde_json::from_str(&response)
-            .with_context(|| format!("Failed to parse AI response as JSON array of file groups. Response was: {}", response))?;
+/// Parse the messages returned by `generate_commit_messages`, which must
+/// have one message per commit
+fn parse_commit_messages(response: &str, expected: usize) -> Result<Vec<String>> {
+    let parsed: CommitMessages = serde_json::from_str(extract_json(response))
+        .with_context(|| format!("Failed to parse AI response as JSON commit messages. Response was: {}", response))?;
+    if parsed.messages.len() != expected {
+        return Err(anyhow::anyhow!("Expected {} commit messages but the model returned {}", expected, parsed.messages.len()));
+    }
+    Ok(parsed.messages.into_iter().map(|message| message.trim().to_string()).collect())
+}
+
+/// Parse the choice returned by `pick_commit_message` into a 0-based index
+/// and the model's reason
+fn parse_commit_pick(response: &str, candidates: usize) -> Result<(usize, String)> {
+    let parsed: CommitPick = serde_json::from_str(extract_json(response))
+        .with_context(|| format!("Failed to parse AI response as a JSON commit message choice. Response was: {}", response))?;
+    if parsed.best == 0 || parsed.best > candidates {
+        return Err(anyhow::anyhow!("The model picked candidate {} but there are only {}", parsed.best, candidates));
+    }
+    Ok((parsed.best - 1, parsed.reason.trim().to_string()))
+}
+
+/// Parse the themes returned by `cluster_commits`, keeping only known
+/// hashes, each in its first theme and in the order of `hashes`
+fn parse_commit_themes(response: &str, hashes: &[&str]) -> Result<Vec<CommitTheme>> {
+    let parsed: CommitThemes = serde_json::from_str(extract_json(response))
+        .with_context(|| format!("Failed to parse AI response as JSON commit themes. Response was: {}", response))?;
+
+    let mut placed = std::collections::HashSet::new();
+    let mut themes = Vec::new();
+    for theme in parsed.themes {
+        let members: std::collections::HashSet<&str> = theme.commits.iter().map(String::as_str).collect();
+        let commits: Vec<String> = hashes.iter()
+            .filter(|hash| members.contains(**hash) && placed.insert(**hash))
+            .map(|hash| hash.to_string())
+            .collect();
+        if !commits.is_empty() {
+            themes.push(CommitTheme { title: theme.title.trim().to_string(), commits });
+        }
+    }
+
+    let rest: Vec<String> = hashes.iter().filter(|hash| !placed.contains(**hash)).map(|hash| hash.to_string()).collect();
+    if !rest.is_empty() {
+        themes.push(CommitTheme { title: "Other changes".to_string(), commits: rest });
+    }
+    Ok(themes)
+}
 
-        Ok(groups)
+/// Extract the outermost JSON object or array from a model response,
+/// ignoring any surrounding prose or code fences
+fn extract_json(response: &str) -> &str {
+    let start = response.find(['{', '[']);
+    let end = response.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => &response[start..=end],
+        _ => response.trim(),
+    }
+}
+
+/// Build the system prompt for diff summaries, starting from `base_prompt`
+/// if one is configured
+fn summary_prompt(base_prompt: Option<&str>, custom_prompt: Option<&str>) -> String {
+    let base_prompt = base_prompt.unwrap_or("You are a helpful AI that summarizes git diffs. Focus on the key changes and their implications. Be concise but informative.");
+    if let Some(custom) = custom_prompt {
+        format!("{}. Additional instruction: {}", base_prompt, custom)
+    } else {
+        base_prompt.to_string()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use backend::ScriptedBackend;
     use git2::Repository;
     use tempfile::TempDir;
 
+    fn scripted_engine(responses: &[&str]) -> (AiEngine, Arc<ScriptedBackend>) {
+        let backend = Arc::new(ScriptedBackend::new(responses.iter().copied()));
+        (AiEngine::from_backend(backend.clone()), backend)
+    }
+
+    /// A diff adding each `(path, contents)` file at the top of a new repository
+    fn added_files_diff<'r>(repo: &'r Repository, files: &[(&str, &str)]) -> Diff<'r> {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (path, contents) in files {
+            builder.insert(path, repo.blob(contents.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        repo.diff_tree_to_tree(None, Some(&tree), None).unwrap()
+    }
+
     #[tokio::test]
     async fn test_diff_summary() {
-        let engine = AiEngine::new().unwrap();
+        let (engine, backend) = scripted_engine(&["Adds a greeting."]);
         let temp_dir = TempDir::new().unwrap();
         let repo = Repository::init(temp_dir.path()).unwrap();
-        
-        // Create an empty diff
-        let diff = repo.diff_tree_to_tree(None, None, None).unwrap();
+        let diff = added_files_diff(&repo, &[("hello.txt", "hello\n")]);
+
+        let summary = engine.summarize_diff(&diff, None).await.unwrap();
+        assert_eq!(summary, "Adds a greeting.");
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].user_message().contains("diff --git a/hello.txt b/hello.txt\n@@ -0,0 +1 @@\n+hello\n"));
+        assert!(requests[0].system_prompt.contains(LINE_REFERENCES_PROMPT));
+
+        let (engine, backend) = scripted_engine(&["Adds a greeting."]);
+        engine.with_line_references(false).summarize_diff(&diff, None).await.unwrap();
+        let request = &backend.requests()[0];
+        assert!(request.user_message().contains("```\n+hello\n"));
+        assert!(!request.system_prompt.contains(LINE_REFERENCES_PROMPT));
+    }
+
+    #[test]
+    fn test_trivial_diffs() {
+        let (engine, _) = scripted_engine(&[]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert!(engine.is_trivial(&added_files_diff(&repo, &[("a.txt", "typo\n")])).unwrap());
+        assert!(!engine.is_trivial(&added_files_diff(&repo, &[("a.txt", &"line\n".repeat(5))])).unwrap());
+        assert!(!engine.is_trivial(&added_files_diff(&repo, &[("a.txt", "a\n"), ("b.txt", "b\n")])).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_diff_is_summarized_in_chunks() {
+        let (engine, backend) = scripted_engine(&["First half.", "Second half.", "The whole change."]);
+        let engine = engine.with_max_input_tokens(40);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("a.txt", &"a\n".repeat(40)), ("b.txt", &"b\n".repeat(40))]);
+
         let summary = engine.summarize_diff(&diff, None).await.unwrap();
-        assert!(summary.contains("No summary available."));
+        assert_eq!(summary, "The whole change.");
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].user_message().contains("part 1 of 2"));
+        assert!(requests[0].user_message().contains("@@ -0,0 +1,40 @@"));
+        assert!(requests[2].user_message().contains("First half.\n\n---\n\nSecond half."));
+    }
+
+    #[tokio::test]
+    async fn test_summary_result_reports_trimming() {
+        let (engine, _) = scripted_engine(&["A small change.", "The gist of many files."]);
+        let engine = engine.with_max_input_tokens(40);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        let result = engine.summarize_diff_result(&added_files_diff(&repo, &[("a.txt", "a\n")]), None).await.unwrap();
+        assert_eq!(result.text, "A small change.");
+        assert_eq!(result.model, engine.model_name());
+        assert!(result.usage.prompt_tokens > 0 && result.usage.completion_tokens > 0);
+        assert!(!result.truncated);
+
+        let contents: Vec<(String, String)> = (0..10).map(|i| (format!("{}.txt", i), "x\n".repeat(40))).collect();
+        let files: Vec<(&str, &str)> = contents.iter().map(|(path, text)| (path.as_str(), text.as_str())).collect();
+        let result = engine.summarize_diff_result(&added_files_diff(&repo, &files), None).await.unwrap();
+        assert_eq!(result.text, "The gist of many files.");
+        assert!(result.truncated);
+
+        // Two chunks whose partial summaries are too long to merge whole
+        let long = "Changes a lot. ".repeat(40);
+        let (engine, backend) = scripted_engine(&[&long, &long, "Both halves."]);
+        let engine = engine.with_max_input_tokens(40);
+        let diff = added_files_diff(&repo, &[("a.txt", &"a\n".repeat(40)), ("b.txt", &"b\n".repeat(40))]);
+        let result = engine.summarize_diff_result(&diff, None).await.unwrap();
+        assert_eq!(result.text, "Both halves.");
+        assert!(result.truncated);
+        assert!(backend.requests()[2].user_message().ends_with("(truncated)"));
+    }
+
+    #[tokio::test]
+    async fn test_review_findings() {
+        let response = r#"{"findings": [
+            {"file": "lib.rs", "line": 2, "severity": "error", "message": "Divides by zero"},
+            {"file": "lib.rs", "severity": "info", "message": "Name is unclear"}
+        ]}"#;
+        let (engine, backend) = scripted_engine(&[response]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("lib.rs", "fn f(x: u32) -> u32 {\n    1 / x\n}\n")]);
+
+        let findings = engine.review_findings(&diff, None).await.unwrap();
+        assert_eq!(findings, vec![
+            ReviewFinding { file: "lib.rs".to_string(), line: Some(2), severity: Severity::Error, message: "Divides by zero".to_string() },
+            ReviewFinding { file: "lib.rs".to_string(), line: None, severity: Severity::Info, message: "Name is unclear".to_string() },
+        ]);
+        assert!(findings.iter().any(|finding| finding.severity >= Severity::Warning));
+        assert!(backend.requests()[0].user_message().contains("File: lib.rs\n"));
+        assert!(parse_review_findings(r#"{"findings": [{"file": "a.rs", "severity": "fatal", "message": "?"}]}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_categorize_files() {
+        let response = r#"{"files": [
+            {"path": "lib.rs", "category": "fix", "summary": "Handles empty input."},
+            {"path": "README.md", "category": "marketing", "summary": "Rewords the intro."}
+        ]}"#;
+        let (engine, backend) = scripted_engine(&[response]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("README.md", "# Demo\n"), ("lib.rs", "fn f() {}\n"), ("notes.txt", "todo\n")]);
+
+        let files = engine.categorize_files(&diff, None).await.unwrap();
+        let categories: Vec<(&str, ChangeCategory)> = files.iter().map(|file| (file.path.as_str(), file.category)).collect();
+        assert_eq!(categories, vec![
+            ("README.md", ChangeCategory::Other),
+            ("lib.rs", ChangeCategory::Fix),
+            ("notes.txt", ChangeCategory::Other),
+        ]);
+        assert_eq!(files[2].summary, "(no summary returned)");
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_fallback_error_kinds() {
+        let api_error = |kind: &str, code: &str| anyhow::Error::from(OpenAIError::ApiError(async_openai::error::ApiError {
+            message: String::new(),
+            r#type: Some(kind.to_string()),
+            param: None,
+            code: Some(serde_json::json!(code)),
+        }));
+        assert!(should_fall_back(&api_error("insufficient_quota", "insufficient_quota")));
+        assert!(should_fall_back(&api_error("invalid_request_error", "model_not_found")));
+        assert!(should_fall_back(&api_error("server_error", "")));
+        assert!(should_fall_back(&api_error("requests", "rate_limit_exceeded")));
+        assert!(!should_fall_back(&api_error("invalid_request_error", "invalid_api_key")));
+        assert!(should_fall_back(&RequestTimedOut(60).into()));
+        assert!(should_fall_back(&AnthropicError("overloaded_error".to_string()).into()));
+        assert!(!should_fall_back(&AnthropicError("invalid_request_error".to_string()).into()));
+        assert!(!should_fall_back(&anyhow::anyhow!("Anthropic API error: overloaded_error")));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_json_is_asked_for_again() {
+        let (engine, backend) = scripted_engine(&["Sure! The groups are a.rs and b.rs", r#"{"groups": [{"files": ["a.rs"]}]}"#]);
+        let groups = engine.generate_parsed_json("Group the files", "a.rs", parse_change_groups).await.unwrap();
+        assert_eq!(groups, vec![vec!["a.rs".to_string()]]);
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].messages[1], ChatMessage::assistant("Sure! The groups are a.rs and b.rs"));
+        assert_eq!(requests[1].user_message(), JSON_RETRY_MESSAGE);
+
+        let (engine, _) = scripted_engine(&["not json", "still not json"]);
+        assert!(engine.generate_parsed_json("Group the files", "a.rs", parse_change_groups).await.is_err());
+    }
+
+    #[test]
+    fn test_model_params() {
+        let params = ModelParams::from_json(r#"{"top_p": 0.5, "stop": ["END"]}"#).unwrap();
+        let mut request = CreateChatCompletionRequest { top_p: Some(1.0), presence_penalty: Some(0.1), ..Default::default() };
+        params.apply(&mut request);
+        assert_eq!(request.top_p, Some(0.5));
+        assert_eq!(request.presence_penalty, Some(0.1));
+        assert_eq!(request.stop, Some(Stop::StringArray(vec!["END".to_string()])));
+
+        let error = format!("{:#}", ModelParams::from_json(r#"{"top_k": 5}"#).unwrap_err());
+        assert!(error.contains("unknown field `top_k`"), "{}", error);
+    }
+
+    #[test]
+    fn test_pr_description_from_markdown() {
+        let pr = PrDescription::from_markdown("# Add login\n\n## Summary\nAdds login.\n");
+        assert_eq!(pr.title, "Add login");
+        assert_eq!(pr.body, "## Summary\nAdds login.");
+        assert_eq!(pr.to_markdown(), "# Add login\n\n## Summary\nAdds login.");
+    }
+
+    #[test]
+    fn test_dedupe_messages() {
+        let messages = vec![
+            "Fix login redirect".to_string(),
+            "fix login redirect.".to_string(),
+            "Add logout button\n\nDetails".to_string(),
+            "Fix  Login Redirect!".to_string(),
+        ];
+        assert_eq!(dedupe_messages(&messages), vec![
+            "Fix login redirect".to_string(),
+            "Add logout button\n\nDetails".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_request_times_out() {
+        let never_responds = std::future::pending::<Result<String>>();
+        let error = within_timeout(Duration::from_millis(10), never_responds).await.unwrap_err();
+        assert!(error.downcast_ref::<RequestTimedOut>().is_some());
+        assert_eq!(within_timeout(Duration::from_secs(1), async { 42 }).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_commit_message_mentions_untracked_files() {
+        let (engine, backend) = scripted_engine(&["Register the parser module"]);
+        let engine = engine.with_untracked_files(vec!["src/parser.rs".to_string()]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("lib.rs", "mod parser;\n")]);
+
+        engine.generate_commit_message(&diff, CommitStyle::Freeform).await.unwrap();
+        let request = &backend.requests()[0];
+        assert!(request.system_prompt.ends_with(TRIVIAL_COMMIT_PROMPT));
+        assert!(request.user_message().contains("+ mod parser;"));
+        assert!(request.user_message().contains("not part of this commit, but the changes may refer to them:\n- src/parser.rs\n"));
+    }
+
+    #[tokio::test]
+    async fn test_detail_levels() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("hello.txt", "hello\n")]);
+        let brief = Detail::Brief.prompt_fragment().unwrap();
+        let detailed = Detail::Detailed.prompt_fragment().unwrap();
+
+        let (engine, backend) = scripted_engine(&["Adds a greeting."]);
+        let engine = engine.with_detail(Detail::Brief);
+        assert_eq!(engine.max_tokens, BRIEF_MAX_TOKENS);
+        engine.summarize_diff(&diff, None).await.unwrap();
+        assert!(backend.requests()[0].system_prompt.contains(brief));
+
+        let (engine, backend) = scripted_engine(&["## Greeting\nAdds hello.txt."]);
+        let engine = engine.with_detail(Detail::Detailed);
+        assert_eq!(engine.max_tokens, DEFAULT_MAX_TOKENS);
+        engine.summarize_diff(&diff, None).await.unwrap();
+        let prompt = &backend.requests()[0].system_prompt;
+        assert!(prompt.contains(detailed) && !prompt.contains(brief));
+
+        let (engine, backend) = scripted_engine(&["Adds a greeting."]);
+        engine.with_detail(Detail::Normal).summarize_diff(&diff, None).await.unwrap();
+        let prompt = &backend.requests()[0].system_prompt;
+        assert!(!prompt.contains(brief) && !prompt.contains(detailed));
+    }
+
+    #[tokio::test]
+    async fn test_response_language() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("lib.rs", "mod parser;\n")]);
+        let language = "natural language with code `es`";
+
+        let (engine, backend) = scripted_engine(&["Registra el módulo del analizador.", "feat(parser): registra el módulo"]);
+        let engine = engine.with_response_language(" es ");
+        engine.summarize_diff(&diff, None).await.unwrap();
+        engine.generate_commit_message(&diff, CommitStyle::Conventional).await.unwrap();
+        let requests = backend.requests();
+        assert!(requests[0].system_prompt.contains(language));
+        assert!(!requests[0].system_prompt.contains("Conventional Commits type"));
+        assert!(requests[1].system_prompt.contains(language));
+        assert!(requests[1].system_prompt.contains("Keep the Conventional Commits type"));
+
+        // English is the default, so asking for it adds nothing
+        let (engine, backend) = scripted_engine(&["Registers the parser module."]);
+        engine.with_response_language("EN").summarize_diff(&diff, None).await.unwrap();
+        assert!(!backend.requests()[0].system_prompt.contains("natural language with code"));
+    }
+
+    #[tokio::test]
+    async fn test_commit_message_matches_examples() {
+        let (engine, backend) = scripted_engine(&["parser: register module"]);
+        let engine = engine.with_commit_examples(vec!["lexer: handle tabs\n".to_string(), "docs: fix typo".to_string()]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("lib.rs", "mod parser;\n")]);
+
+        engine.generate_commit_message(&diff, CommitStyle::Freeform).await.unwrap();
+        let prompt = &backend.requests()[0].system_prompt;
+        assert!(prompt.contains("Match the tone, tense and structure"));
+        assert!(prompt.contains("```\nlexer: handle tabs\n```\n```\ndocs: fix typo\n```"));
+    }
+
+    #[tokio::test]
+    async fn test_commit_message_candidates() {
+        let (engine, backend) = scripted_engine(&[
+            "Fix parser",
+            "Fix parser",
+            "Fix parser crash on empty input",
+            "{\"best\": 2, \"reason\": \"It names the crash\"}",
+        ]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let diff = added_files_diff(&repo, &[("parser.rs", "if input.is_empty() {\n    return None;\n}\n")]);
+
+        let candidates = engine.generate_commit_message_candidates(&diff, CommitStyle::Freeform, 3).await.unwrap();
+        assert_eq!(candidates, vec!["Fix parser", "Fix parser crash on empty input"]);
+        assert_eq!(engine.pick_commit_message(&diff, &candidates).await.unwrap(), (1, "It names the crash".to_string()));
+        let request = &backend.requests()[3];
+        assert!(request.system_prompt.contains("\"best\""));
+        assert!(request.user_message().contains("=== Candidate 2 ===\nFix parser crash on empty input"));
+
+        assert!(parse_commit_pick("{\"best\": 3}", 2).is_err());
+    }
+
+    #[test]
+    fn test_diff_size_limit() {
+        let oversized = "x".repeat(11);
+        let (engine, _) = scripted_engine(&[]);
+        let engine = engine.with_max_diff_bytes(10);
+        assert!(engine.check_diff_size(&oversized).is_ok());
+        assert!(engine.needs_chunking(&oversized));
+
+        let engine = engine.with_chunking(false);
+        let error = engine.check_diff_size(&oversized).unwrap_err();
+        assert!(error.downcast_ref::<DiffTooLarge>().is_some());
+        assert!(!engine.needs_chunking(&oversized));
+    }
+
+    #[test]
+    fn test_parse_base_url() {
+        assert_eq!(parse_base_url("https://openrouter.ai/api/v1/").unwrap(), "https://openrouter.ai/api/v1");
+        assert_eq!(parse_base_url("http://localhost:8080/v1").unwrap(), "http://localhost:8080/v1");
+        assert!(parse_base_url("").unwrap_err().to_string().contains("empty"));
+        assert!(parse_base_url("openrouter.ai/api/v1").is_err());
+        assert!(parse_base_url("https:///v1").is_err());
+    }
+
+    #[test]
+    fn test_extract_json() {
+        assert_eq!(extract_json("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(extract_json("Here you go: [[\"a.rs\"]]"), "[[\"a.rs\"]]");
+        assert_eq!(extract_json(" no json "), "no json");
+    }
+
+    #[test]
+    fn test_parse_change_groups() {
+        let expected = vec![vec!["src/lib.rs".to_string(), "README.md".to_string()], vec!["ci.yml".to_string()]];
+        let json = r#"{"groups": [{"files": ["src/lib.rs", "README.md"]}, {"files": ["ci.yml"]}]}"#;
+        assert_eq!(parse_change_groups(json).unwrap(), expected);
+        let fenced = format!("Here are the groups:\n```json\n{}\n```", json);
+        assert_eq!(parse_change_groups(&fenced).unwrap(), expected);
+        assert!(parse_change_groups("[[\"src/lib.rs\"]]").is_err());
+    }
+
+    #[test]
+    fn test_parse_commit_messages() {
+        let response = r#"{"messages": ["Add parser\n\nHandles nesting.", " Fix typo "]}"#;
+        assert_eq!(parse_commit_messages(response, 2).unwrap(), vec!["Add parser\n\nHandles nesting.", "Fix typo"]);
+        assert!(parse_commit_messages(response, 3).is_err());
+    }
+
+    #[test]
+    fn test_parse_commit_themes() {
+        let response = r#"{"themes": [
+            {"title": "CI fixes", "commits": ["ccc3333", "aaa1111", "fff9999"]},
+            {"title": "Authentication work", "commits": ["aaa1111"]}
+        ]}"#;
+        let themes = parse_commit_themes(response, &["aaa1111", "bbb2222", "ccc3333"]).unwrap();
+        assert_eq!(themes, vec![
+            CommitTheme { title: "CI fixes".to_string(), commits: vec!["aaa1111".to_string(), "ccc3333".to_string()] },
+            CommitTheme { title: "Other changes".to_string(), commits: vec!["bbb2222".to_string()] },
+        ]);
+    }
+
+    #[test]
+    fn test_strip_code_fence() {
+        assert_eq!(strip_code_fence("```rust\nfn main() {}\n```"), "fn main() {}\n");
+        assert_eq!(strip_code_fence("fn main() {}"), "fn main() {}\n");
+    }
+
+    #[test]
+    fn test_expand_gitmoji_shortcode() {
+        assert_eq!(expand_gitmoji_shortcode(":bug: Fix crash on empty input"), "🐛 Fix crash on empty input");
+        assert_eq!(expand_gitmoji_shortcode("✨ Add export"), "✨ Add export");
+    }
+
+    #[test]
+    fn test_offline_commit_message() {
+        let (engine, _) = scripted_engine(&[]);
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut auth = repo.treebuilder(None).unwrap();
+        auth.insert("token.rs", repo.blob(b"fn refresh() {}\n").unwrap(), 0o100644).unwrap();
+        auth.insert("session.rs", repo.blob(b"fn login() {}\nfn logout() {}\n").unwrap(), 0o100644).unwrap();
+        let mut src = repo.treebuilder(None).unwrap();
+        src.insert("auth", auth.write().unwrap(), 0o040000).unwrap();
+        let mut root = repo.treebuilder(None).unwrap();
+        root.insert("src", src.write().unwrap(), 0o040000).unwrap();
+        let tree = repo.find_tree(root.write().unwrap()).unwrap();
+
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+        let message = engine.generate_offline_commit_message(&diff, CommitStyle::Freeform).unwrap();
+        assert_eq!(message, "Add 2 files in src/auth\n\n\
+            2 files changed, 3 insertions(+), 0 deletions(-)\n\n\
+            A src/auth/session.rs\nA src/auth/token.rs");
+        let message = engine.generate_offline_commit_message(&diff, CommitStyle::Conventional).unwrap();
+        assert!(message.starts_with("chore(src): add 2 files in src/auth\n"));
+    }
+
+    #[test]
+    fn test_infer_scope() {
+        let paths = vec![
+            "src/ai/mod.rs".to_string(),
+            "src/main.rs".to_string(),
+            "docs/usage.md".to_string(),
+            "README.md".to_string(),
+        ];
+        assert_eq!(infer_scope(&paths).as_deref(), Some("src"));
+        assert_eq!(infer_scope(&["Cargo.toml".to_string()]), None);
     }
 }
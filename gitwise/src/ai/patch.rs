@@ -0,0 +1,928 @@
+use anyhow::Result;
+use git2::{AttrCheckFlags, AttrValue, Delta, Diff, DiffDelta, DiffHunk, FileMode, Oid, Repository};
+use regex::Regex;
+use similar::{ChangeTag, TextDiff};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use super::tokens;
+
+/// Rough number of characters per token used for budget estimates
+pub const CHARS_PER_TOKEN: usize = 4;
+/// Changed lines of each file kept when a diff is trimmed to budget
+const TRIM_PREVIEW_LINES: usize = 12;
+/// Commit subjects listed for a submodule update
+const SUBMODULE_SUBJECTS: usize = 20;
+/// Windows-1252 characters for bytes 0x80-0x9F, where it differs from
+/// Latin-1; `None` for the five bytes it leaves undefined
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('€'), None, Some('‚'), Some('ƒ'), Some('„'), Some('…'), Some('†'), Some('‡'),
+    Some('ˆ'), Some('‰'), Some('Š'), Some('‹'), Some('Œ'), None, Some('Ž'), None,
+    None, Some('‘'), Some('’'), Some('“'), Some('”'), Some('•'), Some('–'), Some('—'),
+    Some('˜'), Some('™'), Some('š'), Some('›'), Some('œ'), None, Some('ž'), Some('Ÿ'),
+];
+/// Leading bytes searched for a NUL byte when deciding whether content is
+/// binary, as git does
+const BINARY_CHECK_BYTES: usize = 8000;
+/// Extensions of prose files, which are word-diffed with `word_diff`
+const PROSE_EXTENSIONS: &[&str] = &["md", "markdown", "mdx", "rst", "txt", "adoc", "asciidoc", "org", "tex"];
+
+/// The rendered hunks of a single file in a diff
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub path: String,
+    pub hunks: Vec<String>,
+}
+
+impl FilePatch {
+    /// The file's hunks joined into one block of patch text
+    pub fn text(&self) -> String {
+        self.hunks.concat()
+    }
+}
+
+/// Marker placed before changed lines that match a focus pattern
+pub const FOCUS_MARKER: &str = "[FOCUS] ";
+
+/// Options controlling how a diff is rendered for the model
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Replace hunks whose only changes are whitespace by a short note, so
+    /// reformatting noise does not drown out real edits in the same file
+    pub collapse_whitespace: bool,
+    /// Mark added and removed lines matching any of these patterns
+    pub focus: Vec<Regex>,
+    /// Keep `@@` hunk headers so the model can cite line numbers
+    pub hunk_headers: bool,
+    /// Show changes to prose files word by word instead of line by line,
+    /// as `~` lines marking `[-removed-]` and `{+added+}` words
+    pub word_diff: bool,
+    /// Only keep hunks overlapping these lines of the new file
+    pub lines: Option<RangeInclusive<u32>>,
+    /// Reduce files matching these patterns, or marked `linguist-generated`
+    /// in `.gitattributes`, to a one-line note; `None` keeps them in full
+    pub generated: Option<Vec<String>>,
+    /// Git directory of the repository the diff came from, where
+    /// `.gitattributes` and checked-out submodules are looked up
+    pub repo_dir: Option<PathBuf>,
+}
+
+impl RenderOptions {
+    /// Open the repository the diff came from, if it is known
+    pub fn repository(&self) -> Option<Repository> {
+        Repository::open(self.repo_dir.as_ref()?).ok()
+    }
+
+    fn is_focus(&self, content: &str) -> bool {
+        self.focus.iter().any(|pattern| pattern.is_match(content))
+    }
+}
+
+/// Render a diff as per-file patch text for the model
+pub fn render_files(diff: &Diff<'_>, options: &RenderOptions) -> Result<Vec<FilePatch>> {
+    let collapse_whitespace = options.collapse_whitespace;
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut hunk = HunkBuffer::default();
+    // Whether the current file has been noted as not UTF-8
+    let mut noted_encoding = false;
+    let generated = generated_paths(diff, options);
+    let repo = options.repository();
+    diff.print(git2::DiffFormat::Patch, |delta, diff_hunk, line| {
+        use git2::DiffLineType::*;
+        if let (Some(lines), Some(diff_hunk)) = (&options.lines, &diff_hunk) {
+            if !hunk_overlaps(diff_hunk, lines) {
+                return true;
+            }
+        }
+        let changed = matches!(line.origin_value(), Addition | Deletion);
+        if !changed {
+            hunk.flush_words(options);
+        }
+        if is_submodule(&delta) {
+            // The "Subproject commit" lines only carry hashes; describe the update instead
+            if line.origin_value() == FileHeader {
+                hunk.flush(&mut files, collapse_whitespace);
+                files.push(FilePatch { path: delta_path(&delta), hunks: vec![submodule_note(&delta, repo.as_ref())] });
+            }
+            return true;
+        }
+        if delta.flags().is_binary() {
+            // Binary content is noise to the model; record the file once
+            if line.origin_value() == FileHeader {
+                hunk.flush(&mut files, collapse_whitespace);
+                files.push(FilePatch { path: delta_path(&delta), hunks: vec![binary_note(&delta)] });
+            }
+            return true;
+        }
+        if !generated.is_empty() && generated.contains(&delta_path(&delta)) {
+            if line.origin_value() == FileHeader {
+                hunk.flush(&mut files, collapse_whitespace);
+                files.push(FilePatch { path: delta_path(&delta), hunks: vec![generated_note(&delta)] });
+            }
+            return true;
+        }
+        if line.origin_value() == FileHeader {
+            noted_encoding = false;
+        }
+        let (content, fallback) = decode_line(line.content());
+        if let Some(fallback) = fallback.filter(|_| !noted_encoding) {
+            // Noted ahead of the hunk being buffered, so near the file's start
+            if let Some(file) = files.last_mut() {
+                file.hunks.push(fallback.note().to_string());
+                noted_encoding = true;
+            }
+        }
+        if changed && options.word_diff && is_prose(&delta_path(&delta)) {
+            let (side, pending) = match line.origin_value() {
+                Addition => (&mut hunk.added, &mut hunk.words_added),
+                _ => (&mut hunk.removed, &mut hunk.words_removed),
+            };
+            side.push_str(&content);
+            pending.push_str(&content);
+            return true;
+        }
+        let marker = if options.is_focus(&content) { FOCUS_MARKER } else { "" };
+        match line.origin_value() {
+            Addition => {
+                hunk.added.push_str(&content);
+                hunk.lines.push_str(&format!("{}+{}", marker, content));
+            }
+            Deletion => {
+                hunk.removed.push_str(&content);
+                hunk.lines.push_str(&format!("{}-{}", marker, content));
+            }
+            Context => hunk.lines.push_str(&format!(" {}", content)),
+            FileHeader => {
+                hunk.flush(&mut files, collapse_whitespace);
+                let hunks = rename_note(&delta).into_iter().chain(mode_note(&delta)).collect();
+                files.push(FilePatch { path: delta_path(&delta), hunks });
+            }
+            HunkHeader => {
+                hunk.flush(&mut files, collapse_whitespace);
+                if options.hunk_headers {
+                    hunk.lines.push_str(&content);
+                }
+            }
+            _ => (),
+        }
+        true
+    })?;
+    hunk.flush_words(options);
+    hunk.flush(&mut files, collapse_whitespace);
+    Ok(files)
+}
+
+/// How a line that is not valid UTF-8 was decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fallback {
+    /// Read as Windows-1252, the superset of Latin-1 most legacy text uses
+    Windows1252,
+    /// Undecodable bytes replaced
+    Lossy,
+}
+
+impl Fallback {
+    fn note(self) -> &'static str {
+        match self {
+            Fallback::Windows1252 => "(file is not UTF-8; decoded as Windows-1252/Latin-1)\n",
+            Fallback::Lossy => "(file is not valid UTF-8; undecodable bytes omitted)\n",
+        }
+    }
+}
+
+/// Decode a line of a file that may not be UTF-8
+///
+/// Invalid UTF-8 is read as Windows-1252 (and so Latin-1) when every byte is
+/// printable in it. Otherwise invalid bytes are replaced, collapsing each run
+/// of them into one `[undecodable bytes]` marker so they cannot dominate the
+/// prompt.
+pub fn decode_line(bytes: &[u8]) -> (Cow<'_, str>, Option<Fallback>) {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (Cow::Borrowed(text), None);
+    }
+    let windows_1252: Option<String> = bytes.iter()
+        .map(|&byte| match byte {
+            b'\t' | b'\n' | b'\r' | 0x20..=0x7E | 0xA0..=0xFF => Some(char::from(byte)),
+            0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(byte - 0x80)],
+            _ => None,
+        })
+        .collect();
+    if let Some(text) = windows_1252 {
+        return (Cow::Owned(text), Some(Fallback::Windows1252));
+    }
+
+    let lossy = String::from_utf8_lossy(bytes);
+    let replacements = Regex::new("\u{FFFD}+").expect("replacement pattern is valid");
+    (Cow::Owned(replacements.replace_all(&lossy, "[undecodable bytes]").into_owned()), Some(Fallback::Lossy))
+}
+
+/// Whether a hunk touches any of the given lines of the new file
+///
+/// A hunk that only removes lines counts as touching the line it removed
+/// them before.
+fn hunk_overlaps(hunk: &DiffHunk<'_>, lines: &RangeInclusive<u32>) -> bool {
+    let start = hunk.new_start();
+    let end = start + hunk.new_lines().saturating_sub(1);
+    start <= *lines.end() && end >= *lines.start()
+}
+
+/// Whether a path looks like prose, such as Markdown or plain text
+fn is_prose(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| PROSE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Merge removed and added lines into one text that marks changed words as
+/// `[-removed-]` and `{+added+}`, like `git diff --word-diff=plain`
+///
+/// Line breaks are never marked: added ones are kept as they are and removed
+/// ones become a space.
+pub fn word_diff(removed: &str, added: &str) -> String {
+    let diff = TextDiff::from_words(removed, added);
+    let mut text = String::new();
+    let mut open = ChangeTag::Equal;
+    for change in diff.iter_all_changes() {
+        let value = change.value();
+        let line_break = value.contains('\n') && value.trim().is_empty();
+        let tag = if line_break { ChangeTag::Equal } else { change.tag() };
+        if tag != open {
+            text.push_str(match open {
+                ChangeTag::Delete => "-]",
+                ChangeTag::Insert => "+}",
+                ChangeTag::Equal => "",
+            });
+            text.push_str(match tag {
+                ChangeTag::Delete => "[-",
+                ChangeTag::Insert => "{+",
+                ChangeTag::Equal => "",
+            });
+            open = tag;
+        }
+        match change.tag() {
+            ChangeTag::Delete if line_break => text.push(' '),
+            _ => text.push_str(value),
+        }
+    }
+    text.push_str(match open {
+        ChangeTag::Delete => "-]",
+        ChangeTag::Insert => "+}",
+        ChangeTag::Equal => "",
+    });
+    text
+}
+
+/// The path a delta is reported under: its new path, or the old one if deleted
+fn delta_path(delta: &DiffDelta<'_>) -> String {
+    delta.new_file().path()
+        .or_else(|| delta.old_file().path())
+        .map(|p| p.display().to_string())
+        .unwrap_or_default()
+}
+
+/// The single line sent to the model in place of a binary file's content
+pub fn binary_note(delta: &DiffDelta<'_>) -> String {
+    format!("Binary file {} changed\n", delta_path(delta))
+}
+
+/// The single line sent to the model in place of a generated file's content
+pub fn generated_note(delta: &DiffDelta<'_>) -> String {
+    format!("Generated file {} changed (contents left out)\n", delta_path(delta))
+}
+
+/// Default patterns identifying lockfiles and other generated files
+pub const DEFAULT_GENERATED_PATTERNS: &[&str] = &[
+    "Cargo.lock", "package-lock.json", "npm-shrinkwrap.json", "yarn.lock", "pnpm-lock.yaml",
+    "poetry.lock", "Pipfile.lock", "Gemfile.lock", "composer.lock", "go.sum", "*.min.js", "*.min.css",
+];
+
+/// Paths in a diff that `options` has reduced to a note as generated
+///
+/// `.gitattributes` is read from `options.repo_dir`, as the diff does not
+/// say which repository it came from.
+pub fn generated_paths(diff: &Diff<'_>, options: &RenderOptions) -> HashSet<String> {
+    let Some(patterns) = &options.generated else {
+        return HashSet::new();
+    };
+    let repo = options.repository();
+    diff.deltas()
+        .map(|delta| delta_path(&delta))
+        .filter(|path| {
+            patterns.iter().any(|pattern| path_matches(pattern, path))
+                || repo.as_ref().is_some_and(|repo| is_linguist_generated(repo, path))
+        })
+        .collect()
+}
+
+/// Whether `.gitattributes` marks a path as generated for GitHub Linguist
+fn is_linguist_generated(repo: &Repository, path: &str) -> bool {
+    match repo.get_attr(Path::new(path), "linguist-generated", AttrCheckFlags::FILE_THEN_INDEX) {
+        Ok(value) => matches!(AttrValue::from_string(value), AttrValue::True) || value == Some("true"),
+        Err(_) => false,
+    }
+}
+
+/// Whether a delta is a submodule (gitlink) rather than a file
+pub fn is_submodule(delta: &DiffDelta<'_>) -> bool {
+    delta.old_file().mode() == FileMode::Commit || delta.new_file().mode() == FileMode::Commit
+}
+
+/// The text sent to the model in place of a submodule's gitlink change
+///
+/// An update names the old and new commits and, when the submodule is
+/// checked out in `repo`, lists the subjects of the commits it brings in.
+pub fn submodule_note(delta: &DiffDelta<'_>, repo: Option<&Repository>) -> String {
+    let path = delta_path(delta);
+    let (old, new) = (delta.old_file().id(), delta.new_file().id());
+    let short = |oid: Oid| oid.to_string()[..7].to_string();
+    if old.is_zero() {
+        return format!("Submodule {} added at {}\n", path, short(new));
+    }
+    if new.is_zero() {
+        return format!("Submodule {} removed (was at {})\n", path, short(old));
+    }
+
+    let mut note = format!("Submodule {}: {} -> {}\n", path, short(old), short(new));
+    let subjects = repo.and_then(|repo| submodule_subjects(repo, &path, old, new)).unwrap_or_default();
+    for subject in subjects.iter().take(SUBMODULE_SUBJECTS) {
+        note.push_str(&format!("  {}\n", subject));
+    }
+    if subjects.len() > SUBMODULE_SUBJECTS {
+        note.push_str(&format!("  ({} more commits)\n", subjects.len() - SUBMODULE_SUBJECTS));
+    }
+    note
+}
+
+/// Subjects of the commits between two submodule commits, newest first, if
+/// the submodule is checked out in `superproject` and has both
+fn submodule_subjects(superproject: &Repository, path: &str, old: Oid, new: Oid) -> Option<Vec<String>> {
+    let submodule = Repository::open(superproject.workdir()?.join(path)).ok()?;
+    let mut revwalk = submodule.revwalk().ok()?;
+    revwalk.push(new).ok()?;
+    revwalk.hide(old).ok()?;
+    revwalk
+        .map(|oid| Some(submodule.find_commit(oid.ok()?).ok()?.summary()?.to_string()))
+        .collect()
+}
+
+/// The line sent to the model for a renamed or copied file, naming both
+/// paths so a move is not described as a deletion and an addition
+pub fn rename_note(delta: &DiffDelta<'_>) -> Option<String> {
+    let verb = match delta.status() {
+        Delta::Renamed => "Renamed",
+        Delta::Copied => "Copied",
+        _ => return None,
+    };
+    let old = delta.old_file().path()?.display();
+    let new = delta.new_file().path()?.display();
+    Some(format!("{} {} to {}\n", verb, old, new))
+}
+
+/// The line sent to the model when a file's mode changed, which otherwise
+/// leaves no trace in the patch text
+pub fn mode_note(delta: &DiffDelta<'_>) -> Option<String> {
+    let (old, new) = (delta.old_file().mode(), delta.new_file().mode());
+    if old == new || [old, new].iter().any(|mode| matches!(mode, FileMode::Unreadable | FileMode::Commit)) {
+        return None;
+    }
+    let path = delta.new_file().path()?.display();
+    let change = match (old, new) {
+        (FileMode::Blob, FileMode::BlobExecutable) => format!("Made {} executable", path),
+        (FileMode::BlobExecutable, FileMode::Blob) => format!("Made {} no longer executable", path),
+        (_, FileMode::Link) => format!("Replaced {} with a symlink", path),
+        (FileMode::Link, _) => format!("Replaced symlink {} with a regular file", path),
+        _ => format!("Changed the mode of {}", path),
+    };
+    Some(format!("{} ({:o} -> {:o})\n", change, u32::from(old), u32::from(new)))
+}
+
+/// Default patterns identifying test files
+pub const DEFAULT_TEST_PATTERNS: &[&str] = &["tests/", "*_test.*", "*.spec.*"];
+
+/// Remove files matching any of `patterns`, returning how many were removed
+///
+/// A pattern ending in `/` matches a directory anywhere in the path. A pattern
+/// containing `/` elsewhere is matched against the whole path, and any other
+/// pattern against the file name. `*` and `?` are supported as wildcards.
+pub fn remove_matching(files: &mut Vec<FilePatch>, patterns: &[String]) -> usize {
+    let before = files.len();
+    files.retain(|file| !patterns.iter().any(|pattern| path_matches(pattern, &file.path)));
+    before - files.len()
+}
+
+/// Whether a path matches a single pattern, as described in [`remove_matching`]
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    if let Some(dir) = pattern.strip_suffix('/') {
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        return components.iter().any(|component| wildcard_match(dir, component));
+    }
+    if pattern.contains('/') {
+        return wildcard_match(pattern, path);
+    }
+    let name = path.rsplit('/').next().unwrap_or(path);
+    wildcard_match(pattern, name)
+}
+
+/// Match text against a pattern where `*` matches any run of characters and
+/// `?` matches exactly one
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Split raw unified diff text, such as `git format-patch` output, into
+/// per-file patches
+///
+/// Anything before the first `diff --git` line, like the commit message of
+/// a mail patch, is kept as a leading `(patch header)` entry.
+pub fn split_patch_text(text: &str) -> Vec<FilePatch> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            let path = paths.trim_end().rsplit_once(" b/").map_or(paths.trim_end(), |(_, new)| new);
+            files.push(FilePatch { path: path.to_string(), hunks: vec![line.to_string()] });
+            continue;
+        }
+        match files.last_mut() {
+            Some(file) if line.starts_with("@@") => file.hunks.push(line.to_string()),
+            Some(file) => file.hunks.last_mut().expect("patches start with a hunk").push_str(line),
+            None => files.push(FilePatch { path: "(patch header)".to_string(), hunks: vec![line.to_string()] }),
+        }
+    }
+    files
+}
+
+/// Unified diff between two texts, such as files outside a repository,
+/// with a `diff --git` header naming both paths
+pub fn unified_patch(old_path: &str, old: &str, new_path: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    format!(
+        "diff --git a/{0} b/{1}\n{2}",
+        old_path,
+        new_path,
+        diff.unified_diff().header(&format!("a/{}", old_path), &format!("b/{}", new_path))
+    )
+}
+
+/// Whether file content is binary, going by git's check for a NUL byte
+/// near the start
+pub fn is_binary_content(content: &[u8]) -> bool {
+    content.iter().take(BINARY_CHECK_BYTES).any(|&byte| byte == 0)
+}
+
+/// Join rendered files back into a single block of patch text
+pub fn join_files(files: &[FilePatch]) -> String {
+    files.iter().map(FilePatch::text).collect()
+}
+
+/// Join rendered files with a `diff --git` header naming each one
+pub fn join_files_with_headers(files: &[FilePatch]) -> String {
+    files.iter()
+        .map(|file| format!("diff --git a/{0} b/{0}\n{1}", file.path, file.text()))
+        .collect()
+}
+
+/// Fit patch text within `max_tokens` while keeping every file visible
+///
+/// Each file keeps its header and its first few changed lines, starting
+/// with the files that change the most lines. Files that no longer fit are
+/// listed by path under a closing `(N more files changed)` note.
+pub fn trim_to_budget(diff_text: &str, max_tokens: usize, model: &str) -> String {
+    if tokens::count_tokens(diff_text, model) <= max_tokens {
+        return diff_text.to_string();
+    }
+    let mut files = split_patch_text(diff_text);
+    files.sort_by_key(|file| std::cmp::Reverse(changed_lines(&file.text())));
+
+    // Room for the closing note is reserved so every path stays listed
+    let note_chars: usize = 32 + files.iter().map(|file| file.path.len() + 2).sum::<usize>();
+    let max_chars = (max_tokens * tokens::chars_per_token(diff_text, model)).saturating_sub(note_chars);
+    let mut trimmed = String::new();
+    let mut rest = Vec::new();
+    for file in &files {
+        let preview = file_preview(&file.text());
+        if trimmed.len() + preview.len() <= max_chars {
+            trimmed.push_str(&preview);
+        } else {
+            rest.push(file.path.as_str());
+        }
+    }
+    if !rest.is_empty() {
+        trimmed.push_str(&format!("({} more files changed: {})\n", rest.len(), rest.join(", ")));
+    }
+    trimmed
+}
+
+/// Number of added and removed lines in a file's patch text
+fn changed_lines(text: &str) -> usize {
+    text.lines()
+        .filter(|line| !line.starts_with("+++ ") && !line.starts_with("--- "))
+        .filter(|line| line.starts_with(['+', '-']))
+        .count()
+}
+
+/// A file's header lines followed by its first `TRIM_PREVIEW_LINES` lines
+fn file_preview(text: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let header = lines.iter().enumerate()
+        .take_while(|(i, line)| *i == 0 || is_header_line(line))
+        .count();
+    let body = &lines[header..];
+    let mut preview: String = lines[..header].concat();
+    preview.push_str(&body.iter().take(TRIM_PREVIEW_LINES).copied().collect::<String>());
+    if body.len() > TRIM_PREVIEW_LINES {
+        preview.push_str(&format!("({} more lines)\n", body.len() - TRIM_PREVIEW_LINES));
+    }
+    preview
+}
+
+/// Whether a line is file metadata such as `index` or `+++ b/path` rather
+/// than part of a hunk
+fn is_header_line(line: &str) -> bool {
+    line.starts_with("--- ") || line.starts_with("+++ ") || !line.starts_with(['+', '-', ' ', '@'])
+}
+
+/// Lines of the hunk currently being rendered
+#[derive(Default)]
+struct HunkBuffer {
+    lines: String,
+    removed: String,
+    added: String,
+    /// Changed prose lines waiting to be word-diffed
+    words_removed: String,
+    words_added: String,
+}
+
+impl HunkBuffer {
+    /// Append the pending prose changes to the hunk as a word diff
+    fn flush_words(&mut self, options: &RenderOptions) {
+        if self.words_removed.is_empty() && self.words_added.is_empty() {
+            return;
+        }
+        let merged = word_diff(&std::mem::take(&mut self.words_removed), &std::mem::take(&mut self.words_added));
+        for line in merged.lines() {
+            let marker = if options.is_focus(line) { FOCUS_MARKER } else { "" };
+            self.lines.push_str(&format!("{}~{}\n", marker, line));
+        }
+    }
+
+    /// Append the buffered hunk to the last file and reset the buffer
+    fn flush(&mut self, files: &mut Vec<FilePatch>, collapse_whitespace: bool) {
+        if self.lines.is_empty() {
+            return;
+        }
+        if files.is_empty() {
+            files.push(FilePatch { path: String::new(), hunks: Vec::new() });
+        }
+        let text = if collapse_whitespace && is_whitespace_only(&self.removed, &self.added) {
+            "(whitespace-only changes omitted)\n".to_string()
+        } else {
+            std::mem::take(&mut self.lines)
+        };
+        if let Some(file) = files.last_mut() {
+            file.hunks.push(text);
+        }
+        *self = Self::default();
+    }
+}
+
+/// Whether the removed and added text differ only in whitespace
+fn is_whitespace_only(removed: &str, added: &str) -> bool {
+    if removed.is_empty() && added.is_empty() {
+        return false;
+    }
+    let strip = |text: &str| text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    strip(removed) == strip(added)
+}
+
+/// Estimate the number of tokens in a piece of text
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Truncate text to roughly `max_tokens`, noting that it was cut
+pub fn truncate_to_tokens(text: &str, max_tokens: usize) -> String {
+    let max_chars = max_tokens * CHARS_PER_TOKEN;
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}\n(truncated)", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Split rendered files into chunks that each fit within `max_tokens`
+///
+/// Whole files are packed together where possible. A file that is too large
+/// on its own is split by hunk, and a hunk that is still too large is split
+/// by line. The token budget is turned into a character budget using the
+/// diff's own characters per token for `model`.
+pub fn chunk_files(files: &[FilePatch], max_tokens: usize, model: &str) -> Vec<String> {
+    let max_chars = max_tokens.max(1) * tokens::chars_per_token(&join_files(files), model);
+
+    let mut pieces = Vec::new();
+    for file in files {
+        let header = format!("File: {}\n", file.path);
+        let text = file.text();
+        if header.len() + text.len() <= max_chars {
+            pieces.push(format!("{}{}", header, text));
+            continue;
+        }
+        for hunk in &file.hunks {
+            if header.len() + hunk.len() <= max_chars {
+                pieces.push(format!("{}{}", header, hunk));
+            } else {
+                let budget = max_chars.saturating_sub(header.len()).max(1);
+                for part in split_lines(hunk, budget) {
+                    pieces.push(format!("{}{}", header, part));
+                }
+            }
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for piece in pieces {
+        if !current.is_empty() && current.len() + piece.len() > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(&piece);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Split text on line boundaries into parts of at most `max_chars` bytes
+///
+/// A single line longer than the limit is kept whole rather than cut mid-line.
+fn split_lines(text: &str, max_chars: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for line in text.split_inclusive('\n') {
+        if !current.is_empty() && current.len() + line.len() > max_chars {
+            parts.push(std::mem::take(&mut current));
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, hunks: &[&str]) -> FilePatch {
+        FilePatch {
+            path: path.to_string(),
+            hunks: hunks.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_whitespace_only_detection() {
+        assert!(is_whitespace_only("fn main() {\n", "fn main()  {\n"));
+        assert!(is_whitespace_only("\tlet x = 1;\n", "    let x = 1;\n"));
+        assert!(!is_whitespace_only("let x = 1;\n", "let x = 2;\n"));
+        assert!(!is_whitespace_only("", ""));
+    }
+
+    #[test]
+    fn test_default_test_patterns() {
+        let patterns: Vec<String> = DEFAULT_TEST_PATTERNS.iter().map(|p| p.to_string()).collect();
+        let mut files = vec![
+            file("src/lib.rs", &[]),
+            file("tests/integration.rs", &[]),
+            file("crates/core/tests/util.rs", &[]),
+            file("pkg/server_test.go", &[]),
+            file("web/app.spec.ts", &[]),
+            file("src/testsuite.rs", &[]),
+        ];
+
+        assert_eq!(remove_matching(&mut files, &patterns), 4);
+        let kept: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(kept, vec!["src/lib.rs", "src/testsuite.rs"]);
+    }
+
+    #[test]
+    fn test_prose_is_word_diffed() {
+        assert_eq!(word_diff("The quick fox\n", "The slow fox\n"), "The [-quick-]{+slow+} fox\n");
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp_dir.path()).unwrap();
+        let tree = |readme: &str, code: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("README.md", repo.blob(readme.as_bytes()).unwrap(), 0o100644).unwrap();
+            builder.insert("lib.rs", repo.blob(code.as_bytes()).unwrap(), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let old = tree("# Title\nRun the tool daily.\n", "fn a() {}\n");
+        let new = tree("# Title\nRun the tool weekly.\n", "fn b() {}\n");
+        let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), None).unwrap();
+
+        let files = render_files(&diff, &RenderOptions { word_diff: true, ..Default::default() }).unwrap();
+        assert_eq!(files[0].text(), " # Title\n~Run the tool [-daily.-]{+weekly.+}\n");
+        assert_eq!(files[1].text(), "-fn a() {}\n+fn b() {}\n");
+    }
+
+    #[test]
+    fn test_hunks_outside_line_range_are_dropped() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp_dir.path()).unwrap();
+        let tree = |changed: &[usize]| {
+            let content: String = (1..=100)
+                .map(|i| if changed.contains(&i) { format!("changed {}\n", i) } else { format!("line {}\n", i) })
+                .collect();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("notes.txt", repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let diff = repo.diff_tree_to_tree(Some(&tree(&[])), Some(&tree(&[5, 80])), None).unwrap();
+
+        let files = render_files(&diff, &RenderOptions { lines: Some(70..=90), ..Default::default() }).unwrap();
+        let text = files[0].text();
+        assert!(text.contains("+changed 80\n"));
+        assert!(!text.contains("changed 5\n"));
+    }
+
+    #[test]
+    fn test_latin1_file_is_transcoded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp_dir.path()).unwrap();
+        let latin1 = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/latin1.txt"));
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("menu.txt", repo.blob(latin1).unwrap(), 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+        let files = render_files(&diff, &RenderOptions::default()).unwrap();
+        assert_eq!(files[0].text(), "(file is not UTF-8; decoded as Windows-1252/Latin-1)\n+café crème brûlée\n");
+
+        let (text, fallback) = decode_line(b"ok \x00\xff\xfe\x01 ok\n");
+        assert_eq!(text, "ok \u{0}[undecodable bytes]\u{1} ok\n");
+        assert_eq!(fallback, Some(Fallback::Lossy));
+    }
+
+    #[test]
+    fn test_binary_files_are_replaced_by_a_note() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp_dir.path()).unwrap();
+        let png = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pixel.png"));
+        std::fs::write(temp_dir.path().join("pixel.png"), png).unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), "hello\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("pixel.png")).unwrap();
+        index.add_path(std::path::Path::new("notes.txt")).unwrap();
+
+        let diff = repo.diff_tree_to_index(None, Some(&index), None).unwrap();
+        let files = render_files(&diff, &RenderOptions::default()).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "notes.txt");
+        assert_eq!(files[0].text(), "+hello\n");
+        assert_eq!(files[1].path, "pixel.png");
+        assert_eq!(files[1].text(), "Binary file pixel.png changed\n");
+    }
+
+    #[test]
+    fn test_submodule_update_is_described() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp_dir.path()).unwrap();
+        let tree = |commit: &str| {
+            let mut vendor = repo.treebuilder(None).unwrap();
+            vendor.insert("lib", Oid::from_str(commit).unwrap(), 0o160000).unwrap();
+            let mut root = repo.treebuilder(None).unwrap();
+            root.insert("vendor", vendor.write().unwrap(), 0o040000).unwrap();
+            repo.find_tree(root.write().unwrap()).unwrap()
+        };
+        let old = tree("a1b2c3d4e5f60718293a4b5c6d7e8f9012345678");
+        let new = tree("d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d");
+
+        let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), None).unwrap();
+        let options = RenderOptions { repo_dir: Some(repo.path().to_path_buf()), ..Default::default() };
+        let files = render_files(&diff, &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].text(), "Submodule vendor/lib: a1b2c3d -> d4e5f6a\n");
+    }
+
+    #[test]
+    fn test_mode_only_change_is_described() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let blob = repo.blob(b"#!/bin/sh\necho deploy\n").unwrap();
+        let tree = |mode: i32| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("deploy.sh", blob, mode).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+
+        let diff = repo.diff_tree_to_tree(Some(&tree(0o100644)), Some(&tree(0o100755)), None).unwrap();
+        let files = render_files(&diff, &RenderOptions::default()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].text(), "Made deploy.sh executable (100644 -> 100755)\n");
+    }
+
+    #[test]
+    fn test_chunk_files_splits_large_files_by_hunk() {
+        let hunk = "+".repeat(60) + "\n";
+        let files = vec![
+            file("small.rs", &["+a\n"]),
+            file("big.rs", &[&hunk, &hunk, &hunk]),
+        ];
+
+        let chunks = chunk_files(&files, 20, "test-model");
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= 20 * CHARS_PER_TOKEN));
+        assert!(chunks[0].starts_with("File: small.rs\n"));
+        assert_eq!(chunks.iter().filter(|c| c.contains("File: big.rs")).count(), chunks.len() - 1);
+    }
+
+    #[test]
+    fn test_split_patch_text() {
+        let text = "Subject: [PATCH] Fix typo\n\n\
+            diff --git a/src/a.rs b/src/a.rs\nindex 1..2 100644\n--- a/src/a.rs\n+++ b/src/a.rs\n\
+            @@ -1 +1 @@\n-teh\n+the\n@@ -9 +9 @@\n-x\n+y\n\
+            diff --git a/old.txt b/new.txt\nsimilarity index 100%\n";
+        let files = split_patch_text(text);
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["(patch header)", "src/a.rs", "new.txt"]);
+        assert_eq!(files[1].hunks.len(), 3);
+        assert_eq!(join_files(&files), text);
+    }
+
+    #[test]
+    fn test_generated_files_are_collapsed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("Cargo.lock", repo.blob(b"[[package]]\nname = \"git2\"\n").unwrap(), 0o100644).unwrap();
+        builder.insert("main.rs", repo.blob(b"fn main() {}\n").unwrap(), 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+
+        let defaults = DEFAULT_GENERATED_PATTERNS.iter().map(|p| p.to_string()).collect();
+        let files = render_files(&diff, &RenderOptions { generated: Some(defaults), ..Default::default() }).unwrap();
+        assert_eq!(files[0].text(), "Generated file Cargo.lock changed (contents left out)\n");
+        assert_eq!(files[1].text(), "+fn main() {}\n");
+        let files = render_files(&diff, &RenderOptions::default()).unwrap();
+        assert!(files[0].text().contains("+name = \"git2\"\n"));
+
+        std::fs::write(temp_dir.path().join(".gitattributes"), "schema/*.rs linguist-generated\n").unwrap();
+        assert!(is_linguist_generated(&repo, "schema/types.rs"));
+        assert!(!is_linguist_generated(&repo, "src/types.rs"));
+    }
+
+    #[test]
+    fn test_unified_patch() {
+        let patch = unified_patch("old/config.toml", "name = \"a\"\nport = 80\n", "config.toml", "name = \"a\"\nport = 8080\n");
+        assert_eq!(patch, "diff --git a/old/config.toml b/config.toml\n\
+            --- a/old/config.toml\n+++ b/config.toml\n\
+            @@ -1,2 +1,2 @@\n name = \"a\"\n-port = 80\n+port = 8080\n");
+        let files = split_patch_text(&patch);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "config.toml");
+
+        assert!(is_binary_content(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert!(!is_binary_content("naïve text\n".as_bytes()));
+    }
+
+    #[test]
+    fn test_trim_to_budget_keeps_every_path() {
+        let big = (0..200).map(|i| format!("+line {}\n", i)).collect::<String>();
+        let mut files = vec![file("src/big.rs", &[&big])];
+        files.extend((0..30).map(|i| file(&format!("src/small_{}.rs", i), &["-a\n+b\n"])));
+
+        let text = join_files_with_headers(&files);
+        let trimmed = trim_to_budget(&text, 200, "test-model");
+        assert!(estimate_tokens(&trimmed) <= 200);
+        assert!(trimmed.starts_with("diff --git a/src/big.rs b/src/big.rs\n+line 0\n"));
+        assert!(trimmed.contains("(188 more lines)"));
+        assert!(trimmed.contains("more files changed: "));
+        assert!(files.iter().all(|f| trimmed.contains(&f.path)));
+        assert_eq!(trim_to_budget(&text, 100_000, "test-model"), text);
+    }
+}
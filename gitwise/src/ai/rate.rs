@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Seconds of requests at the full rate that may be sent in one burst
+const BURST_SECS: f64 = 10.0;
+/// Lowest rate a rate-limit response can push the limiter down to
+const MIN_RPM: f64 = 1.0;
+/// Share of the configured rate regained after each successful request
+const RECOVERY: f64 = 0.05;
+
+/// A requests-per-minute limit shared by every request an engine makes
+///
+/// This is a token bucket: requests spend a token each, and tokens refill at
+/// the current rate up to a burst of `BURST_SECS` worth. Callers that find
+/// the bucket empty reserve a token anyway and wait until it refills, so
+/// concurrent requests queue up in turn instead of all firing at once. A
+/// rate-limit response halves the rate, which then creeps back up to the
+/// configured one as requests succeed.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rpm: u32) -> Self {
+        Self { bucket: Arc::new(Mutex::new(Bucket::new(f64::from(rpm.max(1)), Instant::now()))) }
+    }
+
+    /// Wait until a request may be sent
+    pub async fn acquire(&self) {
+        let wait = self.lock().reserve(Instant::now());
+        if !wait.is_zero() {
+            tracing::debug!("Rate limit reached, waiting {:.1}s", wait.as_secs_f64());
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Slow down after the provider reported a rate limit
+    pub fn throttle(&self) {
+        let mut bucket = self.lock();
+        bucket.throttle();
+        tracing::debug!("Lowered the request rate to {:.0} per minute", bucket.rpm);
+    }
+
+    /// Speed back up towards the configured rate after a request succeeded
+    pub fn relax(&self) {
+        self.lock().relax();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket.lock().expect("rate limiter lock poisoned")
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Current rate, lowered by rate-limit responses
+    rpm: f64,
+    /// Configured rate
+    max_rpm: f64,
+    /// Tokens available; negative when requests are queued for refills
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(rpm: f64, now: Instant) -> Self {
+        Self { rpm, max_rpm: rpm, tokens: Self::capacity(rpm), refilled_at: now }
+    }
+
+    fn capacity(rpm: f64) -> f64 {
+        (rpm / 60.0 * BURST_SECS).max(1.0)
+    }
+
+    /// Take a token, returning how long to wait until it is available
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rpm / 60.0).min(Self::capacity(self.rpm));
+        self.refilled_at = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens * 60.0 / self.rpm)
+        }
+    }
+
+    fn throttle(&mut self) {
+        self.rpm = (self.rpm / 2.0).max(MIN_RPM);
+        self.tokens = self.tokens.min(0.0);
+    }
+
+    fn relax(&mut self) {
+        self.rpm = (self.rpm + self.max_rpm * RECOVERY).min(self.max_rpm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_queues_requests_past_the_burst() {
+        let start = Instant::now();
+        // 60 per minute allows a burst of 10, then one a second
+        let mut bucket = Bucket::new(60.0, start);
+        for _ in 0..10 {
+            assert_eq!(bucket.reserve(start), Duration::ZERO);
+        }
+        assert_eq!(bucket.reserve(start), Duration::from_secs(1));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+        assert_eq!(bucket.reserve(start + Duration::from_secs(3)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_rate_limit_halves_rate_until_requests_succeed() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(60.0, start);
+        bucket.throttle();
+        assert_eq!(bucket.rpm, 30.0);
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+
+        for _ in 0..100 {
+            bucket.relax();
+        }
+        assert_eq!(bucket.rpm, 60.0);
+    }
+}
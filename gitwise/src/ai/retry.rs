@@ -0,0 +1,178 @@
+use async_openai::error::OpenAIError;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::rate::RateLimiter;
+
+/// Default number of retries for transient API failures
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Default number of times a response that is not valid JSON is asked for again
+pub const DEFAULT_JSON_RETRIES: u32 = 1;
+
+/// Delay before the first retry; doubled for each further attempt
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Read the retry limit from `GITWISE_MAX_RETRIES`
+pub fn max_retries_from_env() -> u32 {
+    std::env::var("GITWISE_MAX_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RETRIES)
+}
+
+/// Read the limit on re-asking for unparsable JSON from `GITWISE_JSON_RETRIES`
+pub fn json_retries_from_env() -> u32 {
+    std::env::var("GITWISE_JSON_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_JSON_RETRIES)
+}
+
+/// Run an OpenAI call, retrying rate limits and server errors with
+/// exponential backoff, plus random jitter if `jitter` is set
+///
+/// Client errors such as bad requests or authentication failures are
+/// returned immediately. With a `limiter`, every attempt waits its turn,
+/// and rate-limit responses slow the limiter down. Each attempt then gets
+/// `timeout` to respond, so waiting for the limiter or between retries does
+/// not count against it; an attempt that runs out of time fails with
+/// `RequestTimedOut`.
+pub async fn with_retry<T, F, Fut>(max_retries: u32, jitter: bool, limiter: Option<&RateLimiter>, timeout: Duration, mut call: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OpenAIError>>,
+{
+    let mut attempt = 0;
+    loop {
+        if let Some(limiter) = limiter {
+            limiter.acquire().await;
+        }
+        let result = super::within_timeout(timeout, call()).await?;
+        if let Some(limiter) = limiter {
+            match &result {
+                Ok(_) => limiter.relax(),
+                Err(e) if is_rate_limit(e) => limiter.throttle(),
+                Err(_) => (),
+            }
+        }
+        match result {
+            Err(e) if attempt < max_retries && is_retryable(&e) => {
+                attempt += 1;
+                let delay = backoff_delay(attempt, jitter);
+                tracing::warn!(
+                    "OpenAI request failed: {}. Retrying in {:.1}s (attempt {}/{})",
+                    e,
+                    delay.as_secs_f64(),
+                    attempt,
+                    max_retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Whether an error is a 429 or rate limit response, other than exhausted
+/// quota
+fn is_rate_limit(error: &OpenAIError) -> bool {
+    match error {
+        OpenAIError::Reqwest(e) => e.status().is_some_and(|status| status.as_u16() == 429),
+        OpenAIError::ApiError(e) => {
+            let kind = e.r#type.as_deref().unwrap_or_default();
+            let code = e.code.as_ref().map(|code| code.to_string()).unwrap_or_default();
+            kind == "rate_limit_exceeded" || kind == "requests" || kind == "tokens" || code.contains("rate_limit_exceeded")
+        }
+        _ => false,
+    }
+}
+
+/// Whether an error is a rate limit, server error or transport failure
+fn is_retryable(error: &OpenAIError) -> bool {
+    match error {
+        OpenAIError::Reqwest(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|status| status.as_u16() == 429 || status.is_server_error())
+        }
+        OpenAIError::ApiError(e) => {
+            let kind = e.r#type.as_deref().unwrap_or_default();
+            let code = e.code.as_ref().map(|code| code.to_string()).unwrap_or_default();
+            // Exhausted quota is reported as a rate limit but will not recover
+            if kind == "insufficient_quota" || code.contains("insufficient_quota") {
+                return false;
+            }
+            kind == "server_error"
+                || kind == "rate_limit_exceeded"
+                || kind == "requests"
+                || kind == "tokens"
+                || code.contains("rate_limit_exceeded")
+        }
+        _ => false,
+    }
+}
+
+/// Whether an error is specific to the model, so that another model may
+/// succeed: a retryable failure, exhausted quota or an unknown model
+pub fn calls_for_other_model(error: &OpenAIError) -> bool {
+    if is_retryable(error) {
+        return true;
+    }
+    match error {
+        OpenAIError::ApiError(e) => {
+            let kind = e.r#type.as_deref().unwrap_or_default();
+            let code = e.code.as_ref().map(|code| code.to_string()).unwrap_or_default();
+            kind == "insufficient_quota" || code.contains("insufficient_quota") || code.contains("model_not_found")
+        }
+        _ => false,
+    }
+}
+
+/// Exponential backoff, with up to 50% random jitter if `jitter` is set
+fn backoff_delay(attempt: u32, jitter: bool) -> Duration {
+    let base = BASE_DELAY * 2u32.saturating_pow(attempt.saturating_sub(1));
+    if !jitter {
+        return base;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let jitter = base.mul_f64(f64::from(nanos % 1000) / 2000.0);
+    base + jitter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_grows_exponentially() {
+        for attempt in 1..=4 {
+            let delay = backoff_delay(attempt, true);
+            let base = BASE_DELAY * 2u32.pow(attempt - 1);
+            assert!(delay >= base);
+            assert!(delay <= base.mul_f64(1.5));
+            assert_eq!(backoff_delay(attempt, false), base);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_each_attempt_is_timed_out() {
+        let never_responds = || std::future::pending::<Result<(), OpenAIError>>();
+        let error = with_retry(3, false, None, Duration::from_millis(10), never_responds).await.unwrap_err();
+        assert!(error.is::<super::super::RequestTimedOut>());
+    }
+
+    #[tokio::test]
+    async fn test_waiting_for_the_rate_limiter_is_not_timed() {
+        // 600 per minute bursts 100 requests, then spaces them 100ms apart
+        let limiter = RateLimiter::new(600);
+        for _ in 0..100 {
+            limiter.acquire().await;
+        }
+        let result = with_retry(0, false, Some(&limiter), Duration::from_millis(50), || async { Ok::<_, OpenAIError>(42) }).await;
+        assert_eq!(result.unwrap(), 42);
+    }
+}
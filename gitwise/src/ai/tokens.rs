@@ -0,0 +1,83 @@
+//! Token counting for prompt budgets
+//!
+//! With the `tokenizer` feature, text is counted with the model's own BPE
+//! encoding from `tiktoken-rs`. Without it, or for models tiktoken does not
+//! know (such as Claude), the four-characters-per-token heuristic is used.
+
+use super::patch;
+
+/// Number of tokens `text` takes up for `model`
+pub fn count_tokens(text: &str, model: &str) -> usize {
+    #[cfg(feature = "tokenizer")]
+    if let Some(count) = bpe::count(text, model) {
+        return count;
+    }
+    #[cfg(not(feature = "tokenizer"))]
+    let _ = model;
+    patch::estimate_tokens(text)
+}
+
+/// Average characters per token of `text` for `model`, used to turn a token
+/// budget into a character budget when packing text
+///
+/// Only a real tokenizer count gives a ratio of its own; the heuristic
+/// rounds token counts up, so it is `patch::CHARS_PER_TOKEN` as is.
+pub fn chars_per_token(text: &str, model: &str) -> usize {
+    #[cfg(feature = "tokenizer")]
+    if let Some(tokens) = bpe::count(text, model).filter(|&tokens| tokens > 0) {
+        return (text.chars().count() / tokens).max(1);
+    }
+    #[cfg(not(feature = "tokenizer"))]
+    let _ = (text, model);
+    patch::CHARS_PER_TOKEN
+}
+
+#[cfg(feature = "tokenizer")]
+mod bpe {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+    use tiktoken_rs::CoreBPE;
+
+    /// Encodings are slow to build, so each is built once and shared
+    static ENCODINGS: OnceLock<Mutex<HashMap<Tokenizer, Arc<CoreBPE>>>> = OnceLock::new();
+
+    /// Count tokens with the model's encoding, or `None` if tiktoken does not
+    /// know the model
+    pub fn count(text: &str, model: &str) -> Option<usize> {
+        let tokenizer = get_tokenizer(model)?;
+        let bpe = {
+            let mut encodings = ENCODINGS.get_or_init(Default::default).lock().ok()?;
+            match encodings.get(&tokenizer) {
+                Some(bpe) => bpe.clone(),
+                None => {
+                    let bpe = Arc::new(tiktoken_rs::get_bpe_from_tokenizer(tokenizer).ok()?);
+                    encodings.insert(tokenizer, bpe.clone());
+                    bpe
+                }
+            }
+        };
+        Some(bpe.encode_with_special_tokens(text).len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_model_uses_heuristic() {
+        assert_eq!(count_tokens("fn main() {}", "claude-3-haiku-20240307"), 3);
+        assert_eq!(chars_per_token("fn main() {}", "my-local-model"), 4);
+        assert_eq!(chars_per_token("fn main() { x }", "my-local-model"), 4);
+    }
+
+    #[cfg(feature = "tokenizer")]
+    #[test]
+    fn test_counts_match_openai() {
+        // Counts from OpenAI's tokenizer for cl100k_base
+        assert_eq!(count_tokens("tiktoken is great!", "gpt-4"), 6);
+        assert_eq!(count_tokens("tiktoken is great!", "gpt-3.5-turbo"), 6);
+        assert_eq!(count_tokens("hello world", "gpt-4"), 2);
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::ai::ModelProvider;
+
+/// Name of the repo-local config file, at the root of the work tree
+const LOCAL_CONFIG_FILE: &str = ".gitwise.toml";
+
+/// Settings read from config files
+///
+/// Every key is optional; unset keys fall through to the next file in
+/// precedence order and finally to the built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// System prompt for diff summaries
+    pub summarize_prompt: Option<String>,
+    /// System prompt for freeform and gitmoji commit messages
+    pub commit_prompt: Option<String>,
+    /// Model used instead of the provider's default
+    pub model: Option<String>,
+    /// Provider used when `--model` is not given, `anthropic` or `openai`
+    pub provider: Option<ModelProvider>,
+    /// Sampling temperature used when `--temperature` is not given
+    pub temperature: Option<f32>,
+    /// Cap on tokens generated per response when `--max-tokens` is not given
+    pub max_tokens: Option<u16>,
+    /// Single-file diffs changing fewer lines than this get a terse prompt;
+    /// 0 turns this off
+    pub trivial_diff_lines: Option<usize>,
+    /// Patterns of lockfiles and other generated files sent as a one-line
+    /// note, replacing the default list
+    pub generated_patterns: Option<Vec<String>>,
+    /// Named sets of settings such as `[profile.work]`, chosen with
+    /// `--profile`
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+}
+
+/// Settings a `[profile.<name>]` table overrides when the profile is chosen
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub summarize_prompt: Option<String>,
+    pub commit_prompt: Option<String>,
+    pub model: Option<String>,
+    pub provider: Option<ModelProvider>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u16>,
+}
+
+impl Profile {
+    fn overridden_by(self, other: Profile) -> Profile {
+        Profile {
+            summarize_prompt: other.summarize_prompt.or(self.summarize_prompt),
+            commit_prompt: other.commit_prompt.or(self.commit_prompt),
+            model: other.model.or(self.model),
+            provider: other.provider.or(self.provider),
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+        }
+    }
+}
+
+/// Where a config file applies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    Global,
+    Repository,
+}
+
+impl Config {
+    /// Load the config files that exist, the local file of the repository
+    /// at `workdir` overriding the global one
+    pub fn load(workdir: Option<&Path>) -> Result<Config> {
+        let mut config = Config::default();
+        for (_, path) in config_paths(workdir) {
+            if !path.exists() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let file: Config = toml::from_str(&text)
+                .with_context(|| format!("Invalid config file {}", path.display()))?;
+            config = config.overridden_by(file);
+        }
+        Ok(config)
+    }
+
+    /// These settings with any set in `other` taking precedence
+    ///
+    /// Profiles with the same name are merged key by key.
+    fn overridden_by(self, other: Config) -> Config {
+        let mut profile = self.profile;
+        for (name, overrides) in other.profile {
+            let merged = match profile.remove(&name) {
+                Some(base) => base.overridden_by(overrides),
+                None => overrides,
+            };
+            profile.insert(name, merged);
+        }
+        Config {
+            summarize_prompt: other.summarize_prompt.or(self.summarize_prompt),
+            commit_prompt: other.commit_prompt.or(self.commit_prompt),
+            model: other.model.or(self.model),
+            provider: other.provider.or(self.provider),
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            trivial_diff_lines: other.trivial_diff_lines.or(self.trivial_diff_lines),
+            generated_patterns: other.generated_patterns.or(self.generated_patterns),
+            profile,
+        }
+    }
+
+    /// These settings with those of the named profile taking precedence
+    pub fn with_profile(self, name: &str) -> Result<Config> {
+        let Some(profile) = self.profile.get(name).cloned() else {
+            let known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+            return Err(match known.is_empty() {
+                true => anyhow!("No profile named '{}'; add a [profile.{}] table to a config file", name, name),
+                false => anyhow!("No profile named '{}'; the config files define {}", name, known.join(", ")),
+            });
+        };
+        Ok(Config {
+            summarize_prompt: profile.summarize_prompt.or(self.summarize_prompt),
+            commit_prompt: profile.commit_prompt.or(self.commit_prompt),
+            model: profile.model.or(self.model),
+            provider: profile.provider.or(self.provider),
+            temperature: profile.temperature.or(self.temperature),
+            max_tokens: profile.max_tokens.or(self.max_tokens),
+            ..self
+        })
+    }
+}
+
+/// Candidate config files from lowest to highest precedence, whether or not
+/// they exist
+///
+/// The global file is `$XDG_CONFIG_HOME/gitwise/config.toml`, falling back to
+/// `~/.config/gitwise/config.toml`. The repo-local file is `.gitwise.toml`
+/// in `workdir`, the root of the repository's work tree.
+pub fn config_paths(workdir: Option<&Path>) -> Vec<(Scope, PathBuf)> {
+    let global = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config")),
+    };
+    let local = workdir.map(|dir| dir.join(LOCAL_CONFIG_FILE));
+
+    global.map(|dir| (Scope::Global, dir.join("gitwise").join("config.toml")))
+        .into_iter()
+        .chain(local.map(|path| (Scope::Repository, path)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repo_config_overrides_global() {
+        let global: Config = toml::from_str("model = \"gpt-4o\"\ntemperature = 0.2\n").unwrap();
+        let local: Config = toml::from_str("temperature = 0.9\nmax_tokens = 512\ncommit_prompt = \"Write terse messages.\"\n").unwrap();
+        let config = Config::default().overridden_by(global).overridden_by(local);
+        assert_eq!(config, Config {
+            summarize_prompt: None,
+            commit_prompt: Some("Write terse messages.".to_string()),
+            model: Some("gpt-4o".to_string()),
+            temperature: Some(0.9),
+            max_tokens: Some(512),
+            ..Default::default()
+        });
+        assert!(toml::from_str::<Config>("temprature = 0.9").is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let global: Config = toml::from_str(
+            "model = \"gpt-4o\"\ntemperature = 0.2\n\
+            [profile.work]\nprovider = \"openai\"\nmodel = \"gpt-4o-mini\"\n\
+            [profile.personal]\nprovider = \"anthropic\"\n",
+        ).unwrap();
+        let local: Config = toml::from_str("[profile.work]\ncommit_prompt = \"Reference the ticket.\"\n").unwrap();
+        let config = Config::default().overridden_by(global).overridden_by(local);
+
+        let work = config.clone().with_profile("work").unwrap();
+        assert_eq!(work.provider, Some(ModelProvider::OpenAI));
+        assert_eq!(work.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(work.commit_prompt.as_deref(), Some("Reference the ticket."));
+        assert_eq!(work.temperature, Some(0.2));
+
+        let personal = config.clone().with_profile("personal").unwrap();
+        assert_eq!(personal.provider, Some(ModelProvider::Anthropic));
+        assert_eq!(personal.model.as_deref(), Some("gpt-4o"));
+
+        let error = config.with_profile("home").unwrap_err().to_string();
+        assert!(error.contains("personal, work"), "{}", error);
+        assert!(toml::from_str::<Config>("[profile.work]\nmodle = \"x\"\n").is_err());
+    }
+}
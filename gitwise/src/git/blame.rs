@@ -0,0 +1,56 @@
+use anyhow::{Context, Result, anyhow};
+use git2::{BlameOptions, Oid, Repository};
+use std::path::Path;
+
+/// Parse a line range such as `L10,30` or `10,30` into 1-based inclusive
+/// bounds
+pub fn parse_line_range(range: &str) -> Result<(usize, usize)> {
+    let range = range.strip_prefix('L').unwrap_or(range);
+    let (start, end) = range.split_once(',')
+        .ok_or_else(|| anyhow!("Line range must look like L10,30"))?;
+    let start: usize = start.trim().parse().with_context(|| format!("Invalid start line '{}'", start))?;
+    let end: usize = end.trim().strip_prefix('L').unwrap_or(end.trim()).parse()
+        .with_context(|| format!("Invalid end line '{}'", end))?;
+    if start == 0 || end < start {
+        return Err(anyhow!("Line range must start at 1 or later and not end before it starts"));
+    }
+    Ok((start, end))
+}
+
+/// Commits that last touched the lines of a file, oldest first
+///
+/// At most `max_commits` of the most recent commits are returned.
+pub fn get_blame_commits(repo: &Repository, path: &str, lines: Option<(usize, usize)>, max_commits: usize) -> Result<Vec<Oid>> {
+    let mut opts = BlameOptions::new();
+    if let Some((start, end)) = lines {
+        opts.min_line(start).max_line(end);
+    }
+    let blame = repo.blame_file(Path::new(path), Some(&mut opts))
+        .with_context(|| format!("Failed to blame {}", path))?;
+
+    let mut commits = Vec::new();
+    for hunk in blame.iter() {
+        let oid = hunk.final_commit_id();
+        if !oid.is_zero() && !commits.iter().any(|(seen, _)| *seen == oid) {
+            commits.push((oid, repo.find_commit(oid)?.time().seconds()));
+        }
+    }
+    commits.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+    commits.truncate(max_commits);
+    commits.reverse();
+    Ok(commits.into_iter().map(|(oid, _)| oid).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line_range() {
+        assert_eq!(parse_line_range("L10,30").unwrap(), (10, 30));
+        assert_eq!(parse_line_range("5,5").unwrap(), (5, 5));
+        assert!(parse_line_range("L30,10").is_err());
+        assert!(parse_line_range("L0,3").is_err());
+        assert!(parse_line_range("10").is_err());
+    }
+}
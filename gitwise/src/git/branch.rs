@@ -0,0 +1,67 @@
+use anyhow::{Result, anyhow};
+use git2::{Reference, Repository};
+
+/// Longest branch name, prefix excluded, that `sanitize_branch_name` keeps
+const MAX_BRANCH_NAME_LENGTH: usize = 50;
+
+/// Turn free text into a kebab-case branch name that is a valid git ref
+///
+/// `prefix`, such as `feature` or `fix/`, is added as a leading path
+/// component.
+pub fn sanitize_branch_name(name: &str, prefix: Option<&str>) -> Result<String> {
+    let kebab = |text: &str| {
+        text.to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+    };
+
+    let mut slug = kebab(name);
+    if slug.len() > MAX_BRANCH_NAME_LENGTH {
+        // Cut at a word boundary
+        slug.truncate(MAX_BRANCH_NAME_LENGTH + 1);
+        slug.truncate(slug.rfind('-').unwrap_or(MAX_BRANCH_NAME_LENGTH));
+    }
+
+    let prefix: Vec<String> = prefix.unwrap_or_default()
+        .split('/')
+        .map(kebab)
+        .filter(|component| !component.is_empty())
+        .collect();
+    let branch = prefix.into_iter().chain(std::iter::once(slug)).collect::<Vec<_>>().join("/");
+
+    if branch.ends_with('/') || !Reference::is_valid_name(&format!("refs/heads/{}", branch)) {
+        return Err(anyhow!("Could not make a valid branch name from '{}'", name));
+    }
+    Ok(branch)
+}
+
+/// Create a branch at HEAD and switch to it, keeping the working tree and
+/// index as they are
+pub fn create_and_checkout_branch(repo: &Repository, name: &str) -> Result<()> {
+    let refname = format!("refs/heads/{}", name);
+    if repo.find_reference(&refname).is_ok() {
+        return Err(anyhow!("Branch '{}' already exists", name));
+    }
+    // On an unborn HEAD there is no commit to branch from; pointing HEAD at
+    // the new name makes the first commit create it
+    if let Ok(head) = repo.head().and_then(|head| head.peel_to_commit()) {
+        repo.branch(name, &head, false)?;
+    }
+    repo.set_head(&refname)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_branch_name() {
+        assert_eq!(sanitize_branch_name("Fix auth token refresh", None).unwrap(), "fix-auth-token-refresh");
+        assert_eq!(sanitize_branch_name("`add_CSV export!`\n", Some("feature/")).unwrap(), "feature/add-csv-export");
+        assert_eq!(sanitize_branch_name("fix..lock ~^:?*[\\ @{", Some("Fix")).unwrap(), "fix/fix-lock");
+        assert!(sanitize_branch_name("!!!", None).is_err());
+    }
+}
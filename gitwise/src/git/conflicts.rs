@@ -0,0 +1,57 @@
+use anyhow::{Result, anyhow};
+use git2::{IndexEntry, Repository};
+
+/// A file with unresolved merge conflicts and the content of each side
+pub struct Conflict {
+    pub path: String,
+    /// Content in the common ancestor, if the file existed there
+    pub ancestor: Option<String>,
+    /// Content on the current branch
+    pub ours: Option<String>,
+    /// Content on the branch being merged in
+    pub theirs: Option<String>,
+    /// Whether any side is binary, making the text content meaningless
+    pub binary: bool,
+}
+
+/// Get all conflicted files in the index
+pub fn get_conflicts(repo: &Repository) -> Result<Vec<Conflict>> {
+    let index = repo.index()?;
+    let mut conflicts = Vec::new();
+
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        let path = [&conflict.our, &conflict.their, &conflict.ancestor]
+            .into_iter()
+            .flatten()
+            .next()
+            .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+            .ok_or_else(|| anyhow!("Conflict entry without a path"))?;
+
+        let mut binary = false;
+        for entry in [&conflict.ancestor, &conflict.our, &conflict.their].into_iter().flatten() {
+            binary |= repo.find_blob(entry.id)?.is_binary();
+        }
+
+        conflicts.push(Conflict {
+            path,
+            ancestor: read_entry(repo, conflict.ancestor.as_ref())?,
+            ours: read_entry(repo, conflict.our.as_ref())?,
+            theirs: read_entry(repo, conflict.their.as_ref())?,
+            binary,
+        });
+    }
+
+    Ok(conflicts)
+}
+
+/// Read the blob behind an index entry as text
+fn read_entry(repo: &Repository, entry: Option<&IndexEntry>) -> Result<Option<String>> {
+    match entry {
+        Some(entry) => {
+            let blob = repo.find_blob(entry.id)?;
+            Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+        }
+        None => Ok(None),
+    }
+}
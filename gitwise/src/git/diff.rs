@@ -1,5 +1,145 @@
-use anyhow::Result;
-use git2::{Diff, Repository};
+use anyhow::{Context, Result};
+use git2::{Delta, Diff, DiffFindOptions, DiffFormat, DiffOptions, Oid, Patch, Repository};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+/// Algorithm used to compute diffs, as in `git diff --diff-algorithm`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiffAlgorithm {
+    /// Git's default
+    #[default]
+    Myers,
+    /// Cleaner hunks for moved and reordered code
+    Patience,
+    /// Spend extra time to find the smallest diff
+    Minimal,
+}
+
+/// Diff settings chosen on the command line, applied to every diff
+#[derive(Debug, Clone, Default)]
+pub struct DiffSettings {
+    /// Unchanged lines shown around each change, git's 3 when unset
+    pub context_lines: Option<u32>,
+    /// Drop lines whose only change is whitespace
+    pub ignore_whitespace: bool,
+    pub algorithm: DiffAlgorithm,
+    /// Subtree every diff is limited to, e.g. `services/payments`
+    pub scope: Option<String>,
+}
+
+static DIFF_SETTINGS: OnceLock<DiffSettings> = OnceLock::new();
+
+/// Set the diff settings once, before any diff is made
+pub fn configure_diffs(settings: DiffSettings) {
+    let _ = DIFF_SETTINGS.set(settings);
+}
+
+/// The subtree diffs are limited to, if any
+pub fn scope() -> Option<&'static str> {
+    DIFF_SETTINGS.get_or_init(DiffSettings::default).scope.as_deref()
+}
+
+/// Fresh diff options with the configured diff settings applied
+pub fn diff_options() -> DiffOptions {
+    pathspec_options(&[], &[])
+}
+
+/// Diff options with the configured settings but no pathspecs
+fn unscoped_diff_options() -> DiffOptions {
+    let mut opts = DiffOptions::new();
+    let settings = DIFF_SETTINGS.get_or_init(DiffSettings::default);
+    if let Some(lines) = settings.context_lines {
+        opts.context_lines(lines);
+    }
+    if settings.ignore_whitespace {
+        opts.ignore_whitespace(true).ignore_whitespace_change(true);
+    }
+    match settings.algorithm {
+        DiffAlgorithm::Myers => (),
+        DiffAlgorithm::Patience => {
+            opts.patience(true);
+        }
+        DiffAlgorithm::Minimal => {
+            opts.minimal(true);
+        }
+    }
+    opts
+}
+
+/// Pair up deleted and added files that are renames or copies of each other
+///
+/// Without this a moved file shows as a full deletion plus a full addition,
+/// doubling its size and hiding that it was only moved.
+pub fn detect_renames(diff: &mut Diff<'_>) -> Result<()> {
+    let mut opts = DiffFindOptions::new();
+    opts.renames(true).copies(true);
+    diff.find_similar(Some(&mut opts))?;
+    Ok(())
+}
+
+/// Whether every change in a non-empty diff disappeared because only
+/// whitespace changed
+///
+/// Ignoring whitespace drops the hunks but not the files, so such a diff
+/// still lists modified files with nothing left to show.
+pub fn whitespace_only(diff: &Diff<'_>) -> Result<bool> {
+    Ok(diff.deltas().len() > 0 && unchanged_files(diff)?.len() == diff.deltas().len())
+}
+
+/// Files a diff lists as modified but with no change left to show, such as
+/// ones whose only edits were whitespace when whitespace is ignored
+pub fn unchanged_files(diff: &Diff<'_>) -> Result<Vec<String>> {
+    let mut unchanged = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        if !is_unchanged(diff, i)? {
+            continue;
+        }
+        if let Some(path) = delta.new_file().path() {
+            unchanged.push(path.display().to_string());
+        }
+    }
+    Ok(unchanged)
+}
+
+/// The diff without the files `unchanged_files` lists
+///
+/// The remaining files' patches are parsed back into a new diff, so the
+/// diff is not recomputed and pathspecs are left alone.
+pub fn without_unchanged_files(diff: &Diff<'_>) -> Result<Diff<'static>> {
+    let mut text = Vec::new();
+    for i in 0..diff.deltas().len() {
+        if is_unchanged(diff, i)? {
+            continue;
+        }
+        if let Some(mut patch) = Patch::from_diff(diff, i)? {
+            text.extend_from_slice(&patch.to_buf()?);
+        }
+    }
+    Ok(Diff::from_buffer(&text)?)
+}
+
+/// Whether the `index`th file of a diff is modified with no change left
+fn is_unchanged(diff: &Diff<'_>, index: usize) -> Result<bool> {
+    let Some(delta) = diff.get_delta(index) else {
+        return Ok(false);
+    };
+    let same_file = delta.status() == Delta::Modified
+        && delta.old_file().mode() == delta.new_file().mode()
+        && !delta.flags().is_binary();
+    Ok(same_file && Patch::from_diff(diff, index)?.is_none_or(|patch| patch.num_hunks() == 0))
+}
+
+/// Hash of a diff's full patch text, to tell whether anything changed
+/// between two diffs of the same files
+pub fn diff_fingerprint(diff: &Diff<'_>) -> Result<String> {
+    let mut hasher = Sha256::new();
+    diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+        hasher.update([line.origin() as u8]);
+        hasher.update(line.content());
+        true
+    })?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
 pub fn get_branch_diff<'a>(repo: &'a Repository, source: &str, target: &str) -> Result<Diff<'a>> {
     let source_branch = repo.find_branch(source, git2::BranchType::Local)?;
@@ -8,11 +148,212 @@ pub fn get_branch_diff<'a>(repo: &'a Repository, source: &str, target: &str) ->
     let source_tree = source_branch.get().peel_to_tree()?;
     let target_tree = target_branch.get().peel_to_tree()?;
     
-    let diff = repo.diff_tree_to_tree(
+    let mut diff = repo.diff_tree_to_tree(
         Some(&source_tree),
         Some(&target_tree),
-        None,
+        Some(&mut diff_options()),
     )?;
+    detect_renames(&mut diff)?;
     
     Ok(diff)
 }
+
+/// Diff what `from` adds since it forked from `to`, as a pull request shows it
+///
+/// The diff runs from the merge base of the two commits to `from`, so changes
+/// that landed on `to` separately are left out.
+pub fn get_merge_base_diff<'a>(repo: &'a Repository, from: Oid, to: Oid, opts: &mut DiffOptions) -> Result<Diff<'a>> {
+    let base = repo.merge_base(from, to)
+        .with_context(|| format!("{} and {} have no common ancestor", from, to))?;
+    let base_tree = repo.find_commit(base)?.tree()?;
+    let from_tree = repo.find_commit(from)?.tree()?;
+
+    let mut diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&from_tree), Some(opts))?;
+    detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// List changed files with their single-letter status, as in `git diff --name-status`
+pub fn get_name_status(diff: &Diff<'_>) -> Vec<(char, String)> {
+    diff.deltas()
+        .map(|delta| {
+            let status = match delta.status() {
+                Delta::Added => 'A',
+                Delta::Deleted => 'D',
+                Delta::Modified => 'M',
+                Delta::Renamed => 'R',
+                Delta::Copied => 'C',
+                Delta::Typechange => 'T',
+                Delta::Untracked => '?',
+                _ => 'X',
+            };
+            let path = delta.new_file().path()
+                .or_else(|| delta.old_file().path())
+                .map(|p| p.display().to_string())
+                .unwrap_or_default();
+            (status, path)
+        })
+        .collect()
+}
+
+/// Diff options limited to paths matching `include` and not matching `exclude`
+///
+/// Patterns are git pathspecs such as `Cargo.lock`, `*.min.js` or `dist`.
+/// Exclusions take precedence; with no inclusions every other path is kept.
+/// Under `--scope` only paths in the scope are kept.
+pub fn pathspec_options(include: &[String], exclude: &[String]) -> DiffOptions {
+    let mut opts = unscoped_diff_options();
+    for pathspec in pathspecs(include, exclude, scope()) {
+        opts.pathspec(pathspec);
+    }
+    opts
+}
+
+/// The pathspecs for `pathspec_options`
+///
+/// libgit2 keeps a path if any pathspec matches, so a scope is applied by
+/// moving inclusions inside it rather than adding it alongside them.
+fn pathspecs(include: &[String], exclude: &[String], scope: Option<&str>) -> Vec<String> {
+    let scope = scope.map(|scope| scope.trim_end_matches('/'));
+    if include.is_empty() && exclude.is_empty() && scope.is_none() {
+        return Vec::new();
+    }
+    // libgit2 applies the first pathspec that matches, so negations go first
+    let mut pathspecs: Vec<String> = exclude.iter().map(|pattern| format!("!{}", pattern)).collect();
+    match scope {
+        Some(scope) if include.is_empty() => pathspecs.push(scope.to_string()),
+        Some(scope) => pathspecs.extend(include.iter().map(|pattern| {
+            if pattern.starts_with(&format!("{}/", scope)) {
+                pattern.clone()
+            } else {
+                format!("{}/{}", scope, pattern.trim_start_matches('/'))
+            }
+        })),
+        None if include.is_empty() => pathspecs.push("*".to_string()),
+        None => pathspecs.extend(include.iter().cloned()),
+    }
+    pathspecs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_whitespace_only() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let blob = |content: &str| repo.blob(content.as_bytes()).unwrap();
+        let tree = |content: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("lib.rs", blob(content), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let old = tree("fn main() {\n    run();\n}\n");
+
+        let mut opts = DiffOptions::new();
+        opts.ignore_whitespace(true).ignore_whitespace_change(true);
+        let reformatted = repo.diff_tree_to_tree(Some(&old), Some(&tree("fn main() {\n\trun();\n}\n")), Some(&mut opts)).unwrap();
+        assert!(whitespace_only(&reformatted).unwrap());
+
+        let mut opts = DiffOptions::new();
+        opts.ignore_whitespace(true).ignore_whitespace_change(true);
+        let changed = repo.diff_tree_to_tree(Some(&old), Some(&tree("fn main() {\n\tstop();\n}\n")), Some(&mut opts)).unwrap();
+        assert!(!whitespace_only(&changed).unwrap());
+        assert!(unchanged_files(&changed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unchanged_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = |files: &[(&str, &str)]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            for (path, content) in files {
+                builder.insert(path, repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+            }
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let old = tree(&[("a.rs", "fn a() {}\n"), ("b.rs", "fn b() {}\n")]);
+        let new = tree(&[("a.rs", "fn  a() {}\n"), ("b.rs", "fn b() { todo!() }\n"), ("c.rs", "\n")]);
+
+        let mut opts = DiffOptions::new();
+        opts.ignore_whitespace(true);
+        let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), Some(&mut opts)).unwrap();
+        assert_eq!(unchanged_files(&diff).unwrap(), vec!["a.rs"]);
+        assert!(!whitespace_only(&diff).unwrap());
+
+        let net = without_unchanged_files(&diff).unwrap();
+        let paths: Vec<String> = net.deltas().map(|delta| delta.new_file().path().unwrap().display().to_string()).collect();
+        assert_eq!(paths, vec!["b.rs", "c.rs"]);
+        assert_eq!(net.stats().unwrap().insertions(), 2);
+    }
+
+    #[test]
+    fn test_diff_fingerprint() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = |content: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("lib.rs", repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let old = tree("fn main() {}\n");
+        let fingerprint = |content: &str| {
+            diff_fingerprint(&repo.diff_tree_to_tree(Some(&old), Some(&tree(content)), None).unwrap()).unwrap()
+        };
+
+        assert_eq!(fingerprint("fn main() { run(); }\n"), fingerprint("fn main() { run(); }\n"));
+        assert_ne!(fingerprint("fn main() { run(); }\n"), fingerprint("fn main() { stop(); }\n"));
+    }
+
+    #[test]
+    fn test_detect_renames() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let blob = repo.blob("fn main() {\n    run();\n}\n".as_bytes()).unwrap();
+        let tree = |path: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert(path, blob, 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+
+        let mut diff = repo.diff_tree_to_tree(Some(&tree("a.rs")), Some(&tree("b.rs")), None).unwrap();
+        assert_eq!(diff.deltas().len(), 2);
+        detect_renames(&mut diff).unwrap();
+        assert_eq!(get_name_status(&diff), vec![('R', "b.rs".to_string())]);
+    }
+
+    #[test]
+    fn test_merge_base_diff_ignores_target_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let commit = |files: &[&str], parents: &[Oid]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            for path in files {
+                builder.insert(path, repo.blob(path.as_bytes()).unwrap(), 0o100644).unwrap();
+            }
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents: Vec<_> = parents.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+            repo.commit(None, &signature, &signature, "Commit", &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+        };
+        let base = commit(&["lib.rs"], &[]);
+        let feature = commit(&["lib.rs", "feature.rs"], &[base]);
+        let main = commit(&["lib.rs", "hotfix.rs"], &[base]);
+
+        let diff = get_merge_base_diff(&repo, feature, main, &mut DiffOptions::new()).unwrap();
+        assert_eq!(get_name_status(&diff), vec![('A', "feature.rs".to_string())]);
+    }
+
+    #[test]
+    fn test_scope_pathspecs() {
+        // Inclusions are moved inside the scope rather than widening it
+        assert_eq!(
+            pathspecs(&["*.md".to_string(), "services/payments/src".to_string()], &["*.lock".to_string()], Some("services/payments")),
+            vec!["!*.lock", "services/payments/*.md", "services/payments/src"]
+        );
+        assert_eq!(pathspecs(&[], &[], None), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context, Result, anyhow};
+use git2::Repository;
+use std::fs;
+use std::path::PathBuf;
+
+/// Marker identifying hooks written by gitwise
+const HOOK_MARKER: &str = "# Installed by gitwise";
+
+/// The prepare-commit-msg hook that pre-fills plain `git commit` messages
+const PREPARE_COMMIT_MSG: &str = r#"#!/bin/sh
+# Installed by gitwise; remove with `gitwise uninstall-hook`
+command -v gitwise >/dev/null 2>&1 || exit 0
+if [ -z "$OPENAI_API_KEY" ] && [ -z "$ANTHROPIC_API_KEY" ] && [ -z "$AZURE_OPENAI_API_KEY" ]; then
+    exit 0
+fi
+exec gitwise commit --hook "$1" ${2:+--hook-source "$2"}
+"#;
+
+/// Path of the prepare-commit-msg hook, honoring `core.hooksPath`
+fn hook_path(repo: &Repository) -> Result<PathBuf> {
+    let hooks_dir = match repo.config()?.get_path("core.hooksPath") {
+        Ok(dir) if dir.is_absolute() => dir,
+        Ok(dir) => repo.workdir().unwrap_or_else(|| repo.path()).join(dir),
+        Err(_) => repo.path().join("hooks"),
+    };
+    Ok(hooks_dir.join("prepare-commit-msg"))
+}
+
+/// Write the prepare-commit-msg hook, refusing to replace another hook
+/// unless `force` is set
+pub fn install_hook(repo: &Repository, force: bool) -> Result<PathBuf> {
+    let path = hook_path(repo)?;
+    if path.exists() && !force {
+        return Err(anyhow!("{} already exists; use --force to replace it", path.display()));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, PREPARE_COMMIT_MSG)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+/// Remove the prepare-commit-msg hook if gitwise installed it
+pub fn uninstall_hook(repo: &Repository) -> Result<PathBuf> {
+    let path = hook_path(repo)?;
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("No hook installed at {}", path.display()))?;
+    if !contents.contains(HOOK_MARKER) {
+        return Err(anyhow!("{} was not installed by gitwise; leaving it in place", path.display()));
+    }
+    fs::remove_file(&path)?;
+    Ok(path)
+}
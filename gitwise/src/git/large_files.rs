@@ -0,0 +1,79 @@
+use anyhow::Result;
+use git2::{Delta, Diff, Repository};
+
+/// Default size above which a staged file is flagged
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+/// Size above which a binary file is flagged as belonging in Git LFS
+const LFS_BINARY_BYTES: u64 = 1024 * 1024;
+
+/// A file a diff adds or changes that is too large to commit comfortably
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargeFile {
+    pub path: String,
+    pub size: u64,
+    pub binary: bool,
+}
+
+impl LargeFile {
+    /// Size in megabytes to one decimal, such as `12.5 MB`
+    pub fn size_label(&self) -> String {
+        format!("{:.1} MB", self.size as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Files a diff adds or modifies that are over `max_bytes`, or binary and
+/// large enough that they probably belong in Git LFS
+///
+/// LFS pointer files are small text files, so files already tracked with
+/// LFS are never flagged.
+pub fn detect_large_files(repo: &Repository, diff: &Diff<'_>, max_bytes: u64) -> Result<Vec<LargeFile>> {
+    let mut large = Vec::new();
+    for delta in diff.deltas() {
+        if !matches!(delta.status(), Delta::Added | Delta::Modified | Delta::Renamed | Delta::Copied | Delta::Typechange) {
+            continue;
+        }
+        let file = delta.new_file();
+        // Submodules and files whose content is not in the object database
+        let Ok(blob) = repo.find_blob(file.id()) else {
+            continue;
+        };
+        let size = blob.size() as u64;
+        let binary = blob.is_binary();
+        if size > max_bytes || binary && size > LFS_BINARY_BYTES {
+            large.push(LargeFile {
+                path: file.path().map(|p| p.display().to_string()).unwrap_or_default(),
+                size,
+                binary,
+            });
+        }
+    }
+    Ok(large)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_large_and_binary_files_are_flagged() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        let text = "line\n".repeat(1000);
+        let binary = vec![0u8; 3 * 512 * 1024];
+        builder.insert("notes.txt", repo.blob(text.as_bytes()).unwrap(), 0o100644).unwrap();
+        builder.insert("model.bin", repo.blob(&binary).unwrap(), 0o100644).unwrap();
+        builder.insert("small.txt", repo.blob(b"hello\n").unwrap(), 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+
+        let model = LargeFile { path: "model.bin".to_string(), size: 3 * 512 * 1024, binary: true };
+        assert_eq!(detect_large_files(&repo, &diff, DEFAULT_MAX_FILE_BYTES).unwrap(), vec![model.clone()]);
+        assert_eq!(model.size_label(), "1.5 MB");
+        assert_eq!(detect_large_files(&repo, &diff, 4000).unwrap(), vec![
+            model,
+            LargeFile { path: "notes.txt".to_string(), size: 5000, binary: false },
+        ]);
+    }
+}
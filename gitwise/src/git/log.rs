@@ -1,7 +1,14 @@
 use anyhow::Result;
-use git2::{Repository, Commit, DiffOptions};
+use git2::{Repository, Commit, Diff, DiffOptions, ErrorCode, Oid};
+use regex::Regex;
+
+/// Files of a reverted or cherry-picked commit listed in its context
+const MAX_REPLAYED_FILES: usize = 20;
 
 /// Get commits in a branch with their diffs
+///
+/// At most `limit` commits (10 by default) are walked. A HEAD with no
+/// commits yet has an empty history rather than being an error.
 pub fn get_log<'a>(repo: &'a Repository, branch_name: Option<&str>, limit: Option<u32>) -> Result<Vec<Commit<'a>>> {
     let mut revwalk = repo.revwalk()?;
     
@@ -10,6 +17,10 @@ pub fn get_log<'a>(repo: &'a Repository, branch_name: Option<&str>, limit: Optio
         let branch_id = repo.find_branch(branch, git2::BranchType::Local)?.get().peel_to_commit()?.id();
         revwalk.push(branch_id)?;
     } else {
+        match repo.head() {
+            Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => return Ok(Vec::new()),
+            result => result?,
+        };
         revwalk.push_head()?;
     }
 
@@ -28,21 +39,354 @@ pub fn get_log<'a>(repo: &'a Repository, branch_name: Option<&str>, limit: Optio
     Ok(commits)
 }
 
+/// Get the commits reachable from `to` but not from `from`, newest first
+pub fn get_commit_range<'a>(repo: &'a Repository, from: Oid, to: Oid, include_merges: bool) -> Result<Vec<Commit<'a>>> {
+    walk_commits(repo, Some(from), to, include_merges)
+}
+
+/// Get every commit reachable from `to`, down to the root commit, newest first
+pub fn get_history<'a>(repo: &'a Repository, to: Oid, include_merges: bool) -> Result<Vec<Commit<'a>>> {
+    walk_commits(repo, None, to, include_merges)
+}
+
+fn walk_commits<'a>(repo: &'a Repository, from: Option<Oid>, to: Oid, include_merges: bool) -> Result<Vec<Commit<'a>>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(to)?;
+    if let Some(from) = from {
+        revwalk.hide(from)?;
+    }
+
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        if include_merges || commit.parent_count() <= 1 {
+            commits.push(commit);
+        }
+    }
+
+    Ok(commits)
+}
+
 /// Get the diff for a commit
 pub fn get_commit_diff<'a>(repo: &'a Repository, commit: &Commit<'a>) -> Result<git2::Diff<'a>> {
     let parent = commit.parent(0).ok();
     let tree = commit.tree()?;
     let parent_tree = parent.and_then(|p| p.tree().ok());
 
-    let mut opts = DiffOptions::new();
-    opts.context_lines(3)
-        .patience(true)
+    let mut opts = super::diff_options();
+    opts.patience(true)
         .minimal(true);
 
-    let diff = match parent_tree {
+    let mut diff = match parent_tree {
         Some(parent_tree) => repo.diff_tree_to_tree(Some(&parent_tree), Some(&tree), Some(&mut opts))?,
         None => repo.diff_tree_to_tree(None, Some(&tree), Some(&mut opts))?,
     };
+    super::detect_renames(&mut diff)?;
 
     Ok(diff)
 }
+
+/// Diff a merge commit against the merge base of its parents
+///
+/// The first-parent diff hides what the other side of the merge did; from
+/// the merge base, the changes of every side are covered combined. Parents
+/// with unrelated histories fall back to the first parent. Returns `None`
+/// for commits that are not merges.
+pub fn get_merge_diff<'a>(repo: &'a Repository, commit: &Commit<'a>, opts: &mut DiffOptions) -> Result<Option<Diff<'a>>> {
+    if commit.parent_count() < 2 {
+        return Ok(None);
+    }
+    let parents: Vec<Oid> = commit.parent_ids().collect();
+    let base = match repo.merge_base_many(&parents) {
+        Ok(base) => base,
+        Err(e) if e.code() == ErrorCode::NotFound => parents[0],
+        Err(e) => return Err(e.into()),
+    };
+    let base_tree = repo.find_commit(base)?.tree()?;
+    let mut diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&commit.tree()?), Some(opts))?;
+    super::detect_renames(&mut diff)?;
+    Ok(Some(diff))
+}
+
+/// The newest `count` commits from `head` with their diffs, newest first
+///
+/// Each diff is taken with fresh `opts`, from the merge base for merges so
+/// every side shows. With `skip_untouched`, commits whose diff is empty are
+/// passed over rather than counted, as `--scope` does for commits outside
+/// its subproject.
+pub fn recent_commit_diffs<'a>(
+    repo: &'a Repository,
+    head: Oid,
+    count: usize,
+    skip_untouched: bool,
+    mut opts: impl FnMut() -> DiffOptions,
+) -> Result<Vec<(Commit<'a>, Diff<'a>)>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        if commits.len() == count {
+            break;
+        }
+        let commit = repo.find_commit(oid?)?;
+        let mut opts = opts();
+        let diff = match get_merge_diff(repo, &commit, &mut opts)? {
+            Some(diff) => diff,
+            None => {
+                let parent_tree = commit.parent(0).ok().map(|c| c.tree()).transpose()?;
+                let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), Some(&mut opts))?;
+                super::detect_renames(&mut diff)?;
+                diff
+            }
+        };
+        if skip_untouched && diff.deltas().len() == 0 {
+            continue;
+        }
+        commits.push((commit, diff));
+    }
+    Ok(commits)
+}
+
+/// Label naming the parents of a merge commit by short id, e.g.
+/// `merge of 1a2b3c4 and 5d6e7f8`, or `None` for other commits
+pub fn merge_label(commit: &Commit<'_>) -> Option<String> {
+    if commit.parent_count() < 2 {
+        return None;
+    }
+    let parents: Vec<String> = commit.parent_ids().map(|id| id.to_string()[..7].to_string()).collect();
+    let (last, rest) = parents.split_last()?;
+    Some(format!("merge of {} and {}", rest.join(", "), last))
+}
+
+/// Summary line for a commit whose diff is empty, such as one made with
+/// `git commit --allow-empty`, so it can be reported without asking the AI
+pub fn empty_commit_summary(commit: &Commit<'_>, diff: &Diff<'_>) -> Option<String> {
+    if diff.deltas().len() > 0 {
+        return None;
+    }
+    Some(format!("[empty commit] {}", commit.summary().unwrap_or_default()))
+}
+
+/// How a commit replays another one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayKind {
+    /// Made by `git revert`, undoing the other commit
+    Revert,
+    /// Made by `git cherry-pick -x`, applying the other commit again
+    CherryPick,
+}
+
+/// The commit a revert or cherry-pick refers to, as named in its message
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayedCommit {
+    pub kind: ReplayKind,
+    /// Hash as written in the message, possibly abbreviated
+    pub hash: String,
+}
+
+/// Find the `This reverts commit <hash>.` line git writes for reverts, or
+/// the `(cherry picked from commit <hash>)` trailer of `cherry-pick -x`
+pub fn find_replayed_commit(message: &str) -> Option<ReplayedCommit> {
+    let patterns = [
+        (ReplayKind::Revert, r"This reverts commit ([0-9a-f]{7,40})\b"),
+        (ReplayKind::CherryPick, r"\(cherry picked from commit ([0-9a-f]{7,40})\)"),
+    ];
+    patterns.into_iter().find_map(|(kind, pattern)| {
+        let captures = Regex::new(pattern).expect("replay pattern is valid").captures(message)?;
+        Some(ReplayedCommit { kind, hash: captures[1].to_string() })
+    })
+}
+
+/// Instructions telling the model what a revert or cherry-pick replays, or
+/// `None` for other commits
+///
+/// The original commit's message and files are included when it exists
+/// locally; otherwise the model is told it is missing.
+pub fn replay_context(repo: &Repository, commit: &Commit<'_>) -> Option<String> {
+    let replayed = find_replayed_commit(commit.message()?)?;
+    let short = &replayed.hash[..7];
+    let original = repo.revparse_single(&replayed.hash).and_then(|object| object.peel_to_commit()).ok();
+    let description = match &original {
+        Some(original) => {
+            let mut files: Vec<String> = get_commit_diff(repo, original)
+                .map(|diff| super::get_name_status(&diff).into_iter().map(|(_, path)| path).collect())
+                .unwrap_or_default();
+            if files.len() > MAX_REPLAYED_FILES {
+                let more = files.len() - MAX_REPLAYED_FILES;
+                files.truncate(MAX_REPLAYED_FILES);
+                files.push(format!("({} more)", more));
+            }
+            format!(
+                "commit {}, whose message was:\n{}\nIt changed: {}",
+                short,
+                original.message().unwrap_or_default().trim(),
+                files.join(", ")
+            )
+        }
+        None => format!("commit {}, which is not available locally, so what it did can only be inferred from this diff", short),
+    };
+    Some(match replayed.kind {
+        ReplayKind::Revert => format!(
+            "This commit reverts {}\nStart the summary with \"This reverts commit {} which ...\", saying what that commit did, then describe what undoing it changes.",
+            description, short
+        ),
+        ReplayKind::CherryPick => format!(
+            "This commit is a cherry-pick of {}\nStart the summary with \"This cherry-picks commit {} which ...\", saying what that commit did, and point out anything this diff does differently.",
+            description, short
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_empty_commit_summary() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+
+        let mut index = repo.index().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "a\n").unwrap();
+        index.add_path(std::path::Path::new("a.txt")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let first = repo.commit(Some("HEAD"), &signature, &signature, "Add a", &tree, &[]).unwrap();
+        let first = repo.find_commit(first).unwrap();
+        let empty = repo.commit(Some("HEAD"), &signature, &signature, "Trigger CI", &tree, &[&first]).unwrap();
+        let empty = repo.find_commit(empty).unwrap();
+
+        let diff = get_commit_diff(&repo, &empty).unwrap();
+        assert_eq!(empty_commit_summary(&empty, &diff).as_deref(), Some("[empty commit] Trigger CI"));
+
+        let diff = get_commit_diff(&repo, &first).unwrap();
+        assert_eq!(empty_commit_summary(&first, &diff), None);
+    }
+
+    #[test]
+    fn test_recent_commit_diffs_skip_untouched_commits() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let commit = |path: &str, time: i64| {
+            let signature = git2::Signature::new("Test", "test@example.com", &git2::Time::new(time, 0)).unwrap();
+            let file = temp_dir.path().join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, path).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new(path)).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+            repo.commit(Some("HEAD"), &signature, &signature, path, &tree, &parent.iter().collect::<Vec<_>>()).unwrap()
+        };
+        commit("services/payments/src/lib.rs", 1700000000);
+        commit("services/billing/src/lib.rs", 1700000001);
+        commit("services/payments/README.md", 1700000002);
+        let head = commit("services/billing/README.md", 1_700_000_003);
+        let scoped = || {
+            let mut opts = DiffOptions::new();
+            opts.pathspec("services/payments");
+            opts
+        };
+        let subjects = |commits: Vec<(Commit<'_>, Diff<'_>)>| -> Vec<String> {
+            commits.iter().map(|(commit, _)| commit.summary().unwrap().to_string()).collect()
+        };
+
+        let touched = recent_commit_diffs(&repo, head, 2, true, scoped).unwrap();
+        assert_eq!(subjects(touched), vec!["services/payments/README.md", "services/payments/src/lib.rs"]);
+        let all = recent_commit_diffs(&repo, head, 2, false, scoped).unwrap();
+        assert_eq!(all[0].1.deltas().len(), 0);
+        assert_eq!(subjects(all), vec!["services/billing/README.md", "services/payments/README.md"]);
+    }
+
+    #[test]
+    fn test_replay_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let mut index = repo.index().unwrap();
+        std::fs::write(temp_dir.path().join("cache.rs"), "struct Cache;\n").unwrap();
+        index.add_path(std::path::Path::new("cache.rs")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let original = repo.commit(None, &signature, &signature, "Add a response cache", &tree, &[]).unwrap();
+        let empty_tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+        let commit = |message: &str| {
+            let oid = repo.commit(None, &signature, &signature, message, &empty_tree, &[&repo.find_commit(original).unwrap()]).unwrap();
+            repo.find_commit(oid).unwrap()
+        };
+
+        let revert = commit(&format!("Revert \"Add a response cache\"\n\nThis reverts commit {}.\n", original));
+        let context = replay_context(&repo, &revert).unwrap();
+        assert!(context.starts_with(&format!("This commit reverts commit {}, whose message was:\nAdd a response cache\nIt changed: cache.rs\n", &original.to_string()[..7])));
+
+        let missing = commit("Fix retries\n\n(cherry picked from commit 0123456789abcdef0123456789abcdef01234567)\n");
+        assert_eq!(find_replayed_commit(missing.message().unwrap()), Some(ReplayedCommit {
+            kind: ReplayKind::CherryPick,
+            hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        }));
+        assert!(replay_context(&repo, &missing).unwrap().contains("commit 0123456, which is not available locally"));
+        assert_eq!(replay_context(&repo, &commit("Add logging")), None);
+    }
+
+    #[test]
+    fn test_history_includes_root_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let root = repo.commit(Some("HEAD"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+        let second = repo.commit(Some("HEAD"), &signature, &signature, "Second", &tree, &[&repo.find_commit(root).unwrap()]).unwrap();
+
+        let ids = |commits: Vec<Commit<'_>>| commits.iter().map(Commit::id).collect::<Vec<_>>();
+        assert_eq!(ids(get_history(&repo, second, false).unwrap()), vec![second, root]);
+        assert_eq!(ids(get_commit_range(&repo, root, second, false).unwrap()), vec![second]);
+    }
+
+    #[test]
+    fn test_log_of_unborn_head_is_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert!(get_log(&repo, None, Some(5)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_merge_diff_covers_both_parents() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let commit = |files: &[&str], message: &str, parents: &[&Commit<'_>]| {
+            let mut index = repo.index().unwrap();
+            for file in files {
+                std::fs::write(temp_dir.path().join(file), format!("{}\n", file)).unwrap();
+                index.add_path(std::path::Path::new(file)).unwrap();
+            }
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let oid = repo.commit(None, &signature, &signature, message, &tree, parents).unwrap();
+            repo.find_commit(oid).unwrap()
+        };
+
+        // base -> main adds c.txt, base -> feature adds b.txt, then merge both
+        let base = commit(&["a.txt"], "Add a", &[]);
+        let feature = commit(&["a.txt", "b.txt"], "Add b", &[&base]);
+        let mut index = repo.index().unwrap();
+        index.remove_path(std::path::Path::new("b.txt")).unwrap();
+        index.write().unwrap();
+        let main = commit(&["c.txt"], "Add c", &[&base]);
+        let merge = commit(&["b.txt"], "Merge branch 'feature'", &[&main, &feature]);
+
+        let paths = |diff: &Diff<'_>| -> Vec<String> {
+            diff.deltas().map(|d| d.new_file().path().unwrap().display().to_string()).collect()
+        };
+        assert_eq!(paths(&get_commit_diff(&repo, &merge).unwrap()), vec!["b.txt"]);
+        let merge_diff = get_merge_diff(&repo, &merge, &mut DiffOptions::new()).unwrap().unwrap();
+        assert_eq!(paths(&merge_diff), vec!["b.txt", "c.txt"]);
+        assert!(get_merge_diff(&repo, &main, &mut DiffOptions::new()).unwrap().is_none());
+
+        let label = merge_label(&merge).unwrap();
+        assert_eq!(label, format!("merge of {} and {}", &main.id().to_string()[..7], &feature.id().to_string()[..7]));
+        assert_eq!(merge_label(&main), None);
+    }
+}
@@ -1,10 +1,28 @@
+mod blame;
+mod branch;
+pub mod conflicts;
 mod diff;
+pub mod hooks;
+mod large_files;
 mod log;
 pub mod staging;
 pub mod pr;
+mod refs;
+mod remote;
+mod rewrite;
+mod secrets;
+mod state;
 
 // Re-export commonly used items
+pub use blame::*;
+pub use branch::*;
 pub use diff::*;
+pub use large_files::*;
 pub use log::*;
 pub use staging::*;
 pub use pr::*;
+pub use refs::*;
+pub use remote::*;
+pub use rewrite::*;
+pub use secrets::*;
+pub use state::*;
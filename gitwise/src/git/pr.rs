@@ -1,8 +1,9 @@
 use std::process::Command;
 use anyhow::{Result, anyhow};
 use git2::Repository;
-use crate::ai::AiEngine;
+use crate::ai::{AiEngine, PrDescription};
 
+#[derive(Default)]
 pub struct PullRequest {
     pub title: Option<String>,
     pub body: Option<String>,
@@ -33,10 +34,10 @@ impl PullRequest {
         self
     }
 
-    pub async fn create(&self) -> Result<()> {
-        let repo = Repository::open_from_env()?;
-        let ai = AiEngine::new()?;
-
+    /// Draft the PR title and description without creating the PR
+    ///
+    /// A custom title or body overrides the corresponding AI-generated part.
+    pub async fn draft(&self, repo: &Repository, ai: &AiEngine) -> Result<PrDescription> {
         // Get the diff between the current branch and the base branch
         let head = repo.head()?.peel_to_commit()?;
         let base_branch = self.base.as_deref().unwrap_or("main");
@@ -49,36 +50,48 @@ impl PullRequest {
             return Err(anyhow!("Base branch '{}' not found", base_branch));
         };
 
-        let diff = repo.diff_tree_to_tree(
+        let mut diff = repo.diff_tree_to_tree(
             Some(&base_commit.tree()?),
             Some(&head.tree()?),
-            None,
+            Some(&mut super::diff_options()),
         )?;
+        super::detect_renames(&mut diff)?;
 
-        // Generate PR title and description using AI if not provided
-        let title = match &self.title {
-            Some(t) => t.clone(),
-            None => {
-                let commit_msg = ai.generate_commit_message(&diff).await?;
-                // Extract first line as title
-                commit_msg.lines().next()
-                    .ok_or_else(|| anyhow!("Failed to generate PR title"))?
-                    .to_string()
-            }
-        };
+        if let (Some(title), Some(body)) = (&self.title, &self.body) {
+            return Ok(PrDescription { title: title.clone(), body: body.clone() });
+        }
 
-        let body = match &self.body {
-            Some(b) => b.clone(),
-            None => {
-                ai.summarize_diff(&diff, Some("Generate a detailed pull request description that explains the changes, their purpose, and any important implementation details. Include a high-level summary at the start.")).await?
+        // Commit subjects on the branch give the model the author's intent
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(head.id())?;
+        revwalk.hide(base_commit.id())?;
+        let mut subjects = Vec::new();
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            if let Some(summary) = commit.summary() {
+                subjects.push(summary.to_string());
             }
-        };
+        }
+
+        let generated = ai.generate_pr_description(&diff, &subjects).await?;
+
+        Ok(PrDescription {
+            title: self.title.clone().unwrap_or(generated.title),
+            body: self.body.clone().unwrap_or(generated.body),
+        })
+    }
+
+    pub async fn create(&self, repo: &Repository, ai: &AiEngine) -> Result<()> {
+        let description = self.draft(repo, ai).await?;
 
         let mut command = Command::new("gh");
         command.arg("pr").arg("create");
+        if let Some(workdir) = repo.workdir() {
+            command.current_dir(workdir);
+        }
         
-        command.arg("--title").arg(&title);
-        command.arg("--body").arg(&body);
+        command.arg("--title").arg(&description.title);
+        command.arg("--body").arg(&description.body);
         
         if let Some(base) = &self.base {
             command.arg("--base").arg(base);
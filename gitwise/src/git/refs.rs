@@ -0,0 +1,191 @@
+use anyhow::{Result, anyhow};
+use git2::{DescribeFormatOptions, DescribeOptions, ErrorCode, ObjectType, Oid, Repository};
+
+/// Resolve a git reference (branch, remote branch such as `origin/main`,
+/// tag, revision or abbreviated commit hash) to a commit
+///
+/// `@{upstream}` (or `@{u}`) is the current branch's upstream, and
+/// `<branch>@{upstream}` another branch's. An abbreviated hash shared by
+/// several commits is an error listing them, and references to blobs or
+/// trees are rejected rather than treated as commits.
+pub fn resolve_reference(repo: &Repository, reference: &str) -> Result<Oid> {
+    if let Some(branch) = upstream_of(reference) {
+        let upstream = upstream_name(repo, branch)?;
+        let target = repo.find_reference(&upstream)
+            .map_err(|_| anyhow!("The upstream {} does not exist locally; run `git fetch` first", upstream))?;
+        return Ok(target.peel_to_commit()?.id());
+    }
+
+    // Try as a direct reference first (branch, tag or remote branch)
+    if let Ok(reference) = repo.find_reference(reference).or_else(|_| repo.resolve_reference_from_short_name(reference)) {
+        return Ok(reference.peel_to_commit()?.id());
+    }
+
+    // Try as a revision (commit hash, abbreviated hash, HEAD~1, etc)
+    match repo.revparse_single(reference) {
+        Ok(object) => object.peel_to_commit()
+            .map(|commit| commit.id())
+            .map_err(|_| anyhow!("{} is a {}, not a commit", reference, object.kind().map_or("object", |kind| kind.str()))),
+        // The prefix may also match trees or blobs; only commits count
+        Err(e) if e.code() == ErrorCode::Ambiguous => {
+            let candidates = commits_with_prefix(repo, reference)?;
+            match candidates.as_slice() {
+                [oid] => Ok(*oid),
+                [] => Err(anyhow!("{} does not match any commit", reference)),
+                _ => Err(anyhow!(
+                    "Ambiguous reference {} matches {} commits:\n{}",
+                    reference,
+                    candidates.len(),
+                    candidates.iter().map(|oid| format!("  {}", oid)).collect::<Vec<_>>().join("\n")
+                )),
+            }
+        }
+        Err(_) => Err(anyhow!("Could not resolve git reference: {}", reference)),
+    }
+}
+
+/// The branch before an `@{upstream}` or `@{u}` suffix, empty for the
+/// current branch, or `None` if `reference` has no such suffix
+fn upstream_of(reference: &str) -> Option<&str> {
+    let lower = reference.to_lowercase();
+    ["@{upstream}", "@{u}"].iter()
+        .find(|suffix| lower.ends_with(*suffix))
+        .map(|suffix| &reference[..reference.len() - suffix.len()])
+}
+
+/// Full name of a branch's upstream, such as `refs/remotes/origin/main`
+///
+/// An empty `branch` (or `HEAD`) means the checked-out branch.
+pub fn upstream_name(repo: &Repository, branch: &str) -> Result<String> {
+    let branch = if branch.is_empty() || branch == "HEAD" {
+        let head = repo.head()?;
+        match head.shorthand().filter(|_| head.is_branch()) {
+            Some(name) => name.to_string(),
+            None => return Err(anyhow!("HEAD is detached, so it has no upstream; name a reference to compare against")),
+        }
+    } else {
+        branch.to_string()
+    };
+
+    let upstream = repo.branch_upstream_name(&format!("refs/heads/{}", branch)).map_err(|_| anyhow!(
+        "Branch '{}' has no upstream; set one with `git branch --set-upstream-to=origin/<branch> {}` or name a reference to compare against",
+        branch, branch
+    ))?;
+    upstream.as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("The upstream of {} is not valid UTF-8", branch))
+}
+
+/// The most recent tag reachable from HEAD, if any
+pub fn latest_tag(repo: &Repository) -> Option<String> {
+    let describe = repo.describe(DescribeOptions::new().describe_tags()).ok()?;
+    describe.format(Some(DescribeFormatOptions::new().abbreviated_size(0))).ok()
+}
+
+/// Create an annotated tag pointing at a commit, signed by the current user
+pub fn create_annotated_tag(repo: &Repository, name: &str, target: Oid, message: &str) -> Result<Oid> {
+    let signature = repo.signature()?;
+    let object = repo.find_object(target, Some(ObjectType::Commit))?;
+    Ok(repo.tag(name, &object, &signature, message, false)?)
+}
+
+/// All commits whose hash starts with `prefix`
+fn commits_with_prefix(repo: &Repository, prefix: &str) -> Result<Vec<Oid>> {
+    let prefix = prefix.to_lowercase();
+    let odb = repo.odb()?;
+    let mut matches = Vec::new();
+    odb.foreach(|oid| {
+        if oid.to_string().starts_with(&prefix) {
+            matches.push(*oid);
+        }
+        true
+    })?;
+
+    let mut commits = Vec::new();
+    for oid in matches {
+        if odb.read_header(oid)?.1 == ObjectType::Commit {
+            commits.push(oid);
+        }
+    }
+    commits.sort();
+    commits.dedup();
+    Ok(commits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_ambiguous_short_hash() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+
+        // Create commits until two share a 4-character prefix
+        let mut by_prefix: HashMap<String, Oid> = HashMap::new();
+        let (first, second) = (0..).find_map(|i| {
+            let oid = repo.commit(None, &signature, &signature, &format!("Commit {}", i), &tree, &[]).unwrap();
+            let prefix = oid.to_string()[..4].to_string();
+            by_prefix.insert(prefix, oid).map(|previous| (previous, oid))
+        }).unwrap();
+        let prefix = &first.to_string()[..4];
+
+        let error = resolve_reference(&repo, prefix).unwrap_err().to_string();
+        assert!(error.contains("Ambiguous"), "{}", error);
+        assert!(error.contains(&first.to_string()) && error.contains(&second.to_string()));
+
+        assert_eq!(resolve_reference(&repo, &first.to_string()[..12]).unwrap(), first);
+        assert!(resolve_reference(&repo, &tree.id().to_string()).is_err());
+    }
+
+    #[test]
+    fn test_latest_tag() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let signature = repo.signature().unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        assert_eq!(latest_tag(&repo), None);
+
+        let first = repo.commit(Some("HEAD"), &signature, &signature, "First", &tree, &[]).unwrap();
+        create_annotated_tag(&repo, "v1.0.0", first, "Release 1.0.0").unwrap();
+        let parent = repo.find_commit(first).unwrap();
+        let second = repo.commit(Some("HEAD"), &signature, &signature, "Second", &tree, &[&parent]).unwrap();
+        create_annotated_tag(&repo, "v1.1.0", second, "Release 1.1.0").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Third", &tree, &[&repo.find_commit(second).unwrap()]).unwrap();
+
+        assert_eq!(latest_tag(&repo).as_deref(), Some("v1.1.0"));
+        assert_eq!(resolve_reference(&repo, "v1.1.0").unwrap(), second);
+    }
+
+    #[test]
+    fn test_upstream_references() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let first = repo.commit(Some("HEAD"), &signature, &signature, "First", &tree, &[]).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Second", &tree, &[&repo.find_commit(first).unwrap()]).unwrap();
+        let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+
+        let error = resolve_reference(&repo, "@{upstream}").unwrap_err().to_string();
+        assert!(error.contains("has no upstream"), "{}", error);
+
+        repo.remote("origin", "https://example.com/repo.git").unwrap();
+        repo.reference(&format!("refs/remotes/origin/{}", branch), first, false, "fetch").unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str(&format!("branch.{}.remote", branch), "origin").unwrap();
+        config.set_str(&format!("branch.{}.merge", branch), &format!("refs/heads/{}", branch)).unwrap();
+
+        assert_eq!(resolve_reference(&repo, "@{upstream}").unwrap(), first);
+        assert_eq!(resolve_reference(&repo, "@{u}").unwrap(), first);
+        assert_eq!(resolve_reference(&repo, &format!("{}@{{u}}", branch)).unwrap(), first);
+        assert_eq!(resolve_reference(&repo, &format!("origin/{}", branch)).unwrap(), first);
+    }
+}
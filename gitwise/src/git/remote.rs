@@ -0,0 +1,121 @@
+use anyhow::{Context, Result, anyhow};
+use git2::{Cred, CredentialType, PushOptions, RemoteCallbacks, Repository};
+use std::fmt;
+
+/// Where the current branch is pushed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushTarget {
+    pub remote: String,
+    /// Local branch pushed
+    pub local_branch: String,
+    /// Branch updated on the remote
+    pub remote_branch: String,
+}
+
+impl fmt::Display for PushTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}/{}", self.local_branch, self.remote, self.remote_branch)
+    }
+}
+
+/// Work out where to push the current branch
+///
+/// Without overrides this is the branch's upstream, or a branch of the same
+/// name on `origin` when it has none.
+pub fn push_target(repo: &Repository, remote: Option<&str>, branch: Option<&str>) -> Result<PushTarget> {
+    let head = repo.head()?;
+    let local_branch = head.shorthand()
+        .filter(|_| head.is_branch())
+        .ok_or_else(|| anyhow!("HEAD is detached; check out a branch to push"))?
+        .to_string();
+    let refname = format!("refs/heads/{}", local_branch);
+
+    let upstream_remote = repo.branch_upstream_remote(&refname).ok()
+        .and_then(|name| name.as_str().map(str::to_string));
+    let upstream_branch = repo.config()?.get_string(&format!("branch.{}.merge", local_branch)).ok()
+        .map(|name| name.trim_start_matches("refs/heads/").to_string());
+
+    Ok(PushTarget {
+        remote: remote.map(str::to_string).or(upstream_remote).unwrap_or_else(|| "origin".to_string()),
+        remote_branch: branch.map(str::to_string).or(upstream_branch).unwrap_or_else(|| local_branch.clone()),
+        local_branch,
+    })
+}
+
+/// Push a local branch to its target
+///
+/// SSH remotes authenticate through ssh-agent and HTTPS remotes with the
+/// token in `GIT_TOKEN`. Refuses to push while the index has unmerged
+/// paths, and fails if the remote rejects the update.
+pub fn push_branch(repo: &Repository, target: &PushTarget) -> Result<()> {
+    if repo.index()?.has_conflicts() {
+        return Err(anyhow!("Unmerged paths remain; resolve them before pushing"));
+    }
+    let mut remote = repo.find_remote(&target.remote)
+        .with_context(|| format!("No remote named '{}'", target.remote))?;
+
+    let mut rejection = None;
+    {
+        // libgit2 keeps asking while credentials are refused, so only offer
+        // each kind once
+        let mut tried = CredentialType::empty();
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(|_url, username, allowed| {
+            let username = username.unwrap_or("git");
+            if allowed.contains(CredentialType::SSH_KEY) && !tried.contains(CredentialType::SSH_KEY) {
+                tried |= CredentialType::SSH_KEY;
+                return Cred::ssh_key_from_agent(username);
+            }
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                tried |= CredentialType::USER_PASS_PLAINTEXT;
+                if let Ok(token) = std::env::var("GIT_TOKEN") {
+                    return Cred::userpass_plaintext("x-access-token", &token);
+                }
+            }
+            if allowed.contains(CredentialType::DEFAULT) && !tried.contains(CredentialType::DEFAULT) {
+                tried |= CredentialType::DEFAULT;
+                return Cred::default();
+            }
+            Err(git2::Error::from_str("no usable credentials; start ssh-agent for SSH remotes or set GIT_TOKEN for HTTPS"))
+        });
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(status) = status {
+                rejection = Some(format!("{}: {}", refname, status));
+            }
+            Ok(())
+        });
+
+        let refspec = format!("refs/heads/{}:refs/heads/{}", target.local_branch, target.remote_branch);
+        remote.push(&[refspec.as_str()], Some(PushOptions::new().remote_callbacks(callbacks)))
+            .with_context(|| format!("Failed to push {}", target))?;
+    }
+
+    match rejection {
+        Some(reason) => Err(anyhow!("{} rejected the push ({})", target.remote, reason)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_push_branch_to_local_remote() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path().join("work")).unwrap();
+        let bare = Repository::init_bare(temp_dir.path().join("remote.git")).unwrap();
+        repo.remote("origin", temp_dir.path().join("remote.git").to_str().unwrap()).unwrap();
+
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, "First", &tree, &[]).unwrap();
+
+        let target = push_target(&repo, None, Some("published")).unwrap();
+        assert_eq!(target.remote, "origin");
+        assert_eq!(target.remote_branch, "published");
+        push_branch(&repo, &target).unwrap();
+        assert_eq!(bare.refname_to_id("refs/heads/published").unwrap(), oid);
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{Result, anyhow};
+use git2::{Oid, Repository};
+
+/// Give the most recent commits on the current branch new messages
+///
+/// `rewordings` pairs each commit with its new message, newest first, and
+/// must be an unbroken run of HEAD's history without merges. The commits are
+/// recreated with the same trees and authors, and the branch is moved to the
+/// new tip, which is returned.
+pub fn reword_commits(repo: &Repository, rewordings: &[(Oid, String)]) -> Result<Oid> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(anyhow!("HEAD is detached; check out a branch to rewrite its commits"));
+    }
+    let Some(tip) = head.target() else {
+        return Err(anyhow!("Invalid HEAD reference"));
+    };
+    if rewordings.is_empty() {
+        return Ok(tip);
+    }
+
+    let mut commits = Vec::new();
+    let mut expected = tip;
+    for (oid, _) in rewordings {
+        if *oid != expected {
+            return Err(anyhow!("Commit {} is not next in the current branch's history", oid));
+        }
+        let commit = repo.find_commit(*oid)?;
+        if commit.parent_count() > 1 {
+            return Err(anyhow!("Commit {} is a merge; merges cannot be reworded", oid));
+        }
+        expected = commit.parent_id(0).unwrap_or_else(|_| Oid::zero());
+        commits.push(commit);
+    }
+
+    // Rebuild from the oldest commit so each new commit has its new parent
+    let mut parent = commits.last().and_then(|commit| commit.parent(0).ok());
+    let mut new_tip = tip;
+    for (commit, (_, message)) in commits.iter().zip(rewordings).rev() {
+        let committer = repo.signature().unwrap_or_else(|_| commit.committer().to_owned());
+        let parents: Vec<_> = parent.iter().collect();
+        new_tip = repo.commit(None, &commit.author(), &committer, message, &commit.tree()?, &parents)?;
+        parent = Some(repo.find_commit(new_tip)?);
+    }
+
+    repo.head()?.set_target(new_tip, &format!("gitwise: reword {} commits", rewordings.len()))?;
+    Ok(new_tip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_reword_recent_commits() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let mut oids = Vec::new();
+        for (i, name) in ["a.txt", "b.txt", "c.txt"].iter().enumerate() {
+            std::fs::write(temp_dir.path().join(name), name).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new(name)).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents: Vec<_> = oids.last().map(|oid| repo.find_commit(*oid).unwrap()).into_iter().collect();
+            let parents: Vec<_> = parents.iter().collect();
+            oids.push(repo.commit(Some("HEAD"), &signature, &signature, &format!("wip {}", i), &tree, &parents).unwrap());
+        }
+
+        let rewordings = vec![(oids[2], "Add c".to_string()), (oids[1], "Add b".to_string())];
+        let tip = reword_commits(&repo, &rewordings).unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(tip));
+
+        let newest = repo.find_commit(tip).unwrap();
+        assert_eq!(newest.message(), Some("Add c"));
+        assert_eq!(newest.tree_id(), repo.find_commit(oids[2]).unwrap().tree_id());
+        let middle = newest.parent(0).unwrap();
+        assert_eq!(middle.message(), Some("Add b"));
+        assert_eq!(middle.parent_id(0).unwrap(), oids[0]);
+
+        // The old commits are no longer HEAD's history
+        assert!(reword_commits(&repo, &[(oids[2], "Again".to_string())]).is_err());
+    }
+}
@@ -1,37 +1,94 @@
 use anyhow::Result;
-use git2::{Repository, Diff, Status, StatusOptions};
+use git2::{Repository, Diff, DiffOptions, ErrorCode, IndexEntry, Oid, Status, StatusOptions, Tree};
+use std::collections::HashMap;
 
-pub fn get_staged_changes<'a>(repo: &'a Repository) -> Result<Diff<'a>> {
-    let head_tree = repo.head()?.peel_to_tree()?;
-    
-    let diff = repo.diff_tree_to_index(
-        Some(&head_tree),
-        None,
-        None,
-    )?;
-    
+/// The tree of the HEAD commit, or `None` before the first commit
+pub fn head_tree(repo: &Repository) -> Result<Option<Tree<'_>>> {
+    match repo.head() {
+        Ok(head) => Ok(Some(head.peel_to_tree()?)),
+        Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Diff from HEAD to the index, treating an unborn HEAD as an empty tree
+///
+/// Pass `pathspec_options` to leave paths out of the diff. Only the diff is
+/// filtered: the index, and so what gets committed, is unchanged. The
+/// in-memory index is used as is, so changes staged with
+/// `stage_tracked_changes` but not written still show.
+pub fn diff_head_to_index<'a>(repo: &'a Repository, opts: &mut DiffOptions) -> Result<Diff<'a>> {
+    let head_tree = head_tree(repo)?;
+    let index = repo.index()?;
+    let mut diff = repo.diff_tree_to_index(head_tree.as_ref(), Some(&index), Some(opts))?;
+    super::detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// Diff from `base` to the working directory, covering both staged and
+/// unstaged changes to tracked files, like `git diff <base>`
+///
+/// This is the tree-to-index diff followed by the index-to-workdir diff, so
+/// a file edited again after staging shows as one change. An unset `base`
+/// is the empty tree.
+pub fn diff_tree_to_worktree<'a>(repo: &'a Repository, base: Option<&Tree<'_>>, opts: &mut DiffOptions) -> Result<Diff<'a>> {
+    let mut diff = repo.diff_tree_to_workdir_with_index(base, Some(opts))?;
+    super::detect_renames(&mut diff)?;
     Ok(diff)
 }
 
+/// All staged changes, with the configured diff settings
+pub fn get_staged_changes<'a>(repo: &'a Repository) -> Result<Diff<'a>> {
+    diff_head_to_index(repo, &mut super::diff_options())
+}
+
 pub fn get_unstaged_changes<'a>(repo: &'a Repository) -> Result<Diff<'a>> {
-    let mut opts = git2::DiffOptions::new();
-    opts.include_untracked(true);
+    diff_index_to_workdir(repo, &mut super::diff_options())
+}
+
+/// Diff from the index to the working directory, including untracked files
+/// as additions with their content
+pub fn diff_index_to_workdir<'a>(repo: &'a Repository, opts: &mut DiffOptions) -> Result<Diff<'a>> {
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
     
-    let diff = repo.diff_index_to_workdir(
+    let mut diff = repo.diff_index_to_workdir(
         None,
-        Some(&mut opts),
+        Some(opts),
     )?;
+    super::detect_renames(&mut diff)?;
     
     Ok(diff)
 }
 
+/// Stage the working tree version of a file, including its deletion
 pub fn stage_file(repo: &Repository, path: &str) -> Result<()> {
     let mut index = repo.index()?;
-    index.add_path(path.as_ref())?;
+    let exists = repo.workdir().is_some_and(|workdir| workdir.join(path).exists());
+    if exists {
+        index.add_path(path.as_ref())?;
+    } else {
+        index.remove_path(path.as_ref())?;
+    }
     index.write()?;
     Ok(())
 }
 
+/// Stage every modification and deletion of tracked files, like
+/// `git commit -a`; untracked files are left out
+///
+/// Unless `write` is set the staged state only lives in the repository's
+/// in-memory index, which later diffs and commits in this process still see.
+pub fn stage_tracked_changes(repo: &Repository, write: bool) -> Result<()> {
+    let mut index = repo.index()?;
+    index.update_all(["*"], None)?;
+    if write {
+        index.write()?;
+    }
+    Ok(())
+}
+
 pub fn get_status(repo: &Repository) -> Result<Vec<(String, Status)>> {
     let mut status_opts = StatusOptions::new();
     status_opts
@@ -50,6 +107,14 @@ pub fn get_status(repo: &Repository) -> Result<Vec<(String, Status)>> {
     Ok(result)
 }
 
+/// Paths of untracked files in the working directory, leaving out ignored ones
+pub fn untracked_files(repo: &Repository) -> Result<Vec<String>> {
+    Ok(get_status(repo)?.into_iter()
+        .filter(|(_, status)| status.contains(Status::WT_NEW))
+        .map(|(path, _)| path)
+        .collect())
+}
+
 /// Group changes by their status (staged/unstaged) and file path
 pub fn get_change_groups(repo: &Repository) -> Result<(Vec<String>, Vec<String>)> {
     let mut staged = Vec::new();
@@ -67,3 +132,332 @@ pub fn get_change_groups(repo: &Repository) -> Result<(Vec<String>, Vec<String>)
     
     Ok((staged, unstaged))
 }
+
+/// Snapshot the index entries for the given paths
+///
+/// Paths that are absent from the index (staged deletions) map to `None`.
+pub fn snapshot_index_entries(repo: &Repository, paths: &[String]) -> Result<HashMap<String, Option<IndexEntry>>> {
+    let index = repo.index()?;
+    Ok(paths
+        .iter()
+        .map(|path| (path.clone(), index.get_path(path.as_ref(), 0)))
+        .collect())
+}
+
+/// Reset the index to match the HEAD tree, unstaging everything
+pub fn reset_index_to_head(repo: &Repository) -> Result<()> {
+    let mut index = repo.index()?;
+    match head_tree(repo)? {
+        Some(head_tree) => index.read_tree(&head_tree)?,
+        None => index.clear()?,
+    }
+    index.write()?;
+    Ok(())
+}
+
+/// Restore previously snapshotted index entries for the given paths
+///
+/// Paths without a snapshot were never staged and are left alone.
+pub fn restore_index_entries(repo: &Repository, entries: &mut HashMap<String, Option<IndexEntry>>, paths: &[String]) -> Result<()> {
+    let mut index = repo.index()?;
+    for path in paths {
+        match entries.remove(path) {
+            Some(Some(entry)) => index.add(&entry)?,
+            Some(None) => index.remove_path(path.as_ref())?,
+            None => (),
+        }
+    }
+    index.write()?;
+    Ok(())
+}
+
+/// The tree of exactly what is staged
+///
+/// The index is reread from disk first: the copy cached by `repo` misses a
+/// `git rm`, `git mv` or mode change staged after the repository was opened.
+pub fn staged_tree(repo: &Repository) -> Result<Tree<'_>> {
+    let mut index = repo.index()?;
+    index.read(true)?;
+    if index.has_conflicts() {
+        return Err(anyhow::anyhow!("The index has unresolved conflicts; resolve and stage them before committing"));
+    }
+    Ok(repo.find_tree(index.write_tree()?)?)
+}
+
+/// Commit the current index on top of HEAD, or as the root commit when
+/// HEAD is unborn
+pub fn commit_index(repo: &Repository, message: &str) -> Result<Oid> {
+    let signature = repo.signature()?;
+    let tree = staged_tree(repo)?;
+    let parent = match head_tree(repo)? {
+        Some(_) => Some(repo.head()?.peel_to_commit()?),
+        None => None,
+    };
+    let parents: Vec<_> = parent.iter().collect();
+
+    let oid = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+
+    Ok(oid)
+}
+
+/// Move the current branch back to HEAD's parent, keeping the undone
+/// commit's changes staged
+///
+/// Returns the undone commit. Root and merge commits are refused.
+pub fn undo_head(repo: &Repository) -> Result<Oid> {
+    let head = repo.head()?.peel_to_commit()?;
+    if head.parent_count() > 1 {
+        return Err(anyhow::anyhow!("Refusing to undo a merge commit"));
+    }
+    let parent = head.parent(0)
+        .map_err(|_| anyhow::anyhow!("HEAD is the first commit; there is no parent to go back to"))?;
+    repo.reset(parent.as_object(), git2::ResetType::Soft, None)?;
+    Ok(head.id())
+}
+
+/// Replace the HEAD commit with one containing the current index
+///
+/// The original author and date are kept unless `reset_author` is set. Merge
+/// commits are refused.
+pub fn amend_head(repo: &Repository, message: &str, reset_author: bool) -> Result<Oid> {
+    let head = repo.head()?.peel_to_commit()?;
+    if head.parent_count() > 1 {
+        return Err(anyhow::anyhow!("Refusing to amend a merge commit"));
+    }
+
+    let tree = staged_tree(repo)?;
+    let signature = repo.signature()?;
+    let author = if reset_author { Some(&signature) } else { None };
+
+    let oid = head.amend(
+        Some("HEAD"),
+        author,
+        Some(&signature),
+        None,
+        Some(message),
+        Some(&tree),
+    )?;
+
+    Ok(oid)
+}
+
+/// Diff from the parent of HEAD to the index, covering both the last commit
+/// and anything staged since
+pub fn get_amend_changes<'a>(repo: &'a Repository, opts: &mut DiffOptions) -> Result<Diff<'a>> {
+    let head = repo.head()?.peel_to_commit()?;
+    let parent_tree = match head.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+
+    let mut diff = repo.diff_tree_to_index(parent_tree.as_ref(), None, Some(opts))?;
+    super::detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_initial_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+
+        std::fs::write(temp_dir.path().join("README.md"), "hello\n").unwrap();
+        stage_file(&repo, "README.md").unwrap();
+        assert_eq!(get_staged_changes(&repo).unwrap().deltas().len(), 1);
+
+        std::fs::write(temp_dir.path().join("README.md"), "hello again\n").unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), "untracked\n").unwrap();
+        stage_tracked_changes(&repo, true).unwrap();
+        assert_eq!(get_staged_changes(&repo).unwrap().deltas().len(), 1);
+        assert_eq!(untracked_files(&repo).unwrap(), vec!["notes.txt"]);
+
+        let oid = commit_index(&repo, "Initial commit").unwrap();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(commit.id(), oid);
+        assert_eq!(commit.parent_count(), 0);
+        assert_eq!(get_staged_changes(&repo).unwrap().deltas().len(), 0);
+        assert!(undo_head(&repo).is_err());
+    }
+
+    #[test]
+    fn test_unstaged_changes_show_untracked_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        std::fs::create_dir(temp_dir.path().join("src")).unwrap();
+        std::fs::write(temp_dir.path().join("src").join("parser.rs"), "fn parse() {}\n").unwrap();
+
+        let diff = get_unstaged_changes(&repo).unwrap();
+        let mut text = String::new();
+        diff.print(git2::DiffFormat::Patch, |_, _, line| {
+            text.push_str(std::str::from_utf8(line.content()).unwrap());
+            true
+        }).unwrap();
+        assert_eq!(diff.deltas().len(), 1);
+        assert!(text.contains("fn parse() {}"));
+    }
+
+    #[test]
+    fn test_commit_matches_index_staged_elsewhere() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        for (path, contents) in [("a.txt", "a\n"), ("b.txt", "b\n"), ("run.sh", "echo hi\n")] {
+            std::fs::write(temp_dir.path().join(path), contents).unwrap();
+            stage_file(&repo, path).unwrap();
+        }
+        commit_index(&repo, "First").unwrap();
+        let b_blob = repo.index().unwrap().get_path("b.txt".as_ref(), 0).unwrap().id;
+
+        // `git rm a.txt`, `git mv b.txt c.txt` and `git update-index --chmod=+x run.sh`
+        // from another process, after `repo` cached its index
+        let other = Repository::open(temp_dir.path()).unwrap();
+        let mut index = other.index().unwrap();
+        std::fs::remove_file(temp_dir.path().join("a.txt")).unwrap();
+        index.remove_path("a.txt".as_ref()).unwrap();
+        std::fs::rename(temp_dir.path().join("b.txt"), temp_dir.path().join("c.txt")).unwrap();
+        index.remove_path("b.txt".as_ref()).unwrap();
+        index.add_path("c.txt".as_ref()).unwrap();
+        let mut script = index.get_path("run.sh".as_ref(), 0).unwrap();
+        script.mode = 0o100755;
+        index.add(&script).unwrap();
+        index.write().unwrap();
+
+        commit_index(&repo, "Second").unwrap();
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        let entries: Vec<(String, i32)> = tree.iter()
+            .map(|entry| (entry.name().unwrap().to_string(), entry.filemode()))
+            .collect();
+        assert_eq!(entries, vec![("c.txt".to_string(), 0o100644), ("run.sh".to_string(), 0o100755)]);
+        assert_eq!(tree.get_name("c.txt").unwrap().id(), b_blob);
+        assert_eq!(get_staged_changes(&repo).unwrap().deltas().len(), 0);
+
+        let mut index = other.index().unwrap();
+        index.read(true).unwrap();
+        index.remove_path("run.sh".as_ref()).unwrap();
+        index.write().unwrap();
+        amend_head(&repo, "Second, without the script", false).unwrap();
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        assert!(tree.get_name("run.sh").is_none());
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_unwritten_tracked_changes_are_staged() {
+        // `commit --all --dry-run` stages without writing the index
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "a\n").unwrap();
+        stage_file(&repo, "a.txt").unwrap();
+        commit_index(&repo, "First").unwrap();
+
+        std::fs::write(temp_dir.path().join("a.txt"), "b\n").unwrap();
+        stage_tracked_changes(&repo, false).unwrap();
+        assert_eq!(get_staged_changes(&repo).unwrap().deltas().len(), 1);
+    }
+
+    #[test]
+    fn test_undo_head_keeps_changes_staged() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+
+        std::fs::write(temp_dir.path().join("a.txt"), "a\n").unwrap();
+        stage_file(&repo, "a.txt").unwrap();
+        let first = commit_index(&repo, "First").unwrap();
+        std::fs::write(temp_dir.path().join("b.txt"), "b\n").unwrap();
+        stage_file(&repo, "b.txt").unwrap();
+        let second = commit_index(&repo, "Second").unwrap();
+
+        assert_eq!(undo_head(&repo).unwrap(), second);
+        assert_eq!(repo.head().unwrap().target(), Some(first));
+        let staged = get_staged_changes(&repo).unwrap();
+        assert_eq!(super::super::get_name_status(&staged), vec![('A', "b.txt".to_string())]);
+    }
+
+    #[test]
+    fn test_staged_worktree_and_commit_diffs() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let write = |path: &str, content: &str| std::fs::write(temp_dir.path().join(path), content).unwrap();
+
+        write("a.txt", "a\n");
+        write("b.txt", "b\n");
+        stage_file(&repo, "a.txt").unwrap();
+        stage_file(&repo, "b.txt").unwrap();
+        commit_index(&repo, "Initial commit").unwrap();
+        let initial = head_tree(&repo).unwrap().unwrap();
+
+        write("a.txt", "a staged\n");
+        stage_file(&repo, "a.txt").unwrap();
+        write("a.txt", "a staged then edited\n");
+        write("b.txt", "b unstaged\n");
+        let paths = |diff: Diff<'_>| -> Vec<(char, String)> { super::super::get_name_status(&diff) };
+
+        let staged = diff_head_to_index(&repo, &mut DiffOptions::new()).unwrap();
+        assert_eq!(paths(staged), vec![('M', "a.txt".to_string())]);
+        let worktree = diff_tree_to_worktree(&repo, Some(&initial), &mut DiffOptions::new()).unwrap();
+        assert_eq!(paths(worktree), vec![('M', "a.txt".to_string()), ('M', "b.txt".to_string())]);
+
+        stage_tracked_changes(&repo, true).unwrap();
+        commit_index(&repo, "Edit both").unwrap();
+        let committed = repo.diff_tree_to_tree(Some(&initial), head_tree(&repo).unwrap().as_ref(), None).unwrap();
+        assert_eq!(paths(committed).len(), 2);
+        let clean = diff_tree_to_worktree(&repo, head_tree(&repo).unwrap().as_ref(), &mut DiffOptions::new()).unwrap();
+        assert_eq!(clean.deltas().len(), 0);
+    }
+
+    #[test]
+    fn test_restore_only_touches_snapshotted_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        std::fs::write(temp_dir.path().join("staged.txt"), "staged\n").unwrap();
+        std::fs::write(temp_dir.path().join("untracked.txt"), "untracked\n").unwrap();
+        stage_file(&repo, "staged.txt").unwrap();
+
+        let paths = vec!["staged.txt".to_string(), "untracked.txt".to_string()];
+        let mut entries = snapshot_index_entries(&repo, &["staged.txt".to_string()]).unwrap();
+        reset_index_to_head(&repo).unwrap();
+        restore_index_entries(&repo, &mut entries, &paths).unwrap();
+        let staged = get_staged_changes(&repo).unwrap();
+        assert_eq!(super::super::get_name_status(&staged), vec![('A', "staged.txt".to_string())]);
+    }
+
+    #[test]
+    fn test_excluded_paths_left_out_of_staged_diff() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        std::fs::create_dir(temp_dir.path().join("vendor")).unwrap();
+        std::fs::write(temp_dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(temp_dir.path().join("vendor/lib.rs"), "pub fn vendored() {}\n").unwrap();
+        stage_file(&repo, "main.rs").unwrap();
+        stage_file(&repo, "vendor/lib.rs").unwrap();
+
+        let diff = diff_head_to_index(&repo, &mut super::super::pathspec_options(&[], &["vendor".to_string()])).unwrap();
+        assert_eq!(super::super::get_name_status(&diff), vec![('A', "main.rs".to_string())]);
+        assert_eq!(get_staged_changes(&repo).unwrap().deltas().len(), 2);
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result, anyhow};
+use git2::{Oid, Repository};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// File in the git directory holding the last-seen commit of each branch
+const STATE_FILE: &str = "gitwise-state";
+
+/// The commit `gitwise since` last summarized up to, per branch
+///
+/// Markers are kept as JSON in `.git/gitwise-state`, shared by all
+/// worktrees. Updates write a temporary file and rename it into place, so
+/// an interrupted run never leaves a half-written file behind.
+#[derive(Debug, Clone)]
+pub struct SeenMarkers {
+    path: PathBuf,
+}
+
+impl SeenMarkers {
+    /// The markers of a repository
+    pub fn open(repo: &Repository) -> Self {
+        Self { path: common_dir(repo).join(STATE_FILE) }
+    }
+
+    /// The last-seen commit of `branch`, if one was recorded
+    pub fn get(&self, branch: &str) -> Result<Option<Oid>> {
+        match self.load()?.get(branch) {
+            Some(hex) => Ok(Some(Oid::from_str(hex).with_context(|| format!("Invalid commit for {} in {}", branch, self.path.display()))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record `oid` as the last-seen commit of `branch`
+    pub fn set(&self, branch: &str, oid: Oid) -> Result<()> {
+        let mut markers = self.load()?;
+        markers.insert(branch.to_string(), oid.to_string());
+
+        let temp = self.path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp, serde_json::to_string_pretty(&markers)?)
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        fs::rename(&temp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid state file {}", self.path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+}
+
+/// The git directory shared by all worktrees
+///
+/// A linked worktree's own git directory names it in a `commondir` file.
+fn common_dir(repo: &Repository) -> PathBuf {
+    let path = repo.path();
+    match fs::read_to_string(path.join("commondir")) {
+        Ok(dir) => path.join(dir.trim()),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Name of the checked-out branch, which markers are keyed by
+pub fn current_branch(repo: &Repository) -> Result<String> {
+    let head = repo.head()?;
+    match head.shorthand().filter(|_| head.is_branch()) {
+        Some(name) => Ok(name.to_string()),
+        None => Err(anyhow!("HEAD is detached; check out a branch first")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_markers_are_kept_per_branch() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let markers = SeenMarkers::open(&repo);
+        let main = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let feature = Oid::from_str("2222222222222222222222222222222222222222").unwrap();
+
+        assert_eq!(markers.get("main").unwrap(), None);
+        markers.set("main", main).unwrap();
+        markers.set("feature/login", feature).unwrap();
+        markers.set("main", feature).unwrap();
+
+        let markers = SeenMarkers::open(&repo);
+        assert_eq!(markers.get("main").unwrap(), Some(feature));
+        assert_eq!(markers.get("feature/login").unwrap(), Some(feature));
+        let files: Vec<_> = fs::read_dir(repo.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with(STATE_FILE))
+            .collect();
+        assert_eq!(files, vec![STATE_FILE]);
+    }
+}
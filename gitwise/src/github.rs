@@ -0,0 +1,91 @@
+use anyhow::{Context, Result, anyhow};
+use git2::Repository;
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::Deserialize;
+
+const API_URL: &str = "https://api.github.com";
+
+/// A repository on GitHub, as `owner/name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitHubRepo {
+    pub owner: String,
+    pub name: String,
+}
+
+impl GitHubRepo {
+    /// The GitHub repository the `origin` remote points at
+    pub fn from_origin(repo: &Repository) -> Result<Self> {
+        let remote = repo.find_remote("origin").context("No 'origin' remote")?;
+        let url = remote.url().ok_or_else(|| anyhow!("The 'origin' remote URL is not valid UTF-8"))?;
+        parse_remote_url(url).ok_or_else(|| anyhow!("The 'origin' remote ({}) is not a GitHub repository", url))
+    }
+}
+
+/// Parse `owner/name` from an HTTPS or SSH GitHub remote URL
+fn parse_remote_url(url: &str) -> Option<GitHubRepo> {
+    let path = ["https://github.com/", "http://github.com/", "ssh://git@github.com/", "git@github.com:"]
+        .iter()
+        .find_map(|prefix| url.strip_prefix(prefix))?;
+    let path = path.trim_end_matches('/');
+    let (owner, name) = path.strip_suffix(".git").unwrap_or(path).split_once('/')?;
+    if owner.is_empty() || name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some(GitHubRepo { owner: owner.to_string(), name: name.to_string() })
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    number: u64,
+}
+
+/// Post a comment on the open pull request for the current branch
+///
+/// The repository is taken from the `origin` remote and the token from
+/// `GITHUB_TOKEN`. Returns the pull request number, or `None` when the
+/// branch has no open pull request.
+pub async fn comment_on_pull_request(repo: &Repository, body: &str) -> Result<Option<u64>> {
+    let token = std::env::var("GITHUB_TOKEN").map_err(|_| anyhow!("Set GITHUB_TOKEN to post to GitHub"))?;
+    let github = GitHubRepo::from_origin(repo)?;
+    let head = repo.head()?;
+    let branch = head.shorthand()
+        .filter(|_| head.is_branch())
+        .ok_or_else(|| anyhow!("HEAD is detached; check out the pull request's branch"))?;
+
+    let client = reqwest::Client::new();
+    let request = |builder: reqwest::RequestBuilder| {
+        builder.bearer_auth(&token)
+            .header(USER_AGENT, "gitwise")
+            .header(ACCEPT, "application/vnd.github+json")
+    };
+
+    let pulls: Vec<PullRequest> = request(client.get(format!("{}/repos/{}/{}/pulls", API_URL, github.owner, github.name)))
+        .query(&[("head", format!("{}:{}", github.owner, branch)), ("state", "open".to_string())])
+        .send().await?
+        .error_for_status().context("Failed to look up the pull request")?
+        .json().await?;
+    let Some(pull) = pulls.first() else {
+        return Ok(None);
+    };
+
+    request(client.post(format!("{}/repos/{}/{}/issues/{}/comments", API_URL, github.owner, github.name, pull.number)))
+        .json(&serde_json::json!({ "body": body }))
+        .send().await?
+        .error_for_status().with_context(|| format!("Failed to comment on pull request #{}", pull.number))?;
+    Ok(Some(pull.number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_url() {
+        let expected = Some(GitHubRepo { owner: "octo".to_string(), name: "gitwise".to_string() });
+        assert_eq!(parse_remote_url("https://github.com/octo/gitwise.git"), expected);
+        assert_eq!(parse_remote_url("https://github.com/octo/gitwise"), expected);
+        assert_eq!(parse_remote_url("git@github.com:octo/gitwise.git"), expected);
+        assert_eq!(parse_remote_url("ssh://git@github.com/octo/gitwise.git"), expected);
+        assert_eq!(parse_remote_url("https://gitlab.com/octo/gitwise.git"), None);
+    }
+}
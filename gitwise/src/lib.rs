@@ -0,0 +1,29 @@
+//! AI-assisted summaries, commit messages and reviews of git changes
+//!
+//! This is the library behind the `gitwise` command. [`AiEngine`] talks to
+//! the model provider and [`git`] collects the diffs, logs and references
+//! it works on:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let repo = git2::Repository::open_from_env()?;
+//! let head = repo.find_commit(gitwise::resolve_reference(&repo, "HEAD")?)?;
+//! let parent = head.parent(0)?;
+//! let diff = repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&head.tree()?), None)?;
+//! let summary = gitwise::AiEngine::new()?.summarize_diff(&diff, None).await?;
+//! println!("{}", summary);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod ai;
+pub mod config;
+#[cfg(feature = "github")]
+pub mod github;
+pub mod git;
+pub mod lint;
+pub mod template;
+pub mod version;
+
+pub use ai::AiEngine;
+pub use git::resolve_reference;
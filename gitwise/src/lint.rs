@@ -0,0 +1,251 @@
+use regex::Regex;
+
+/// Commit types allowed by Conventional Commits
+pub const CONVENTIONAL_TYPES: &[&str] = &[
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// Maximum subject length asked of freeform commit messages
+pub const FREEFORM_SUBJECT_LENGTH: usize = 50;
+/// Maximum subject length asked of Conventional Commits messages
+pub const CONVENTIONAL_SUBJECT_LENGTH: usize = 72;
+/// Column at which commit message bodies are wrapped
+pub const BODY_WRAP: usize = 72;
+
+/// Rules checked on generated commit messages, which only concern layout
+const FORMAT_RULES: &[&str] = &["subject-length", "blank-line-after-subject", "body-wrap"];
+
+/// Rules a commit message is checked against
+#[derive(Debug, Clone)]
+pub struct MessageRules {
+    pub max_subject_length: usize,
+    /// Require a Conventional Commits subject
+    pub conventional: bool,
+    /// Trailer keys that must be present, e.g. `Signed-off-by`
+    pub required_trailers: Vec<String>,
+    /// Maximum length of body lines, if checked
+    pub wrap: Option<usize>,
+}
+
+/// Outcome of checking one rule
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RuleResult {
+    pub rule: String,
+    pub passed: bool,
+    /// Why the rule failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl RuleResult {
+    fn new(rule: impl Into<String>, failure: Option<String>) -> Self {
+        Self { rule: rule.into(), passed: failure.is_none(), detail: failure }
+    }
+}
+
+/// Check a commit message against every rule
+pub fn check_message(message: &str, rules: &MessageRules) -> Vec<RuleResult> {
+    let message = message.trim();
+    let mut lines = message.lines();
+    let subject = lines.next().unwrap_or_default();
+
+    let mut results = vec![
+        RuleResult::new("subject-not-empty", subject.trim().is_empty().then(|| "subject line is empty".to_string())),
+        RuleResult::new("subject-length", (subject.chars().count() > rules.max_subject_length).then(|| {
+            format!("subject is {} characters, max {}", subject.chars().count(), rules.max_subject_length)
+        })),
+        RuleResult::new("subject-no-period", subject.ends_with('.').then(|| "subject ends with a period".to_string())),
+        RuleResult::new("blank-line-after-subject", lines.next().is_some_and(|line| !line.trim().is_empty())
+            .then(|| "second line is not blank".to_string())),
+        RuleResult::new("imperative-mood", non_imperative_verb(description(subject)).map(|word| {
+            format!("'{}' does not look imperative (use e.g. 'Add' rather than 'Added' or 'Adds')", word)
+        })),
+    ];
+
+    if let Some(wrap) = rules.wrap {
+        // Trailers and single long words such as URLs cannot be wrapped
+        let long_line = message.lines().enumerate().skip(1).find(|(_, line)| {
+            line.chars().count() > wrap && line.trim().contains(' ') && trailer_key(line).is_none()
+        });
+        results.push(RuleResult::new("body-wrap", long_line.map(|(i, line)| {
+            format!("line {} is {} characters, max {}", i + 1, line.chars().count(), wrap)
+        })));
+    }
+
+    if rules.conventional {
+        results.push(RuleResult::new("conventional-format", (!is_conventional(subject)).then(|| {
+            format!("subject is not `<type>(<scope>): <subject>` with type one of {}", CONVENTIONAL_TYPES.join(", "))
+        })));
+    }
+
+    let trailers = trailer_keys(message);
+    for required in &rules.required_trailers {
+        let present = trailers.iter().any(|key| key.eq_ignore_ascii_case(required));
+        results.push(RuleResult::new(
+            format!("trailer:{}", required),
+            (!present).then(|| format!("missing `{}:` trailer", required)),
+        ));
+    }
+
+    results
+}
+
+/// Layout problems of a generated commit message: an overlong subject, a
+/// missing blank line after it, or body lines wider than `wrap`
+pub fn format_violations(message: &str, max_subject_length: usize, wrap: usize) -> Vec<RuleResult> {
+    let rules = MessageRules {
+        max_subject_length,
+        conventional: false,
+        required_trailers: Vec::new(),
+        wrap: Some(wrap),
+    };
+    check_message(message, &rules)
+        .into_iter()
+        .filter(|result| !result.passed && FORMAT_RULES.contains(&result.rule.as_str()))
+        .collect()
+}
+
+/// Whether a subject follows the Conventional Commits format
+pub fn is_conventional(subject: &str) -> bool {
+    let pattern = format!(r"^({})(\([^()\s]+\))?!?: \S", CONVENTIONAL_TYPES.join("|"));
+    Regex::new(&pattern).expect("conventional commit pattern is valid").is_match(subject)
+}
+
+/// The part of a subject after any Conventional Commits prefix
+fn description(subject: &str) -> &str {
+    match subject.split_once(": ") {
+        Some((prefix, rest)) if is_conventional(subject) && !prefix.contains(' ') => rest,
+        _ => subject,
+    }
+}
+
+/// The first word of a description if it looks like a past-tense, gerund or
+/// third-person verb rather than an imperative
+fn non_imperative_verb(description: &str) -> Option<&str> {
+    let word = description.split_whitespace().next()?;
+    let lower = word.to_lowercase();
+    let past = lower.ends_with("ed") && !lower.ends_with("eed") && lower != "embed";
+    let gerund = lower.ends_with("ing") && !["bring", "ping", "string"].contains(&lower.as_str());
+    let third_person = lower.ends_with('s') && !["ss", "us", "is"].iter().any(|end| lower.ends_with(end));
+    if lower.len() > 3 && (past || gerund || third_person) {
+        Some(word)
+    } else {
+        None
+    }
+}
+
+/// Trailer key and value marking commits whose message gitwise wrote
+pub const GENERATED_BY_KEY: &str = "Generated-by";
+pub const GENERATED_BY_VALUE: &str = "gitwise";
+
+/// Whether the trailer block at the end of a message has `Key: value`
+pub fn has_trailer(message: &str, key: &str, value: &str) -> bool {
+    let trailer = format!("{}: {}", key, value);
+    message.trim_end().rsplit_once("\n\n")
+        .is_some_and(|(_, last)| last.lines().any(|line| line.trim() == trailer))
+}
+
+/// Append `Key: value` trailers to a message
+///
+/// Trailers go after a blank line at the end of the body, joining an
+/// existing trailer block if the message already ends with one. Trailers
+/// already present are not repeated.
+pub fn append_trailers(message: &str, key: &str, values: &[String]) -> String {
+    let mut message = message.trim_end().to_string();
+    let new: Vec<String> = values.iter()
+        .map(|value| format!("{}: {}", key, value))
+        .filter(|trailer| !message.lines().any(|line| line.trim() == trailer))
+        .collect();
+    if new.is_empty() {
+        return message;
+    }
+
+    let ends_with_trailers = message.rsplit_once("\n\n")
+        .is_some_and(|(_, last)| last.lines().all(|line| trailer_key(line).is_some()));
+    message.push_str(if ends_with_trailers { "\n" } else { "\n\n" });
+    message.push_str(&new.join("\n"));
+    message
+}
+
+/// Keys of the `Key: value` trailers in the last paragraph of a message
+fn trailer_keys(message: &str) -> Vec<&str> {
+    let Some((_, last)) = message.rsplit_once("\n\n") else {
+        return Vec::new();
+    };
+    last.lines().filter_map(trailer_key).collect()
+}
+
+/// The key of a `Key: value` trailer line
+fn trailer_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once(": ")?;
+    ((!key.is_empty() && !key.contains(' ')) || key == "BREAKING CHANGE").then_some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(message: &str, rules: &MessageRules) -> Vec<String> {
+        check_message(message, rules)
+            .into_iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_check_message() {
+        let rules = MessageRules {
+            max_subject_length: FREEFORM_SUBJECT_LENGTH,
+            conventional: false,
+            required_trailers: vec!["Signed-off-by".to_string()],
+            wrap: None,
+        };
+        assert!(failed("Add parser\n\nDetails.\n\nSigned-off-by: A <a@example.com>", &rules).is_empty());
+        assert_eq!(failed("Added parser.\nDetails", &rules), vec![
+            "subject-no-period",
+            "blank-line-after-subject",
+            "imperative-mood",
+            "trailer:Signed-off-by",
+        ]);
+    }
+
+    #[test]
+    fn test_format_violations() {
+        let long_body = format!("Add parser\n\n{}", "word ".repeat(20));
+        let rules: Vec<String> = format_violations(&long_body, 50, 72).into_iter().map(|r| r.rule).collect();
+        assert_eq!(rules, vec!["body-wrap"]);
+        let url = format!("Add parser\n\nSee:\nhttps://example.com/{}", "x".repeat(80));
+        assert!(format_violations(&url, 50, 72).is_empty());
+        assert!(format_violations("Added parser.\n\nDetails.", 50, 72).is_empty());
+    }
+
+    #[test]
+    fn test_append_trailers() {
+        let authors = vec!["Ada <ada@example.com>".to_string()];
+        assert_eq!(
+            append_trailers("Add parser\n\nHandles nesting.\n", "Co-authored-by", &authors),
+            "Add parser\n\nHandles nesting.\n\nCo-authored-by: Ada <ada@example.com>"
+        );
+        assert_eq!(
+            append_trailers("Add parser\n\nSigned-off-by: Bob <bob@example.com>", "Co-authored-by", &authors),
+            "Add parser\n\nSigned-off-by: Bob <bob@example.com>\nCo-authored-by: Ada <ada@example.com>"
+        );
+        let once = append_trailers("Add parser", "Co-authored-by", &authors);
+        assert_eq!(append_trailers(&once, "Co-authored-by", &authors), once);
+
+        let generated = append_trailers("Add parser", GENERATED_BY_KEY, &[GENERATED_BY_VALUE.to_string()]);
+        assert!(has_trailer(&generated, GENERATED_BY_KEY, GENERATED_BY_VALUE));
+        assert!(!has_trailer("Add parser\n\nGenerated-by: gitwise is great", GENERATED_BY_KEY, GENERATED_BY_VALUE));
+    }
+
+    #[test]
+    fn test_conventional_format() {
+        assert!(is_conventional("feat(api): add endpoint"));
+        assert!(is_conventional("fix!: drop legacy flag"));
+        assert!(!is_conventional("feature: add endpoint"));
+        assert!(!is_conventional("Add endpoint"));
+        assert_eq!(non_imperative_verb(description("fix(cli): fixes crash")), Some("fixes"));
+        assert_eq!(non_imperative_verb(description("refactor: process queue")), None);
+    }
+}
@@ -1,30 +1,233 @@
-use anyhow::{Result, anyhow};
-use clap::{Parser, Subcommand, ValueEnum};
+use anyhow::{Context, Result, anyhow};
+use clap::{CommandFactory, Parser, Subcommand};
+use futures::StreamExt;
 use git2::{Repository, Oid};
-use tracing::info;
+use tracing::{debug, info, warn};
 use tracing_subscriber::fmt;
 
-mod ai;
+#[macro_use]
+mod output;
+mod markup;
 mod utils;
-mod git;
 
+use gitwise::{ai, config, git, lint, template, version};
+#[cfg(feature = "github")]
+use gitwise::github;
 use git::staging;
+use output::{Color, OutputFormat};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// Enable verbose logging
-    #[arg(short, long, help = "Enable verbose logging")]
-    verbose: bool,
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, help = "Log the model, prompts, diff size and token usage to stderr; repeat (-vv) to also log the full diff sent")]
+    verbose: u8,
+
+    /// Repository to work in instead of the current directory's
+    #[arg(long, global = true, value_name = "PATH", help = "Work in the repository containing PATH (any directory inside it works) instead of the current directory's")]
+    repo: Option<std::path::PathBuf>,
+
+    /// Only print the final result
+    #[arg(short, long, global = true, conflicts_with = "verbose", help = "Suppress logging and warnings; only print the final result and errors")]
+    quiet: bool,
+
+    /// Base URL of an OpenAI-compatible API
+    #[arg(long, global = true, value_name = "URL", help = "Send OpenAI requests to an OpenAI-compatible API such as a proxy or OpenRouter (overrides OPENAI_BASE_URL)")]
+    base_url: Option<String>,
+
+    /// File holding the OpenAI API key
+    #[arg(long, global = true, value_name = "PATH", help = "Read the OpenAI API key from this file, taking precedence over OPENAI_API_KEY, OPENAI_API_KEY_FILE and the keychain")]
+    openai_api_key_file: Option<std::path::PathBuf>,
+
+    /// Seconds to wait for each model response
+    #[arg(long, global = true, value_name = "SECS", env = "GITWISE_TIMEOUT", default_value_t = ai::DEFAULT_TIMEOUT_SECS, value_parser = clap::value_parser!(u64).range(1..), help = "Seconds to wait for each model response before failing with 'request timed out'")]
+    timeout: u64,
+
+    /// Most AI requests sent per minute
+    #[arg(long, global = true, value_name = "N", env = "GITWISE_RPM", value_parser = clap::value_parser!(u32).range(1..), help = "Send at most N AI requests per minute, shared by concurrent requests such as history --concurrency; rate-limit responses slow it down further")]
+    rpm: Option<u32>,
+
+    /// Natural language for summaries and commit messages
+    #[arg(long, global = true, value_name = "CODE", value_parser = parse_language_code, help = "Write summaries and commit messages in this language (e.g. 'es', 'fr', 'ja'; default English); code identifiers stay untranslated")]
+    language: Option<String>,
+
+    /// When to color text results
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = output::ColorChoice::Auto, help = "Color text results: auto (only on a terminal, unless NO_COLOR is set), always or never")]
+    color: output::ColorChoice,
+
+    /// Config profile to use
+    #[arg(long, global = true, value_name = "NAME", env = "GITWISE_PROFILE", help = "Use the settings of a [profile.NAME] table in the config files over the top-level ones; command-line flags still win")]
+    profile: Option<String>,
 
     /// Force a specific AI model provider
     #[arg(long, value_enum, help = "Force a specific AI model provider (e.g., 'anthropic' or 'openai')")]
     model: Option<ModelProvider>,
 
+    /// Omit hunks that only change whitespace from AI input
+    #[arg(long, global = true, help = "Replace whitespace-only hunks with a note while keeping substantive hunks")]
+    collapse_whitespace_hunks: bool,
+
+    /// Do not ask summaries to cite file:line locations
+    #[arg(long, global = true, help = "Leave file paths and @@ hunk headers out of summary input and do not ask for file:line citations, for terser summaries")]
+    no_line_refs: bool,
+
+    /// Send lockfiles and generated files in full
+    #[arg(long, global = true, help = "Send lockfiles (Cargo.lock, package-lock.json, ...) and files marked linguist-generated in full instead of as a one-line note")]
+    include_generated: bool,
+
+    /// Word-diff prose files
+    #[arg(long, global = true, help = "Send changes to prose files (Markdown, reStructuredText, plain text, ...) as word-level changes instead of whole changed lines; code stays line-based")]
+    word_diff: bool,
+
+    /// Estimated token budget for diff text in a single AI request
+    #[arg(long, global = true, default_value_t = ai::DEFAULT_MAX_INPUT_TOKENS, help = "Estimated token budget for diff text in one request; larger diffs are summarized in chunks")]
+    max_input_tokens: usize,
+
+    /// Largest diff sent in one request
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = ai::DEFAULT_MAX_DIFF_BYTES, help = "Largest diff sent in one request; bigger diffs are summarized in chunks, or rejected before any request with --no-chunk")]
+    max_diff_bytes: usize,
+
+    /// Never summarize diffs in chunks
+    #[arg(long, global = true, help = "Send each diff in one request instead of summarizing diffs over --max-input-tokens in chunks; diffs over --max-diff-bytes are then rejected")]
+    no_chunk: bool,
+
+    /// Subproject directory that all diffs are limited to
+    #[arg(long, global = true, value_name = "DIR", help = "Limit every diff to a monorepo subproject such as services/payments, and tell the model which subproject it is looking at; history only walks commits that touched it")]
+    scope: Option<String>,
+
+    /// File whose contents are prepended as context to every AI request
+    #[arg(long, global = true, env = "GITWISE_CONTEXT_FILE", help = "File whose contents are prepended as context to every AI request (e.g., a description of the project's domain)")]
+    context_prefix_file: Option<std::path::PathBuf>,
+
+    /// Output format for command results
+    #[arg(long, global = true, value_enum, default_value_t = markup::Format::Plain, help = "Output format for results: plain text, Markdown for GitHub, Slack mrkdwn, or JSON for scripts ('text' is the same as 'plain')")]
+    format: markup::Format,
+
+    /// Emit JSON instead of human-readable text (same as --format json)
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Line endings for generated commit messages
+    #[arg(long, global = true, value_enum, env = "GITWISE_LINE_ENDING", default_value_t = output::LineEnding::Lf, help = "Line endings for generated commit messages (lf or crlf)")]
+    line_ending: output::LineEnding,
+
+    /// Leave test files out of summaries
+    #[arg(long, global = true, help = "Leave test files out of summaries to focus on production code")]
+    no_tests: bool,
+
+    /// Patterns identifying test files for --no-tests
+    #[arg(long = "test-pattern", global = true, value_name = "PATTERN", help = "Pattern identifying test files for --no-tests (repeatable; defaults to 'tests/', '*_test.*', '*.spec.*')")]
+    test_patterns: Vec<String>,
+
+    /// Lines of context around each change
+    #[arg(long, global = true, value_name = "N", help = "Lines of unchanged context around each change sent to the AI (default 3; 0 minimizes tokens, more helps the model understand changes)")]
+    context: Option<u32>,
+
+    /// Diff algorithm
+    #[arg(long, global = true, value_enum, value_name = "ALGORITHM", default_value_t = git::DiffAlgorithm::Myers, help = "Diff algorithm for every diff sent to the AI; patience often gives cleaner hunks for refactors and reordered code")]
+    diff_algorithm: git::DiffAlgorithm,
+
+    /// Ignore changes that only touch whitespace
+    #[arg(long, global = true, help = "Drop hunks that only change whitespace before they reach the AI (diff, commit and history)")]
+    ignore_whitespace: bool,
+
+    /// Bypass the on-disk summary cache
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Days after which cached summaries are evicted
+    #[arg(long, global = true, env = "GITWISE_CACHE_TTL_DAYS", default_value_t = ai::cache::DEFAULT_CACHE_TTL_DAYS)]
+    cache_ttl_days: u64,
+
+    /// Sampling temperature for OpenAI requests
+    #[arg(long, global = true, value_parser = parse_temperature, help = "Sampling temperature for OpenAI requests, 0.0-2.0 (default 0.3 for commit messages and changelogs, 0.7 otherwise)")]
+    temperature: Option<f32>,
+
+    /// Make output as reproducible as possible
+    #[arg(long, global = true, conflicts_with = "temperature", help = "Make output as reproducible as possible for tests and golden files: temperature 0, a fixed seed and no retry jitter (best effort; OpenAI only, and models are not fully deterministic)")]
+    deterministic: bool,
+
+    /// Extra sampling parameters as JSON
+    #[arg(long, global = true, value_name = "JSON", value_parser = parse_params, help = "Extra OpenAI sampling parameters as a JSON object, e.g. '{\"top_p\": 0.9, \"stop\": [\"\\n\\n\"]}'; accepts top_p, frequency_penalty, presence_penalty, stop and user")]
+    params: Option<ai::ModelParams>,
+
+    /// Models to try in turn when a request fails
+    #[arg(long, global = true, value_name = "MODEL", value_delimiter = ',', help = "Models to try in turn when a request fails with a rate limit, server error, exhausted quota, unknown model or timeout, after retries (e.g. 'gpt-4o-mini,gpt-3.5-turbo'; repeatable)")]
+    model_fallback: Vec<String>,
+
+    /// Maximum number of tokens generated per response
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..), help = "Maximum number of tokens generated per response (default 1024 for commit messages and branch names, 4096 for changelogs, release notes, PR descriptions and explain, 2048 otherwise)")]
+    max_tokens: Option<u16>,
+
+    /// Write the result to a file instead of stdout
+    #[arg(long, global = true, value_name = "PATH", help = "Write the result (summary, message, changelog, ...) to a file; progress and diagnostics stay on the terminal")]
+    output: Option<std::path::PathBuf>,
+
+    /// Overwrite existing files
+    #[arg(long, global = true, help = "Overwrite an existing --output file or prepare-commit-msg hook, or undo a commit gitwise did not create")]
+    force: bool,
+
+    /// Skip safety checks on commits, like `git commit --no-verify`
+    #[arg(long, global = true, help = "Skip secret scanning and commit message checks (no revision round, no warnings) for this run; the commit is still generated and created. This weakens safety checks, so use it only for known false positives")]
+    no_verify: bool,
+
+    /// Report token usage and estimated cost when done
+    #[arg(long, global = true, help = "Print token usage and estimated cost to stderr when done (with commit --dry-run, also a pre-flight prompt estimate)")]
+    show_cost: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Check that `--co-author` looks like `Name <email>`
+fn parse_co_author(value: &str) -> Result<String, String> {
+    let pattern = regex::Regex::new(r"^[^<>]*[^<>\s] <[^<>\s@]+@[^<>\s@]+>$").expect("co-author pattern is valid");
+    let value = value.trim();
+    if pattern.is_match(value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("'{}' is not in the form 'Name <email>'", value))
+    }
+}
+
+/// Check that `--language` looks like a language code such as `es` or `pt-BR`
+fn parse_language_code(value: &str) -> Result<String, String> {
+    let pattern = regex::Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$").expect("language code pattern is valid");
+    let value = value.trim();
+    if pattern.is_match(value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("'{}' is not a language code such as 'es', 'fr' or 'pt-BR'", value))
+    }
+}
+
+/// Parse and range-check `--temperature`
+fn parse_temperature(value: &str) -> Result<f32, String> {
+    let temperature: f32 = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+    if !ai::TEMPERATURE_RANGE.contains(&temperature) {
+        return Err(format!("{} is outside the range 0.0-2.0", temperature));
+    }
+    Ok(temperature)
+}
+
+/// Parse `--params` as a JSON object of extra model parameters
+fn parse_params(value: &str) -> Result<ai::ModelParams, String> {
+    ai::ModelParams::from_json(value).map_err(|e| format!("{:#}", e))
+}
+
+/// Parse `--lines` given as `START,END`, optionally prefixed with `L` as in
+/// `git log -L`
+fn parse_line_range(value: &str) -> Result<std::ops::RangeInclusive<u32>, String> {
+    let invalid = || format!("'{}' is not a line range such as 'L10,50'", value);
+    let (start, end) = value.trim().trim_start_matches('L').split_once(',').ok_or_else(invalid)?;
+    let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+    let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+    if start == 0 || end < start {
+        return Err(format!("'{}' must run from line 1 or later to a line no earlier than its start", value));
+    }
+    Ok(start..=end)
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Intelligently stage changes by feature
@@ -44,24 +247,230 @@ enum Commands {
         /// Custom PR description
         #[arg(long, help = "Custom PR description (if not provided, will be AI-generated)")]
         body: Option<String>,
+        /// Print the drafted PR as Markdown instead of creating it
+        #[arg(long, help = "Print the drafted title and description as Markdown instead of creating the PR")]
+        print: bool,
     },
     /// Summarize changes between git references
     Diff {
-        /// First git reference (branch, commit, or tag)
+        /// First git reference (branch, commit, tag, or @{upstream})
         #[arg(default_value = "HEAD")]
         from: String,
-        /// Second git reference (branch, commit, or tag)
+        /// Second git reference (branch, commit, or tag); defaults to HEAD
         #[arg()]
         to: Option<String>,
         /// Show staged changes instead
-        #[arg(short, long)]
+        #[arg(short, long, help = "Summarize staged changes (HEAD to index) instead of committed ones")]
         staged: bool,
+        /// Compare against the current branch's upstream
+        #[arg(long, conflicts_with_all = ["staged", "all", "patch_file", "stdin"], help = "Compare against the current branch's upstream (e.g. origin/main) instead of <FROM>, showing what the branch has that its upstream does not")]
+        upstream: bool,
+        /// Show staged and unstaged changes since <FROM>
+        #[arg(long, conflicts_with_all = ["to", "staged", "merge_base"], help = "Summarize everything not yet committed: staged and unstaged changes to tracked files, compared with <FROM> (default HEAD)")]
+        worktree: bool,
+        /// Summarize staged and unstaged changes together
+        #[arg(long, conflicts_with_all = ["to", "staged", "worktree", "merge_base", "patch_file", "stdin", "stream", "llm_self_check", "breaking", "by_file", "name_status", "stat"], help = "Summarize everything you have touched, staged, unstaged and untracked, saying which changes are staged and which are not")]
+        all: bool,
+        /// Only show what `from` adds since it forked from `to`
+        #[arg(long, requires = "to", conflicts_with = "staged", help = "Diff from the merge base of the two references to <FROM>, as a pull request shows it, leaving out changes that landed on <TO> separately")]
+        merge_base: bool,
+        /// Summarize a patch file instead of repository changes
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["to", "staged", "worktree", "stdin", "by_file", "name_status", "stream", "llm_self_check"], help = "Summarize a patch file (e.g. from 'git format-patch') without needing a repository")]
+        patch_file: Option<std::path::PathBuf>,
+        /// Read a patch from stdin instead of repository changes
+        #[arg(long, conflicts_with_all = ["to", "staged", "worktree", "by_file", "name_status", "stream", "llm_self_check"], help = "Summarize a patch read from stdin without needing a repository")]
+        stdin: bool,
         /// Custom prompt for AI summarization
         #[arg(long, help = "Custom prompt for AI summarization (e.g., 'Focus on security changes' or 'List only modified functions')")]
         prompt: Option<String>,
+        /// Read the custom prompt from a file
+        #[arg(long, value_name = "PATH", conflicts_with = "prompt", help = "Read the custom prompt from a file; a '{diff}' placeholder makes it the whole request with the diff substituted")]
+        prompt_file: Option<std::path::PathBuf>,
+        /// Print the summary as it is generated
+        #[arg(long)]
+        stream: bool,
+        /// Ask the model to report its confidence and what needs human review
+        #[arg(long, conflicts_with = "stream", help = "Ask the model to report its confidence and flag anything it cannot determine from the diff alone")]
+        llm_self_check: bool,
+        /// Add a section on removed or changed public Rust items
+        #[arg(long, conflicts_with_all = ["patch_file", "stdin", "llm_self_check", "by_file", "name_status"], help = "End the summary with a 'Breaking changes' section covering public Rust items (pub fn, pub struct, trait methods) that were removed or changed")]
+        breaking: bool,
+        /// Summarize each changed file separately
+        #[arg(long, conflicts_with_all = ["stream", "llm_self_check", "name_status"], help = "Summarize each changed file separately, printing 'path: summary' (one JSON object per line with --json)")]
+        by_file: bool,
+        /// Describe only the end-state difference of a range
+        #[arg(long, conflicts_with_all = ["staged", "worktree", "all", "patch_file", "stdin"], help = "Summarize only the net effect of the range, what differs between its two ends, leaving out files whose changes cancel out; unlike 'history', which goes commit by commit, add-then-remove churn is never mentioned")]
+        net: bool,
+        /// Tag each changed file with the kind of change, shown as a table
+        #[arg(long, conflicts_with_all = ["patch_file", "stdin", "stream", "llm_self_check", "breaking", "by_file", "name_status", "stat", "interactive"], help = "Summarize each changed file in a table tagged feature, fix, refactor, test, docs, chore or other (one JSON object per line with --json)")]
+        categorize: bool,
+        /// List changed files with their status instead of summarizing
+        #[arg(long, help = "List changed files with their status instead of summarizing (one JSON object per line with --json)")]
+        name_status: bool,
+        /// Print a diffstat instead of summarizing
+        #[arg(long, conflicts_with_all = ["patch_file", "stdin", "stream", "llm_self_check", "breaking", "by_file", "name_status"], help = "Print a git-style diffstat (files changed, insertions, deletions) without calling the model")]
+        stat: bool,
+        /// Cap for per-file output modes
+        #[arg(long, value_name = "N", help = "When more than N files changed, fall back to the --over-limit view in per-file output modes")]
+        max_output_files: Option<usize>,
+        /// Fallback when --max-output-files is exceeded
+        #[arg(long, value_enum, default_value_t = output::OverLimit::Summarize)]
+        over_limit: output::OverLimit,
+        /// Highlight changed lines matching a regex and call them out
+        #[arg(long = "focus-regex", value_name = "PATTERN", help = "Pay special attention to added/removed lines matching this regex (repeatable, e.g. 'unsafe' or '\\.unwrap\\(')")]
+        focus_regex: Vec<regex::Regex>,
+        /// Tailor the summary to a particular reader
+        #[arg(long, value_enum, value_name = "AUDIENCE", help = "Tailor vocabulary and focus to a reader (combine with --prompt for tone)")]
+        explain_for: Option<ai::Audience>,
+        /// How much detail the summary goes into
+        #[arg(long, value_enum, value_name = "LEVEL", default_value_t = ai::Detail::Normal, help = "Summary length: brief (one-sentence TL;DR), normal, or detailed (broken down by area)")]
+        detail: ai::Detail,
+        /// Pick a cheaper model for small diffs and a stronger one for large diffs
+        #[arg(long, help = "Use a cheaper model for small diffs and a stronger one for larger diffs")]
+        budget_aware_model: bool,
+        /// Changed lines up to which --budget-aware-model treats a diff as small
+        #[arg(long, value_name = "LINES", env = "GITWISE_SMALL_DIFF_LINES", default_value_t = ai::DEFAULT_SMALL_DIFF_LINES)]
+        small_diff_lines: usize,
+        /// Model --budget-aware-model uses for small diffs
+        #[arg(long, value_name = "MODEL", env = "GITWISE_SMALL_MODEL", help = "Model for small diffs with --budget-aware-model (default: gpt-3.5-turbo or claude-3-haiku)")]
+        small_model: Option<String>,
+        /// Model --budget-aware-model uses for larger diffs
+        #[arg(long, value_name = "MODEL", env = "GITWISE_LARGE_MODEL", help = "Model for larger diffs with --budget-aware-model (default: gpt-4o or claude-3-sonnet)")]
+        large_model: Option<String>,
+        /// Only consider paths matching a pathspec
+        #[arg(long, value_name = "GLOB", help = "Only send paths matching this pathspec to the AI (repeatable)")]
+        include: Vec<String>,
+        /// Leave paths matching a pathspec out
+        #[arg(long, value_name = "GLOB", help = "Never send paths matching this pathspec to the AI (repeatable, e.g. 'Cargo.lock' or '*.min.js')")]
+        exclude: Vec<String>,
+        /// Only summarize changes to this file
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["patch_file", "stdin", "include"], help = "Only summarize the changes to this file")]
+        path: Option<String>,
+        /// Only summarize hunks overlapping these lines of the file
+        #[arg(long, value_name = "L<START>,<END>", requires = "path", conflicts_with_all = ["name_status", "stat"], value_parser = parse_line_range, help = "With --path, only summarize the hunks that overlap these lines of the new version of the file (e.g. 'L10,50')")]
+        lines: Option<std::ops::RangeInclusive<u32>>,
+        /// Ask follow-up questions about the diff after the summary
+        #[arg(long, conflicts_with_all = ["patch_file", "stdin", "all", "stream", "llm_self_check", "by_file", "name_status", "stat"], help = "After the summary, keep the diff in context and answer follow-up questions typed at a '>' prompt; end with /quit or Ctrl-D")]
+        interactive: bool,
+    },
+    /// Review changes for bugs, security concerns and style issues
+    Review {
+        /// Base git reference (branch, commit, or tag)
+        #[arg(default_value = "HEAD")]
+        from: String,
+        /// Git reference to review; defaults to the working directory
+        to: Option<String>,
+        /// Extra instructions for the reviewer
+        #[arg(long, help = "Extra instructions for the reviewer (e.g., 'We target Rust 1.70')")]
+        prompt: Option<String>,
+        /// Add a section on removed or changed public Rust items
+        #[arg(long, help = "End the review with a 'Breaking changes' section covering public Rust items that were removed or changed")]
+        breaking: bool,
+        /// Post the review as a comment on the branch's GitHub pull request
+        #[arg(long, help = "Post the review as a comment on the open pull request for the current branch, found from the 'origin' remote (needs GITHUB_TOKEN and the 'github' build feature)")]
+        post_github: bool,
+        /// Exit with an error if there are findings this severe or worse
+        #[arg(long, value_enum, value_name = "SEVERITY", help = "Review as a list of findings and exit with an error if any is at or above this severity")]
+        fail_on: Option<ai::Severity>,
+    },
+    /// Suggest test cases for changes, without writing any files
+    Tests {
+        /// Base git reference (branch, commit, or tag)
+        #[arg(default_value = "HEAD")]
+        from: String,
+        /// Git reference to suggest tests for; defaults to the working directory
+        to: Option<String>,
     },
     /// Generate a commit message for staged changes
-    Commit,
+    Commit {
+        /// Offer to split the commit when staged changes are unrelated
+        #[arg(long, help = "Group staged changes first and offer to split them into several commits if they are unrelated")]
+        split_on_conflict: bool,
+        /// Generate a Conventional Commits message
+        #[arg(long, help = "Generate a Conventional Commits message (e.g., 'feat(api): add endpoint')")]
+        conventional: bool,
+        /// Start the subject with a gitmoji
+        #[arg(long, conflicts_with = "conventional", help = "Start the subject with a gitmoji (e.g., '✨ Add export', '🐛 Fix crash') chosen from the standard set")]
+        gitmoji: bool,
+        /// Stage modified and deleted tracked files first
+        #[arg(short, long, conflicts_with_all = ["split", "hook"], help = "Stage all modified and deleted tracked files first, like 'git commit -a'; untracked files are left out")]
+        all: bool,
+        /// Split staged and unstaged changes into several commits
+        #[arg(long, conflicts_with = "split_on_conflict", help = "Group staged and unstaged changes by feature and commit each group after confirmation")]
+        split: bool,
+        /// Replace the last commit, regenerating its message
+        #[arg(long, conflicts_with_all = ["split", "split_on_conflict"], help = "Replace the last commit with one covering it plus any newly staged changes, with a regenerated message")]
+        amend: bool,
+        /// With --amend, make the current user the author
+        #[arg(long, requires = "amend")]
+        reset_author: bool,
+        /// Credit a pair-programming partner
+        #[arg(long = "co-author", value_name = "NAME <EMAIL>", value_parser = parse_co_author, help = "Add a 'Co-authored-by: Name <email>' trailer (repeatable)")]
+        co_authors: Vec<String>,
+        /// Write the message from diff stats without calling a model
+        #[arg(long, conflicts_with_all = ["split", "split_on_conflict"], help = "Write a plain message from the diff stats (e.g. 'Update 3 files in src/auth') without calling a model; used automatically when no provider can be reached")]
+        offline: bool,
+        /// Refuse to commit changes that look like they contain secrets
+        #[arg(long, env = "GITWISE_SCAN_SECRETS", help = "Scan changes for API keys, private keys and other high-entropy strings before sending anything to the AI, and abort if any are found (set GITWISE_SCAN_SECRETS=true to make this the default)")]
+        scan_secrets: bool,
+        /// Commit even if --scan-secrets finds something
+        #[arg(long, help = "Skip the --scan-secrets check, e.g. for false positives")]
+        allow_secrets: bool,
+        /// Size in megabytes above which staged files are flagged
+        #[arg(long, value_name = "MB", default_value_t = 5, help = "Warn about staged files larger than this many megabytes, and binary files over 1 MB that probably belong in Git LFS")]
+        max_file_size: u64,
+        /// Abort instead of warning about large files
+        #[arg(long, conflicts_with = "allow_large", help = "Abort instead of warning when staged files are too large or look like binaries for Git LFS")]
+        strict: bool,
+        /// Skip the large file check
+        #[arg(long, help = "Commit large and binary files without a warning")]
+        allow_large: bool,
+        /// Number of alternative messages to generate
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=10), conflicts_with_all = ["offline", "split", "split_on_conflict"], help = "Generate N alternative messages, have the model recommend the best one, and let you choose between them (the recommendation is used when not run in a terminal)")]
+        candidates: u8,
+        /// Longest subject a generated message may have
+        #[arg(long, value_name = "N", help = "Longest subject a generated message may have before it is sent back for revision (default 50, or 72 with --conventional)")]
+        subject_max: Option<usize>,
+        /// Column at which the body of a generated message is wrapped
+        #[arg(long, value_name = "N", default_value_t = lint::BODY_WRAP, help = "Column at which the body of a generated message is word-wrapped (code fences and long URLs are left alone)")]
+        wrap: usize,
+        /// Edit the generated message in $EDITOR before committing
+        #[arg(short, long, help = "Open the generated message in $GIT_EDITOR or $EDITOR (default vi) before committing; an empty message aborts")]
+        edit: bool,
+        /// Run as a prepare-commit-msg hook, filling in git's message file
+        #[arg(long, value_name = "MESSAGE_FILE", conflicts_with_all = ["split", "split_on_conflict", "amend", "edit"], help = "prepare-commit-msg hook mode: fill the message file git passes in instead of committing; nothing is printed to stdout")]
+        hook: Option<std::path::PathBuf>,
+        /// Commit message source git passes to the hook
+        #[arg(long, requires = "hook", value_name = "SOURCE")]
+        hook_source: Option<String>,
+        /// Print the generated message without committing
+        #[arg(long, conflicts_with_all = ["split", "split_on_conflict", "hook"], help = "Print the message that would be used without creating a commit or touching the index")]
+        dry_run: bool,
+        /// Only consider paths matching a pathspec
+        #[arg(long, value_name = "GLOB", help = "Only send staged paths matching this pathspec to the AI (repeatable); other staged files are still committed")]
+        include: Vec<String>,
+        /// Leave paths matching a pathspec out
+        #[arg(long, value_name = "GLOB", help = "Never send staged paths matching this pathspec to the AI (repeatable, e.g. 'Cargo.lock' or 'vendor'); they are still committed, just left out of the prompt")]
+        exclude: Vec<String>,
+        /// Mention untracked files to the AI
+        #[arg(long, help = "List untracked files in the prompt so the message can refer to new files the staged changes use; they are not staged or committed")]
+        include_untracked: bool,
+        /// Existing commits whose message style to match
+        #[arg(long, value_name = "REF", conflicts_with = "offline", help = "Match the tone, tense and structure of this commit's message; repeat to give several examples")]
+        template_commit: Vec<String>,
+        /// Lay the message out with a template
+        #[arg(long, value_name = "PATH", help = "Lay out the message with a template using {subject}, {body}, {files} (the committed paths) and {refs} (an issue key such as PROJ-123 from the branch name) placeholders plus any static trailers; lines whose placeholder is empty are dropped")]
+        template: Option<std::path::PathBuf>,
+        /// Push after committing
+        #[arg(long, conflicts_with_all = ["hook", "dry_run"], help = "Push the branch after committing, to its upstream or the same branch on 'origin' (SSH through ssh-agent, HTTPS with GIT_TOKEN)")]
+        push: bool,
+        /// Remote to push to
+        #[arg(long, value_name = "NAME", requires = "push", help = "Remote to push to instead of the branch's upstream remote")]
+        remote: Option<String>,
+        /// Remote branch to push to
+        #[arg(long, value_name = "NAME", requires = "push", help = "Branch to update on the remote instead of the upstream branch")]
+        branch: Option<String>,
+    },
     /// Summarize git history
     History {
         /// Git reference to start from (branch, commit, or tag)
@@ -73,6 +482,159 @@ enum Commands {
         /// Custom prompt for AI summarization
         #[arg(long, help = "Custom prompt for AI summarization (e.g., 'Focus on API changes' or 'Summarize in bullet points')")]
         prompt: Option<String>,
+        /// Read the custom prompt from a file
+        #[arg(long, value_name = "PATH", conflicts_with = "prompt", help = "Read the custom prompt from a file; a '{diff}' placeholder makes it the whole request with the diff substituted")]
+        prompt_file: Option<std::path::PathBuf>,
+        /// Number of commits summarized at the same time
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..), help = "Number of commits summarized at the same time; lower it if you hit rate limits")]
+        concurrency: u32,
+        /// Only consider paths matching a pathspec
+        #[arg(long, value_name = "GLOB", help = "Only send paths matching this pathspec to the AI (repeatable)")]
+        include: Vec<String>,
+        /// Leave paths matching a pathspec out
+        #[arg(long, value_name = "GLOB", help = "Never send paths matching this pathspec to the AI (repeatable, e.g. 'Cargo.lock' or '*.min.js')")]
+        exclude: Vec<String>,
+        /// Group commits by theme
+        #[arg(long, help = "Group the commits into themes (e.g. 'Authentication work', 'CI fixes') with the commits behind each, instead of listing a summary per commit")]
+        group: bool,
+        /// Write new messages for the commits and rewrite them
+        #[arg(long, conflicts_with = "group", help = "Write new messages for the last --count commits on the current branch in a single request and rewrite them after confirmation")]
+        regenerate: bool,
+        /// Only show the regenerated messages
+        #[arg(long, requires = "regenerate", help = "Show the regenerated messages without rewriting any commits")]
+        dry_run: bool,
+    },
+    /// Summarize a single commit
+    Show {
+        /// Commit to summarize
+        #[arg(default_value = "HEAD")]
+        reference: String,
+    },
+    /// Explain a commit in depth: the problem, the approach and its trade-offs
+    Explain {
+        /// Commit to explain
+        #[arg(default_value = "HEAD")]
+        reference: String,
+    },
+    /// Generate a changelog section for a range of commits
+    Changelog {
+        /// Starting git reference, exclusive; defaults to the most recent tag reachable from HEAD
+        from: Option<String>,
+        /// Ending git reference, inclusive
+        #[arg(default_value = "HEAD")]
+        to: String,
+        /// Include merge commits
+        #[arg(long)]
+        include_merges: bool,
+    },
+    /// Write release notes for the commits since a tag
+    ReleaseNotes {
+        /// Previous release tag, exclusive; defaults to the most recent tag reachable from HEAD
+        prev_tag: Option<String>,
+        /// Also create an annotated tag at HEAD with the notes as its message
+        #[arg(long, value_name = "NAME", help = "Create an annotated tag with this name at HEAD, using the release notes as its message")]
+        tag: Option<String>,
+    },
+    /// Summarize what landed on the current branch since it was last checked
+    Since {
+        /// Where to start instead of the last-seen commit; required the first time on a branch
+        reference: Option<String>,
+        /// Preview without marking HEAD as seen
+        #[arg(long)]
+        no_advance: bool,
+    },
+    /// Summarize the differences between two files, which need not be in a repository
+    DiffFiles {
+        /// The original file
+        old: std::path::PathBuf,
+        /// The changed file
+        new: std::path::PathBuf,
+        /// Custom prompt to use for the summary
+        #[arg(long, help = "Custom prompt for AI summarization (e.g., 'Focus on configuration changes')")]
+        prompt: Option<String>,
+        /// Read the custom prompt from a file
+        #[arg(long, value_name = "PATH", conflicts_with = "prompt", help = "Read the custom prompt from a file; a '{diff}' placeholder makes it the whole request with the diff substituted")]
+        prompt_file: Option<std::path::PathBuf>,
+    },
+    /// Re-summarize the uncommitted changes whenever a file in the working tree changes
+    Watch {
+        /// Milliseconds to wait for saves to settle before summarizing
+        #[arg(long, value_name = "MS", default_value_t = 500, help = "Wait until files have stopped changing for this many milliseconds before summarizing")]
+        debounce: u64,
+    },
+    /// Suggest a branch name for the staged changes, or unstaged ones if nothing is staged
+    Branch {
+        /// Prefix such as `feature/` or `fix/`
+        #[arg(long, help = "Prefix for the branch name (e.g. 'feature/' or 'fix/')")]
+        prefix: Option<String>,
+        /// Create the branch and switch to it
+        #[arg(long, help = "Create the branch at HEAD and switch to it, keeping your changes")]
+        checkout: bool,
+    },
+    /// Suggest a semantic version bump for a range of commits
+    Bump {
+        /// Commit range as `<from>..<to>`; `<to>` defaults to HEAD
+        range: String,
+        /// Current version, to also print the next one
+        #[arg(long, value_name = "SEMVER", help = "Current version (e.g. 1.3.2 or v1.3.2); also print the next version")]
+        current: Option<version::Version>,
+    },
+    /// Check an existing commit message against message rules
+    VerifyMessage {
+        /// Commit whose message to check
+        #[arg(default_value = "HEAD")]
+        reference: String,
+        /// Require a Conventional Commits subject
+        #[arg(long)]
+        conventional: bool,
+        /// Maximum subject length
+        #[arg(long, value_name = "N", help = "Maximum subject length (default 50, or 72 with --conventional)")]
+        max_subject_length: Option<usize>,
+        /// Trailer that must be present
+        #[arg(long = "require-trailer", value_name = "KEY", help = "Trailer that must be present, e.g. 'Signed-off-by' (repeatable)")]
+        required_trailers: Vec<String>,
+    },
+    /// Undo the last commit if gitwise created it, keeping its changes staged
+    Undo,
+    /// Install a prepare-commit-msg hook that pre-fills `git commit` messages
+    InstallHook,
+    /// Remove the prepare-commit-msg hook installed by `install-hook`
+    UninstallHook,
+    /// Explain how a file or range of lines came to be
+    Blame {
+        /// File to explain, relative to the repository root
+        path: String,
+        /// Line range such as L10,30
+        range: Option<String>,
+        /// Maximum number of commits to consider
+        #[arg(long, default_value_t = 10, help = "Only consider the most recent N commits touching the lines, to bound token usage")]
+        max_commits: usize,
+    },
+    /// Explain merge or rebase conflicts and suggest resolutions
+    Conflicts,
+    /// Propose AI merges for conflicted files as `.gitwise-resolved` sidecars (experimental)
+    ResolveDiff,
+    /// Manage the on-disk summary cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Report tokens used and their estimated cost across runs, per day and model
+    Usage {
+        /// Forget all recorded usage
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Inspect config files
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
     },
     /// Show commit history with AI-generated summaries
     Log {
@@ -85,7 +647,19 @@ enum Commands {
     },
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Remove all cached summaries
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// List config files in precedence order and whether each exists
+    Path,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
 enum ModelProvider {
     /// Use Anthropic's Claude model
     Anthropic,
@@ -93,56 +667,671 @@ enum ModelProvider {
     OpenAI,
 }
 
-/// Resolve a git reference (branch, tag, or commit hash) to a commit
-fn resolve_reference(repo: &Repository, reference: &str) -> Result<Oid> {
-    // Try as a direct reference first (branch or tag)
-    if let Ok(reference) = repo.find_reference(reference) {
-        return Ok(reference.peel_to_commit()?.id());
+/// Open the repository containing `path`, or the one found from the
+/// environment and current directory like git does
+fn open_repo(path: Option<&std::path::Path>) -> Result<Repository> {
+    match path {
+        Some(path) => Repository::discover(path)
+            .with_context(|| format!("No git repository found at {}", path.display())),
+        None => Ok(Repository::open_from_env()?),
     }
+}
 
-    // Try as a revision (commit hash, HEAD~1, etc)
-    if let Ok(revspec) = repo.revparse_single(reference) {
-        return Ok(revspec.peel_to_commit()?.id());
+/// Resolve the custom prompt from `--prompt` or `--prompt-file`
+fn read_prompt(prompt: &Option<String>, prompt_file: &Option<std::path::PathBuf>) -> Result<Option<String>> {
+    match (prompt, prompt_file) {
+        (Some(_), Some(_)) => Err(anyhow!("--prompt and --prompt-file cannot be used together")),
+        (_, Some(path)) => {
+            let prompt = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read prompt file {}", path.display()))?;
+            Ok(Some(prompt.trim().to_string()))
+        }
+        (prompt, None) => Ok(prompt.clone()),
     }
+}
 
-    // Try as a short commit hash
-    let partial_hash = reference.to_string();
-    if partial_hash.len() >= 4 {
-        let mut found_oid = None;
-        repo.odb()?.foreach(|oid| {
-            if oid.to_string().starts_with(&partial_hash) {
-                found_oid = Some(*oid);
-                false // Stop iteration
-            } else {
-                true // Continue iteration
+/// Print categorized files as a table of category, path and summary
+fn print_categorized_files(files: &[ai::CategorizedFile]) {
+    let path_width = files.iter().map(|file| file.path.chars().count()).max().unwrap_or(0).max("FILE".len());
+    outln!("{}", output::paint(format!("{:<8}  {:<path_width$}  SUMMARY", "CATEGORY", "FILE"), Color::Bold));
+    for file in files {
+        // Padded before painting so color codes do not count towards the width
+        outln!(
+            "{}  {:<path_width$}  {}",
+            output::paint(format!("{:<8}", file.category), Color::Cyan),
+            file.path,
+            markup::render(&file.summary).trim_end()
+        );
+    }
+}
+
+/// A review finding as a single `file:line: severity: message` line
+fn format_finding(finding: &ai::ReviewFinding) -> String {
+    let location = match finding.line {
+        Some(line) => format!("{}:{}", finding.file, line),
+        None => finding.file.clone(),
+    };
+    format!("{}: {}: {}", location, finding.severity, finding.message)
+}
+
+/// Post a review as a comment on the current branch's pull request
+#[cfg(feature = "github")]
+async fn post_review_to_github(repo: &Repository, review: &str) -> Result<()> {
+    match github::comment_on_pull_request(repo, review).await? {
+        Some(number) => eprintln!("Posted review to pull request #{}", number),
+        None => eprintln!("No open pull request for the current branch; review not posted"),
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "github"))]
+async fn post_review_to_github(_repo: &Repository, _review: &str) -> Result<()> {
+    Err(anyhow!("--post-github needs gitwise built with the 'github' feature (cargo build --features github)"))
+}
+
+/// Diff between two references, from their merge base with `merge_base`;
+/// without `to` the diff is to HEAD
+fn range_diff<'r>(repo: &'r Repository, from: &str, to: Option<&str>, merge_base: bool, opts: &mut git2::DiffOptions) -> Result<git2::Diff<'r>> {
+    if let (true, Some(to)) = (merge_base, to) {
+        let from_oid = git::resolve_reference(repo, from)?;
+        let to_oid = git::resolve_reference(repo, to)?;
+        return git::get_merge_base_diff(repo, from_oid, to_oid, opts);
+    }
+    let from_tree = repo.find_commit(git::resolve_reference(repo, from)?)?.tree()?;
+    let to_tree = match to {
+        Some(to) => repo.find_commit(git::resolve_reference(repo, to)?)?.tree()?,
+        None => repo.head()?.peel_to_tree()?,
+    };
+    Ok(repo.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), Some(opts))?)
+}
+
+/// Diff from `from` to `to`, or to the working directory and index when `to`
+/// is unset
+fn reference_diff<'r>(repo: &'r Repository, from: &str, to: Option<&str>) -> Result<git2::Diff<'r>> {
+    let from_tree = repo.find_commit(git::resolve_reference(repo, from)?)?.tree()?;
+    let mut diff = match to {
+        Some(to) => {
+            let to_tree = repo.find_commit(git::resolve_reference(repo, to)?)?.tree()?;
+            repo.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), Some(&mut git::diff_options()))?
+        }
+        None => return staging::diff_tree_to_worktree(repo, Some(&from_tree), &mut git::diff_options()),
+    };
+    git::detect_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// Report a created commit, ending with its full oid on a line of its own
+///
+/// JSON output includes the message's layout `violations`; text output has
+/// already warned about them.
+fn print_commit(oid: Oid, message: &str, violations: &[lint::RuleResult], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            outln!("{}\n{}", output::paint("Created commit with message:", Color::Bold), message);
+            outln!("{}", output::paint(oid, Color::Yellow));
+        }
+        OutputFormat::Json => {
+            outln!("{}", serde_json::json!({ "oid": oid.to_string(), "message": message, "violations": violations }));
+        }
+    }
+}
+
+/// Print a commit message that was generated but not committed
+fn print_message(message: &str, violations: &[lint::RuleResult], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => outln!("{}", message),
+        OutputFormat::Json => outln!("{}", serde_json::json!({ "message": message, "violations": violations })),
+    }
+}
+
+/// Settings shared by every commit message gitwise generates
+struct CommitSettings {
+    style: ai::CommitStyle,
+    format: OutputFormat,
+    line_ending: output::LineEnding,
+    edit: bool,
+    /// `Name <email>` of each co-author to credit in a trailer
+    co_authors: Vec<String>,
+    /// Write messages from diff stats instead of asking a model
+    offline: bool,
+    /// Alternative messages to generate and choose between
+    candidates: u8,
+    /// Whether the user can be asked to choose between candidates
+    interactive: bool,
+    /// Abort when the changes look like they contain secrets
+    scan_secrets: bool,
+    /// Size above which staged files are flagged, or `None` to skip the check
+    max_file_bytes: Option<u64>,
+    /// Abort rather than warn about large files
+    strict_large_files: bool,
+    /// Check generated messages against the subject and wrap limits
+    check_messages: bool,
+    /// Longest subject allowed in generated messages
+    subject_max: usize,
+    /// Widest body line allowed in generated messages
+    wrap: usize,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    /// Where to push once committed, with `--push`
+    push: Option<git::PushTarget>,
+    /// Layout generated messages are filled into
+    template: Option<template::MessageTemplate>,
+    /// Issue key from the branch name, for the template's `{refs}`
+    refs: Option<String>,
+}
+
+impl CommitSettings {
+    /// Diff options that keep `--include`/`--exclude` paths out of the prompt
+    fn diff_options(&self) -> git2::DiffOptions {
+        git::pathspec_options(&self.include, &self.exclude)
+    }
+
+    /// Push the branch if `--push` was given
+    fn push(&self, repo: &Repository) -> Result<()> {
+        let Some(target) = &self.push else {
+            return Ok(());
+        };
+        let spinner = output::spinner(&format!("Pushing {}", target));
+        let pushed = git::push_branch(repo, target);
+        spinner.finish_and_clear();
+        pushed?;
+        match self.format {
+            OutputFormat::Text => outln!("Pushed {}", output::paint(target, Color::Cyan)),
+            OutputFormat::Json => outln!("{}", serde_json::json!({
+                "pushed": { "remote": target.remote, "branch": target.remote_branch },
+            })),
+        }
+        Ok(())
+    }
+
+    /// Layout problems of a message, or none when checks are off
+    fn violations(&self, message: &str) -> Vec<lint::RuleResult> {
+        if !self.check_messages {
+            return Vec::new();
+        }
+        lint::format_violations(message, self.subject_max, self.wrap)
+    }
+
+    /// Fail if secret scanning is on and the diff seems to add a secret
+    fn check_secrets(&self, diff: &git2::Diff<'_>) -> Result<()> {
+        if !self.scan_secrets {
+            return Ok(());
+        }
+        let findings = git::detect_secrets(diff)?;
+        if findings.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = findings.iter()
+            .map(|finding| format!("  {}:{}: {}", finding.path, finding.line, finding.kind))
+            .collect();
+        Err(fail(Outcome::Validation, format!(
+            "Possible secrets in the changes:\n{}\nRemove them, or pass --allow-secrets if they are false positives",
+            lines.join("\n")
+        )))
+    }
+
+    /// Warn about files too large to commit comfortably, or fail with
+    /// `--strict`
+    ///
+    /// `diff` should be everything about to be committed, not the diff
+    /// narrowed by `--include` and `--exclude`.
+    fn check_large_files(&self, repo: &Repository, diff: &git2::Diff<'_>) -> Result<()> {
+        let Some(max_bytes) = self.max_file_bytes else {
+            return Ok(());
+        };
+        let large = git::detect_large_files(repo, diff, max_bytes)?;
+        if large.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = large.iter()
+            .map(|file| format!("  {} ({}{})", file.path, file.size_label(), if file.binary { ", binary" } else { "" }))
+            .collect();
+        let message = format!("Large files are staged; consider Git LFS for them:\n{}", lines.join("\n"));
+        if self.strict_large_files {
+            return Err(fail(Outcome::Validation, format!("{}\nUnstage them, or commit without --strict to only be warned", message)));
+        }
+        warn!("{}", message);
+        Ok(())
+    }
+
+    /// Generated messages with the index of the model's pick and its reason;
+    /// just the one message unless `--candidates` asked for more
+    async fn generate(&self, engine: &ai::AiEngine, diff: &git2::Diff<'_>) -> Result<(Vec<String>, usize, String)> {
+        if self.candidates <= 1 {
+            return Ok((vec![engine.generate_commit_message(diff, self.style).await?], 0, String::new()));
+        }
+        let candidates = engine.generate_commit_message_candidates(diff, self.style, self.candidates).await?;
+        let (best, reason) = engine.pick_commit_message(diff, &candidates).await?;
+        Ok((candidates, best, reason))
+    }
+
+    /// Let the user choose between candidate messages, defaulting to the
+    /// model's pick, which is taken as is when there is no one to ask
+    fn choose(&self, mut candidates: Vec<String>, best: usize, reason: &str) -> Result<String> {
+        if candidates.len() > 1 && self.interactive {
+            for (i, message) in candidates.iter().enumerate() {
+                let label = if i == best { format!("{} (recommended)", i + 1) } else { (i + 1).to_string() };
+                outln!("{}\n{}\n", output::paint(label, Color::Bold), message);
+            }
+            if !reason.is_empty() {
+                outln!("Recommended because: {}\n", reason);
+            }
+            let choice = utils::choose("Use which message?", candidates.len(), best)?;
+            return Ok(candidates.swap_remove(choice));
+        }
+        Ok(candidates.swap_remove(best))
+    }
+
+    /// Generate a commit message for a diff and apply the local conventions,
+    /// letting the user edit it first if requested
+    ///
+    /// A generated message that breaks the subject length, blank line or
+    /// wrap rules is sent back for one revision. Whatever violations remain
+    /// are returned alongside the message.
+    async fn message(&self, engine: &ai::AiEngine, repo: &Repository, diff: &git2::Diff<'_>) -> Result<(String, Vec<lint::RuleResult>)> {
+        self.check_secrets(diff)?;
+        let mut offline = self.offline;
+        let mut message = if offline {
+            engine.generate_offline_commit_message(diff, self.style)?
+        } else {
+            match output::with_spinner("Generating commit message", self.generate(engine, diff)).await {
+                Err(e) if e.downcast_ref::<ai::ProviderUnavailable>().is_some() => {
+                    warn!("{}; falling back to an offline commit message", e);
+                    offline = true;
+                    engine.generate_offline_commit_message(diff, self.style)?
+                }
+                result => {
+                    let (candidates, best, reason) = result?;
+                    self.choose(candidates, best, &reason)?
+                }
+            }
+        };
+        message = utils::wrap_body(&message, self.wrap);
+
+        let violations = self.violations(&message);
+        if !offline && !violations.is_empty() {
+            let problems: Vec<String> = violations.iter().filter_map(|v| v.detail.clone()).collect();
+            debug!("Generated message breaks {}; asking for a revision", problems.join(", "));
+            message = output::with_spinner("Revising commit message", engine.revise_commit_message(diff, self.style, &message, &problems)).await?;
+            message = utils::wrap_body(&message, self.wrap);
+        }
+
+        if let Some(template) = &self.template {
+            let files: Vec<String> = git::get_name_status(diff).into_iter().map(|(_, path)| path).collect();
+            message = template.render(&template::TemplateValues { message: &message, files: &files, refs: self.refs.as_deref() });
+        }
+        message = lint::append_trailers(&message, "Co-authored-by", &self.co_authors);
+        message = lint::append_trailers(&message, lint::GENERATED_BY_KEY, &[lint::GENERATED_BY_VALUE.to_string()]);
+        if self.edit {
+            message = utils::edit_in_editor(&repo.path().join("COMMIT_EDITMSG"), &message)?;
+            if message.is_empty() {
+                return Err(anyhow!("Aborting commit due to empty commit message"));
+            }
+        }
+
+        let violations = self.violations(&message);
+        if !violations.is_empty() && !self.edit {
+            let problems: Vec<String> = violations.iter().filter_map(|v| v.detail.clone()).collect();
+            warn!("Commit message still breaks the rules ({}); use --edit to fix it by hand", problems.join("; "));
+        }
+        Ok((self.line_ending.normalize(&message), violations))
+    }
+}
+
+/// Answer follow-up questions about a diff, read from stdin, until EOF or
+/// `/quit`
+///
+/// A question the model fails to answer is reported and can be asked again.
+async fn chat_about_diff(engine: &ai::AiEngine, diff: &git2::Diff<'_>, summary: &str) -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    let mut conversation = engine.start_conversation(diff, summary)?;
+    eprintln!("\nAsk a follow-up question, or /quit to finish.");
+    loop {
+        eprint!("> ");
+        std::io::stderr().flush()?;
+        let mut question = String::new();
+        if std::io::stdin().lock().read_line(&mut question)? == 0 {
+            break;
+        }
+        match question.trim() {
+            "" => continue,
+            "/quit" => break,
+            question => match output::with_spinner("Thinking", engine.ask(&mut conversation, question)).await {
+                Ok(answer) => outln!("\n{}\n", markup::render(&answer)),
+                Err(e) => warn!("{}", e),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Fill git's commit message file from the prepare-commit-msg hook
+///
+/// The file is only filled for plain commits whose message is still empty
+/// (ignoring comments) or comes from a commit template. Messages given with
+/// `-m`/`-F`, merges, squashes and amends are left alone.
+async fn fill_commit_message_file(repo: &Repository, engine: &ai::AiEngine, settings: &CommitSettings, message_file: &std::path::Path, source: Option<&str>) -> Result<()> {
+    let existing = std::fs::read_to_string(message_file)
+        .with_context(|| format!("Failed to read {}", message_file.display()))?;
+    let has_message = existing.lines().any(|line| !line.starts_with('#') && !line.trim().is_empty());
+
+    match source.unwrap_or("") {
+        "" if !has_message => (),
+        "template" => (),
+        _ => return Ok(()),
+    }
+
+    let diff = staging::diff_head_to_index(repo, &mut settings.diff_options())?;
+    if diff.deltas().len() == 0 {
+        return Ok(());
+    }
+
+    settings.check_large_files(repo, &staging::get_staged_changes(repo)?)?;
+    let (message, _) = settings.message(engine, repo, &diff).await?;
+    let separator = settings.line_ending.normalize("\n");
+    let contents = if existing.trim().is_empty() {
+        format!("{}{}", message, separator)
+    } else {
+        format!("{}{}{}", message, separator, settings.line_ending.normalize(&existing))
+    };
+    std::fs::write(message_file, contents)
+        .with_context(|| format!("Failed to write {}", message_file.display()))?;
+    Ok(())
+}
+
+/// How `commit_groups` stages each group
+#[derive(Clone, Copy, PartialEq)]
+enum SplitMode {
+    /// Commit the staged version of each file without further prompts
+    Staged,
+    /// Commit the working tree version of each file, confirming each commit
+    Interactive,
+}
+
+/// Commit each group of files separately, in order
+///
+/// The staged state of every file is snapshotted and the index reset to
+/// HEAD. Each group is then staged and committed on its own, either from the
+/// snapshot or from the working tree depending on `mode`. Files that were
+/// staged but not committed as part of a group are restaged afterwards, also
+/// when a commit fails part way through.
+async fn commit_groups(repo: &Repository, engine: &ai::AiEngine, groups: &[Vec<String>], settings: &CommitSettings, mode: SplitMode) -> Result<()> {
+    let (staged_files, _) = staging::get_change_groups(repo)?;
+    let mut entries = staging::snapshot_index_entries(repo, &staged_files)?;
+    staging::reset_index_to_head(repo)?;
+
+    let mut skipped = Vec::new();
+    let result = commit_each_group(repo, engine, groups, settings, mode, &mut entries, &mut skipped).await;
+
+    // Put back anything that was staged but not committed, dropping a group
+    // left half staged by an error
+    staging::reset_index_to_head(repo)?;
+    let leftover: Vec<String> = entries.keys().cloned().collect();
+    if !leftover.is_empty() {
+        staging::restore_index_entries(repo, &mut entries, &leftover)?;
+        eprintln!("\nLeft staged: {}", leftover.join(", "));
+    }
+    result?;
+    if !skipped.is_empty() {
+        eprintln!("Skipped groups left uncommitted: {}", skipped.join(", "));
+    }
+
+    Ok(())
+}
+
+/// The loop of `commit_groups`, removing each committed file from `entries`
+async fn commit_each_group(
+    repo: &Repository,
+    engine: &ai::AiEngine,
+    groups: &[Vec<String>],
+    settings: &CommitSettings,
+    mode: SplitMode,
+    entries: &mut std::collections::HashMap<String, Option<git2::IndexEntry>>,
+    skipped: &mut Vec<String>,
+) -> Result<()> {
+    for (i, group) in groups.iter().enumerate() {
+        match mode {
+            SplitMode::Staged => staging::restore_index_entries(repo, entries, group)?,
+            SplitMode::Interactive => {
+                for path in group {
+                    staging::stage_file(repo, path)?;
+                }
+            }
+        }
+
+        let diff = staging::diff_head_to_index(repo, &mut settings.diff_options())?;
+        settings.check_large_files(repo, &staging::get_staged_changes(repo)?)?;
+        let (message, violations) = settings.message(engine, repo, &diff).await?;
+
+        if mode == SplitMode::Interactive {
+            eprintln!("\nGroup {}/{}: {}", i + 1, groups.len(), group.join(", "));
+            eprintln!("Suggested commit message:\n{}\n", message);
+            if !utils::confirm("Create this commit?")? {
+                staging::reset_index_to_head(repo)?;
+                skipped.extend(group.iter().cloned());
+                continue;
+            }
+        }
+
+        let oid = staging::commit_index(repo, &message)?;
+        for path in group {
+            entries.remove(path);
+        }
+        if settings.format == OutputFormat::Text {
+            outln!("\nCommit {}/{}:", i + 1, groups.len());
+        }
+        print_commit(oid, &message, &violations, settings.format);
+    }
+    Ok(())
+}
+
+/// Where a changelog or release starts, exclusive: `from` if given, else
+/// the most recent tag reachable from HEAD
+///
+/// `None` means there are no tags, so the whole history is included.
+fn release_start(repo: &Repository, from: Option<&str>) -> Result<Option<(String, Oid)>> {
+    let reference = match from {
+        Some(reference) => reference.to_string(),
+        None => match git::latest_tag(repo) {
+            Some(tag) => {
+                eprintln!("Starting after {}, the most recent tag", output::paint(&tag, Color::Yellow));
+                tag
+            }
+            None => {
+                eprintln!("No tags found; starting from the root commit");
+                return Ok(None);
+            }
+        },
+    };
+    let oid = git::resolve_reference(repo, &reference)?;
+    Ok(Some((reference, oid)))
+}
+
+/// Summarize the uncommitted changes, then again each time the working
+/// tree changes, until Ctrl-C
+async fn watch(engine: &ai::AiEngine, repo_path: Option<&std::path::Path>, debounce: std::time::Duration) -> Result<()> {
+    use notify::Watcher;
+
+    let repo = open_repo(repo_path)?;
+    let workdir = repo.workdir().ok_or_else(|| anyhow!("Cannot watch a bare repository"))?.to_path_buf();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = tx.send(event.paths);
+        }
+    })?;
+    watcher.watch(&workdir, notify::RecursiveMode::Recursive)?;
+    eprintln!("Watching {} for changes; press Ctrl-C to stop", workdir.display());
+
+    let mut fingerprint = None;
+    loop {
+        if let Err(e) = summarize_worktree(engine, &workdir, &mut fingerprint).await {
+            warn!("Failed to summarize the changes: {:#}", e);
+        }
+        // Wait for a change to a file that can show up in the diff, then
+        // for saves to stop arriving before looking again
+        loop {
+            let Some(paths) = rx.recv().await else {
+                return Ok(());
+            };
+            if paths.iter().any(|path| affects_worktree_diff(&repo, &workdir, path)) {
+                break;
             }
-        })?;
-        
-        if let Some(oid) = found_oid {
-            return Ok(oid);
         }
+        while let Ok(Some(_)) = tokio::time::timeout(debounce, rx.recv()).await {}
+    }
+}
+
+/// Whether a change to `path` can change the diff of the working tree
+///
+/// Inside `.git` only the index counts, since staging and committing both
+/// rewrite it. Ignored files never appear in the diff.
+fn affects_worktree_diff(repo: &Repository, workdir: &std::path::Path, path: &std::path::Path) -> bool {
+    if path.starts_with(repo.path()) {
+        return path == repo.path().join("index");
     }
+    match path.strip_prefix(workdir) {
+        Ok(relative) => !repo.is_path_ignored(relative).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Clear the screen and print a summary of the changes since HEAD, unless
+/// the diff is the same as last time
+async fn summarize_worktree(engine: &ai::AiEngine, workdir: &std::path::Path, fingerprint: &mut Option<String>) -> Result<()> {
+    // Reopened each time so that new commits and index changes are seen
+    let repo = Repository::open(workdir)?;
+    let head_tree = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(_) => None,
+    };
+    let diff = staging::diff_tree_to_worktree(&repo, head_tree.as_ref(), &mut git::diff_options())?;
+    let current = git::diff_fingerprint(&diff)?;
+    if fingerprint.as_ref() == Some(&current) {
+        debug!("Diff unchanged, not summarizing again");
+        return Ok(());
+    }
+    *fingerprint = Some(current);
+
+    let summary = if diff.deltas().len() == 0 {
+        "No uncommitted changes".to_string()
+    } else {
+        markup::render(&output::with_spinner("Summarizing", engine.summarize_diff(&diff, None)).await?)
+    };
+    if output::results_to_terminal() {
+        out!("\x1b[2J\x1b[H");
+    }
+    outln!("{}", summary);
+    eprintln!("Updated {}", output::paint(chrono::Local::now().format("%H:%M:%S"), Color::Yellow));
+    Ok(())
+}
 
-    Err(anyhow::anyhow!("Could not resolve git reference: {}", reference))
+/// Write the completion script for `shell`
+fn print_completions(shell: clap_complete::Shell, out: &mut dyn std::io::Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "gitwise", out);
+}
+
+/// Exit statuses scripts can tell apart; other errors exit with 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// There was nothing to commit, stage or summarize
+    NoChanges = 2,
+    /// The AI provider is not configured, could not be reached or refused
+    /// the request
+    Api = 3,
+    /// A check failed, such as secret scanning or `review --fail-on`
+    Validation = 4,
+}
+
+/// An error that exits with a specific status
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+struct Failure {
+    outcome: Outcome,
+    message: String,
+}
+
+/// An error exiting with `outcome`'s status
+fn fail(outcome: Outcome, message: impl Into<String>) -> anyhow::Error {
+    Failure { outcome, message: message.into() }.into()
+}
+
+/// The process exit status for an error
+fn exit_status(error: &anyhow::Error) -> u8 {
+    match error.downcast_ref::<Failure>() {
+        Some(failure) => failure.outcome as u8,
+        None if ai::is_provider_error(error) => Outcome::Api as u8,
+        None => 1,
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> std::process::ExitCode {
+    match try_main().await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(error) => {
+            match error.downcast_ref::<Failure>() {
+                // Not a failure as such, so reported like a result
+                Some(Failure { outcome: Outcome::NoChanges, message }) if output::json_results() => {
+                    outln!("{}", serde_json::json!({ "status": "no_changes", "message": message }));
+                }
+                Some(Failure { outcome: Outcome::NoChanges, message }) => outln!("{}", message),
+                _ => eprintln!("Error: {:?}", error),
+            }
+            std::process::ExitCode::from(exit_status(&error))
+        }
+    }
+}
+
+async fn try_main() -> Result<()> {
     dotenv::dotenv().ok();
     let cli = Cli::parse();
-
-    // Initialize logging
-    if cli.verbose {
-        fmt()
-            .with_max_level(tracing::Level::DEBUG)
-            .init();
-    } else {
-        fmt()
-            .with_max_level(tracing::Level::INFO)
-            .init();
+    if let Commands::Completions { shell } = cli.command {
+        print_completions(shell, &mut std::io::stdout());
+        return Ok(());
     }
 
-    let mut engine = ai::AiEngine::new()?;
+    // Initialize logging on stderr so stdout stays clean for results
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => tracing::Level::ERROR,
+        (false, 0) => tracing::Level::INFO,
+        (false, 1) => tracing::Level::DEBUG,
+        (false, _) => tracing::Level::TRACE,
+    };
+    fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+
+    // Outside a repository only the global config applies; commands that
+    // need one report the error when they open it
+    let repo = match &cli.repo {
+        Some(path) => Some(open_repo(Some(path))?),
+        None => open_repo(None).ok(),
+    };
+
+    git::configure_diffs(git::DiffSettings {
+        context_lines: cli.context,
+        ignore_whitespace: cli.ignore_whitespace,
+        algorithm: cli.diff_algorithm,
+        scope: cli.scope.clone(),
+    });
+
+    let workdir = repo.as_ref().and_then(|repo| repo.workdir());
+    let mut engine = ai::AiEngine::for_profile(cli.profile.as_deref().filter(|name| !name.is_empty()), workdir)?;
+    if let Some(url) = &cli.base_url {
+        engine = engine.with_openai_base_url(url)?;
+    }
+    if let Some(rpm) = cli.rpm {
+        engine = engine.with_rate_limit(rpm);
+    }
+    if let Some(path) = &cli.openai_api_key_file {
+        engine = engine.with_openai_api_key(&ai::keys::read_key_file(path)?)?;
+    }
+    if let Some(language) = &cli.language {
+        engine = engine.with_response_language(language);
+    }
     
     // Apply model provider if specified
     if let Some(provider) = cli.model {
@@ -154,10 +1343,165 @@ async fn main() -> Result<()> {
     } else {
         info!("Using default model provider selection");
     }
+    if let Some(params) = &cli.params {
+        engine = engine.with_params(params.clone());
+    }
+    if let Some(repo) = &repo {
+        engine = engine.with_repository(repo.path());
+    }
+    engine = engine
+        .with_timeout(std::time::Duration::from_secs(cli.timeout))
+        .with_whitespace_hunks_collapsed(cli.collapse_whitespace_hunks)
+        .with_word_diff(cli.word_diff)
+        .with_line_references(!cli.no_line_refs)
+        .with_generated_files(cli.include_generated)
+        .with_max_input_tokens(cli.max_input_tokens)
+        .with_max_diff_bytes(cli.max_diff_bytes)
+        .with_chunking(!cli.no_chunk)
+        .with_model_fallback(cli.model_fallback.clone());
+    // Commit messages and changelogs follow strict formats, so default to
+    // less creative sampling for them
+    let default_temperature = match cli.command {
+        Commands::Add { .. } | Commands::Commit { .. } | Commands::Changelog { .. } => 0.3,
+        _ => ai::DEFAULT_TEMPERATURE,
+    };
+    let temperature = cli.temperature.or(engine.config().temperature).unwrap_or(default_temperature);
+    engine = engine.with_temperature(temperature)?;
+    // Commit messages and branch names are short; changelogs, PR
+    // descriptions and explanations can run long
+    let default_max_tokens = match cli.command {
+        Commands::Add { .. } | Commands::Commit { .. } | Commands::Branch { .. } => ai::TERSE_MAX_TOKENS,
+        Commands::Changelog { .. } | Commands::ReleaseNotes { .. } | Commands::Pr { .. } | Commands::Explain { .. } => ai::LONG_FORM_MAX_TOKENS,
+        _ => ai::DEFAULT_MAX_TOKENS,
+    };
+    let max_tokens = cli.max_tokens.or(engine.config().max_tokens).unwrap_or(default_max_tokens);
+    engine = engine.with_max_tokens(max_tokens);
+    if cli.deterministic {
+        engine = engine.with_deterministic_sampling();
+    }
+    if let Some(scope) = &cli.scope {
+        engine = engine.with_scope(scope);
+    }
+    if let Some(path) = &cli.context_prefix_file {
+        let context = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read context prefix file {}", path.display()))?;
+        engine = engine.with_context_prefix(context);
+    }
+    if cli.no_tests {
+        let patterns = if cli.test_patterns.is_empty() {
+            ai::DEFAULT_TEST_PATTERNS.iter().map(|p| p.to_string()).collect()
+        } else {
+            cli.test_patterns.clone()
+        };
+        engine = engine.with_tests_excluded(patterns);
+    }
+    if !cli.no_cache {
+        match ai::cache::SummaryCache::open(cli.cache_ttl_days) {
+            Ok(cache) => engine = engine.with_cache(cache),
+            Err(e) => debug!("Summary cache unavailable: {}", e),
+        }
+    }
+    match ai::cost::UsageLedger::open() {
+        Ok(ledger) => engine = engine.with_usage_ledger(ledger),
+        Err(e) => debug!("Usage ledger unavailable: {}", e),
+    }
+
+    let format = if cli.json { OutputFormat::Json } else { cli.format.output() };
+    markup::set_markup(cli.format.markup());
+    if let Some(path) = &cli.output {
+        output::redirect_results(path, cli.force)?;
+    }
+    output::enable_progress(!cli.quiet && format == OutputFormat::Text);
+    output::set_json_results(format == OutputFormat::Json);
+    output::enable_color(cli.color, format);
+
+    let usage = engine.usage_meter();
+    let result = run(&cli, engine, format).await;
+    if cli.show_cost {
+        print_usage(&usage);
+    }
+    result
+}
+
+/// Print the usage recorded in the ledger, one line per day and model
+fn print_ledger(entries: &ai::cost::LedgerEntries, format: OutputFormat) {
+    let mut total = ai::cost::TokenUsage::default();
+    let mut total_cost = Some(0.0);
+    let mut rows = Vec::new();
+    for (day, models) in entries {
+        for (model, usage) in models {
+            let cost = usage.cost(model);
+            total.prompt_tokens += usage.prompt_tokens;
+            total.completion_tokens += usage.completion_tokens;
+            total_cost = total_cost.zip(cost).map(|(total, cost)| total + cost);
+            match format {
+                OutputFormat::Text => outln!(
+                    "{}  {}: {} prompt + {} completion = {}, estimated cost {}",
+                    day,
+                    output::paint(model, Color::Cyan),
+                    usage.prompt_tokens,
+                    usage.completion_tokens,
+                    usage.total(),
+                    cost.map_or("unknown".to_string(), |cost| format!("${:.4}", cost))
+                ),
+                OutputFormat::Json => rows.push(serde_json::json!({
+                    "date": day,
+                    "model": model,
+                    "prompt_tokens": usage.prompt_tokens,
+                    "completion_tokens": usage.completion_tokens,
+                    "estimated_cost": cost,
+                })),
+            }
+        }
+    }
+
+    match format {
+        OutputFormat::Text if entries.is_empty() => outln!("No usage recorded yet"),
+        OutputFormat::Text => outln!(
+            "Total: {} tokens, estimated cost {}",
+            total.total(),
+            total_cost.map_or("unknown (some models have no known price)".to_string(), |cost| format!("${:.4}", cost))
+        ),
+        OutputFormat::Json => outln!("{}", serde_json::json!({
+            "usage": rows,
+            "total_tokens": total.total(),
+            "estimated_cost": total_cost,
+        })),
+    }
+}
+
+/// Print the tokens used per model and their estimated cost to stderr
+fn print_usage(usage: &ai::cost::UsageMeter) {
+    let totals = usage.totals();
+    if totals.is_empty() {
+        eprintln!("Tokens: none used");
+        return;
+    }
+    let mut total_cost = Some(0.0);
+    for (model, usage) in &totals {
+        let cost = usage.cost(model);
+        eprintln!(
+            "Tokens ({}): {} prompt + {} completion = {}, estimated cost {}",
+            model,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            usage.total(),
+            cost.map_or("unknown".to_string(), |cost| format!("${:.4}", cost))
+        );
+        total_cost = total_cost.zip(cost).map(|(total, cost)| total + cost);
+    }
+    if totals.len() > 1 {
+        if let Some(total_cost) = total_cost {
+            eprintln!("Estimated total cost: ${:.4}", total_cost);
+        }
+    }
+}
 
+/// Run the selected subcommand
+async fn run(cli: &Cli, engine: ai::AiEngine, format: OutputFormat) -> Result<()> {
     match &cli.command {
         Commands::Add { prompt } => {
-            let repo = Repository::open_from_env()?;
+            let repo = open_repo(cli.repo.as_deref())?;
             
             // Get staged and unstaged changes
             let staged_diff = staging::get_staged_changes(&repo)?;
@@ -168,16 +1512,14 @@ async fn main() -> Result<()> {
             
             // Skip if no changes
             if unstaged_files.is_empty() {
-                println!("No changes to stage.");
-                return Ok(());
+                return Err(fail(Outcome::NoChanges, "No changes to stage."));
             }
             
             // Analyze changes and group them by feature
-            let groups = engine.analyze_changes(&staged_diff, &unstaged_diff, prompt.as_deref()).await?;
+            let groups = output::with_spinner("Grouping changes", engine.analyze_changes(&staged_diff, &unstaged_diff, prompt.as_deref())).await?;
             
             if groups.is_empty() {
-                println!("No changes to stage.");
-                return Ok(());
+                return Err(fail(Outcome::NoChanges, "No changes to stage."));
             }
 
             // Take the first group as our suggestion
@@ -191,11 +1533,12 @@ async fn main() -> Result<()> {
 
             // Get fresh diff after staging
             let new_staged_diff = staging::get_staged_changes(&repo)?;
-            let commit_msg = engine.generate_commit_message(&new_staged_diff).await?;
+            let commit_msg = output::with_spinner("Generating commit message", engine.generate_commit_message(&new_staged_diff, ai::CommitStyle::Freeform)).await?;
             
-            println!("\nSuggested commit message:\n{}", commit_msg);
+            outln!("\nSuggested commit message:\n{}", commit_msg);
         }
-        Commands::Pr { base, title, body } => {
+        Commands::Pr { base, title, body, print } => {
+            let repo = open_repo(cli.repo.as_deref())?;
             let mut pr = git::pr::PullRequest::new();
             
             if let Some(t) = title {
@@ -208,71 +1551,370 @@ async fn main() -> Result<()> {
                 pr = pr.with_base(base_branch.clone());
             }
             
-            pr.create().await?;
-            println!("✨ Pull request created successfully!");
+            if *print {
+                outln!("{}", output::with_spinner("Drafting pull request", pr.draft(&repo, &engine)).await?.to_markdown());
+            } else {
+                pr.create(&repo, &engine).await?;
+                println!("✨ Pull request created successfully!");
+            }
         }
-        Commands::Diff { from, to, staged, prompt } => {
-            let repo = Repository::open_from_env()?;
-            let diff = if *staged {
+        Commands::Diff { from, to, staged, upstream, worktree, all, merge_base, patch_file, stdin, prompt, prompt_file, stream, llm_self_check, breaking, by_file, net, categorize, name_status, stat, max_output_files, over_limit, focus_regex, explain_for, detail, budget_aware_model, small_diff_lines, small_model, large_model, include, exclude, path, lines, interactive } => {
+            let mut engine = engine
+                .with_focus_patterns(focus_regex.clone())
+                .with_breaking_changes_flagged(*breaking);
+            if *interactive && format == OutputFormat::Json {
+                return Err(anyhow!("--interactive answers questions as text; it cannot be combined with --json"));
+            }
+            if let Some(lines) = lines {
+                engine = engine.with_line_range(lines.clone());
+            }
+            let include = &match path {
+                Some(path) => vec![path.clone()],
+                None => include.clone(),
+            };
+            let from = if *upstream { "@{upstream}" } else { from.as_str() };
+            if let Some(audience) = explain_for {
+                engine = engine.with_audience(*audience);
+            }
+            engine = engine.with_detail(*detail);
+            let prompt = &read_prompt(prompt, prompt_file)?;
+            let patch_text = match patch_file {
+                Some(path) => Some(std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read patch file {}", path.display()))?),
+                None if *stdin => {
+                    let mut text = String::new();
+                    std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).context("Failed to read patch from stdin")?;
+                    Some(text)
+                }
+                None => None,
+            };
+            if let Some(patch_text) = patch_text {
+                let summary = output::with_spinner("Summarizing patch", engine.summarize_patch_text(&patch_text, prompt.as_deref())).await?;
+                match format {
+                    OutputFormat::Text => outln!("{}\n{}", output::paint("Changes Summary:", Color::Bold), markup::render(&summary)),
+                    OutputFormat::Json => outln!("{}", serde_json::json!({ "summary": summary })),
+                }
+                return Ok(());
+            }
+
+            let repo = open_repo(cli.repo.as_deref())?;
+            if *all {
+                let staged_diff = staging::diff_head_to_index(&repo, &mut git::pathspec_options(include, exclude))?;
+                let unstaged_diff = staging::diff_index_to_workdir(&repo, &mut git::pathspec_options(include, exclude))?;
+                let summary = output::with_spinner("Summarizing changes", engine.summarize_combined(&staged_diff, &unstaged_diff, prompt.as_deref())).await?;
+                match format {
+                    OutputFormat::Text => outln!("{}\n{}", output::paint("Changes Summary:", Color::Bold), markup::render(&summary)),
+                    OutputFormat::Json => outln!("{}", serde_json::json!({ "summary": summary })),
+                }
+                return Ok(());
+            }
+
+            let mut diff = if *staged {
                 // Get diff of staged changes
-                let mut opts = git2::DiffOptions::new();
-                let head_tree = repo.head()?.peel_to_tree()?;
-                repo.diff_tree_to_index(Some(&head_tree), None, Some(&mut opts))?
+                staging::diff_head_to_index(&repo, &mut git::pathspec_options(include, exclude))?
+            } else if *worktree {
+                let from_tree = repo.find_commit(git::resolve_reference(&repo, from)?)?.tree()?;
+                staging::diff_tree_to_worktree(&repo, Some(&from_tree), &mut git::pathspec_options(include, exclude))?
             } else {
-                // Get diff between references
-                let from_commit = repo.find_commit(resolve_reference(&repo, &from)?)?;
-                let from_tree = from_commit.tree()?;
+                range_diff(&repo, from, to.as_deref(), *merge_base, &mut git::pathspec_options(include, exclude))?
+            };
+            git::detect_renames(&mut diff)?;
+            if cli.ignore_whitespace && !*name_status && !*stat && git::whitespace_only(&diff)? {
+                return Err(fail(Outcome::NoChanges, "Only whitespace changed; nothing to summarize with --ignore-whitespace"));
+            }
+            if *net {
+                // Files whose changes cancel out still show up when whitespace
+                // is ignored; leave them out
+                let unchanged = git::unchanged_files(&diff)?;
+                if !unchanged.is_empty() {
+                    debug!("Leaving out {} files with no net change", unchanged.len());
+                    diff = git::without_unchanged_files(&diff)?;
+                }
+                if diff.deltas().len() == 0 {
+                    return Err(fail(Outcome::NoChanges, "No net change in the range; nothing to summarize"));
+                }
+                engine = engine.with_net_effect(true);
+            }
+
+            if *budget_aware_model {
+                engine = engine.with_budget_aware_model(&diff, &ai::ModelBudget {
+                    small_diff_lines: *small_diff_lines,
+                    small_model: small_model.clone(),
+                    large_model: large_model.clone(),
+                })?;
+            }
 
-                let to_tree = if let Some(to) = to {
-                    let to_commit = repo.find_commit(resolve_reference(&repo, &to)?)?;
-                    to_commit.tree()?
+            if *by_file {
+                for file in output::with_spinner("Summarizing files", engine.summarize_files(&diff, prompt.as_deref())).await? {
+                    match format {
+                        OutputFormat::Text => outln!("{}: {}", output::paint(&file.path, Color::Cyan), markup::render(&file.summary)),
+                        OutputFormat::Json => outln!("{}", serde_json::to_string(&file)?),
+                    }
+                }
+            } else if *categorize {
+                let files = output::with_spinner("Categorizing files", engine.categorize_files(&diff, prompt.as_deref())).await?;
+                match format {
+                    OutputFormat::Text => print_categorized_files(&files),
+                    OutputFormat::Json => {
+                        for file in &files {
+                            outln!("{}", serde_json::to_string(file)?);
+                        }
+                    }
+                }
+            } else if *name_status {
+                let entries: Vec<output::FileEntry> = git::get_name_status(&diff)
+                    .into_iter()
+                    .map(|(status, path)| output::FileEntry { status, path })
+                    .collect();
+                output::print_file_entries(&entries, format, *max_output_files, *over_limit)?;
+            } else if *stat {
+                let stats = diff.stats()?;
+                match format {
+                    OutputFormat::Text => out!("{}", stats.to_buf(git2::DiffStatsFormat::FULL, 80)?.as_str().unwrap_or_default()),
+                    OutputFormat::Json => outln!("{}", serde_json::json!({
+                        "files_changed": stats.files_changed(),
+                        "insertions": stats.insertions(),
+                        "deletions": stats.deletions(),
+                    })),
+                }
+            } else if *llm_self_check {
+                let checked = output::with_spinner("Summarizing changes", engine.summarize_diff_with_self_check(&diff, prompt.as_deref())).await?;
+                if format == OutputFormat::Json {
+                    outln!("{}", serde_json::to_string(&checked)?);
                 } else {
-                    // If no 'to' reference is provided, use the working directory
-                    repo.head()?.peel_to_tree()?
-                };
+                    outln!("{}\n{}", output::paint("Changes Summary:", Color::Bold), markup::render(&checked.summary));
+                    outln!("\n{} {:?}", output::paint("Confidence:", Color::Cyan), checked.confidence);
+                    if !checked.unclear.is_empty() {
+                        outln!("\n{}", output::paint("Needs human review:", Color::Yellow));
+                        for item in &checked.unclear {
+                            outln!("  - {}", item);
+                        }
+                    }
+                }
+            } else if *stream && format == OutputFormat::Text {
+                let mut chunks = engine.summarize_diff_stream(&diff, prompt.as_deref()).await?;
+                outln!("{}", output::paint("Changes Summary:", Color::Bold));
+                let mut renderer = markup::Renderer::new(cli.format.markup());
+                while let Some(chunk) = chunks.next().await {
+                    out!("{}", renderer.push(&chunk?));
+                    output::flush_results()?;
+                }
+                outln!("{}", renderer.finish());
+            } else {
+                let summary = output::with_spinner("Summarizing changes", engine.summarize_diff(&diff, prompt.as_deref())).await?;
+                match format {
+                    OutputFormat::Text => outln!("{}\n{}", output::paint("Changes Summary:", Color::Bold), markup::render(&summary)),
+                    OutputFormat::Json => outln!("{}", serde_json::json!({ "summary": summary })),
+                }
+                if *interactive {
+                    chat_about_diff(&engine, &diff, &summary).await?;
+                }
+            }
+        }
+        Commands::Review { from, to, prompt, breaking, post_github, fail_on } => {
+            let engine = engine.with_breaking_changes_flagged(*breaking);
+            let repo = open_repo(cli.repo.as_deref())?;
+            let diff = reference_diff(&repo, from, to.as_deref())?;
+            if format == OutputFormat::Text && fail_on.is_none() {
+                let review = output::with_spinner("Reviewing changes", engine.review_diff(&diff, prompt.as_deref())).await?;
+                outln!("{}", markup::render(&review));
+                if *post_github {
+                    post_review_to_github(&repo, &review).await?;
+                }
+                return Ok(());
+            }
 
-                repo.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)?
+            let findings = output::with_spinner("Reviewing changes", engine.review_findings(&diff, prompt.as_deref())).await?;
+            match format {
+                OutputFormat::Text if findings.is_empty() => outln!("No findings."),
+                OutputFormat::Text => findings.iter().for_each(|finding| outln!("{}", format_finding(finding))),
+                OutputFormat::Json => outln!("{}", serde_json::json!({ "findings": findings })),
+            }
+            if *post_github {
+                let lines: Vec<String> = findings.iter().map(|finding| format!("- `{}`", format_finding(finding))).collect();
+                let review = if lines.is_empty() { "No findings.".to_string() } else { lines.join("\n") };
+                post_review_to_github(&repo, &review).await?;
+            }
+            if let Some(threshold) = *fail_on {
+                let failing = findings.iter().filter(|finding| finding.severity >= threshold).count();
+                if failing > 0 {
+                    return Err(fail(Outcome::Validation, format!("{} review finding(s) at or above {} severity", failing, threshold)));
+                }
+            }
+        }
+        Commands::Tests { from, to } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let diff = reference_diff(&repo, from, to.as_deref())?;
+            let suggestions = output::with_spinner("Suggesting tests", engine.suggest_tests(&diff)).await?;
+            match format {
+                OutputFormat::Text => outln!("{}", markup::render(&suggestions)),
+                OutputFormat::Json => outln!("{}", serde_json::json!({ "suggestions": suggestions })),
+            }
+        }
+        Commands::Commit { split_on_conflict, conventional, gitmoji, all, split, amend, reset_author, co_authors, offline, candidates, scan_secrets, allow_secrets, max_file_size, strict, allow_large, subject_max, wrap, edit, hook, hook_source, dry_run, include, exclude, include_untracked, template_commit, template, push, remote, branch } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let engine = if *include_untracked {
+                engine.with_untracked_files(staging::untracked_files(&repo)?)
+            } else {
+                engine
+            };
+            let examples = template_commit.iter()
+                .map(|reference| -> Result<String> {
+                    let commit = repo.find_commit(git::resolve_reference(&repo, reference)?)?;
+                    Ok(commit.message().unwrap_or_default().to_string())
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let engine = engine.with_commit_examples(examples);
+            if cli.no_verify {
+                warn!("--no-verify: secret scanning and commit message checks are off");
+            }
+            let settings = CommitSettings {
+                style: match (conventional, gitmoji) {
+                    (true, _) => ai::CommitStyle::Conventional,
+                    (_, true) => ai::CommitStyle::Gitmoji,
+                    _ => ai::CommitStyle::Freeform,
+                },
+                format,
+                line_ending: cli.line_ending,
+                edit: *edit,
+                co_authors: co_authors.clone(),
+                offline: *offline,
+                candidates: *candidates,
+                interactive: hook.is_none() && format == OutputFormat::Text && std::io::IsTerminal::is_terminal(&std::io::stdin()),
+                scan_secrets: *scan_secrets && !*allow_secrets && !cli.no_verify,
+                max_file_bytes: (!*allow_large && !cli.no_verify).then(|| max_file_size.saturating_mul(1024 * 1024)),
+                strict_large_files: *strict,
+                check_messages: !cli.no_verify,
+                subject_max: subject_max.unwrap_or(if *conventional { lint::CONVENTIONAL_SUBJECT_LENGTH } else { lint::FREEFORM_SUBJECT_LENGTH }),
+                wrap: *wrap,
+                include: include.clone(),
+                exclude: exclude.clone(),
+                // Resolved up front so a detached HEAD fails before any request
+                push: push.then(|| git::push_target(&repo, remote.as_deref(), branch.as_deref())).transpose()?,
+                template: template.as_deref().map(template::MessageTemplate::load).transpose()?,
+                refs: repo.head().ok()
+                    .and_then(|head| head.shorthand().map(str::to_string))
+                    .and_then(|branch| template::issue_key(&branch)),
             };
 
-            let summary = engine.summarize_diff(&diff, prompt.as_deref()).await?;
-            println!("Changes Summary:\n{}", summary);
-        }
-        Commands::Commit => {
-            let repo = Repository::open_from_env()?;
+            if let Some(message_file) = hook {
+                // A failing hook would abort the user's commit, so only warn
+                if let Err(e) = fill_commit_message_file(&repo, &engine, &settings, message_file, hook_source.as_deref()).await {
+                    eprintln!("gitwise: could not generate a commit message: {}", e);
+                }
+                return Ok(());
+            }
+
+            if *all {
+                // A dry run must leave the index file alone
+                staging::stage_tracked_changes(&repo, !*dry_run)?;
+            }
+
+            if *amend {
+                let head = repo.head()?.peel_to_commit()?;
+                if head.parent_count() > 1 {
+                    return Err(anyhow!("HEAD is a merge commit; refusing to amend it"));
+                }
+
+                let diff = staging::get_amend_changes(&repo, &mut settings.diff_options())?;
+                if *dry_run && cli.show_cost {
+                    eprintln!("Estimated prompt tokens: ~{}", engine.estimate_commit_message_tokens(&diff, settings.style)?);
+                }
+                // Everything staged is committed, whatever the pathspecs left out of the prompt
+                settings.check_large_files(&repo, &staging::get_amend_changes(&repo, &mut git::diff_options())?)?;
+                let (message, violations) = settings.message(&engine, &repo, &diff).await?;
+                if *dry_run {
+                    print_message(&message, &violations, settings.format);
+                    return Ok(());
+                }
+                let oid = staging::amend_head(&repo, &message, *reset_author)?;
+                print_commit(oid, &message, &violations, settings.format);
+                settings.push(&repo)?;
+                return Ok(());
+            }
+
+            if *split {
+                let staged_diff = staging::get_staged_changes(&repo)?;
+                let unstaged_diff = staging::get_unstaged_changes(&repo)?;
+                settings.check_secrets(&staged_diff)?;
+                settings.check_secrets(&unstaged_diff)?;
+                let groups: Vec<Vec<String>> = output::with_spinner("Grouping changes", engine.analyze_changes(&staged_diff, &unstaged_diff, None)).await?
+                    .into_iter()
+                    .filter(|group| !group.is_empty())
+                    .collect();
+                if groups.is_empty() {
+                    return Err(fail(Outcome::NoChanges, "No changes to commit"));
+                }
+
+                // A file changed in both the index and the working tree is
+                // committed with its working tree content, so it must only be
+                // committed once even if the model lists it in several groups
+                let mut seen = std::collections::HashSet::new();
+                let groups: Vec<Vec<String>> = groups.into_iter()
+                    .map(|group| group.into_iter().filter(|path| seen.insert(path.clone())).collect::<Vec<_>>())
+                    .filter(|group| !group.is_empty())
+                    .collect();
+
+                commit_groups(&repo, &engine, &groups, &settings, SplitMode::Interactive).await?;
+                settings.push(&repo)?;
+                return Ok(());
+            }
             
             // Check if there are staged changes
-            let mut index = repo.index()?;
+            let index = repo.index()?;
             if index.is_empty() {
-                println!("No changes to commit");
-                return Ok(());
+                return Err(fail(Outcome::NoChanges, "No changes to commit"));
             }
             
             // Get the diff of staged changes
-            let mut opts = git2::DiffOptions::new();
-            let head_tree = repo.head()?.peel_to_tree()?;
-            let diff = repo.diff_tree_to_index(Some(&head_tree), None, Some(&mut opts))?;
-            
-            let message = engine.generate_commit_message(&diff).await?;
-            
-            // Create the commit
-            let signature = repo.signature()?;
-            let tree_id = index.write_tree()?;
-            let tree = repo.find_tree(tree_id)?;
-            let parent = repo.head()?.peel_to_commit()?;
+            let diff = staging::diff_head_to_index(&repo, &mut settings.diff_options())?;
+            if diff.deltas().len() == 0 {
+                return Err(fail(Outcome::NoChanges, "No changes to commit"));
+            }
+            if cli.ignore_whitespace && git::whitespace_only(&diff)? {
+                return Err(fail(Outcome::NoChanges, "No changes to commit besides whitespace, which --ignore-whitespace leaves out"));
+            }
+
+            settings.check_secrets(&diff)?;
+
+            if *split_on_conflict {
+                let no_changes = repo.diff_tree_to_tree(None, None, None)?;
+                let groups: Vec<Vec<String>> = output::with_spinner("Grouping changes", engine.analyze_changes(&diff, &no_changes, None)).await?
+                    .into_iter()
+                    .filter(|group| !group.is_empty())
+                    .collect();
+
+                if groups.len() > 1 {
+                    eprintln!("⚠️  Staged changes look like {} unrelated groups:", groups.len());
+                    for (i, group) in groups.iter().enumerate() {
+                        eprintln!("  Group {}: {}", i + 1, group.join(", "));
+                    }
+                    if utils::confirm("Split them into separate commits?")? {
+                        commit_groups(&repo, &engine, &groups, &settings, SplitMode::Staged).await?;
+                        settings.push(&repo)?;
+                        return Ok(());
+                    }
+                }
+            }
             
-            repo.commit(
-                Some("HEAD"),
-                &signature,
-                &signature,
-                &message,
-                &tree,
-                &[&parent],
-            )?;
+            if *dry_run && cli.show_cost {
+                eprintln!("Estimated prompt tokens: ~{}", engine.estimate_commit_message_tokens(&diff, settings.style)?);
+            }
+            // Everything staged is committed, whatever the pathspecs left out of the prompt
+            settings.check_large_files(&repo, &staging::get_staged_changes(&repo)?)?;
+            let (message, violations) = settings.message(&engine, &repo, &diff).await?;
+            if *dry_run {
+                print_message(&message, &violations, settings.format);
+                return Ok(());
+            }
+            let oid = staging::commit_index(&repo, &message)?;
             
-            println!("Created commit with message:\n{}", message);
+            print_commit(oid, &message, &violations, settings.format);
+            settings.push(&repo)?;
         }
-        Commands::History { reference, count, prompt } => {
-            let repo = Repository::open_from_env()?;
+        Commands::History { reference, count, prompt, prompt_file, concurrency, include, exclude, group, regenerate, dry_run } => {
+            let prompt = &read_prompt(prompt, prompt_file)?;
+            let repo = open_repo(cli.repo.as_deref())?;
             let branch = if reference == "HEAD" {
                 None
             } else {
@@ -280,42 +1922,535 @@ async fn main() -> Result<()> {
             };
             
             let commits = git::get_log(&repo, branch, Some(*count))?;
+            if commits.is_empty() {
+                outln!("No commits yet");
+                return Ok(());
+            }
+            if *regenerate && branch.is_some() {
+                return Err(anyhow!("--regenerate rewrites the current branch; check out {} instead of naming it", reference));
+            }
             
-            let mut revwalk = repo.revwalk()?;
-            revwalk.push(repo.head()?.target().ok_or_else(|| anyhow!("Invalid HEAD reference"))?)?;
-            revwalk.set_sorting(git2::Sort::TIME)?;
+            // Compute every diff first, then summarize several at a time. Under
+            // --scope only commits that touched the subproject count
+            let head = repo.head()?.target().ok_or_else(|| anyhow!("Invalid HEAD reference"))?;
+            let commits = git::recent_commit_diffs(&repo, head, *count as usize, git::scope().is_some(), || git::pathspec_options(include, exclude))?;
+            let mut pending = Vec::new();
+            for (commit, diff) in commits {
+                let oid = commit.id();
+                let mut empty = git::empty_commit_summary(&commit, &diff);
+                if empty.is_none() && cli.ignore_whitespace && git::whitespace_only(&diff)? {
+                    empty = Some(format!("[whitespace-only changes] {}", commit.summary().unwrap_or_default()));
+                }
+                let merge = git::merge_label(&commit);
+                // Reverts and cherry-picks are summarized knowing what they replay
+                let prompt = match (prompt, git::replay_context(&repo, &commit)) {
+                    (Some(prompt), Some(context)) => Some(format!("{}\n\n{}", prompt, context)),
+                    (prompt, context) => context.or_else(|| prompt.clone()),
+                };
+                pending.push((oid, commit.summary().unwrap_or("No summary").to_string(), diff, empty, merge, prompt));
+            }
 
-            let mut summaries = Vec::new();
-            for (i, oid) in revwalk.take(*count as usize).enumerate() {
-                let oid = oid?;
-                let commit = repo.find_commit(oid)?;
-                let tree = commit.tree()?;
-                
-                let parent = commit.parent(0).ok();
-                let parent_tree = parent.as_ref().map(|c| c.tree()).transpose()?;
-                
-                let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
-                let summary = engine.summarize_diff(&diff, prompt.as_deref()).await?;
-                
-                summaries.push(format!(
-                    "Commit {} - {}\n{}\n",
-                    &oid.to_string()[..7],
-                    commit.summary().unwrap_or("No summary"),
-                    summary
+            if *regenerate {
+                if let Some((oid, ..)) = pending.iter().find(|(_, _, _, _, merge, _)| merge.is_some()) {
+                    return Err(anyhow!("Commit {} is a merge; lower --count to stop before it", &oid.to_string()[..7]));
+                }
+                let diffs: Vec<_> = pending.iter().map(|(_, _, diff, ..)| diff).collect();
+                let messages = output::with_spinner("Writing commit messages", engine.generate_commit_messages(&diffs)).await?;
+                let rewordings: Vec<(git2::Oid, String)> = pending.iter()
+                    .zip(messages)
+                    .map(|((oid, ..), message)| (*oid, utils::wrap_body(&message, lint::BODY_WRAP)))
+                    .collect();
+
+                match format {
+                    OutputFormat::Text => {
+                        for (i, ((oid, subject, ..), (_, message))) in pending.iter().zip(&rewordings).enumerate() {
+                            if i > 0 {
+                                out!("\n---\n\n");
+                            }
+                            out!("Commit {} - {}\n", output::paint(&oid.to_string()[..7], Color::Yellow), output::paint(subject, Color::Red));
+                            out!("{}\n", message);
+                        }
+                    }
+                    OutputFormat::Json => {
+                        let commits: Vec<_> = pending.iter().zip(&rewordings)
+                            .map(|((oid, subject, ..), (_, message))| serde_json::json!({ "oid": oid.to_string(), "old_subject": subject, "message": message }))
+                            .collect();
+                        outln!("{}", serde_json::json!({ "commits": commits }));
+                    }
+                }
+
+                if *dry_run || !utils::confirm(&format!("Rewrite these {} commits?", rewordings.len()))? {
+                    return Ok(());
+                }
+                let tip = git::reword_commits(&repo, &rewordings)?;
+                outln!("Rewrote {} commits; HEAD is now {}", rewordings.len(), output::paint(&tip.to_string()[..7], Color::Yellow));
+                return Ok(());
+            }
+
+            // `buffered` keeps results in commit order
+            let progress = output::progress_bar(pending.len() as u64, "Summarizing commits");
+            let summaries: Vec<Result<String>> = futures::stream::iter(&pending)
+                .map(|(_, _, diff, empty, _, prompt)| {
+                    let engine = &engine;
+                    let progress = &progress;
+                    async move {
+                        let summary = match empty {
+                            Some(summary) => Ok(summary.clone()),
+                            None => engine.summarize_diff(diff, prompt.as_deref()).await,
+                        };
+                        progress.inc(1);
+                        summary
+                    }
+                })
+                .buffered(*concurrency as usize)
+                .collect()
+                .await;
+            progress.finish_and_clear();
+
+            // A commit whose request timed out is skipped rather than ending the run
+            let mut entries = Vec::new();
+            for ((oid, subject, _, _, merge, _), summary) in pending.iter().zip(summaries) {
+                match summary {
+                    Ok(summary) => entries.push((*oid, subject.clone(), summary, merge.clone())),
+                    Err(e) if e.downcast_ref::<ai::RequestTimedOut>().is_some() => {
+                        warn!("Skipping commit {}: {}", &oid.to_string()[..7], e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if *group {
+                let summaries: Vec<(String, String)> = entries.iter()
+                    .map(|(oid, subject, summary, _)| (oid.to_string()[..7].to_string(), format!("{}\n{}", subject, summary)))
+                    .collect();
+                let themes = output::with_spinner("Grouping commits", engine.cluster_commits(&summaries)).await?;
+                match format {
+                    OutputFormat::Text => {
+                        outln!("{}", output::paint("Git History by Theme:", Color::Bold));
+                        for theme in &themes {
+                            outln!("\n{}", output::paint(&theme.title, Color::Bold));
+                            for hash in &theme.commits {
+                                let subject = entries.iter()
+                                    .find(|(oid, ..)| oid.to_string().starts_with(hash.as_str()))
+                                    .map_or("", |(_, subject, ..)| subject.as_str());
+                                outln!("- {} {}", output::paint(hash, Color::Yellow), subject);
+                            }
+                        }
+                    }
+                    OutputFormat::Json => outln!("{}", serde_json::json!({ "themes": themes })),
+                }
+                return Ok(());
+            }
+
+            match format {
+                OutputFormat::Text => {
+                    outln!("{}\n", output::paint("Git History Summary:", Color::Bold));
+                    for (i, (oid, subject, summary, merge)) in entries.iter().enumerate() {
+                        if i > 0 {
+                            out!("\n---\n\n");
+                        }
+                        out!("Commit {} - {}\n", output::paint(&oid.to_string()[..7], Color::Yellow), subject);
+                        if let Some(merge) = merge {
+                            out!("{}\n", output::paint(format!("Merge summary ({}; all sides since their merge base):", merge), Color::Cyan));
+                        }
+                        out!("{}\n", markup::render(summary));
+                    }
+                }
+                OutputFormat::Json => {
+                    let commits: Vec<_> = entries.iter()
+                        .map(|(oid, _, summary, merge)| serde_json::json!({ "oid": oid.to_string(), "merge": merge, "summary": summary }))
+                        .collect();
+                    outln!("{}", serde_json::json!({ "commits": commits }));
+                }
+            }
+        }
+        Commands::Show { reference } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let commit = repo.find_commit(git::resolve_reference(&repo, reference)?)?;
+            // Merges are summarized against their first parent
+            let merge = commit.parent_count() > 1;
+            let diff = git::get_commit_diff(&repo, &commit)?;
+            let context = git::replay_context(&repo, &commit);
+            let summary = match git::empty_commit_summary(&commit, &diff) {
+                Some(summary) => summary,
+                None => output::with_spinner("Summarizing commit", engine.summarize_diff(&diff, context.as_deref())).await?,
+            };
+            let message = commit.message().unwrap_or_default().trim();
+
+            match format {
+                OutputFormat::Text => {
+                    let note = if merge { " (merge; summarized against its first parent)" } else { "" };
+                    outln!("Commit {}{}\n\n{}\n\n{}\n{}", output::paint(commit.id(), Color::Yellow), note, message, output::paint("Summary:", Color::Bold), markup::render(&summary));
+                }
+                OutputFormat::Json => {
+                    outln!("{}", serde_json::json!({
+                        "oid": commit.id().to_string(),
+                        "merge": merge,
+                        "message": message,
+                        "summary": summary,
+                    }));
+                }
+            }
+        }
+        Commands::Explain { reference } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let commit = repo.find_commit(git::resolve_reference(&repo, reference)?)?;
+            let diff = match git::get_merge_diff(&repo, &commit, &mut git::diff_options())? {
+                Some(diff) => diff,
+                None => git::get_commit_diff(&repo, &commit)?,
+            };
+            let message = commit.message().unwrap_or_default().trim();
+            let explanation = match git::empty_commit_summary(&commit, &diff) {
+                Some(summary) => summary,
+                None => output::with_spinner("Explaining commit", engine.explain_commit(&diff, message)).await?,
+            };
+
+            match format {
+                OutputFormat::Text => outln!("Commit {}\n\n{}\n\n{}", output::paint(commit.id(), Color::Yellow), message, markup::render(&explanation)),
+                OutputFormat::Json => outln!("{}", serde_json::json!({
+                    "oid": commit.id().to_string(),
+                    "message": message,
+                    "explanation": explanation,
+                })),
+            }
+        }
+        Commands::Changelog { from, to, include_merges } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let to_oid = git::resolve_reference(&repo, to)?;
+            let commits = match release_start(&repo, from.as_deref())? {
+                Some((_, from_oid)) => git::get_commit_range(&repo, from_oid, to_oid, *include_merges)?,
+                None => git::get_history(&repo, to_oid, *include_merges)?,
+            };
+            let messages: Vec<String> = commits.iter()
+                .filter_map(|commit| commit.message().map(|m| m.trim().to_string()))
+                .collect();
+
+            let changelog = output::with_spinner("Writing changelog", engine.generate_changelog(&messages)).await?;
+            outln!("{}", markup::render(&changelog));
+        }
+        Commands::ReleaseNotes { prev_tag, tag } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let start = release_start(&repo, prev_tag.as_deref()).map_err(|e| match git::latest_tag(&repo) {
+                Some(latest) => anyhow!("{}; the most recent tag is {}", e, latest),
+                None => e,
+            })?;
+            let head = git::resolve_reference(&repo, "HEAD")?;
+
+            let commits = match &start {
+                Some((_, from_oid)) => git::get_commit_range(&repo, *from_oid, head, false)?,
+                None => git::get_history(&repo, head, false)?,
+            };
+            if commits.is_empty() {
+                let since = start.map(|(reference, _)| reference).unwrap_or_else(|| "the root commit".to_string());
+                return Err(fail(Outcome::NoChanges, format!("No commits since {}", since)));
+            }
+            let messages: Vec<String> = commits.iter()
+                .filter_map(|commit| commit.message().map(|m| m.trim().to_string()))
+                .collect();
+
+            let notes = output::with_spinner("Writing release notes", engine.generate_release_notes(&messages)).await?;
+            if let Some(name) = tag {
+                git::create_annotated_tag(&repo, name, head, &format!("{}\n", notes.trim()))?;
+                eprintln!("Created tag {}", name);
+            }
+            outln!("{}", markup::render(&notes));
+        }
+        Commands::Since { reference, no_advance } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let branch = git::current_branch(&repo)?;
+            let markers = git::SeenMarkers::open(&repo);
+            let from_oid = match (reference, markers.get(&branch)?) {
+                (Some(reference), _) => git::resolve_reference(&repo, reference)?,
+                (None, Some(oid)) => repo.find_commit(oid)
+                    .map_err(|_| anyhow!("The last-seen commit {} of {} no longer exists; pass a reference to start from", &oid.to_string()[..7], branch))?
+                    .id(),
+                (None, None) => return Err(anyhow!("Nothing seen on {} yet; pass a reference to start from, e.g. `gitwise since HEAD~5`", branch)),
+            };
+            let head = repo.head()?.peel_to_commit()?;
+
+            let commits = git::get_commit_range(&repo, from_oid, head.id(), false)?;
+            if commits.is_empty() {
+                eprintln!("Nothing new on {} since {}", branch, &from_oid.to_string()[..7]);
+            } else {
+                let subjects: Vec<String> = commits.iter()
+                    .map(|commit| format!("- {}", commit.summary().unwrap_or_default()))
+                    .collect();
+                let prompt = format!(
+                    "These changes landed on {} since it was last checked. Summarize them as a short standup-style update \
+                    of what landed, grouped by theme. The commits were:\n{}",
+                    branch, subjects.join("\n")
+                );
+                let from_tree = repo.find_commit(from_oid)?.tree()?;
+                let mut diff = repo.diff_tree_to_tree(Some(&from_tree), Some(&head.tree()?), Some(&mut git::diff_options()))?;
+                git::detect_renames(&mut diff)?;
+
+                eprintln!("{} commits on {} since {}", commits.len(), branch, output::paint(&from_oid.to_string()[..7], Color::Yellow));
+                let summary = output::with_spinner("Summarizing", engine.summarize_diff(&diff, Some(&prompt))).await?;
+                outln!("{}", markup::render(&summary));
+            }
+
+            if !*no_advance {
+                markers.set(&branch, head.id())?;
+                eprintln!("Marked {} as seen on {}", output::paint(&head.id().to_string()[..7], Color::Yellow), branch);
+            }
+        }
+        Commands::DiffFiles { old, new, prompt, prompt_file } => {
+            let read = |path: &std::path::Path| std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()));
+            let (old_content, new_content) = (read(old)?, read(new)?);
+            let (old_name, new_name) = (old.display().to_string(), new.display().to_string());
+            if old_content == new_content {
+                eprintln!("{} and {} are identical", old_name, new_name);
+                return Ok(());
+            }
+            if ai::is_binary_content(&old_content) || ai::is_binary_content(&new_content) {
+                eprintln!("Binary files {} and {} differ; only text files can be summarized", old_name, new_name);
+                return Ok(());
+            }
+
+            let patch = ai::unified_patch(&old_name, &String::from_utf8_lossy(&old_content), &new_name, &String::from_utf8_lossy(&new_content));
+            let prompt = read_prompt(prompt, prompt_file)?;
+            let summary = output::with_spinner("Summarizing", engine.summarize_patch_text(&patch, prompt.as_deref())).await?;
+            match format {
+                OutputFormat::Text => outln!("{}\n{}", output::paint("Changes Summary:", Color::Bold), markup::render(&summary)),
+                OutputFormat::Json => outln!("{}", serde_json::json!({ "summary": summary })),
+            }
+        }
+        Commands::Watch { debounce } => {
+            tokio::select! {
+                result = watch(&engine, cli.repo.as_deref(), std::time::Duration::from_millis(*debounce)) => result?,
+                _ = tokio::signal::ctrl_c() => eprintln!("Stopped watching"),
+            }
+        }
+        Commands::Branch { prefix, checkout } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let mut diff = staging::get_staged_changes(&repo)?;
+            if diff.deltas().len() == 0 {
+                diff = staging::get_unstaged_changes(&repo)?;
+            }
+            if diff.deltas().len() == 0 {
+                return Err(fail(Outcome::NoChanges, "No changes to name a branch after"));
+            }
+
+            let suggestion = output::with_spinner("Naming branch", engine.suggest_branch_name(&diff)).await?;
+            let name = git::sanitize_branch_name(&suggestion, prefix.as_deref())?;
+            if *checkout {
+                git::create_and_checkout_branch(&repo, &name)?;
+                eprintln!("Switched to a new branch '{}'", name);
+            }
+            outln!("{}", name);
+        }
+        Commands::Bump { range, current } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let (from, to) = range.split_once("..").unwrap_or((range.as_str(), ""));
+            let to = if to.is_empty() { "HEAD" } else { to };
+            let from_commit = repo.find_commit(git::resolve_reference(&repo, from)?)?;
+            let to_commit = repo.find_commit(git::resolve_reference(&repo, to)?)?;
+
+            let commits = git::get_commit_range(&repo, from_commit.id(), to_commit.id(), false)?;
+            let messages: Vec<String> = commits.iter()
+                .filter_map(|commit| commit.message().map(|m| m.trim().to_string()))
+                .collect();
+            let mut diff = repo.diff_tree_to_tree(Some(&from_commit.tree()?), Some(&to_commit.tree()?), Some(&mut git::diff_options()))?;
+            git::detect_renames(&mut diff)?;
+            if messages.is_empty() && diff.deltas().len() == 0 {
+                return Err(fail(Outcome::NoChanges, format!("No changes between {} and {}", from, to)));
+            }
+
+            let suggestion = output::with_spinner("Assessing changes", engine.suggest_version_bump(&diff, &messages)).await?;
+            let next = current.as_ref().map(|version| version.bump(suggestion.bump));
+            match format {
+                OutputFormat::Text => {
+                    outln!("{}", suggestion.bump);
+                    if let Some(next) = &next {
+                        outln!("{}", next);
+                    }
+                    eprintln!("{}", suggestion.rationale);
+                }
+                OutputFormat::Json => {
+                    let mut json = serde_json::json!({ "bump": suggestion.bump, "rationale": suggestion.rationale });
+                    if let Some(next) = &next {
+                        json["next_version"] = serde_json::json!(next.to_string());
+                    }
+                    outln!("{}", json);
+                }
+            }
+        }
+        Commands::VerifyMessage { reference, conventional, max_subject_length, required_trailers } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let commit = repo.find_commit(git::resolve_reference(&repo, reference)?)?;
+            let message = commit.message().ok_or_else(|| anyhow!("Commit message is not valid UTF-8"))?;
+            let default_length = if *conventional { lint::CONVENTIONAL_SUBJECT_LENGTH } else { lint::FREEFORM_SUBJECT_LENGTH };
+            let rules = lint::MessageRules {
+                max_subject_length: max_subject_length.unwrap_or(default_length),
+                conventional: *conventional,
+                required_trailers: required_trailers.clone(),
+                wrap: None,
+            };
+
+            let results = lint::check_message(message, &rules);
+            let failures = results.iter().filter(|result| !result.passed).count();
+            match format {
+                OutputFormat::Text => {
+                    for result in &results {
+                        match &result.detail {
+                            None => outln!("{} {}", output::paint("✓", Color::Green), result.rule),
+                            Some(detail) => outln!("{} {}: {}", output::paint("✗", Color::Red), result.rule, detail),
+                        }
+                    }
+                }
+                OutputFormat::Json => {
+                    outln!("{}", serde_json::json!({ "oid": commit.id().to_string(), "passed": failures == 0, "rules": results }));
+                }
+            }
+            if failures > 0 {
+                return Err(fail(Outcome::Validation, format!("Commit message failed {} of {} rules", failures, results.len())));
+            }
+        }
+        Commands::Undo => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let head = repo.head()?.peel_to_commit()?;
+            let short = head.id().to_string()[..7].to_string();
+            let subject = head.summary().unwrap_or_default().to_string();
+            if !cli.force && !lint::has_trailer(head.message().unwrap_or_default(), lint::GENERATED_BY_KEY, lint::GENERATED_BY_VALUE) {
+                return Err(anyhow!(
+                    "HEAD ({} {}) has no '{}: {}' trailer, so gitwise did not create it; use --force to undo it anyway",
+                    short, subject, lint::GENERATED_BY_KEY, lint::GENERATED_BY_VALUE
                 ));
+            }
+            staging::undo_head(&repo)?;
+            outln!("Undid {} {}; its changes are staged", output::paint(&short, Color::Yellow), subject);
+        }
+        Commands::InstallHook => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let path = git::hooks::install_hook(&repo, cli.force)?;
+            eprintln!("Installed {}", path.display());
+        }
+        Commands::UninstallHook => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let path = git::hooks::uninstall_hook(&repo)?;
+            eprintln!("Removed {}", path.display());
+        }
+        Commands::Blame { path, range, max_commits } => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let lines = range.as_deref().map(git::parse_line_range).transpose()?;
+            let oids = git::get_blame_commits(&repo, path, lines, *max_commits)?;
+            if oids.is_empty() {
+                return Err(fail(Outcome::NoChanges, format!("No committed history for {}", path)));
+            }
 
-                if i < *count as usize - 1 {
-                    summaries.push(String::from("\n---\n\n"));
+            let mut history = Vec::new();
+            for oid in oids {
+                let commit = repo.find_commit(oid)?;
+                let parent_tree = commit.parent(0).ok().map(|parent| parent.tree()).transpose()?;
+                let mut opts = git::pathspec_options(std::slice::from_ref(path), &[]);
+                let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), Some(&mut opts))?;
+                git::detect_renames(&mut diff)?;
+                history.push((commit.message().unwrap_or_default().to_string(), diff));
+            }
+
+            let narrative = output::with_spinner("Tracing history", engine.summarize_blame(path, &history)).await?;
+            match format {
+                OutputFormat::Text => outln!("{}", markup::render(&narrative)),
+                OutputFormat::Json => outln!("{}", serde_json::json!({ "path": path, "summary": narrative })),
+            }
+        }
+        Commands::Conflicts => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let (binary, conflicts): (Vec<_>, Vec<_>) = git::conflicts::get_conflicts(&repo)?
+                .into_iter()
+                .partition(|conflict| conflict.binary);
+            if conflicts.is_empty() && binary.is_empty() {
+                return Err(fail(Outcome::NoChanges, "No conflicts to summarize. 🎉"));
+            }
+
+            let summary = if conflicts.is_empty() {
+                None
+            } else {
+                Some(output::with_spinner("Explaining conflicts", engine.summarize_conflicts(&conflicts)).await?)
+            };
+            let skipped: Vec<&str> = binary.iter().map(|conflict| conflict.path.as_str()).collect();
+            match format {
+                OutputFormat::Text => {
+                    if let Some(summary) = &summary {
+                        outln!("{}", markup::render(summary));
+                    }
+                    if !skipped.is_empty() {
+                        outln!("\nSkipped binary conflicts: {}", skipped.join(", "));
+                    }
                 }
+                OutputFormat::Json => {
+                    outln!("{}", serde_json::json!({ "summary": summary, "skipped_binary": skipped }));
+                }
+            }
+        }
+        Commands::ResolveDiff => {
+            let repo = open_repo(cli.repo.as_deref())?;
+            let workdir = repo.workdir().ok_or_else(|| anyhow!("Repository has no working directory"))?;
+            let conflicts = git::conflicts::get_conflicts(&repo)?;
+            if conflicts.is_empty() {
+                return Err(fail(Outcome::NoChanges, "No conflicted files."));
             }
 
-            println!("Git History Summary:\n");
-            for summary in summaries {
-                print!("{}", summary);
+            eprintln!("⚠️  EXPERIMENTAL: proposals below are AI-suggested and UNVERIFIED.");
+            eprintln!("   Working files are never modified. Review each proposal before applying it.\n");
+            let mut resolved = Vec::new();
+            let mut skipped = Vec::new();
+            for conflict in &conflicts {
+                if conflict.binary {
+                    eprintln!("  {} is binary, skipped", conflict.path);
+                    skipped.push(conflict.path.as_str());
+                    continue;
+                }
+                let proposal = output::with_spinner(&format!("Resolving {}", conflict.path), engine.propose_resolution(conflict)).await?;
+                let sidecar = workdir.join(format!("{}.gitwise-resolved", conflict.path));
+                std::fs::write(&sidecar, proposal)
+                    .with_context(|| format!("Failed to write {}", sidecar.display()))?;
+                if format == OutputFormat::Text {
+                    outln!("  {} -> {}", conflict.path, sidecar.display());
+                }
+                resolved.push(serde_json::json!({ "path": conflict.path, "proposal": sidecar }));
+            }
+            match format {
+                OutputFormat::Text => eprintln!("\nCompare with e.g. `git diff --no-index <file> <file>.gitwise-resolved`."),
+                OutputFormat::Json => outln!("{}", serde_json::json!({ "resolved": resolved, "skipped_binary": skipped })),
             }
         }
+        Commands::Cache { action: CacheAction::Clear } => {
+            ai::cache::clear()?;
+            outln!("Summary cache cleared.");
+        }
+        Commands::Usage { reset } => {
+            let ledger = ai::cost::UsageLedger::open()?;
+            if *reset {
+                ledger.reset().await?;
+                outln!("Usage ledger cleared.");
+                return Ok(());
+            }
+            print_ledger(&ledger.entries()?, format);
+        }
+        Commands::Config { action: ConfigAction::Path } => {
+            let repo = open_repo(cli.repo.as_deref()).ok();
+            for (scope, path) in config::config_paths(repo.as_ref().and_then(|repo| repo.workdir())) {
+                let scope = match scope {
+                    config::Scope::Global => "global",
+                    config::Scope::Repository => "repository",
+                };
+                match format {
+                    OutputFormat::Text => {
+                        let state = if path.exists() { "in effect" } else { "not found" };
+                        outln!("{} ({}, {})", output::paint(path.display(), Color::Cyan), scope, state);
+                    }
+                    OutputFormat::Json => outln!("{}", serde_json::json!({
+                        "scope": scope,
+                        "path": path.display().to_string(),
+                        "exists": path.exists(),
+                    })),
+                }
+            }
+        }
+        Commands::Completions { .. } => unreachable!("completions are printed before any setup"),
         Commands::Log { branch, limit } => {
-            let repo = Repository::open_from_env()?;
+            let repo = open_repo(cli.repo.as_deref())?;
             let commits = git::get_log(&repo, branch.as_deref(), Some(*limit))?;
             
             // Build the log output
@@ -329,26 +2464,26 @@ async fn main() -> Result<()> {
                     .format("%Y-%m-%d %H:%M:%S");
                 
                 // Commit header
-                output.push_str(&format!("\n\x1b[33mcommit {}\x1b[0m\n", hash));
+                output.push_str(&format!("\n{}\n", output::paint(format!("commit {}", hash), Color::Yellow)));
                 output.push_str(&format!("Author: {}\n", commit.author()));
                 output.push_str(&format!("Date:   {}\n\n", datetime));
                 
                 // AI Summary
                 let diff = git::get_commit_diff(&repo, &commit)?;
-                let summary = engine.generate_commit_message(&diff).await?;
-                output.push_str("\x1b[36mAI Summary:\x1b[0m\n");
+                let summary = output::with_spinner("Summarizing commit", engine.generate_commit_message(&diff, ai::CommitStyle::Freeform)).await?;
+                output.push_str(&format!("{}\n", output::paint("AI Summary:", Color::Cyan)));
                 output.push_str(&format!("{}\n", summary.replace("\n", "\n    ")));
                 
                 // Separator
-                output.push_str("\n\x1b[90m----------------------------------------\x1b[0m\n");
+                output.push_str(&format!("\n{}\n", output::paint("-".repeat(40), Color::Gray)));
                 
                 // Original message
                 if let Some(msg) = commit.message() {
-                    output.push_str("\x1b[32mOriginal Message:\x1b[0m\n");
+                    output.push_str(&format!("{}\n", output::paint("Original Message:", Color::Green)));
                     output.push_str(&format!("{}\n", msg.trim().replace("\n", "\n    ")));
                 }
                 
-                output.push_str("\n");
+                output.push('\n');
             }
             
             // Open in pager
@@ -368,3 +2503,29 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bash_completions() {
+        let mut script = Vec::new();
+        print_completions(clap_complete::Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.starts_with("_gitwise() {"));
+        assert!(script.contains("complete -F _gitwise"));
+        for subcommand in ["diff", "commit", "history", "completions"] {
+            assert!(script.contains(&format!("gitwise,{})", subcommand)), "no completion for {}", subcommand);
+        }
+        assert!(script.contains("--max-diff-bytes"));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        assert_eq!(exit_status(&fail(Outcome::NoChanges, "No changes to commit")), 2);
+        assert_eq!(exit_status(&fail(Outcome::Validation, "Possible secrets").context("Commit failed")), 4);
+        assert_eq!(exit_status(&anyhow!(ai::AnthropicError("invalid x-api-key".to_string())).context("Summarizing")), 3);
+        assert_eq!(exit_status(&anyhow!("Not a git repository")), 1);
+    }
+}
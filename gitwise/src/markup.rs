@@ -0,0 +1,198 @@
+use regex::Regex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::output::OutputFormat;
+
+/// What `--format` selects: text marked up for where it is pasted, or JSON
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Format {
+    /// Plain text without markup
+    #[default]
+    #[value(alias = "text")]
+    Plain,
+    /// GitHub-flavored Markdown
+    Markdown,
+    /// Slack mrkdwn
+    Slack,
+    /// Machine-readable JSON
+    Json,
+}
+
+impl Format {
+    pub fn output(self) -> OutputFormat {
+        match self {
+            Format::Json => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        }
+    }
+
+    pub fn markup(self) -> Markup {
+        match self {
+            Format::Markdown => Markup::Markdown,
+            Format::Slack => Markup::Slack,
+            _ => Markup::Plain,
+        }
+    }
+}
+
+/// Markup that AI-written text is rendered in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Markup {
+    Plain,
+    Markdown,
+    Slack,
+}
+
+/// Markup of text results, stored as its index in `MARKUPS`
+static MARKUP: AtomicU8 = AtomicU8::new(0);
+const MARKUPS: [Markup; 3] = [Markup::Plain, Markup::Markdown, Markup::Slack];
+
+/// Choose the markup that `render` produces
+pub fn set_markup(markup: Markup) {
+    let index = MARKUPS.iter().position(|m| *m == markup).unwrap_or_default();
+    MARKUP.store(index as u8, Ordering::Relaxed);
+}
+
+/// Render AI-written Markdown in the markup chosen with `--format`
+pub fn render(text: &str) -> String {
+    let mut renderer = Renderer::new(MARKUPS[MARKUP.load(Ordering::Relaxed) as usize]);
+    let mut rendered = renderer.push(text);
+    rendered.push_str(&renderer.finish());
+    rendered
+}
+
+/// Renders Markdown a line at a time, so streamed text can be rendered as
+/// it arrives
+///
+/// Headings, bullets, bold text, links and code fences are rewritten for the
+/// target; everything inside a code block is left alone.
+pub struct Renderer {
+    markup: Markup,
+    in_code: bool,
+    /// Text of the line still being received
+    partial: String,
+    heading: Regex,
+    bullet: Regex,
+    bold: Regex,
+    link: Regex,
+}
+
+impl Renderer {
+    pub fn new(markup: Markup) -> Self {
+        Self {
+            markup,
+            in_code: false,
+            partial: String::new(),
+            heading: Regex::new(r"^#{1,6}\s+(.*?)\s*#*$").expect("heading pattern is valid"),
+            bullet: Regex::new(r"^(\s*)[*+-]\s+").expect("bullet pattern is valid"),
+            bold: Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").expect("bold pattern is valid"),
+            link: Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").expect("link pattern is valid"),
+        }
+    }
+
+    /// Render the complete lines in `chunk`, keeping any trailing partial
+    /// line until the rest of it arrives
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.partial.push_str(chunk);
+        let Some(end) = self.partial.rfind('\n') else {
+            return String::new();
+        };
+        let complete: String = self.partial.drain(..=end).collect();
+        complete.lines().filter_map(|line| self.line(line)).map(|line| line + "\n").collect()
+    }
+
+    /// Render whatever is left of the last line
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.partial);
+        if rest.is_empty() {
+            return String::new();
+        }
+        self.line(&rest).unwrap_or_default()
+    }
+
+    /// Render one line, or `None` to leave it out
+    fn line(&mut self, line: &str) -> Option<String> {
+        if line.trim_start().starts_with("```") {
+            self.in_code = !self.in_code;
+            return match self.markup {
+                Markup::Markdown => Some(line.to_string()),
+                // Slack ignores a fence's language, and plain text has no fences
+                Markup::Slack => Some("```".to_string()),
+                Markup::Plain => None,
+            };
+        }
+        if self.in_code || self.markup == Markup::Markdown && !self.bullet.is_match(line) {
+            return Some(line.to_string());
+        }
+
+        if let Some(captures) = self.heading.captures(line) {
+            let title = self.inline(&captures[1], true);
+            return Some(match self.markup {
+                Markup::Slack => format!("*{}*", title),
+                _ => title,
+            });
+        }
+        let (indent, rest) = match self.bullet.captures(line) {
+            Some(captures) => {
+                let marker = if self.markup == Markup::Slack { "• " } else { "- " };
+                (format!("{}{}", &captures[1], marker), &line[captures[0].len()..])
+            }
+            None => (String::new(), line),
+        };
+        Some(format!("{}{}", indent, self.inline(rest, false)))
+    }
+
+    /// Rewrite bold text and links for the target; `strip_bold` drops bold
+    /// markers inside a Slack heading, which is bold already
+    fn inline(&self, text: &str, strip_bold: bool) -> String {
+        let (bold, link) = match self.markup {
+            Markup::Markdown => return text.to_string(),
+            Markup::Slack if strip_bold => ("$1$2", "<$2|$1>"),
+            Markup::Slack => ("*$1$2*", "<$2|$1>"),
+            Markup::Plain => ("$1$2", "$1 ($2)"),
+        };
+        let text = self.bold.replace_all(text, bold);
+        self.link.replace_all(&text, link).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "## Summary\n\n* Adds **retry** to [the client](https://example.com)\n  + Backs off\n\n```rust\n* not a bullet\n```\n";
+
+    fn render_as(markup: Markup, text: &str) -> String {
+        let mut renderer = Renderer::new(markup);
+        let mut rendered = renderer.push(text);
+        rendered.push_str(&renderer.finish());
+        rendered
+    }
+
+    #[test]
+    fn test_render_targets() {
+        assert_eq!(
+            render_as(Markup::Plain, SAMPLE),
+            "Summary\n\n- Adds retry to the client (https://example.com)\n  - Backs off\n\n* not a bullet\n"
+        );
+        assert_eq!(
+            render_as(Markup::Markdown, SAMPLE),
+            "## Summary\n\n- Adds **retry** to [the client](https://example.com)\n  - Backs off\n\n```rust\n* not a bullet\n```\n"
+        );
+        assert_eq!(
+            render_as(Markup::Slack, SAMPLE),
+            "*Summary*\n\n• Adds *retry* to <https://example.com|the client>\n  • Backs off\n\n```\n* not a bullet\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_streamed_chunks_render_like_whole_text() {
+        let mut renderer = Renderer::new(Markup::Slack);
+        let mut rendered = String::new();
+        for chunk in SAMPLE.as_bytes().chunks(5) {
+            rendered.push_str(&renderer.push(std::str::from_utf8(chunk).unwrap()));
+        }
+        rendered.push_str(&renderer.finish());
+        assert_eq!(rendered, render_as(Markup::Slack, SAMPLE));
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::{Context, Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Like `print!`, but writes to the `--output` file when one is set
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::output::write_result(format_args!($($arg)*))
+    };
+}
+
+/// Like `println!`, but writes to the `--output` file when one is set
+macro_rules! outln {
+    () => {
+        out!("\n")
+    };
+    ($($arg:tt)*) => {
+        out!("{}\n", format_args!($($arg)*))
+    };
+}
+
+/// File that results are written to instead of stdout
+static RESULT_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Send results to a file instead of stdout, refusing to replace an
+/// existing file unless `force` is set
+pub fn redirect_results(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        return Err(anyhow!("{} already exists; use --force to overwrite it", path.display()));
+    }
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    *RESULT_FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
+/// Write part of a result to stdout or the `--output` file
+///
+/// Panics if writing fails, as `print!` does.
+pub fn write_result(args: std::fmt::Arguments<'_>) {
+    let mut file = RESULT_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    let written = match file.as_mut() {
+        Some(file) => file.write_fmt(args),
+        None => std::io::stdout().write_fmt(args),
+    };
+    written.expect("failed to write result");
+}
+
+/// Flush partially written results, e.g. while streaming
+pub fn flush_results() -> Result<()> {
+    match RESULT_FILE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(file) => file.flush()?,
+        None => std::io::stdout().flush()?,
+    }
+    Ok(())
+}
+
+/// Whether results are written as JSON
+static JSON_RESULTS: AtomicBool = AtomicBool::new(false);
+
+/// Record that results are written as JSON, so outcomes reported after the
+/// command returns match the rest of the output
+pub fn set_json_results(enabled: bool) {
+    JSON_RESULTS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether results are written as JSON
+pub fn json_results() -> bool {
+    JSON_RESULTS.load(Ordering::Relaxed)
+}
+
+/// Whether spinners and progress bars are drawn on stderr
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// How often spinners advance
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Allow progress indicators on stderr, which stay hidden anyway when stderr
+/// is not a terminal
+pub fn enable_progress(enabled: bool) {
+    PROGRESS.store(enabled && std::io::stderr().is_terminal(), Ordering::Relaxed);
+}
+
+/// A spinner on stderr, or a hidden one when progress is disabled
+pub fn spinner(message: &str) -> ProgressBar {
+    if !PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let spinner = ProgressBar::new_spinner().with_message(message.to_string());
+    spinner.enable_steady_tick(TICK_INTERVAL);
+    spinner
+}
+
+/// A progress bar over `len` steps on stderr, or a hidden one when progress
+/// is disabled
+pub fn progress_bar(len: u64, message: &str) -> ProgressBar {
+    if !PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(len).with_message(message.to_string());
+    bar.set_style(ProgressStyle::with_template("{spinner} {msg} [{bar:30}] {pos}/{len}")
+        .expect("progress template is valid")
+        .progress_chars("=> "));
+    bar.enable_steady_tick(TICK_INTERVAL);
+    bar
+}
+
+/// Await a future behind a spinner, clearing the spinner line before the
+/// result is printed
+pub async fn with_spinner<F: Future>(message: &str, future: F) -> F::Output {
+    let spinner = spinner(message);
+    let output = future.await;
+    spinner.finish_and_clear();
+    output
+}
+
+/// Whether text results are colored
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// When to color text results, following git's `--color`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    /// Always color text results
+    Always,
+    /// Never color
+    Never,
+}
+
+/// Colors used to highlight parts of text results
+#[derive(Clone, Copy, Debug)]
+pub enum Color {
+    Bold,
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+    Gray,
+}
+
+impl Color {
+    fn ansi_code(self) -> &'static str {
+        match self {
+            Color::Bold => "1",
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Cyan => "36",
+            Color::Gray => "90",
+        }
+    }
+}
+
+/// Whether results go to a terminal rather than a file or pipe
+pub fn results_to_terminal() -> bool {
+    RESULT_FILE.lock().unwrap_or_else(|e| e.into_inner()).is_none() && std::io::stdout().is_terminal()
+}
+
+/// Decide whether results are colored; JSON results never are
+///
+/// Call after `redirect_results`, as `auto` only colors results written to
+/// a terminal.
+pub fn enable_color(choice: ColorChoice, format: OutputFormat) {
+    let enabled = format == OutputFormat::Text && match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color && results_to_terminal()
+        }
+    };
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// `text` in `color` when results are colored, otherwise unchanged
+pub fn paint(text: impl std::fmt::Display, color: Color) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", color.ansi_code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// Machine-readable JSON
+    Json,
+}
+
+/// Line ending convention for generated commit messages
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum LineEnding {
+    /// Unix-style `\n`
+    #[default]
+    Lf,
+    /// Windows-style `\r\n`
+    Crlf,
+}
+
+impl LineEnding {
+    /// Convert every line break in `text`, whatever its style, to this convention
+    pub fn normalize(self, text: &str) -> String {
+        let unified = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self {
+            LineEnding::Lf => unified,
+            LineEnding::Crlf => unified.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// What to do when a per-file output mode exceeds `--max-output-files`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OverLimit {
+    /// Group files by top-level directory instead of listing them
+    Summarize,
+    /// List the first N files and note how many were left out
+    Truncate,
+    /// Fail without printing anything
+    Error,
+}
+
+/// A changed file and its single-letter status, as in `git diff --name-status`
+pub struct FileEntry {
+    pub status: char,
+    pub path: String,
+}
+
+/// Print one line per changed file, or a fallback view when there are more
+/// than `max_files`
+///
+/// JSON output is one object per line so large listings can be streamed.
+pub fn print_file_entries(entries: &[FileEntry], format: OutputFormat, max_files: Option<usize>, over_limit: OverLimit) -> Result<()> {
+    let limit = match max_files {
+        Some(max) if entries.len() > max => max,
+        _ => {
+            for entry in entries {
+                print_entry(entry, format);
+            }
+            return Ok(());
+        }
+    };
+
+    match over_limit {
+        OverLimit::Error => Err(anyhow!(
+            "{} files changed, more than --max-output-files {}",
+            entries.len(),
+            limit
+        )),
+        OverLimit::Truncate => {
+            for entry in &entries[..limit] {
+                print_entry(entry, format);
+            }
+            let omitted = entries.len() - limit;
+            match format {
+                OutputFormat::Text => outln!("... and {} more files", omitted),
+                OutputFormat::Json => outln!("{}", serde_json::json!({ "omitted": omitted })),
+            }
+            Ok(())
+        }
+        OverLimit::Summarize => {
+            // Top-level directory -> status -> count
+            let mut groups: BTreeMap<&str, BTreeMap<char, usize>> = BTreeMap::new();
+            for entry in entries {
+                let group = entry.path.split_once('/').map_or(".", |(top, _)| top);
+                *groups.entry(group).or_default().entry(entry.status).or_default() += 1;
+            }
+
+            if format == OutputFormat::Text {
+                outln!("{} files changed (more than {}), grouped by directory:", entries.len(), limit);
+            }
+            for (group, statuses) in &groups {
+                let total: usize = statuses.values().sum();
+                match format {
+                    OutputFormat::Text => {
+                        let breakdown = statuses.iter()
+                            .map(|(status, count)| format!("{} {}", status, count))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        outln!("{}/\t{} files ({})", group, total, breakdown);
+                    }
+                    OutputFormat::Json => {
+                        let statuses: BTreeMap<String, usize> = statuses.iter()
+                            .map(|(status, count)| (status.to_string(), *count))
+                            .collect();
+                        outln!("{}", serde_json::json!({ "group": group, "files": total, "statuses": statuses }));
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_entry(entry: &FileEntry, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => outln!("{}\t{}", entry.status, paint(&entry.path, Color::Cyan)),
+        OutputFormat::Json => outln!("{}", serde_json::json!({ "status": entry.status.to_string(), "path": entry.path })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_ending_lf() {
+        let message = "Add parser\r\n\r\nHandles nested\rinput\n";
+        assert_eq!(LineEnding::Lf.normalize(message), "Add parser\n\nHandles nested\ninput\n");
+    }
+
+    #[test]
+    fn test_line_ending_crlf() {
+        let message = "Add parser\n\r\nHandles nested\rinput\n";
+        assert_eq!(LineEnding::Crlf.normalize(message), "Add parser\r\n\r\nHandles nested\r\ninput\r\n");
+    }
+}
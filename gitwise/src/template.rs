@@ -0,0 +1,176 @@
+use anyhow::{Context, Result, anyhow};
+use regex::Regex;
+use std::path::Path;
+
+/// Placeholders a commit message template may contain
+const PLACEHOLDERS: &[&str] = &["subject", "body", "files", "refs"];
+
+/// A commit message layout with `{subject}`, `{body}`, `{files}` and
+/// `{refs}` placeholders
+///
+/// Lines starting with `#` are comments, as in git's commit templates, and
+/// `{{`/`}}` stand for literal braces. A line with a placeholder that has no
+/// value, such as `Refs: {refs}` on a branch without an issue key, is left
+/// out entirely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageTemplate {
+    text: String,
+}
+
+/// What a template's placeholders are filled with
+#[derive(Debug, Clone, Default)]
+pub struct TemplateValues<'a> {
+    /// The generated message, whose first line is the subject
+    pub message: &'a str,
+    /// Paths of the committed files
+    pub files: &'a [String],
+    /// Issue key for `{refs}`, e.g. from `issue_key`
+    pub refs: Option<&'a str>,
+}
+
+impl MessageTemplate {
+    /// Read and check a template file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid template {}", path.display()))
+    }
+
+    /// Check a template, which must use `{subject}` and only known
+    /// placeholders
+    pub fn parse(text: &str) -> Result<Self> {
+        let text: String = text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| format!("{}\n", line))
+            .collect();
+
+        let mut has_subject = false;
+        for line in text.lines() {
+            for token in tokens(line)? {
+                if let Token::Placeholder(name) = token {
+                    if !PLACEHOLDERS.contains(&name) {
+                        return Err(anyhow!("Unknown placeholder {{{}}}; use one of {}", name, PLACEHOLDERS.iter()
+                            .map(|p| format!("{{{}}}", p))
+                            .collect::<Vec<_>>()
+                            .join(", ")));
+                    }
+                    has_subject |= name == "subject";
+                }
+            }
+        }
+        if !has_subject {
+            return Err(anyhow!("The template must contain {{subject}}"));
+        }
+        Ok(Self { text })
+    }
+
+    /// Fill the placeholders, dropping lines whose placeholders are empty
+    pub fn render(&self, values: &TemplateValues<'_>) -> String {
+        let (subject, body) = match values.message.trim().split_once('\n') {
+            Some((subject, body)) => (subject.trim(), body.trim()),
+            None => (values.message.trim(), ""),
+        };
+        let files = values.files.iter().map(|path| format!("- {}", path)).collect::<Vec<_>>().join("\n");
+        let value = |name: &str| match name {
+            "subject" => subject,
+            "body" => body,
+            "files" => files.as_str(),
+            _ => values.refs.unwrap_or_default(),
+        };
+
+        let mut lines = Vec::new();
+        for line in self.text.lines() {
+            let tokens = tokens(line).expect("template was checked when parsed");
+            let empty = tokens.iter().any(|token| matches!(token, Token::Placeholder(name) if value(name).is_empty()));
+            if empty {
+                continue;
+            }
+            lines.push(tokens.iter()
+                .map(|token| match token {
+                    Token::Text(text) => *text,
+                    Token::Placeholder(name) => value(name),
+                })
+                .collect::<String>());
+        }
+
+        // Collapse the blank lines left by dropped sections
+        let message = lines.join("\n");
+        let blank_lines = Regex::new(r"\n\s*\n(\s*\n)+").expect("blank line pattern is valid");
+        blank_lines.replace_all(message.trim(), "\n\n").to_string()
+    }
+}
+
+/// Part of a template line
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template line into text and placeholders
+fn tokens(line: &str) -> Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(['{', '}']) {
+        tokens.push(Token::Text(&rest[..start]));
+        let after = &rest[start + 1..];
+        if rest[start..].starts_with("{{") {
+            tokens.push(Token::Text("{"));
+            rest = &after[1..];
+        } else if rest[start..].starts_with("}}") {
+            tokens.push(Token::Text("}"));
+            rest = &after[1..];
+        } else if rest[start..].starts_with('}') {
+            return Err(anyhow!("Unmatched '}}' in line {:?}; write '}}}}' for a literal brace", line));
+        } else {
+            let end = after.find('}')
+                .ok_or_else(|| anyhow!("Unclosed '{{' in line {:?}; write '{{{{' for a literal brace", line))?;
+            tokens.push(Token::Placeholder(&after[..end]));
+            rest = &after[end + 1..];
+        }
+    }
+    tokens.push(Token::Text(rest));
+    Ok(tokens)
+}
+
+/// The Jira-style issue key in a branch name, e.g. `PROJ-123` from
+/// `feature/proj-123-login`
+pub fn issue_key(branch: &str) -> Option<String> {
+    let pattern = Regex::new(r"(?:^|[^A-Za-z0-9])([A-Za-z][A-Za-z0-9]*-[0-9]+)").expect("issue key pattern is valid");
+    pattern.captures(branch).map(|captures| captures[1].to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let template = MessageTemplate::parse(
+            "# Our house style\n{subject}\n\n{body}\n\nFiles:\n{files}\n\nRefs: {refs}\nTeam: {{platform}}\n",
+        ).unwrap();
+        let files = vec!["src/auth.rs".to_string()];
+        let values = TemplateValues { message: "Add login\n\nChecks passwords.", files: &files, refs: Some("PROJ-123") };
+        assert_eq!(
+            template.render(&values),
+            "Add login\n\nChecks passwords.\n\nFiles:\n- src/auth.rs\n\nRefs: PROJ-123\nTeam: {platform}"
+        );
+
+        let values = TemplateValues { message: "Add login", files: &files, refs: None };
+        assert_eq!(template.render(&values), "Add login\n\nFiles:\n- src/auth.rs\n\nTeam: {platform}");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(MessageTemplate::parse("{body}").is_err());
+        assert!(MessageTemplate::parse("{subject}\n\n{ticket}").is_err());
+        assert!(MessageTemplate::parse("{subject}\n\n{body").is_err());
+    }
+
+    #[test]
+    fn test_issue_key() {
+        assert_eq!(issue_key("feature/proj-123-login").as_deref(), Some("PROJ-123"));
+        assert_eq!(issue_key("ABC-9").as_deref(), Some("ABC-9"));
+        assert_eq!(issue_key("main"), None);
+    }
+}
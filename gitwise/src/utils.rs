@@ -1,7 +1,161 @@
-use anyhow::Result;
-use git2::Repository;
+use anyhow::{Context, Result, anyhow};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::Command;
 
-/// Get the current git repository
-fn get_current_repo() -> Result<Repository> {
-    Ok(Repository::open_from_env()?)
+/// Ask a yes/no question on stdin, defaulting to no
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Ask for a number from 1 to `count` on stdin, returning its index; an
+/// empty or unrecognized answer picks `default`
+pub fn choose(question: &str, count: usize, default: usize) -> Result<usize> {
+    out!("{} [1-{}, default {}] ", question, count, default + 1);
+    crate::output::flush_results()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(match answer.trim().parse::<usize>() {
+        Ok(number) if (1..=count).contains(&number) => number - 1,
+        _ => default,
+    })
+}
+
+/// Let the user edit text in their editor, git-style
+///
+/// The text is written to `path` followed by a comment explaining what to do,
+/// `$GIT_EDITOR` or `$EDITOR` (falling back to `vi`) is launched on it, and
+/// the saved contents are returned with `#` comment lines removed.
+pub fn edit_in_editor(path: &Path, text: &str) -> Result<String> {
+    std::fs::write(path, format!(
+        "{}\n\n# Edit the commit message above. Lines starting with '#' are ignored,\n# and an empty message aborts the commit.\n",
+        text.trim_end()
+    ))?;
+
+    let editor = std::env::var("GIT_EDITOR")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    // Run through the shell so editors configured with arguments work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to launch editor '{}'", editor))?;
+    if !status.success() {
+        return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+    }
+
+    let edited = std::fs::read_to_string(path)?;
+    let message = edited
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(message.trim().to_string())
+}
+
+/// Word-wrap the body of a commit message at `width` characters
+///
+/// The subject line, blank lines, lines already short enough and anything
+/// inside a code fence are kept as they are. Wrapped list items continue
+/// under the item's text, and words longer than `width` (such as URLs) are
+/// put on a line of their own rather than broken.
+pub fn wrap_body(msg: &str, width: usize) -> String {
+    let mut lines = msg.lines();
+    let mut wrapped: Vec<String> = lines.next().map(str::to_string).into_iter().collect();
+    let mut in_fence = false;
+    for line in lines {
+        let text = line.trim_start();
+        if text.starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence || text.starts_with("```") || line.chars().count() <= width {
+            wrapped.push(line.to_string());
+            continue;
+        }
+
+        let (prefix, words) = line.split_at(line.len() - text.len() + list_marker_len(text));
+        let hanging = " ".repeat(prefix.chars().count());
+        let mut current = prefix.to_string();
+        let mut empty = true;
+        for word in words.split_whitespace() {
+            if !empty && current.chars().count() + 1 + word.chars().count() > width {
+                wrapped.push(std::mem::replace(&mut current, hanging.clone()));
+                empty = true;
+            }
+            if !empty {
+                current.push(' ');
+            }
+            current.push_str(word);
+            empty = false;
+        }
+        wrapped.push(current);
+    }
+
+    let mut body = wrapped.join("\n");
+    if msg.ends_with('\n') {
+        body.push('\n');
+    }
+    body
+}
+
+/// Length of a `- `, `* `, `+ `, `1. ` or `1) ` list marker at the start of
+/// a line, or 0
+fn list_marker_len(text: &str) -> usize {
+    if ["- ", "* ", "+ "].iter().any(|marker| text.starts_with(marker)) {
+        return 2;
+    }
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let rest = &text[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        digits + 2
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_body() {
+        let subject = format!("Add parser {}", "x".repeat(80));
+        let message = format!(
+            "{}\n\nShort line.\n\n{}\n- {}\n\n```\n{}\n```\n",
+            subject,
+            "word ".repeat(20).trim_end(),
+            "item ".repeat(16).trim_end(),
+            "code ".repeat(20).trim_end(),
+        );
+        let expected = format!(
+            "{}\n\nShort line.\n\n{}\n{}\n- {}\n  {}\n\n```\n{}\n```\n",
+            subject,
+            "word ".repeat(14).trim_end(),
+            "word ".repeat(6).trim_end(),
+            "item ".repeat(14).trim_end(),
+            "item ".repeat(2).trim_end(),
+            "code ".repeat(20).trim_end(),
+        );
+        assert_eq!(wrap_body(&message, 72), expected);
+    }
+
+    #[test]
+    fn test_wrap_body_keeps_long_urls_whole() {
+        let url = format!("https://example.com/{}", "x".repeat(80));
+        let message = format!("Fix link\n\nSee {} for details.", url);
+        assert_eq!(wrap_body(&message, 72), format!("Fix link\n\nSee\n{}\nfor details.", url));
+
+        let short = "Fix link\n\nAlready short.\n  Indented.\n\nDone.";
+        assert_eq!(wrap_body(short, 72), short);
+    }
 }
@@ -0,0 +1,90 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Semantic versioning component to increment for a release
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionBump {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl fmt::Display for VersionBump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VersionBump::Major => "major",
+            VersionBump::Minor => "minor",
+            VersionBump::Patch => "patch",
+        })
+    }
+}
+
+/// A `MAJOR.MINOR.PATCH` version, optionally written with a leading `v`
+///
+/// Pre-release and build metadata are accepted but dropped when bumping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    prefix: bool,
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl Version {
+    /// The next version after applying a bump
+    pub fn bump(&self, bump: VersionBump) -> Version {
+        let (major, minor, patch) = match bump {
+            VersionBump::Major => (self.major + 1, 0, 0),
+            VersionBump::Minor => (self.major, self.minor + 1, 0),
+            VersionBump::Patch => (self.major, self.minor, self.patch + 1),
+        };
+        Version { prefix: self.prefix, major, minor, patch }
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || anyhow!("'{}' is not a MAJOR.MINOR.PATCH version", value);
+        let (prefix, rest) = match value.trim().strip_prefix('v') {
+            Some(rest) => (true, rest),
+            None => (false, value.trim()),
+        };
+        let core = rest.split(['-', '+']).next().unwrap_or_default();
+        let parts: Vec<u64> = core.split('.')
+            .map(|part| part.parse().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+        match parts[..] {
+            [major, minor, patch] => Ok(Version { prefix, major, minor, patch }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.prefix { "v" } else { "" };
+        write!(f, "{}{}.{}.{}", prefix, self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump_version() {
+        let version: Version = "1.3.7".parse().unwrap();
+        assert_eq!(version.bump(VersionBump::Major).to_string(), "2.0.0");
+        assert_eq!(version.bump(VersionBump::Minor).to_string(), "1.4.0");
+        assert_eq!(version.bump(VersionBump::Patch).to_string(), "1.3.8");
+        let tagged: Version = "v0.9.0-rc.1".parse().unwrap();
+        assert_eq!(tagged.bump(VersionBump::Minor).to_string(), "v0.10.0");
+        assert!("1.3".parse::<Version>().is_err());
+        assert!("one.two.three".parse::<Version>().is_err());
+    }
+}